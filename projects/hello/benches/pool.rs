@@ -0,0 +1,249 @@
+//! 比较 [`hello::ThreadPool`] 与最初那种所有 worker 共用一个
+//! `Arc<Mutex<mpsc::Receiver<Job>>>` 的线程池。
+//!
+//! 运行：`cargo bench --bench pool`
+
+use std::{
+    hint::black_box,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Condvar, Mutex,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+/// 最初的线程池，只去掉了每个作业一次的 `println!`，否则比较的就是标准输出的锁了。
+mod legacy {
+    use std::{
+        sync::{mpsc, Arc, Mutex},
+        thread,
+    };
+
+    type Job = Box<dyn FnOnce() + Send + 'static>;
+
+    pub struct ThreadPool {
+        workers: Vec<thread::JoinHandle<()>>,
+        sender: Option<mpsc::Sender<Job>>,
+    }
+
+    impl ThreadPool {
+        pub fn new(size: usize) -> ThreadPool {
+            assert!(size > 0);
+
+            let (sender, receiver) = mpsc::channel::<Job>();
+            let receiver = Arc::new(Mutex::new(receiver));
+
+            let workers = (0..size)
+                .map(|_| {
+                    let receiver = Arc::clone(&receiver);
+                    thread::spawn(move || loop {
+                        let message = receiver.lock().unwrap().recv();
+                        match message {
+                            Ok(job) => job(),
+                            Err(_) => break,
+                        }
+                    })
+                })
+                .collect();
+
+            ThreadPool {
+                workers,
+                sender: Some(sender),
+            }
+        }
+
+        pub fn execute<F>(&self, f: F)
+        where
+            F: FnOnce() + Send + 'static,
+        {
+            self.sender.as_ref().unwrap().send(Box::new(f)).unwrap();
+        }
+    }
+
+    impl Drop for ThreadPool {
+        fn drop(&mut self) {
+            drop(self.sender.take());
+            for worker in self.workers.drain(..) {
+                worker.join().unwrap();
+            }
+        }
+    }
+}
+
+trait Pool: Sync {
+    fn submit(&self, job: impl FnOnce() + Send + 'static);
+}
+
+impl Pool for legacy::ThreadPool {
+    fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        self.execute(job);
+    }
+}
+
+impl Pool for hello::ThreadPool {
+    fn submit(&self, job: impl FnOnce() + Send + 'static) {
+        if self.execute(job).is_err() {
+            panic!("线程池已关闭");
+        }
+    }
+}
+
+/// 计数到零时唤醒等待者。
+struct Latch {
+    remaining: AtomicUsize,
+    done: Mutex<bool>,
+    changed: Condvar,
+}
+
+impl Latch {
+    fn new(count: usize) -> Arc<Latch> {
+        Arc::new(Latch {
+            remaining: AtomicUsize::new(count),
+            done: Mutex::new(false),
+            changed: Condvar::new(),
+        })
+    }
+
+    fn count_down(&self) {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) == 1 {
+            *self.done.lock().unwrap() = true;
+            self.changed.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut done = self.done.lock().unwrap();
+        while !*done {
+            done = self.changed.wait(done).unwrap();
+        }
+    }
+}
+
+/// 一种负载：`submitters` 个线程各提交 `jobs` 个作业，每个作业调用一次 `work`。
+struct Workload {
+    name: &'static str,
+    submitters: usize,
+    jobs: usize,
+    work: fn(),
+}
+
+impl Workload {
+    /// 把全部作业提交给 `pool` 并等待它们完成，返回所用时间。
+    fn run(&self, pool: &impl Pool) -> Duration {
+        let latch = Latch::new(self.submitters * self.jobs);
+        let start = Instant::now();
+
+        thread::scope(|scope| {
+            for _ in 0..self.submitters {
+                let latch = Arc::clone(&latch);
+                scope.spawn(move || {
+                    for _ in 0..self.jobs {
+                        let latch = Arc::clone(&latch);
+                        let work = self.work;
+                        pool.submit(move || {
+                            work();
+                            latch.count_down();
+                        });
+                    }
+                });
+            }
+        });
+
+        latch.wait();
+        start.elapsed()
+    }
+}
+
+fn nothing() {}
+
+fn spin() {
+    black_box((0..2_000u64).fold(0, |acc, i| acc ^ black_box(i).wrapping_mul(31)));
+}
+
+fn sleep() {
+    thread::sleep(Duration::from_millis(5));
+}
+
+const ROUNDS: usize = 7;
+
+/// 运行 `ROUNDS` 轮，返回中位数。每轮都新建线程池，线程的创建不计入时间。
+fn measure<P: Pool>(workload: &Workload, make: impl Fn() -> P) -> Duration {
+    let mut samples: Vec<Duration> = (0..ROUNDS)
+        .map(|_| {
+            let pool = make();
+            workload.run(&pool)
+        })
+        .collect();
+    samples.sort();
+    samples[ROUNDS / 2]
+}
+
+fn report(workload: &Workload, label: &str, elapsed: Duration) {
+    let jobs = (workload.submitters * workload.jobs) as f64;
+    println!(
+        "{:<24} {:<28} {:>10.2?} {:>12.0} 作业/秒",
+        workload.name,
+        label,
+        elapsed,
+        jobs / elapsed.as_secs_f64()
+    );
+}
+
+fn main() {
+    let threads = thread::available_parallelism().map_or(4, |n| n.get());
+
+    let fixed = [
+        Workload {
+            name: "空作业，1 个提交者",
+            submitters: 1,
+            jobs: 200_000,
+            work: nothing,
+        },
+        Workload {
+            name: "空作业，4 个提交者",
+            submitters: 4,
+            jobs: 50_000,
+            work: nothing,
+        },
+        Workload {
+            name: "短计算，1 个提交者",
+            submitters: 1,
+            jobs: 50_000,
+            work: spin,
+        },
+    ];
+
+    println!("{threads} 个 worker，每项取 {ROUNDS} 轮的中位数\n");
+
+    for workload in &fixed {
+        let old = measure(workload, || legacy::ThreadPool::new(threads));
+        report(workload, "Mutex<mpsc::Receiver>", old);
+
+        let new = measure(workload, || hello::ThreadPool::new(threads));
+        report(workload, "工作窃取", new);
+    }
+
+    // 阻塞型的作业：固定大小的旧线程池只能排队，可伸缩的线程池会增加 worker。
+    let bursty = Workload {
+        name: "5ms 阻塞作业，突发",
+        submitters: 1,
+        jobs: 256,
+        work: sleep,
+    };
+
+    let old = measure(&bursty, || legacy::ThreadPool::new(threads));
+    report(&bursty, "Mutex<mpsc::Receiver>", old);
+
+    let new = measure(&bursty, || {
+        hello::ThreadPool::builder(threads)
+            .max_threads(threads * 8)
+            .build()
+            .unwrap()
+    });
+    report(
+        &bursty,
+        &format!("工作窃取，最多 {} 线程", threads * 8),
+        new,
+    );
+}
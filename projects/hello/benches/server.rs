@@ -0,0 +1,146 @@
+//! 在同样的负载下比较 [`hello::Server::run`] 与 [`hello::Server::run_async`]。
+//!
+//! 运行：`cargo bench --bench server`
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, TcpListener, TcpStream},
+    thread,
+    time::{Duration, Instant},
+};
+
+use hello::{ConnectionOptions, Request, Response, Server, ShutdownReport, StatusCode, ThreadPool};
+
+const WORKERS: usize = 4;
+
+#[derive(Clone, Copy)]
+enum Mode {
+    Threaded,
+    Async,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Threaded => "每连接一个 worker",
+            Mode::Async => "每连接一个任务",
+        }
+    }
+}
+
+/// 一种负载：先打开 `idle` 个什么也不发的连接，再由 `clients` 个客户端各在一个持久连接上
+/// 依次发送 `requests` 个请求。
+struct Workload {
+    name: &'static str,
+    idle: usize,
+    clients: usize,
+    requests: usize,
+}
+
+fn start(
+    mode: Mode,
+) -> (
+    SocketAddr,
+    hello::ShutdownHandle,
+    thread::JoinHandle<ShutdownReport>,
+) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let handler =
+        |request: Request| Response::new(StatusCode::Ok).with_body(request.path().to_string());
+    let options = ConnectionOptions {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..ConnectionOptions::default()
+    };
+    let server = Server::new(listener, ThreadPool::new(WORKERS), handler)
+        .options(options)
+        .drain_timeout(Duration::from_secs(5));
+    let addr = server.local_addr().unwrap();
+    let handle = server.shutdown_handle();
+
+    let server = thread::spawn(move || match mode {
+        Mode::Threaded => server.run().unwrap(),
+        Mode::Async => server.run_async().unwrap(),
+    });
+    (addr, handle, server)
+}
+
+/// 读取一个带 `Content-Length` 的响应。
+fn read_response(reader: &mut impl BufRead) {
+    let mut length = 0;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line).unwrap();
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Content-Length: ") {
+            length = value.parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+}
+
+impl Workload {
+    fn run(&self, mode: Mode) -> Duration {
+        let (addr, handle, server) = start(mode);
+        let idle: Vec<TcpStream> = (0..self.idle)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+
+        let start = Instant::now();
+        thread::scope(|scope| {
+            for _ in 0..self.clients {
+                scope.spawn(|| {
+                    let stream = TcpStream::connect(addr).unwrap();
+                    let mut reader = BufReader::new(&stream);
+                    let mut writer = &stream;
+                    for _ in 0..self.requests {
+                        writer.write_all(b"GET /bench HTTP/1.1\r\n\r\n").unwrap();
+                        read_response(&mut reader);
+                    }
+                });
+            }
+        });
+        let elapsed = start.elapsed();
+
+        drop(idle);
+        handle.shutdown();
+        server.join().unwrap();
+        elapsed
+    }
+}
+
+fn main() {
+    let workloads = [
+        Workload {
+            name: "16 个持久连接",
+            idle: 0,
+            clients: 16,
+            requests: 2_000,
+        },
+        Workload {
+            name: "另有 32 个空闲连接",
+            idle: 32,
+            clients: 4,
+            requests: 2_000,
+        },
+    ];
+
+    println!("{WORKERS} 个 worker，空闲超时 200ms\n");
+
+    for workload in &workloads {
+        for mode in [Mode::Threaded, Mode::Async] {
+            let elapsed = workload.run(mode);
+            let requests = (workload.clients * workload.requests) as f64;
+            println!(
+                "{:<20} {:<20} {:>10.2?} {:>10.0} 请求/秒",
+                workload.name,
+                mode.name(),
+                elapsed,
+                requests / elapsed.as_secs_f64()
+            );
+        }
+    }
+}
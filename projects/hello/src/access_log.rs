@@ -0,0 +1,204 @@
+use std::{
+    error::Error,
+    fmt,
+    fs::OpenOptions,
+    io::{self, Write},
+    net::SocketAddr,
+    path::Path,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
+
+use crate::date::DateTime;
+
+/// 访问日志的格式。两种格式的末尾都附加处理请求所用的微秒数（Apache 的 `%D`）。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum LogFormat {
+    /// Common Log Format：`主机 - - [时间] "请求行" 状态码 字节数`。
+    #[default]
+    Common,
+    /// Combined Log Format：在 Common 之后加上 `"Referer" "User-Agent"`。
+    Combined,
+}
+
+/// 无法识别的访问日志格式名称。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseLogFormatError(pub String);
+
+impl fmt::Display for ParseLogFormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "未知的访问日志格式：{:?}", self.0)
+    }
+}
+
+impl Error for ParseLogFormatError {}
+
+impl FromStr for LogFormat {
+    type Err = ParseLogFormatError;
+
+    /// 不区分大小写地解析 `common` 或 `combined`。
+    fn from_str(s: &str) -> Result<LogFormat, ParseLogFormatError> {
+        if s.eq_ignore_ascii_case("common") {
+            Ok(LogFormat::Common)
+        } else if s.eq_ignore_ascii_case("combined") {
+            Ok(LogFormat::Combined)
+        } else {
+            Err(ParseLogFormatError(s.to_string()))
+        }
+    }
+}
+
+/// 一个已处理请求的访问日志条目。
+#[derive(Clone, Debug)]
+pub struct AccessEntry<'a> {
+    pub peer: Option<SocketAddr>,
+    pub time: SystemTime,
+    /// 请求行，比如 `GET /index.html HTTP/1.1`。
+    pub request_line: &'a str,
+    pub status: u16,
+    /// 发送的响应体字节数，不含头部。
+    pub bytes: u64,
+    pub duration: Duration,
+    pub referer: Option<&'a str>,
+    pub user_agent: Option<&'a str>,
+}
+
+impl AccessEntry<'_> {
+    /// 按 `format` 格式化为一行，不含换行符。
+    pub fn format(&self, format: LogFormat) -> String {
+        let host = self
+            .peer
+            .map_or_else(|| "-".to_string(), |peer| peer.ip().to_string());
+        let bytes = match self.bytes {
+            0 => "-".to_string(),
+            n => n.to_string(),
+        };
+
+        let mut line = format!(
+            "{host} - - [{}] \"{}\" {} {bytes}",
+            DateTime::from(self.time).clf(),
+            Escaped(self.request_line),
+            self.status,
+        );
+        if format == LogFormat::Combined {
+            line.push_str(&format!(
+                " \"{}\" \"{}\"",
+                Escaped(self.referer.unwrap_or("-")),
+                Escaped(self.user_agent.unwrap_or("-"))
+            ));
+        }
+        line.push_str(&format!(" {}", self.duration.as_micros()));
+        line
+    }
+}
+
+/// 转义引号、反斜杠与控制字符，使客户端提供的内容无法伪造日志行。
+struct Escaped<'a>(&'a str);
+
+impl fmt::Display for Escaped<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for c in self.0.chars() {
+            match c {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                c if c.is_control() => write!(f, "\\x{:02x}", c as u32)?,
+                c => write!(f, "{c}")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// 把访问日志逐行写入文件或标准错误。
+pub struct AccessLog {
+    format: LogFormat,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(writer: impl Write + Send + 'static, format: LogFormat) -> AccessLog {
+        AccessLog {
+            format,
+            out: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn stderr(format: LogFormat) -> AccessLog {
+        AccessLog::new(io::stderr(), format)
+    }
+
+    /// 以追加方式打开（必要时创建）`path`。
+    pub fn file(path: impl AsRef<Path>, format: LogFormat) -> io::Result<AccessLog> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(AccessLog::new(file, format))
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    pub fn log(&self, entry: &AccessEntry<'_>) {
+        let mut line = entry.format(self.format);
+        line.push('\n');
+
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // 访问日志写不出去不应影响请求的处理。
+        let _ = out.write_all(line.as_bytes()).and_then(|()| out.flush());
+    }
+}
+
+impl fmt::Debug for AccessLog {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AccessLog")
+            .field("format", &self.format)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::time::UNIX_EPOCH;
+
+    fn entry() -> AccessEntry<'static> {
+        AccessEntry {
+            peer: Some("127.0.0.1:50000".parse().unwrap()),
+            time: UNIX_EPOCH + Duration::from_secs(971_185_736),
+            request_line: "GET /apache_pb.gif HTTP/1.0",
+            status: 200,
+            bytes: 2326,
+            duration: Duration::from_micros(1500),
+            referer: Some("http://www.example.com/start.html"),
+            user_agent: None,
+        }
+    }
+
+    #[test]
+    fn formats_common_and_combined() {
+        assert_eq!(
+            entry().format(LogFormat::Common),
+            "127.0.0.1 - - [10/Oct/2000:13:48:56 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 1500"
+        );
+        assert_eq!(
+            entry().format(LogFormat::Combined),
+            "127.0.0.1 - - [10/Oct/2000:13:48:56 +0000] \"GET /apache_pb.gif HTTP/1.0\" 200 2326 \
+             \"http://www.example.com/start.html\" \"-\" 1500"
+        );
+    }
+
+    #[test]
+    fn escapes_client_supplied_text() {
+        let entry = AccessEntry {
+            bytes: 0,
+            user_agent: Some("evil\" \n200"),
+            ..entry()
+        };
+        let line = entry.format(LogFormat::Combined);
+
+        assert!(line.contains("\" 200 - \""));
+        assert!(line.contains("\"evil\\\" \\x0a200\""));
+        assert!(!line.contains('\n'));
+    }
+}
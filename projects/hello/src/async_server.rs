@@ -0,0 +1,1129 @@
+use std::{
+    future,
+    io::{self, BufReader, BufWriter, Read, Write},
+    mem,
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc::{sync_channel, Receiver, SyncSender, TrySendError},
+        Arc,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::TcpListener,
+    sync::{mpsc, oneshot},
+};
+use trpl::Either;
+
+use crate::{
+    chunked::MAX_CHUNK_LINE,
+    connection::{
+        error_response, is_timeout, ConnectionOptions, Exchange, LINGER_LIMIT, LINGER_TIMEOUT,
+        SHUTDOWN_POLL_INTERVAL,
+    },
+    log::Logger,
+    request::{Limits, ParseError, Request, Version},
+    response::Response,
+    router::Handler,
+    server::{Listener, Security},
+    status::StatusCode,
+    upgrade::Upgraded,
+    ExecuteError, ShutdownReport, ThreadPool,
+};
+
+#[cfg(feature = "tls")]
+use {crate::tls::Tls, tokio::net::TcpStream};
+
+/// 接受循环检查关闭信号的间隔。
+const ACCEPT_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// 每次从套接字读取的最大字节数。
+const READ_CHUNK: usize = 16 * 1024;
+
+/// 处理器写出的响应在发送前最多缓冲多少段。
+const RESPONSE_CHUNKS: usize = 8;
+
+/// 队列已满时，重新尝试提交处理器作业的间隔。
+const RETRY_INTERVAL: Duration = Duration::from_millis(5);
+
+/// 所有连接任务共享的状态。
+pub(crate) struct Context {
+    pub(crate) pool: ThreadPool,
+    pub(crate) handler: Arc<dyn Handler>,
+    pub(crate) options: ConnectionOptions,
+    pub(crate) queue_timeout: Duration,
+    pub(crate) logger: Arc<dyn Logger>,
+}
+
+/// 在 trpl 的运行时上接受并处理连接，直到请求关闭；见 [`crate::Server::run_async`]。
+pub(crate) fn run(
+    listeners: Vec<Listener>,
+    context: Context,
+    drain_timeout: Duration,
+) -> io::Result<ShutdownReport> {
+    for listener in &listeners {
+        listener.socket.set_nonblocking(true)?;
+    }
+
+    let context = Arc::new(context);
+    let deadline = trpl::block_on(serve(listeners, Arc::clone(&context), drain_timeout))?;
+
+    // 运行时已经随 block_on 一起关闭，连接任务都已被丢弃。
+    let context = Arc::into_inner(context).expect("运行时关闭后不再有连接任务");
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    Ok(context.pool.shutdown(remaining))
+}
+
+/// 接受连接直到请求关闭，再在 `drain_timeout` 内等待进行中的连接结束。
+///
+/// 返回整个关闭过程的截止时间，线程池在剩下的时间里等待作业结束。
+async fn serve(
+    listeners: Vec<Listener>,
+    context: Arc<Context>,
+    drain_timeout: Duration,
+) -> io::Result<Instant> {
+    let active = Arc::new(AtomicUsize::new(0));
+    let mut acceptors = Vec::new();
+
+    for listener in listeners {
+        let socket = TcpListener::from_std(listener.socket)?;
+        let context = Arc::clone(&context);
+        let active = Arc::clone(&active);
+        acceptors.push(trpl::spawn_task(accept(
+            socket,
+            listener.security,
+            context,
+            active,
+        )));
+    }
+
+    let shutdown = context.options.shutdown.clone();
+    while !shutdown.is_shutdown() {
+        trpl::sleep(ACCEPT_POLL_INTERVAL).await;
+    }
+
+    // 丢弃监听套接字，不再接受新连接；进行中的连接自己会注意到关闭信号。
+    for acceptor in &acceptors {
+        acceptor.abort();
+    }
+    let deadline = Instant::now() + drain_timeout;
+    while active.load(Ordering::Acquire) > 0 && Instant::now() < deadline {
+        trpl::sleep(Duration::from_millis(10)).await;
+    }
+
+    let unfinished = active.load(Ordering::Acquire);
+    if unfinished > 0 {
+        context.logger.warn(
+            "server",
+            "关闭期限已到，放弃未结束的连接",
+            &[("connections", &unfinished)],
+        );
+    }
+    Ok(deadline)
+}
+
+/// 连接任务结束时减少活动连接数。
+struct Active(Arc<AtomicUsize>);
+
+impl Drop for Active {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+async fn accept(
+    listener: TcpListener,
+    security: Security,
+    context: Arc<Context>,
+    active: Arc<AtomicUsize>,
+) {
+    loop {
+        let (stream, peer) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                // 比如文件描述符耗尽；稍后重试，而不是让整个服务器退出。
+                context
+                    .logger
+                    .error("server", "接受连接失败", &[("error", &e)]);
+                trpl::sleep(ACCEPT_POLL_INTERVAL).await;
+                continue;
+            }
+        };
+
+        active.fetch_add(1, Ordering::AcqRel);
+        let guard = Active(Arc::clone(&active));
+        let context = Arc::clone(&context);
+        let security = security.clone();
+
+        trpl::spawn_task(async move {
+            let _guard = guard;
+            let result = match security {
+                Security::Plain => {
+                    let (reader, writer) = stream.into_split();
+                    serve_connection(reader, writer, peer, false, &context).await
+                }
+                #[cfg(feature = "tls")]
+                Security::Tls(tls) => serve_tls(&tls, stream, peer, &context).await,
+            };
+            if let Err(e) = result {
+                let peer = peer.to_string();
+                context
+                    .logger
+                    .warn("server", "连接出错", &[("peer", &peer), ("error", &e)]);
+            }
+        });
+    }
+}
+
+/// 在读取头部的期限内完成 TLS 握手，之后与明文连接一样处理。
+#[cfg(feature = "tls")]
+async fn serve_tls(
+    tls: &Tls,
+    stream: TcpStream,
+    peer: SocketAddr,
+    context: &Context,
+) -> io::Result<()> {
+    let handshake = tls.acceptor().accept(stream);
+    let stream = match context.options.header_timeout {
+        None => handshake.await?,
+        Some(timeout) => match trpl::race(handshake, trpl::sleep(timeout)).await {
+            Either::Left(result) => result?,
+            Either::Right(()) => return Err(io::ErrorKind::TimedOut.into()),
+        },
+    };
+
+    let (reader, writer) = tokio::io::split(stream);
+    serve_connection(reader, writer, peer, true, context).await
+}
+
+/// [`crate::serve_connection`] 的异步版本：读写都在任务中进行，只有处理器在线程池上运行。
+///
+/// 因此空闲的持久连接和慢速的客户端不占用 worker。
+async fn serve_connection(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    peer: SocketAddr,
+    secure: bool,
+    context: &Context,
+) -> io::Result<()> {
+    let options = &context.options;
+    let mut buf = Vec::new();
+    let mut served = 0;
+
+    loop {
+        if !wait_for_request(&mut reader, &mut buf, options).await? {
+            break;
+        }
+        let start = Instant::now();
+        let time = SystemTime::now();
+
+        let (mut request, body) = match read_request(&mut reader, &mut buf, context).await {
+            Ok(read) => read,
+            Err(ParseError::ConnectionClosed) => break,
+            Err(ParseError::Io(e)) if !is_timeout(&e) => return Err(e),
+            Err(e) => {
+                let response = error_response(&e).with_header("Connection", "close");
+                let status = response.status();
+                let bytes = write_response(&mut writer, response, options).await?;
+                Exchange::rejected(start, time, options).log(options, Some(peer), status, bytes);
+                linger(&mut reader, &mut writer).await;
+                return Ok(());
+            }
+        };
+
+        served += 1;
+        request.set_connection(Some(peer), secure);
+        let exchange = Exchange::new(&request, served, start, time, options);
+        let streamed = body.is_some();
+
+        let Some((exchange, status, bytes)) = respond(
+            request,
+            exchange,
+            body,
+            &mut reader,
+            &mut buf,
+            &mut writer,
+            context,
+        )
+        .await?
+        else {
+            break;
+        };
+        exchange.log(options, Some(peer), status, bytes);
+        if !exchange.keep_alive {
+            // 处理器不一定读完了请求体。
+            if streamed {
+                linger(&mut reader, &mut writer).await;
+                return Ok(());
+            }
+            break;
+        }
+    }
+
+    // 对端可能已经先关闭了连接，此时关闭失败无关紧要。
+    let _ = writer.shutdown().await;
+    Ok(())
+}
+
+/// 在线程池上运行处理器，并把它写出的响应发送给客户端；返回响应的状态码与发送的
+/// 响应体字节数，供访问日志使用。
+///
+/// 线程池繁忙时以 503 回应；处理器 panic 时响应可能只写了一半，只能关闭连接。
+/// 这两种情况都返回 `None`。处理器边读边取请求体时，这里一边把从连接读到的数据
+/// 转交给 `body`，一边发送响应。处理器升级连接时，升级回调也在线程池上运行，这里
+/// 在连接与回调之间转发数据，直到回调放下连接。
+async fn respond(
+    request: Request,
+    exchange: Exchange,
+    body: Option<SyncSender<Vec<u8>>>,
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    writer: &mut (impl AsyncWrite + Unpin),
+    context: &Context,
+) -> io::Result<Option<(Exchange, StatusCode, u64)>> {
+    let (chunks, mut received) = mpsc::channel(RESPONSE_CHUNKS);
+    let (done, finished) = oneshot::channel();
+    let handler = Arc::clone(&context.handler);
+    let options = context.options.clone();
+
+    let mut job = move || {
+        let mut exchange = exchange;
+        let mut response = handler.handle(request);
+        exchange.prepare(&mut response, &options);
+
+        let upgrade = response.take_upgrade();
+        let status = response.status();
+        let sent = response.send(
+            &mut BufWriter::new(ChannelWriter(chunks.clone())),
+            exchange.version,
+            exchange.head_only,
+        );
+
+        if let (Some(upgrade), Ok(_)) = (upgrade, &sent) {
+            let (incoming, received) = sync_channel(RESPONSE_CHUNKS);
+            if chunks.blocking_send(Chunk::Upgraded(incoming)).is_ok() {
+                upgrade.run(Upgraded::channel(received, ChannelWriter(chunks)));
+            }
+        }
+        let _ = done.send((exchange, status, sent));
+    };
+
+    // 不能阻塞运行时的线程，所以队列已满时隔一会儿再试，直到 queue_timeout。
+    let queued = Instant::now();
+    loop {
+        match context.pool.try_execute(job) {
+            Ok(()) => break,
+            Err(ExecuteError::Full(f)) if queued.elapsed() < context.queue_timeout => {
+                job = f;
+                trpl::sleep(RETRY_INTERVAL).await;
+            }
+            Err(_) => {
+                context
+                    .logger
+                    .warn("server", "线程池繁忙，以 503 回应", &[]);
+                let response = Response::plain(StatusCode::ServiceUnavailable)
+                    .with_header("Retry-After", "1")
+                    .with_header("Connection", "close");
+                write_response(writer, response, &context.options).await?;
+                return Ok(None);
+            }
+        }
+    }
+
+    if let Some(incoming) = body {
+        // 这样的连接之后就关闭，不再升级。
+        let deadline = context.options.body_timeout.map(|t| Instant::now() + t);
+        relay(
+            reader,
+            buf,
+            incoming,
+            deadline,
+            writer,
+            &mut received,
+            context,
+        )
+        .await?;
+    }
+    while let Some(chunk) = received.recv().await {
+        match chunk {
+            Chunk::Data(bytes) => write_all(writer, &bytes, context.options.write_timeout).await?,
+            Chunk::Upgraded(incoming) => {
+                relay(reader, buf, incoming, None, writer, &mut received, context).await?;
+                break;
+            }
+        }
+    }
+
+    let Ok((exchange, status, sent)) = finished.await else {
+        return Ok(None);
+    };
+    Ok(Some((exchange, status, sent?)))
+}
+
+/// 在连接与处理器线程之间转发数据：先转交 `buf` 中已经读到的数据，之后从连接读到的
+/// 数据都发往 `incoming`，同时发送处理器写出的数据，直到处理器不再写出。
+///
+/// 用于升级之后的连接，以及边读边取的请求体。对端关闭连接或者到了 `deadline` 时，
+/// 处理器读到 EOF，这里继续发送它写出的数据直到它结束。
+async fn relay(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    incoming: SyncSender<Vec<u8>>,
+    deadline: Option<Instant>,
+    writer: &mut (impl AsyncWrite + Unpin),
+    received: &mut mpsc::Receiver<Chunk>,
+    context: &Context,
+) -> io::Result<()> {
+    let inbound = async {
+        // 先转交升级之前已经读到的数据。
+        let mut bytes = mem::take(buf);
+        loop {
+            if !bytes.is_empty() {
+                // 回调读得慢时，与提交作业一样隔一会儿再试，不阻塞运行时的线程。
+                let mut pending = mem::take(&mut bytes);
+                loop {
+                    match incoming.try_send(pending) {
+                        Ok(()) => break,
+                        Err(TrySendError::Full(rest)) => {
+                            pending = rest;
+                            trpl::sleep(RETRY_INTERVAL).await;
+                        }
+                        Err(TrySendError::Disconnected(_)) => return future::pending().await,
+                    }
+                }
+            }
+            match read_more(reader, &mut bytes, deadline).await {
+                Ok(0) => break,
+                Ok(_) => {}
+                Err(e) if is_timeout(&e) => break,
+                Err(e) => return Err(e),
+            }
+        }
+        drop(incoming);
+        future::pending().await
+    };
+
+    let outbound = async {
+        while let Some(chunk) = received.recv().await {
+            if let Chunk::Data(bytes) = chunk {
+                write_all(writer, &bytes, context.options.write_timeout).await?;
+            }
+        }
+        Ok(())
+    };
+
+    match trpl::race(outbound, inbound).await {
+        Either::Left(result) | Either::Right(result) => result,
+    }
+}
+
+/// 处理器线程经由通道交给连接任务的内容。
+enum Chunk {
+    /// 要发送给客户端的数据。
+    Data(Vec<u8>),
+    /// 101 响应已经写完，之后从连接读到的数据都发往这个通道。
+    Upgraded(SyncSender<Vec<u8>>),
+}
+
+/// 处理器线程上读取请求体的一端：连接任务把从连接读到的数据经由通道转交过来，
+/// 放下发送端时读到 EOF。
+struct ChannelReader {
+    incoming: Receiver<Vec<u8>>,
+    buffered: io::Cursor<Vec<u8>>,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.position() == self.buffered.get_ref().len() as u64 {
+            match self.incoming.recv() {
+                Ok(bytes) => self.buffered = io::Cursor::new(bytes),
+                Err(_) => return Ok(0),
+            }
+        }
+        Read::read(&mut self.buffered, buf)
+    }
+}
+
+/// 把处理器线程上写出的数据经由通道交给连接任务发送。
+///
+/// 外面套一层 [`BufWriter`]，使较小的响应整个作为一段发送，与阻塞版本一样只写一次。
+///
+/// 通道是有界的，客户端读得慢时处理器线程会在这里等待，与阻塞版本一样受写超时约束。
+struct ChannelWriter(mpsc::Sender<Chunk>);
+
+impl Write for ChannelWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .blocking_send(Chunk::Data(buf.to_vec()))
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// 等待下一个请求的第一个字节，语义与阻塞版本相同。
+async fn wait_for_request(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    options: &ConnectionOptions,
+) -> io::Result<bool> {
+    let deadline = options.idle_timeout.map(|t| Instant::now() + t);
+
+    loop {
+        if !buf.is_empty() {
+            return Ok(true);
+        }
+        if options.shutdown.is_shutdown() {
+            return Ok(false);
+        }
+
+        let now = Instant::now();
+        let slice = match deadline {
+            Some(deadline) if deadline <= now => return Ok(false),
+            Some(deadline) => (deadline - now).min(SHUTDOWN_POLL_INTERVAL),
+            None => SHUTDOWN_POLL_INTERVAL,
+        };
+
+        match read_more(reader, buf, Some(now + slice)).await {
+            Ok(n) => return Ok(n > 0),
+            Err(e) if is_timeout(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// 从套接字读取更多数据追加到 `buf`，返回读到的字节数；对端关闭连接时返回 0。
+///
+/// `deadline` 已过时返回 `TimedOut` 错误。
+async fn read_more(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    deadline: Option<Instant>,
+) -> io::Result<usize> {
+    let mut chunk = [0; READ_CHUNK];
+    let read = reader.read(&mut chunk);
+
+    let result = match deadline {
+        None => read.await,
+        Some(deadline) => {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match trpl::race(read, trpl::sleep(timeout)).await {
+                Either::Left(result) => result,
+                Either::Right(()) => return Err(io::ErrorKind::TimedOut.into()),
+            }
+        }
+    };
+    let n = match result {
+        // TLS 客户端常常不发送 close_notify 就关闭连接，HTTP 自己标明了消息的边界，
+        // 当作正常关闭即可。
+        Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+        result => result?,
+    };
+
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+/// 在各自的期限内读取请求头部与请求体，并从 `buf` 中移除已解析的部分。
+///
+/// 头部、长度已知的请求体与分块的请求体都先确认已经完整到达再交给解析器，不会每读
+/// 一段就把整个缓冲区重新解析一遍。请求体由处理器边读边取时只读取头部，并返回向
+/// 请求体转交数据的通道，见 [`relay`]。
+async fn read_request(
+    reader: &mut (impl AsyncRead + Unpin),
+    buf: &mut Vec<u8>,
+    context: &Context,
+) -> Result<(Request, Option<SyncSender<Vec<u8>>>), ParseError> {
+    let deadline = |timeout: Option<Duration>| timeout.map(|t| Instant::now() + t);
+    let options = &context.options;
+    let limits = &options.limits;
+
+    // 头部读全（或者超出上限）之后才解析，免得每读一段就把整个缓冲区重新解析一遍。
+    let header_deadline = deadline(options.header_timeout);
+    let mut scan = HeadScan::default();
+    let (head, head_len) = loop {
+        let too_large = limits.max_header_size.is_some_and(|max| buf.len() > max);
+        if scan.is_complete(buf) || too_large {
+            let mut rest = &buf[..];
+            let head = Request::parse_head(&mut rest, limits)?;
+            break (head, buf.len() - rest.len());
+        }
+        if read_more(reader, buf, header_deadline).await? == 0 {
+            // 对端关闭了连接：解析已有的部分，区分空闲关闭与不完整的请求。
+            let mut rest = &buf[..];
+            return Err(match Request::parse_head(&mut rest, limits) {
+                Ok(_) => ParseError::UnexpectedEof,
+                Err(e) => e,
+            });
+        }
+    };
+
+    if head.has_body() && context.handler.streams_body(&head) {
+        let mut head = head;
+        let (incoming, received) = sync_channel(RESPONSE_CHUNKS);
+        let body = ChannelReader {
+            incoming: received,
+            buffered: io::Cursor::new(Vec::new()),
+        };
+        head.stream_body(BufReader::new(body), limits)?;
+        buf.drain(..head_len);
+        return Ok((head, Some(incoming)));
+    }
+
+    // 长度已知的请求体先读够再解析，避免每读一段就重新解析一遍。
+    let body_deadline = deadline(options.body_timeout);
+    if !head.headers().contains("Transfer-Encoding") {
+        let length = head
+            .header("Content-Length")
+            .and_then(|v| v.parse::<u64>().ok());
+        let fits = |len: &u64| limits.max_body_size.is_none_or(|max| *len <= max);
+
+        if let Some(length) = length.filter(fits) {
+            let wanted = head_len.saturating_add(usize::try_from(length).unwrap_or(usize::MAX));
+            while buf.len() < wanted {
+                if read_more(reader, buf, body_deadline).await? == 0 {
+                    return Err(ParseError::UnexpectedEof);
+                }
+            }
+        }
+    }
+
+    // 长度已知或没有请求体时，这里就能解析完整的请求；不支持的传输编码等错误也在这里
+    // 报告。
+    match parse_buffered(buf, limits) {
+        Err(ParseError::UnexpectedEof) => {}
+        result => return result.map(|request| (request, None)),
+    }
+
+    // 分块的请求体：跟踪分块的边界，读到最后一块之后再解析一次。
+    let mut scan = ChunkScan::new(head_len);
+    while !scan.is_complete(buf, limits)? {
+        if read_more(reader, buf, body_deadline).await? == 0 {
+            return Err(ParseError::UnexpectedEof);
+        }
+    }
+    parse_buffered(buf, limits).map(|request| (request, None))
+}
+
+/// 解析缓冲区开头的请求，成功时从 `buf` 中移除已解析的部分。
+fn parse_buffered(buf: &mut Vec<u8>, limits: &Limits) -> Result<Request, ParseError> {
+    let mut rest = &buf[..];
+    let request = Request::parse_with_limits(&mut rest, limits)?;
+    let consumed = buf.len() - rest.len();
+    buf.drain(..consumed);
+    Ok(request)
+}
+
+/// 在不断增长的缓冲区中寻找请求头部的结尾，记住扫描到的位置，每个字节只看一次。
+#[derive(Default)]
+struct HeadScan {
+    /// 当前这一行的起点。
+    line_start: usize,
+    /// 已经扫描过的字节数。
+    scanned: usize,
+    /// 是否已经遇到请求行；请求行之前的空行不算头部的结尾。
+    started: bool,
+}
+
+impl HeadScan {
+    fn is_complete(&mut self, buf: &[u8]) -> bool {
+        while let Some(i) = buf[self.scanned..].iter().position(|&b| b == b'\n') {
+            let end = self.scanned + i;
+            let line = &buf[self.line_start..end];
+            self.line_start = end + 1;
+            self.scanned = end + 1;
+            if !line.is_empty() && line != b"\r" {
+                self.started = true;
+            } else if self.started {
+                return true;
+            }
+        }
+        self.scanned = buf.len();
+        false
+    }
+}
+
+/// 在不断增长的缓冲区中跟踪分块请求体的边界，记住扫描到的位置，每个字节只看一次。
+///
+/// 只检查分块的格式与各项上限，不解码；超出上限的请求体不必等它全部到达就能拒绝。
+struct ChunkScan {
+    /// 下一个要看的字节。
+    pos: usize,
+    state: ChunkState,
+    /// 已经声明的请求体字节数。
+    body: u64,
+    /// 块大小行与块之后的 CRLF 合计的字节数。
+    framing: u64,
+    /// 尾部字段合计的字节数。
+    trailer: usize,
+}
+
+enum ChunkState {
+    /// 等待块大小行。
+    Size,
+    /// 块数据中还没有到达的字节数。
+    Data(u64),
+    /// 块数据之后的 CRLF。
+    DataEnd,
+    /// 尾部字段，直到空行为止。
+    Trailer,
+}
+
+impl ChunkScan {
+    /// `start` 是请求体在缓冲区中的起点。
+    fn new(start: usize) -> ChunkScan {
+        ChunkScan {
+            pos: start,
+            state: ChunkState::Size,
+            body: 0,
+            framing: 0,
+            trailer: 0,
+        }
+    }
+
+    /// 请求体完整时返回 `true`。
+    ///
+    /// 块大小行超过 [`MAX_CHUNK_LINE`]、请求体或块的框架超过请求体上限、尾部字段超过
+    /// 头部上限时返回相应的错误。
+    fn is_complete(&mut self, buf: &[u8], limits: &Limits) -> Result<bool, ParseError> {
+        let malformed = |msg: String| ParseError::MalformedChunk(msg);
+
+        loop {
+            if let ChunkState::Data(remaining) = self.state {
+                let available = (buf.len() - self.pos) as u64;
+                let n = remaining.min(available);
+                self.pos += n as usize;
+                if n < remaining {
+                    self.state = ChunkState::Data(remaining - n);
+                    return Ok(false);
+                }
+                self.state = ChunkState::DataEnd;
+                continue;
+            }
+
+            let Some(end) = buf[self.pos..].iter().position(|&b| b == b'\n') else {
+                let partial = buf.len() - self.pos;
+                if matches!(self.state, ChunkState::Trailer) {
+                    self.check_trailer(self.trailer + partial, limits)?;
+                } else if partial > MAX_CHUNK_LINE {
+                    return Err(malformed(format!("块头部超过 {MAX_CHUNK_LINE} 字节")));
+                }
+                return Ok(false);
+            };
+            let raw = &buf[self.pos..self.pos + end + 1];
+            let line = raw.strip_suffix(b"\n").unwrap_or(raw);
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            self.pos += raw.len();
+
+            match self.state {
+                ChunkState::Trailer => {
+                    self.trailer += raw.len();
+                    self.check_trailer(self.trailer, limits)?;
+                    if line.is_empty() {
+                        return Ok(true);
+                    }
+                    continue;
+                }
+                _ if raw.len() > MAX_CHUNK_LINE => {
+                    return Err(malformed(format!("块头部超过 {MAX_CHUNK_LINE} 字节")));
+                }
+                ChunkState::DataEnd if !line.is_empty() => {
+                    return Err(malformed("块数据之后缺少 CRLF".to_string()));
+                }
+                ChunkState::DataEnd => self.state = ChunkState::Size,
+                _ => {
+                    let size = chunk_size(line).ok_or_else(|| {
+                        malformed(format!("块大小无效：{:?}", String::from_utf8_lossy(line)))
+                    })?;
+
+                    self.body = self.body.saturating_add(size);
+                    if let Some(limit) = limits.max_body_size.filter(|&max| self.body > max) {
+                        return Err(ParseError::BodyTooLarge { limit });
+                    }
+                    self.state = match size {
+                        0 => ChunkState::Trailer,
+                        _ => ChunkState::Data(size),
+                    };
+                }
+            }
+
+            // 很小的块也是合法的，但块的框架不能远远多于请求体的上限。
+            self.framing += raw.len() as u64;
+            if let Some(limit) = limits.max_body_size
+                && self.framing > limit.max(64 * 1024)
+            {
+                return Err(ParseError::BodyTooLarge { limit });
+            }
+        }
+    }
+
+    /// 尾部字段共 `len` 个字节，超出头部上限时返回错误。
+    fn check_trailer(&self, len: usize, limits: &Limits) -> Result<(), ParseError> {
+        match limits.max_header_size {
+            Some(limit) if len > limit => Err(ParseError::HeaderTooLarge { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// 块大小行中的块大小，忽略块扩展（`;name=value`）。
+fn chunk_size(line: &[u8]) -> Option<u64> {
+    let size = line.split(|&b| b == b';').next()?;
+    let size = std::str::from_utf8(size).ok()?.trim();
+    if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u64::from_str_radix(size, 16).ok()
+}
+
+/// 写出一段数据；`timeout` 限制的是整段数据的写出时间。
+async fn write_all(
+    writer: &mut (impl AsyncWrite + Unpin),
+    bytes: &[u8],
+    timeout: Option<Duration>,
+) -> io::Result<()> {
+    let write = writer.write_all(bytes);
+    match timeout {
+        None => write.await,
+        Some(timeout) => match trpl::race(write, trpl::sleep(timeout)).await {
+            Either::Left(result) => result,
+            Either::Right(()) => Err(io::ErrorKind::TimedOut.into()),
+        },
+    }
+}
+
+/// 在连接任务中直接写出一个不经过处理器的响应，比如 408 或 503。
+///
+/// 返回写出的响应体字节数，与 [`Response::send`] 相同。
+async fn write_response(
+    writer: &mut (impl AsyncWrite + Unpin),
+    mut response: Response,
+    options: &ConnectionOptions,
+) -> io::Result<u64> {
+    response.stamp();
+    let mut bytes = Vec::new();
+    let sent = response.send(&mut bytes, Version::Http11, false)?;
+    write_all(writer, &bytes, options.write_timeout).await?;
+    Ok(sent)
+}
+
+/// 在拒绝请求、或者处理器没有读完请求体时，关闭连接之前读掉对端已经发出的数据；
+/// 见阻塞版本中的同名函数。
+async fn linger(reader: &mut (impl AsyncRead + Unpin), writer: &mut (impl AsyncWrite + Unpin)) {
+    let _ = writer.shutdown().await;
+
+    let deadline = Instant::now() + LINGER_TIMEOUT;
+    let mut drained = Vec::new();
+    let mut total = 0;
+    while total < LINGER_LIMIT {
+        match read_more(reader, &mut drained, Some(deadline)).await {
+            Ok(n) if n > 0 => {
+                total += n as u64;
+                drained.clear();
+            }
+            _ => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Read,
+        net::{Shutdown, TcpStream},
+        thread,
+    };
+
+    use crate::{Request, Response, Server, ShutdownHandle};
+
+    use super::*;
+
+    fn start(
+        pool: ThreadPool,
+        options: ConnectionOptions,
+    ) -> (
+        SocketAddr,
+        ShutdownHandle,
+        thread::JoinHandle<ShutdownReport>,
+    ) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let handler = |request: Request| {
+            let ms = request.query("ms").unwrap_or("0").parse().unwrap();
+            thread::sleep(Duration::from_millis(ms));
+            Response::new(StatusCode::Ok).with_body(request.path().to_string())
+        };
+        let server = Server::new(listener, pool, handler)
+            .options(options)
+            .drain_timeout(Duration::from_secs(5));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+
+        (
+            addr,
+            handle,
+            thread::spawn(move || server.run_async().unwrap()),
+        )
+    }
+
+    fn exchange(addr: SocketAddr, raw: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(raw).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn serves_pipelined_and_chunked_requests() {
+        let (addr, handle, server) = start(ThreadPool::new(2), ConnectionOptions::default());
+
+        let out = exchange(
+            addr,
+            b"GET /one HTTP/1.1\r\n\r\n\
+              POST /two HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n\
+              HEAD /three HTTP/1.1\r\n\r\n\
+              GET /four HTTP/1.0\r\n\r\n",
+        );
+
+        assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 4);
+        assert!(out.find("/one").unwrap() < out.find("/two").unwrap());
+        assert!(!out.contains("/three"));
+        assert!(out.ends_with("Connection: close\r\nContent-Length: 5\r\n\r\n/four"));
+
+        handle.shutdown();
+        assert!(server.join().unwrap().is_clean());
+    }
+
+    #[test]
+    fn finds_the_end_of_the_head_across_reads() {
+        let mut scan = HeadScan::default();
+        let mut buf = Vec::new();
+        for (part, complete) in [
+            (&b"\r\n\r\nGET / HT"[..], false),
+            (b"TP/1.1\r\nHost: a\r", false),
+            (b"\n", false),
+            (b"\r", false),
+            (b"\nbody", true),
+        ] {
+            buf.extend_from_slice(part);
+            assert_eq!(scan.is_complete(&buf), complete, "{part:?}");
+        }
+
+        let mut scan = HeadScan::default();
+        assert!(scan.is_complete(b"GET / HTTP/1.0\n\n"));
+    }
+
+    #[test]
+    fn tracks_chunked_bodies_across_reads() {
+        let limits = Limits::default();
+        let mut scan = ChunkScan::new(4);
+        let mut buf = b"HEAD".to_vec();
+        for (part, complete) in [
+            (&b"4\r\nWi"[..], false),
+            (b"ki\r", false),
+            (b"\n5;name=value\r\npedia\r\n0\r\nExpires: ", false),
+            (b"never\r\n", false),
+            (b"\r\nNEXT", true),
+        ] {
+            buf.extend_from_slice(part);
+            assert_eq!(
+                scan.is_complete(&buf, &limits).unwrap(),
+                complete,
+                "{part:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_oversized_chunked_bodies_before_they_arrive() {
+        let limits = Limits {
+            max_header_size: Some(16),
+            max_body_size: Some(8),
+        };
+        let scan = |raw: &[u8]| ChunkScan::new(0).is_complete(raw, &limits);
+
+        assert!(matches!(
+            scan(b"5\r\nhello\r\n4\r\n"),
+            Err(ParseError::BodyTooLarge { limit: 8 })
+        ));
+        assert!(matches!(
+            scan(b"0\r\nX: 0123456789abcdef"),
+            Err(ParseError::HeaderTooLarge { limit: 16 })
+        ));
+        assert!(matches!(
+            scan(b"1\r\nx\r\nzz\r\n"),
+            Err(ParseError::MalformedChunk(_))
+        ));
+        let long_line = format!("1;{}", "x".repeat(MAX_CHUNK_LINE));
+        assert!(matches!(
+            scan(long_line.as_bytes()),
+            Err(ParseError::MalformedChunk(_))
+        ));
+
+        // 每块只有一个字节时，块的框架很快就会超过上限。
+        let limits = Limits {
+            max_body_size: Some(1 << 20),
+            ..limits
+        };
+        let tiny = format!("1;{}\r\nx\r\n", "x".repeat(4000)).repeat(300);
+        assert!(matches!(
+            ChunkScan::new(0).is_complete(tiny.as_bytes(), &limits),
+            Err(ParseError::BodyTooLarge { .. })
+        ));
+    }
+
+    #[test]
+    fn idle_connections_do_not_hold_workers() {
+        let (addr, handle, server) = start(ThreadPool::new(1), ConnectionOptions::default());
+
+        // 阻塞版本中，每个空闲的持久连接都会占住唯一的 worker 直到空闲超时。
+        let idle: Vec<TcpStream> = (0..8).map(|_| TcpStream::connect(addr).unwrap()).collect();
+
+        let start = Instant::now();
+        let out = exchange(addr, b"GET /busy HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(out.ends_with("/busy"));
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        drop(idle);
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn enforces_timeouts_and_limits() {
+        let path = std::env::temp_dir().join(format!("hello-async-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = crate::AccessLog::file(&path, crate::LogFormat::Common).unwrap();
+        let mut options = ConnectionOptions {
+            header_timeout: Some(Duration::from_millis(200)),
+            access_log: Some(Arc::new(log)),
+            ..ConnectionOptions::default()
+        };
+        options.limits.max_body_size = Some(16);
+        let (addr, handle, server) = start(ThreadPool::new(1), options);
+
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(b"GET / HTTP/1.1\r\nHost:").unwrap();
+        let mut out = String::new();
+        slow.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        let out = exchange(addr, b"POST / HTTP/1.1\r\nContent-Length: 17\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+
+        let out = exchange(addr, b"GET /\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        handle.shutdown();
+        server.join().unwrap();
+
+        // 被拒绝的请求也写进访问日志，请求行记为 `-`。
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let statuses: Vec<&str> = contents
+            .lines()
+            .filter_map(|line| line.split_once("] \"-\" ")?.1.split(' ').next())
+            .collect();
+        assert_eq!(statuses, ["408", "413", "400"]);
+    }
+
+    #[test]
+    fn streams_request_bodies_to_handlers_that_ask() {
+        /// 边读边取请求体的处理器：每读到一段就经由通道报告，最后回显整个请求体。
+        struct Upload(std::sync::mpsc::Sender<String>);
+
+        impl Handler for Upload {
+            fn handle(&self, mut request: Request) -> Response {
+                // 没有请求体的请求照常处理。
+                let Some(mut body) = request.take_body_stream() else {
+                    return Response::new(StatusCode::Ok).with_body(request.body().to_vec());
+                };
+                let mut received = Vec::new();
+                let mut buf = [0; 1024];
+                loop {
+                    match body.read(&mut buf) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            received.extend_from_slice(&buf[..n]);
+                            let _ = self.0.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+                        }
+                        Err(e) => {
+                            let e = e.downcast::<ParseError>().unwrap_or_else(ParseError::from);
+                            return error_response(&e);
+                        }
+                    }
+                }
+                Response::new(StatusCode::Ok).with_body(received)
+            }
+
+            fn streams_body(&self, _: &Request) -> bool {
+                true
+            }
+        }
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let (reads, read) = std::sync::mpsc::channel();
+        let mut options = ConnectionOptions::default();
+        options.limits.max_body_size = Some(10);
+        let server = Server::new(listener, ThreadPool::new(1), Upload(reads)).options(options);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run_async().unwrap());
+
+        // 处理器在请求体到齐之前就读到了第一段，之后连接关闭。
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        assert_eq!(read.recv_timeout(Duration::from_secs(5)).unwrap(), "hello");
+        stream.write_all(b"world").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+        assert!(out.contains("Connection: close\r\n"));
+        assert!(out.ends_with("\r\n\r\nhelloworld"));
+
+        // 分块编码的请求体读到超出上限时出错。
+        let out = exchange(
+            addr,
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n6\r\nworld!\r\n0\r\n\r\n",
+        );
+        assert!(
+            out.starts_with("HTTP/1.1 413 Content Too Large\r\n"),
+            "{out}"
+        );
+        let out = exchange(addr, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn drains_in_flight_requests_on_shutdown() {
+        let (addr, handle, server) = start(ThreadPool::new(1), ConnectionOptions::default());
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /slow?ms=300 HTTP/1.1\r\n\r\n")
+            .unwrap();
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.contains("Connection: close"));
+        assert!(out.ends_with("/slow"));
+        let _ = stream.shutdown(Shutdown::Both);
+
+        assert!(server.join().unwrap().is_clean());
+        assert!(TcpStream::connect(addr).is_err());
+    }
+}
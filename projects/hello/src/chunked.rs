@@ -0,0 +1,272 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read, Write},
+};
+
+/// 块大小行（包括块扩展与行尾）的最大长度。
+pub const MAX_CHUNK_LINE: usize = 4096;
+
+/// 解码 `Transfer-Encoding: chunked` 消息体的读取器。
+///
+/// 读到最后的零长度块后，会把尾部字段（trailer）一并读掉并返回 EOF，
+/// 底层读取器停在下一条消息的开头。
+///
+/// 块大小行不能超过 [`MAX_CHUNK_LINE`] 字节，尾部字段合计不能超过
+/// [`ChunkedReader::max_trailer_size`]，以免超长的行被整个读进内存。
+pub struct ChunkedReader<R> {
+    inner: R,
+    /// 当前块中尚未读取的字节数。
+    remaining: u64,
+    done: bool,
+    max_trailer_size: Option<usize>,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    pub fn new(inner: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            inner,
+            remaining: 0,
+            done: false,
+            max_trailer_size: Some(64 * 1024),
+        }
+    }
+
+    /// 设置尾部字段合计的最大字节数，默认 64 KiB，`None` 表示不限制。
+    ///
+    /// 超出时读取失败，错误中携带 [`TrailerTooLarge`]。
+    pub fn max_trailer_size(mut self, limit: Option<usize>) -> ChunkedReader<R> {
+        self.max_trailer_size = limit;
+        self
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// 读取一行，去掉行尾。一行（包括行尾）最多读 `budget` 个字节，读到的字节数从
+    /// `budget` 中扣除；读完 `budget` 还没有遇到行尾时返回 `None`。
+    fn read_line(&mut self, budget: &mut usize) -> io::Result<Option<String>> {
+        let mut line = Vec::new();
+        let n = (&mut self.inner)
+            .take(*budget as u64)
+            .read_until(b'\n', &mut line)?;
+        *budget -= n;
+
+        if !line.ends_with(b"\n") {
+            if *budget == 0 {
+                return Ok(None);
+            }
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        line.pop();
+        if line.ends_with(b"\r") {
+            line.pop();
+        }
+
+        String::from_utf8(line)
+            .map(Some)
+            .map_err(|_| invalid("块头部不是有效的 UTF-8"))
+    }
+
+    fn read_chunk_line(&mut self) -> io::Result<String> {
+        let mut budget = MAX_CHUNK_LINE;
+        self.read_line(&mut budget)?
+            .ok_or_else(|| invalid(format!("块头部超过 {MAX_CHUNK_LINE} 字节")))
+    }
+
+    /// 读取下一个块的大小行，忽略块扩展（`;name=value`）。
+    fn next_chunk(&mut self) -> io::Result<()> {
+        let line = self.read_chunk_line()?;
+        let size = line.split(';').next().unwrap_or_default().trim();
+
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid(format!("块大小无效：{line:?}")));
+        }
+        self.remaining =
+            u64::from_str_radix(size, 16).map_err(|_| invalid(format!("块太大：{line:?}")))?;
+
+        if self.remaining == 0 {
+            self.skip_trailer()?;
+            self.done = true;
+        }
+
+        Ok(())
+    }
+
+    /// 丢弃尾部字段，直到空行为止。
+    fn skip_trailer(&mut self) -> io::Result<()> {
+        let limit = self.max_trailer_size.unwrap_or(usize::MAX);
+        let mut budget = limit;
+        loop {
+            match self.read_line(&mut budget)? {
+                Some(line) if line.is_empty() => return Ok(()),
+                Some(_) => {}
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        TrailerTooLarge { limit },
+                    ));
+                }
+            }
+        }
+    }
+
+    fn end_chunk(&mut self) -> io::Result<()> {
+        if !self.read_chunk_line()?.is_empty() {
+            return Err(invalid("块数据之后缺少 CRLF"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        while self.remaining == 0 {
+            if self.done {
+                return Ok(0);
+            }
+            self.next_chunk()?;
+        }
+
+        let max = buf.len().min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let n = self.inner.read(&mut buf[..max])?;
+
+        if n == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= n as u64;
+
+        if self.remaining == 0 {
+            self.end_chunk()?;
+        }
+
+        Ok(n)
+    }
+}
+
+/// 以 `Transfer-Encoding: chunked` 格式写出消息体的写入器。
+///
+/// 每次 `write` 产生一个块；必须调用 [`ChunkedWriter::finish`] 写出结束块。
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// 写出零长度的结束块，返回底层写入器。
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // 零长度的块表示消息结束，因此空写入什么也不做。
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// 尾部字段超过了 [`ChunkedReader::max_trailer_size`]。
+#[derive(Debug)]
+pub struct TrailerTooLarge {
+    pub limit: usize,
+}
+
+impl fmt::Display for TrailerTooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "尾部字段超过 {} 字节的上限", self.limit)
+    }
+}
+
+impl Error for TrailerTooLarge {}
+
+fn invalid(msg: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_chunks_extensions_and_trailers() {
+        let mut raw: &[u8] =
+            b"4\r\nWiki\r\n5;name=value\r\npedia\r\nE\r\n in\r\n\r\nchunks.\r\n0\r\nExpires: never\r\n\r\nNEXT";
+
+        let mut body = String::new();
+        let mut reader = ChunkedReader::new(&mut raw);
+        reader.read_to_string(&mut body).unwrap();
+
+        assert_eq!(body, "Wikipedia in\r\n\r\nchunks.");
+        assert_eq!(raw, b"NEXT");
+    }
+
+    #[test]
+    fn rejects_malformed_chunks() {
+        for raw in [&b"zz\r\nabc\r\n0\r\n\r\n"[..], b"3\r\nabcd\r\n0\r\n\r\n", b"5\r\nab"] {
+            let mut body = Vec::new();
+            assert!(ChunkedReader::new(raw).read_to_end(&mut body).is_err());
+        }
+    }
+
+    #[test]
+    fn limits_chunk_lines_and_trailers() {
+        let long_extension = format!("3;{}\r\nabc\r\n0\r\n\r\n", "x".repeat(MAX_CHUNK_LINE));
+        let mut body = Vec::new();
+        let error = ChunkedReader::new(long_extension.as_bytes())
+            .read_to_end(&mut body)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+
+        let trailer = b"3\r\nabc\r\n0\r\nA: 1\r\nB: 2\r\n\r\n";
+        let mut body = Vec::new();
+        ChunkedReader::new(&trailer[..])
+            .max_trailer_size(Some(14))
+            .read_to_end(&mut body)
+            .unwrap();
+        assert_eq!(body, b"abc");
+
+        let error = ChunkedReader::new(&trailer[..])
+            .max_trailer_size(Some(13))
+            .read_to_end(&mut body)
+            .unwrap_err();
+        let too_large = error.get_ref().unwrap().downcast_ref::<TrailerTooLarge>();
+        assert_eq!(too_large.unwrap().limit, 13);
+    }
+
+    #[test]
+    fn writer_round_trips() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"hello, ").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b"chunked world").unwrap();
+        let encoded = writer.finish().unwrap();
+
+        assert_eq!(encoded, b"7\r\nhello, \r\nD\r\nchunked world\r\n0\r\n\r\n");
+
+        let mut decoded = String::new();
+        ChunkedReader::new(&encoded[..]).read_to_string(&mut decoded).unwrap();
+        assert_eq!(decoded, "hello, chunked world");
+    }
+}
@@ -0,0 +1,601 @@
+//! 一个小的阻塞 HTTP/1.1 客户端，主要用来在测试中向服务器发送请求。
+//!
+//! ```no_run
+//! use hello::{Client, StatusCode};
+//!
+//! let client = Client::new("127.0.0.1:7878".parse().unwrap());
+//! let response = client.get("/").unwrap();
+//! assert_eq!(response.status(), StatusCode::Ok);
+//! println!("{}", response.text());
+//! ```
+
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpStream},
+    time::Duration,
+};
+
+use crate::{
+    chunked::{ChunkedReader, ChunkedWriter},
+    headers::Headers,
+    request::{Method, ParseError, Request, Version},
+    response::SERVER,
+    status::StatusCode,
+};
+
+/// 状态行与头部合计的上限。
+const MAX_HEAD_SIZE: u64 = 64 * 1024;
+
+/// 向一个服务器发送请求的客户端。
+///
+/// 每个请求使用一个新连接，请求中没有 `Connection` 头部时加上 `Connection: close`；
+/// 需要在持久连接上发送多个请求时使用 [`Client::connect`]。
+#[derive(Clone, Debug)]
+pub struct Client {
+    addr: SocketAddr,
+    timeout: Option<Duration>,
+}
+
+/// 到服务器的一个连接，可以依次发送多个请求。
+#[derive(Debug)]
+pub struct Connection {
+    reader: BufReader<TcpStream>,
+    host: String,
+    /// 服务器表示要关闭连接，或者响应体读到了 EOF。
+    closed: bool,
+}
+
+/// 一个已读完的响应。
+#[derive(Clone, Debug)]
+pub struct ClientResponse {
+    status: StatusCode,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+}
+
+/// 发送请求或者读取响应时的错误。
+#[derive(Debug)]
+pub enum ClientError {
+    /// 连接出错，包括超时。
+    Io(io::Error),
+    /// 请求目标不是有效的路径。
+    InvalidRequest(ParseError),
+    /// 响应不符合 HTTP/1.1。
+    InvalidResponse(String),
+    /// 读取边读边发的请求体时出错，见 [`Request::take_body_stream`]。
+    Body(io::Error),
+    /// 服务器已经关闭了连接，不能再发送请求。
+    Closed,
+}
+
+impl fmt::Display for ClientError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ClientError::Io(e) => write!(f, "读写连接时出错：{e}"),
+            ClientError::InvalidRequest(e) => write!(f, "请求无效：{e}"),
+            ClientError::InvalidResponse(reason) => write!(f, "响应无效：{reason}"),
+            ClientError::Body(e) => write!(f, "读取请求体时出错：{e}"),
+            ClientError::Closed => f.write_str("服务器已经关闭了连接"),
+        }
+    }
+}
+
+impl Error for ClientError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ClientError::Io(e) => Some(e),
+            ClientError::InvalidRequest(e) => Some(e),
+            ClientError::Body(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ClientError {
+    fn from(e: io::Error) -> ClientError {
+        ClientError::Io(e)
+    }
+}
+
+impl From<ParseError> for ClientError {
+    fn from(e: ParseError) -> ClientError {
+        ClientError::InvalidRequest(e)
+    }
+}
+
+fn invalid(reason: impl Into<String>) -> ClientError {
+    ClientError::InvalidResponse(reason.into())
+}
+
+impl Client {
+    /// 向 `addr` 发送请求的客户端，默认的读写超时为 30 秒。
+    pub fn new(addr: SocketAddr) -> Client {
+        Client {
+            addr,
+            timeout: Some(Duration::from_secs(30)),
+        }
+    }
+
+    /// 设置连接、单次读取与单次写入的超时，`None` 表示一直等待。
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// 打开一个持久连接。
+    pub fn connect(&self) -> io::Result<Connection> {
+        let stream = match self.timeout {
+            Some(timeout) => TcpStream::connect_timeout(&self.addr, timeout)?,
+            None => TcpStream::connect(self.addr)?,
+        };
+        stream.set_read_timeout(self.timeout)?;
+        stream.set_write_timeout(self.timeout)?;
+        Ok(Connection::new(stream))
+    }
+
+    /// 发送 `GET target`，比如 `client.get("/search?q=rust")`。
+    pub fn get(&self, target: &str) -> Result<ClientResponse, ClientError> {
+        self.send(Request::new(Method::Get, target)?)
+    }
+
+    /// 在一个新连接上发送 `request`。
+    pub fn send(&self, mut request: Request) -> Result<ClientResponse, ClientError> {
+        if !request.headers().contains("Connection") {
+            request.headers_mut().insert("Connection", "close");
+        }
+        self.connect()?.send(&request)
+    }
+}
+
+impl Connection {
+    /// 在一个已建立的连接上发送请求；默认的 `Host` 是对端的地址。
+    pub fn new(stream: TcpStream) -> Connection {
+        let host = stream
+            .peer_addr()
+            .map(|addr| addr.to_string())
+            .unwrap_or_default();
+        Connection {
+            reader: BufReader::new(stream),
+            host,
+            closed: false,
+        }
+    }
+
+    pub fn get_ref(&self) -> &TcpStream {
+        self.reader.get_ref()
+    }
+
+    /// 服务器是否已经表示要关闭这个连接。
+    pub fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    /// 发送 `request` 并读完响应，跳过 `100 Continue` 这样的中间响应。
+    ///
+    /// 请求中没有 `Host` 时使用对端的地址；请求体不为空时加上 `Content-Length`。
+    /// 服务器交给处理器边读边取的请求体原样边读边发：请求带有 `Content-Length` 时
+    /// 直接发送，否则按分块编码发送。
+    pub fn send(&mut self, request: &Request) -> Result<ClientResponse, ClientError> {
+        let (mut response, framing) = self.exchange(request)?;
+        if framing == Framing::UntilEof {
+            self.closed = true;
+        }
+        BodyReader::new(&mut self.reader, framing).read_to_end(&mut response.body)?;
+        Ok(response)
+    }
+
+    /// 与 [`send`](Connection::send) 相同，但只读完响应头部，响应体由返回的读取器边读边取。
+    ///
+    /// 读取器占有连接，读完之后连接也随之关闭。
+    pub fn send_streaming(
+        mut self,
+        request: &Request,
+    ) -> Result<(ClientResponse, BodyReader), ClientError> {
+        let (response, framing) = self.exchange(request)?;
+        Ok((response, BodyReader::new(self.reader, framing)))
+    }
+
+    /// 发送请求并读取最终响应的头部，返回响应体的长度信息。
+    fn exchange(&mut self, request: &Request) -> Result<(ClientResponse, Framing), ClientError> {
+        if self.closed {
+            return Err(ClientError::Closed);
+        }
+        self.write_request(request)?;
+
+        // 101 之后连接已经不再是 HTTP，其余的 1xx 之后还有最终的响应。
+        let response = loop {
+            let response = self.read_head()?;
+            match response.status.as_u16() {
+                100 | 102..=199 => continue,
+                _ => break response,
+            }
+        };
+
+        if !keeps_alive(response.version, &response.headers) {
+            self.closed = true;
+        }
+        let framing = if request.method() == Method::Head || !response.has_body() {
+            Framing::Length(0)
+        } else {
+            Framing::of(&response.headers)?
+        };
+        Ok((response, framing))
+    }
+
+    fn write_request(&mut self, request: &Request) -> Result<(), ClientError> {
+        let mut head = format!("{} {} HTTP/1.1\r\n", request.method(), request.target());
+        if !request.headers().contains("Host") {
+            head.push_str(&format!("Host: {}\r\n", self.host));
+        }
+        if !request.headers().contains("User-Agent") {
+            head.push_str(&format!("User-Agent: {SERVER}\r\n"));
+        }
+        for (name, value) in request.headers().iter() {
+            head.push_str(&format!("{name}: {value}\r\n"));
+        }
+        let body = request.body();
+        let streamed = request.body_stream().cloned();
+        let chunked = streamed.is_some() && !request.headers().contains("Content-Length");
+        if chunked && !request.headers().contains("Transfer-Encoding") {
+            head.push_str("Transfer-Encoding: chunked\r\n");
+        } else if !body.is_empty() && !request.headers().contains("Content-Length") {
+            head.push_str(&format!("Content-Length: {}\r\n", body.len()));
+        }
+        head.push_str("\r\n");
+
+        let stream = self.reader.get_mut();
+        let mut out = head.into_bytes();
+        out.extend_from_slice(body);
+        stream.write_all(&out)?;
+
+        match streamed {
+            Some(mut body) if chunked => {
+                let mut chunks = ChunkedWriter::new(&mut *stream);
+                copy_body(&mut body, &mut chunks)?;
+                chunks.finish()?;
+            }
+            Some(mut body) => copy_body(&mut body, stream)?,
+            None => {}
+        }
+        stream.flush()?;
+        Ok(())
+    }
+
+    fn read_head(&mut self) -> Result<ClientResponse, ClientError> {
+        let mut head = (&mut self.reader).take(MAX_HEAD_SIZE);
+        let mut read_line = || -> Result<String, ClientError> {
+            let mut line = Vec::new();
+            head.read_until(b'\n', &mut line)?;
+            if !line.ends_with(b"\n") {
+                return Err(match head.limit() {
+                    0 => invalid("响应头部过长"),
+                    _ => io::Error::from(io::ErrorKind::UnexpectedEof).into(),
+                });
+            }
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+            String::from_utf8(line).map_err(|_| invalid("响应头部不是有效的 UTF-8"))
+        };
+
+        let status_line = read_line()?;
+        let mut parts = status_line.splitn(3, ' ');
+        let version = match parts.next() {
+            Some("HTTP/1.1") => Version::Http11,
+            Some("HTTP/1.0") => Version::Http10,
+            _ => return Err(invalid(format!("状态行无效：{status_line:?}"))),
+        };
+        let status = parts
+            .next()
+            .filter(|code| code.len() == 3 && code.bytes().all(|b| b.is_ascii_digit()))
+            .and_then(|code| code.parse().ok())
+            .and_then(StatusCode::from_code)
+            .ok_or_else(|| invalid(format!("状态码无效：{status_line:?}")))?;
+
+        let mut headers = Headers::new();
+        loop {
+            let line = read_line()?;
+            if line.is_empty() {
+                break;
+            }
+            let (name, value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("头部无效：{line:?}")))?;
+            headers.append(name.trim(), value.trim());
+        }
+
+        Ok(ClientResponse {
+            status,
+            version,
+            headers,
+            body: Vec::new(),
+        })
+    }
+}
+
+/// 把边读边取的请求体写给服务器，区分读取请求体与写连接时的错误。
+fn copy_body(body: &mut impl Read, out: &mut impl Write) -> Result<(), ClientError> {
+    let mut buf = [0; 16 * 1024];
+    loop {
+        let n = match body.read(&mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(ClientError::Body(e)),
+        };
+        out.write_all(&buf[..n])?;
+    }
+}
+
+/// 响应体的长度信息。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Framing {
+    Length(u64),
+    Chunked,
+    /// 既没有 `Transfer-Encoding` 也没有 `Content-Length`，读到连接关闭为止。
+    UntilEof,
+}
+
+impl Framing {
+    fn of(headers: &Headers) -> Result<Framing, ClientError> {
+        if headers.has_token("Transfer-Encoding", "chunked") {
+            return Ok(Framing::Chunked);
+        }
+        match headers.get("Content-Length") {
+            Some(length) => length
+                .parse()
+                .map(Framing::Length)
+                .map_err(|_| invalid(format!("Content-Length 无效：{length:?}"))),
+            None => Ok(Framing::UntilEof),
+        }
+    }
+}
+
+/// 边读边取的响应体，见 [`Connection::send_streaming`]。
+///
+/// 读到响应体的末尾时返回 EOF；连接在此之前关闭时返回 `UnexpectedEof` 错误。
+pub struct BodyReader<R = BufReader<TcpStream>> {
+    body: Body<R>,
+}
+
+enum Body<R> {
+    Length { reader: R, remaining: u64 },
+    Chunked(ChunkedReader<R>),
+    UntilEof(R),
+}
+
+impl<R: BufRead> BodyReader<R> {
+    fn new(reader: R, framing: Framing) -> BodyReader<R> {
+        let body = match framing {
+            Framing::Length(remaining) => Body::Length { reader, remaining },
+            Framing::Chunked => Body::Chunked(ChunkedReader::new(reader)),
+            Framing::UntilEof => Body::UntilEof(reader),
+        };
+        BodyReader { body }
+    }
+
+    /// 由 `Content-Length` 得知的剩余长度；分块编码或者读到连接关闭为止的响应体为
+    /// `None`。
+    pub fn remaining(&self) -> Option<u64> {
+        match self.body {
+            Body::Length { remaining, .. } => Some(remaining),
+            Body::Chunked(_) | Body::UntilEof(_) => None,
+        }
+    }
+}
+
+impl<R: BufRead> Read for BodyReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.body {
+            Body::Length { reader, remaining } => {
+                if *remaining == 0 || buf.is_empty() {
+                    return Ok(0);
+                }
+                let len = buf
+                    .len()
+                    .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                let n = reader.read(&mut buf[..len])?;
+                if n == 0 {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                *remaining -= n as u64;
+                Ok(n)
+            }
+            Body::Chunked(reader) => reader.read(buf),
+            Body::UntilEof(reader) => reader.read(buf),
+        }
+    }
+}
+
+impl<R> fmt::Debug for BodyReader<R> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.body {
+            Body::Length { remaining, .. } => f
+                .debug_struct("Length")
+                .field("remaining", remaining)
+                .finish(),
+            Body::Chunked(_) => f.write_str("Chunked"),
+            Body::UntilEof(_) => f.write_str("UntilEof"),
+        }
+    }
+}
+
+/// 按版本与 `Connection` 头部判断这个响应之后连接是否保持打开。
+fn keeps_alive(version: Version, headers: &Headers) -> bool {
+    match version {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+impl ClientResponse {
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// 以 UTF-8 解码的响应体，无效的字节替换为 U+FFFD。
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// 1xx、204 与 304 响应没有响应体（RFC 9112 第 6.3 节）。
+    fn has_body(&self) -> bool {
+        !matches!(self.status.as_u16(), 100..=199 | 204 | 304)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{net::TcpListener, thread};
+
+    /// 在一个连接上读取请求、依次写出 `responses` 的服务器，返回它读到的内容。
+    fn serve_once(responses: &'static [&'static str]) -> (SocketAddr, thread::JoinHandle<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut received = String::new();
+            for response in responses {
+                let request = Request::parse(&mut reader).unwrap();
+                received.push_str(&format!("{} {}\n", request.method(), request.target()));
+                for (name, value) in request.headers().iter() {
+                    received.push_str(&format!("{name}: {value}\n"));
+                }
+                received.push_str(&String::from_utf8_lossy(request.body()));
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+            received
+        });
+        (addr, server)
+    }
+
+    #[test]
+    fn reads_each_kind_of_body_on_one_connection() {
+        let (addr, server) = serve_once(&[
+            "HTTP/1.1 100 Continue\r\n\r\nHTTP/1.1 200 OK\r\nContent-Length: 5\r\nX-A: 1\r\n\r\nhello",
+            "HTTP/1.1 201 Created\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n2\r\nde\r\n0\r\n\r\n",
+            "HTTP/1.1 304 Not Modified\r\nETag: \"x\"\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 3\r\n\r\n",
+            "HTTP/1.0 404 Not Found\r\n\r\nto the end",
+        ]);
+
+        let mut connection = Client::new(addr).connect().unwrap();
+        let mut post = Request::new(Method::Post, "/form?a=1").unwrap();
+        post.headers_mut().insert("Content-Type", "text/plain");
+        post.set_body(b"payload".to_vec());
+
+        let response = connection.send(&post).unwrap();
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert_eq!(response.header("x-a"), Some("1"));
+        assert_eq!(response.text(), "hello");
+
+        let response = connection
+            .send(&Request::new(Method::Get, "/chunked").unwrap())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::Created);
+        assert_eq!(response.body(), b"abcde");
+
+        let response = connection
+            .send(&Request::new(Method::Get, "/cached").unwrap())
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NotModified);
+        assert!(response.body().is_empty());
+
+        // HEAD 的响应带有 Content-Length，但没有响应体。
+        let response = connection
+            .send(&Request::new(Method::Head, "/").unwrap())
+            .unwrap();
+        assert_eq!(response.header("Content-Length"), Some("3"));
+        assert!(response.body().is_empty());
+        assert!(!connection.is_closed());
+
+        let response = connection
+            .send(&Request::new(Method::Get, "/gone").unwrap())
+            .unwrap();
+        assert_eq!(response.version(), Version::Http10);
+        assert_eq!(response.text(), "to the end");
+        assert!(connection.is_closed());
+        assert!(matches!(
+            connection.send(&Request::new(Method::Get, "/").unwrap()),
+            Err(ClientError::Closed)
+        ));
+
+        let received = server.join().unwrap();
+        let host = format!("Host: {addr}\n");
+        assert!(
+            received.starts_with(&format!("POST /form?a=1\n{host}")),
+            "{received}"
+        );
+        assert!(
+            received.contains("Content-Length: 7\npayload"),
+            "{received}"
+        );
+        assert!(
+            received.contains(&format!("GET /gone\n{host}")),
+            "{received}"
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_responses() {
+        for response in [
+            "HTTP/2 200 OK\r\n\r\n",
+            "HTTP/1.1 2000 OK\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nno colon\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: ten\r\n\r\n",
+        ] {
+            let responses: &'static [&'static str] = Box::leak(Box::new([response]));
+            let (addr, _server) = serve_once(responses);
+            let error = Client::new(addr).get("/").unwrap_err();
+            assert!(
+                matches!(error, ClientError::InvalidResponse(_)),
+                "{response:?}: {error}"
+            );
+        }
+
+        // 响应体比 Content-Length 短。
+        let (addr, _server) = serve_once(&["HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nshort"]);
+        let error = Client::new(addr).get("/").unwrap_err();
+        assert!(
+            matches!(&error, ClientError::Io(e) if e.kind() == io::ErrorKind::UnexpectedEof),
+            "{error}"
+        );
+
+        // 请求目标无效时不会连接服务器。
+        let error = Client::new("127.0.0.1:9".parse().unwrap())
+            .get("no-slash")
+            .unwrap_err();
+        assert!(matches!(error, ClientError::InvalidRequest(_)), "{error}");
+    }
+}
@@ -0,0 +1,410 @@
+use std::{
+    io::{self, Read, Write},
+    mem,
+};
+
+use crate::{
+    deflate::{self, Encoder, Format},
+    middleware::Middleware,
+    request::{Request, Version},
+    response::{Body, Response},
+    router::Handler,
+    status::StatusCode,
+};
+
+/// 按 `Accept-Encoding` 以 gzip 或 deflate 压缩响应体的中间件。
+///
+/// 只压缩可压缩类型（文本、JSON、JavaScript、XML、SVG 等）且不小于
+/// [`min_size`](Compression::min_size) 的响应体；长度未知的流式响应体总是压缩。
+/// 已经带 `Content-Encoding`、`Content-Range` 或 `Cache-Control: no-transform`
+/// 的响应原样返回。
+///
+/// HTTP/1.0 请求的文件响应体不压缩，压缩后长度未知，会迫使连接关闭。
+///
+/// 304 响应要带上与 200 响应相同的 `Vary`，但它没有响应体，无从得知原来的长度与
+/// （通常也没有的）类型，因此除了类型明确不可压缩的之外都加上 `Vary: Accept-Encoding`；
+/// 协商出压缩格式时，强 `ETag` 也像压缩后的 200 响应一样降为弱校验器。
+pub struct Compression {
+    min_size: u64,
+}
+
+impl Compression {
+    /// 不压缩小于 1 KiB 的响应体，它们省下的字节抵不过 gzip 自身的开销。
+    pub fn new() -> Compression {
+        Compression { min_size: 1024 }
+    }
+
+    pub fn min_size(mut self, bytes: u64) -> Compression {
+        self.min_size = bytes;
+        self
+    }
+
+    /// 这个响应的表示是否会随 `Accept-Encoding` 变化。
+    fn applies_to(&self, response: &Response) -> bool {
+        let headers = response.headers();
+
+        !response.status().forbids_body()
+            && response.status() != StatusCode::PartialContent
+            && !headers.contains("Content-Encoding")
+            && !headers.contains("Content-Range")
+            && !headers.has_token("Cache-Control", "no-transform")
+            && headers.get("Content-Type").is_some_and(is_compressible)
+            && response
+                .body()
+                .content_length()
+                .is_none_or(|len| len >= self.min_size)
+    }
+
+    /// 304 响应对应的 200 响应是否可能随 `Accept-Encoding` 变化。
+    fn may_vary(response: &Response) -> bool {
+        let headers = response.headers();
+
+        response.status() == StatusCode::NotModified
+            && !headers.contains("Content-Encoding")
+            && !headers.has_token("Cache-Control", "no-transform")
+            && headers.get("Content-Type").is_none_or(is_compressible)
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Compression {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let format = negotiate(request.header("Accept-Encoding").unwrap_or_default());
+        let version = request.version();
+        let if_none_match = request.header("If-None-Match").map(str::to_string);
+        let mut response = next.handle(request);
+
+        let applies = self.applies_to(&response);
+        if !applies && !Compression::may_vary(&response) {
+            return response;
+        }
+        if !response.headers().has_token("Vary", "Accept-Encoding") {
+            response.headers_mut().append("Vary", "Accept-Encoding");
+        }
+        let Some(format) = format else {
+            return response;
+        };
+
+        // 304 要带上与压缩后的 200 响应相同的弱校验器。客户端手里是强校验器时，它拿到的
+        // 是没有压缩的响应（比如小于 min_size），校验器原样保留。
+        if !applies {
+            let headers = response.headers_mut();
+            let strong = headers.get("ETag").filter(|tag| tag.starts_with('"'));
+            if let Some(etag) = strong
+                && !if_none_match.is_some_and(|tags| tags.split(',').any(|t| t.trim() == etag))
+            {
+                let weak = format!("W/{etag}");
+                headers.insert("ETag", weak);
+            }
+            return response;
+        }
+
+        // HTTP/1.0 客户端只能通过关闭连接得知流式响应体的结束，长度已知的响应体不值得为
+        // 压缩放弃持久连接。
+        if version == Version::Http10 && matches!(response.body(), Body::Reader { .. }) {
+            return response;
+        }
+
+        let body = mem::replace(response.body_mut(), Body::Bytes(Vec::new()));
+        *response.body_mut() = match body {
+            Body::Bytes(bytes) => Body::Bytes(deflate::compress(&bytes, format)),
+            Body::Reader { reader, len } => {
+                Body::Stream(Box::new(EncodingReader::new(reader.take(len), format)))
+            }
+            Body::Stream(reader) => Body::Stream(Box::new(EncodingReader::new(reader, format))),
+        };
+
+        let headers = response.headers_mut();
+        headers.insert(
+            "Content-Encoding",
+            match format {
+                Format::Gzip => "gzip",
+                Format::Zlib => "deflate",
+            },
+        );
+        headers.remove("Content-Length");
+        // 压缩后的表示不再支持按字节范围请求。
+        headers.remove("Accept-Ranges");
+        // 压缩后的字节与原来不同，强校验器降为弱校验器。
+        if let Some(etag) = headers.get("ETag").filter(|tag| tag.starts_with('"')) {
+            let weak = format!("W/{etag}");
+            headers.insert("ETag", weak);
+        }
+        response
+    }
+}
+
+/// 判断 `Content-Type` 所指的类型是否值得压缩；图片、音视频、字体与压缩包大多已经
+/// 压缩过了。
+pub fn is_compressible(content_type: &str) -> bool {
+    let essence = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    essence.starts_with("text/")
+        || essence.ends_with("+json")
+        || essence.ends_with("+xml")
+        || matches!(
+            essence.as_str(),
+            "application/json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "image/svg+xml"
+                | "image/x-icon"
+        )
+}
+
+/// 按 `Accept-Encoding` 选择压缩格式；客户端不接受压缩，或者更想要原样的响应体时
+/// 返回 `None`。q 值相同时优先 gzip。
+pub fn negotiate(accept_encoding: &str) -> Option<Format> {
+    let mut gzip = None;
+    let mut deflate = None;
+    let mut identity = None;
+    let mut any = None;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let coding = parts.next().unwrap_or_default().trim();
+        let q = parts.find_map(|p| p.trim().strip_prefix("q=").or(p.trim().strip_prefix("Q=")));
+        // 无法解析的 q 值当作 0，格式错误的项不会意外地被选中。
+        let q = match q {
+            Some(q) => parse_qvalue(q.trim()).unwrap_or(0.0),
+            None => 1.0,
+        };
+
+        let slot = match coding.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => &mut gzip,
+            "deflate" => &mut deflate,
+            "identity" => &mut identity,
+            "*" => &mut any,
+            _ => continue,
+        };
+        *slot = Some(q);
+    }
+
+    let gzip = gzip.or(any).unwrap_or(0.0);
+    let deflate = deflate.or(any).unwrap_or(0.0);
+    // 没有提到 identity 时，它可以接受，但排在任何明确列出的编码之后。
+    let identity = identity.unwrap_or(f32::MIN_POSITIVE);
+
+    let (format, q) = if deflate > gzip {
+        (Format::Zlib, deflate)
+    } else {
+        (Format::Gzip, gzip)
+    };
+    (q > 0.0 && q >= identity).then_some(format)
+}
+
+/// 按 RFC 9110 第 12.4.2 节解析 q 值：`0` 或 `1`，之后最多三位小数，不超过 1。
+fn parse_qvalue(s: &str) -> Option<f32> {
+    let (int, frac) = s.split_once('.').unwrap_or((s, ""));
+    if !matches!(int, "0" | "1") || frac.len() > 3 || !frac.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let q = s.parse::<f32>().ok()?;
+    (q <= 1.0).then_some(q)
+}
+
+/// 边读边压缩的响应体。
+///
+/// 每从内层读到一批数据就压缩并刷新一次，流式响应体的每一块都能及时送达客户端。
+struct EncodingReader<R> {
+    inner: R,
+    encoder: Option<Encoder<Vec<u8>>>,
+    input: Box<[u8]>,
+    output: Vec<u8>,
+    pos: usize,
+}
+
+impl<R: Read> EncodingReader<R> {
+    fn new(inner: R, format: Format) -> EncodingReader<R> {
+        EncodingReader {
+            inner,
+            encoder: Some(Encoder::new(Vec::new(), format)),
+            input: vec![0; 64 * 1024].into_boxed_slice(),
+            output: Vec::new(),
+            pos: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for EncodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.output.len() {
+            let Some(encoder) = &mut self.encoder else {
+                return Ok(0);
+            };
+
+            let n = match self.inner.read(&mut self.input) {
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            };
+            self.output = if n == 0 {
+                self.encoder.take().unwrap().finish()?
+            } else {
+                encoder.write_all(&self.input[..n])?;
+                encoder.flush()?;
+                mem::take(encoder.get_mut())
+            };
+            self.pos = 0;
+        }
+
+        let n = buf.len().min(self.output.len() - self.pos);
+        buf[..n].copy_from_slice(&self.output[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    use crate::{middleware::Stack, request::Method};
+
+    fn page() -> String {
+        "<p>Hello, compressed world!</p>\n".repeat(200)
+    }
+
+    fn app() -> Stack {
+        Stack::new(|request: Request| match request.path() {
+            "/page" => Response::html(StatusCode::Ok, page()).with_header("ETag", "\"v1\""),
+            "/small" => Response::html(StatusCode::Ok, "<p>hi</p>"),
+            "/image" => Response::new(StatusCode::Ok)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096]),
+            "/file" => Response::html(StatusCode::Ok, "")
+                .with_reader(io::Cursor::new(page().into_bytes()), page().len() as u64),
+            "/cached" => Response::new(StatusCode::NotModified).with_header("ETag", "\"v1\""),
+            "/cached-image" => Response::new(StatusCode::NotModified)
+                .with_header("Content-Type", "image/png"),
+            "/stream" => Response::html(StatusCode::Ok, "")
+                .with_chunks(vec![b"<p>one</p>".to_vec(), b"<p>two</p>".to_vec()]),
+            _ => Response::plain(StatusCode::NotFound),
+        })
+        .wrap(Compression::new())
+    }
+
+    fn get(path: &str, accept_encoding: Option<&str>) -> Response {
+        let mut request = Request::new(Method::Get, path).unwrap();
+        if let Some(accept) = accept_encoding {
+            request.headers_mut().insert("Accept-Encoding", accept);
+        }
+        app().handle(request)
+    }
+
+    /// 按 HTTP/1.0 写出响应，取出响应体（流式响应体不会被分块编码）。
+    fn body_of(response: Response) -> Vec<u8> {
+        let mut out = Vec::new();
+        response.send(&mut out, Version::Http10, false).unwrap();
+        let start = out.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        out.split_off(start)
+    }
+
+    #[test]
+    fn negotiates_encodings() {
+        assert_eq!(negotiate("gzip, deflate, br"), Some(Format::Gzip));
+        assert_eq!(negotiate("deflate"), Some(Format::Zlib));
+        assert_eq!(negotiate("gzip;q=0.5, deflate;q=0.8"), Some(Format::Zlib));
+        assert_eq!(negotiate("*"), Some(Format::Gzip));
+        assert_eq!(negotiate("*;q=0.5, gzip;q=0"), Some(Format::Zlib));
+        assert_eq!(negotiate("gzip;q=0"), None);
+        assert_eq!(negotiate("gzip;q=0.5, identity"), None);
+        assert_eq!(negotiate("br"), None);
+        assert_eq!(negotiate("gzip;q=high, deflate;q=0.1"), Some(Format::Zlib));
+        assert_eq!(negotiate("gzip;q=+1, deflate;q=2"), None);
+        assert_eq!(negotiate(""), None);
+    }
+
+    #[test]
+    fn compresses_eligible_bodies() {
+        let response = get("/page", Some("gzip, deflate"));
+        let headers = response.headers();
+        assert_eq!(headers.get("Content-Encoding"), Some("gzip"));
+        assert!(headers.has_token("Vary", "Accept-Encoding"));
+        assert_eq!(headers.get("ETag"), Some("W/\"v1\""));
+
+        let compressed = body_of(response);
+        assert!(compressed.len() < page().len() / 10);
+        let mut plain = String::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, page());
+
+        let response = get("/file", Some("deflate"));
+        assert_eq!(response.headers().get("Content-Encoding"), Some("deflate"));
+        assert_eq!(response.body().content_length(), None);
+        let mut plain = String::new();
+        ZlibDecoder::new(&body_of(response)[..])
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, page());
+
+        let response = get("/stream", Some("gzip"));
+        let mut plain = String::new();
+        GzDecoder::new(&body_of(response)[..])
+            .read_to_string(&mut plain)
+            .unwrap();
+        assert_eq!(plain, "<p>one</p><p>two</p>");
+    }
+
+    #[test]
+    fn leaves_other_responses_alone() {
+        let response = get("/page", None);
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert!(response.headers().has_token("Vary", "Accept-Encoding"));
+        assert_eq!(response.headers().get("ETag"), Some("\"v1\""));
+
+        for path in ["/small", "/image"] {
+            let response = get(path, Some("gzip"));
+            assert!(!response.headers().contains("Content-Encoding"), "{path}");
+            assert!(!response.headers().contains("Vary"), "{path}");
+        }
+    }
+
+    #[test]
+    fn keeps_http10_keep_alive_for_file_bodies() {
+        let raw = b"GET /file HTTP/1.0\r\nAccept-Encoding: gzip\r\n\r\n";
+        let request = Request::parse(&mut &raw[..]).unwrap();
+        let response = app().handle(request);
+
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert!(response.headers().has_token("Vary", "Accept-Encoding"));
+        assert_eq!(response.body().content_length(), Some(page().len() as u64));
+    }
+
+    #[test]
+    fn not_modified_keeps_the_vary_and_validator_of_the_full_response() {
+        let response = get("/cached", Some("gzip"));
+        assert_eq!(response.status(), StatusCode::NotModified);
+        assert!(response.headers().has_token("Vary", "Accept-Encoding"));
+        assert!(!response.headers().contains("Content-Encoding"));
+        assert_eq!(response.headers().get("ETag"), Some("W/\"v1\""));
+
+        assert_eq!(get("/cached", None).headers().get("ETag"), Some("\"v1\""));
+        // 客户端拿着强校验器，说明它缓存的是没有压缩的响应。
+        let mut request = Request::new(Method::Get, "/cached").unwrap();
+        request.headers_mut().insert("Accept-Encoding", "gzip");
+        request
+            .headers_mut()
+            .insert("If-None-Match", "\"v0\", \"v1\"");
+        let response = app().handle(request);
+        assert_eq!(response.headers().get("ETag"), Some("\"v1\""));
+
+        let response = get("/cached-image", Some("gzip"));
+        assert!(!response.headers().contains("Vary"));
+    }
+}
@@ -0,0 +1,1641 @@
+use std::{
+    error::Error,
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use crate::{access_log::LogFormat, log::Level};
+
+/// 访问日志的去向。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AccessLogTarget {
+    Off,
+    Stderr,
+    /// 以追加方式写入的文件。
+    File(PathBuf),
+}
+
+/// 服务器的运行方式，见 [`crate::Server::run`] 与 [`crate::Server::run_async`]。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ServerMode {
+    /// 每个连接占用线程池中的一个 worker。
+    #[default]
+    Threaded,
+    /// 每个连接是一个异步任务，线程池只执行处理器。
+    Async,
+}
+
+/// 服务器的全部设置。
+///
+/// 设置依次来自默认值、`--config` 指定的文件与其余命令行参数，后者覆盖前者。
+/// 文件使用 TOML 的一个子集：
+///
+/// ```toml
+/// [server]
+/// listen = ["127.0.0.1:7878", "[::1]:7878"]
+/// root = "public"
+/// max_request_size = "1MiB"
+///
+/// [pool]
+/// threads = 4
+///
+/// [timeouts]
+/// idle = "5s"
+///
+/// [tls]
+/// listen = ["127.0.0.1:7443"]
+/// cert = "cert.pem"
+/// key = "key.pem"
+///
+/// [host.blog]
+/// names = ["blog.example.com", "*.blog.example.com"]
+/// root = "sites/blog"
+///
+/// [proxy.api]
+/// prefix = "/api"
+/// upstreams = ["127.0.0.1:9001", "127.0.0.1:9002"]
+/// strip_prefix = true
+/// ```
+///
+/// 文件中的相对路径相对于文件所在的目录，命令行中的相对路径相对于当前目录。
+/// 虚拟主机（`[host.<站点>]`）与反向代理（`[proxy.<名字>]`）只能在文件中设置。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Config {
+    /// 接受 HTTP 连接的地址。与 `tls_listen` 合计至少一个。
+    pub listen: Vec<SocketAddr>,
+    /// 接受 HTTPS 连接的地址，需要以 `tls` 特性编译。
+    pub tls_listen: Vec<SocketAddr>,
+    /// PEM 格式的证书链，第一个是服务器自己的证书。
+    pub tls_cert: Option<PathBuf>,
+    /// PEM 格式的私钥。
+    pub tls_key: Option<PathBuf>,
+    /// 以线程池还是异步任务处理连接。
+    pub mode: ServerMode,
+    /// 线程池平时保留的 worker 数。
+    pub threads: usize,
+    /// 繁忙时线程池最多扩容到的 worker 数。
+    pub max_threads: usize,
+    /// 等待 worker 的连接最多排多少个。
+    pub queue_capacity: usize,
+    /// 静态文件的根目录。
+    pub root: PathBuf,
+    /// 文件不存在时作为 404 响应体的页面，相对于 `root`。
+    pub not_found_page: Option<String>,
+    /// 静态文件缓存的容量，每个站点各有一个；0 表示不缓存。
+    pub file_cache_size: u64,
+    /// 请求行与头部合计的最大字节数。
+    pub max_header_size: usize,
+    /// 请求体的最大字节数。
+    pub max_request_size: u64,
+    /// 持久连接两个请求之间允许的最长空闲时间。
+    pub idle_timeout: Duration,
+    /// 读完请求头部的期限。
+    pub header_timeout: Duration,
+    /// 读完请求体的期限。
+    pub body_timeout: Duration,
+    /// 单次写出响应最长阻塞多久。
+    pub write_timeout: Duration,
+    /// 关闭时等待进行中的请求完成的最长时间。
+    pub drain_timeout: Duration,
+    /// 队列已满时新连接最多等待多久，超时后以 503 拒绝。
+    pub queue_timeout: Duration,
+    pub log_level: Level,
+    pub access_log: AccessLogTarget,
+    pub access_log_format: LogFormat,
+    /// 按 `Host` 头部区分的其它站点；不属于任何站点的请求由 `root` 处的默认站点处理。
+    pub hosts: Vec<HostConfig>,
+    /// 转发给上游服务器的路径前缀，对全部站点生效。
+    pub proxies: Vec<ProxyConfig>,
+}
+
+/// 一个虚拟主机，见 [`crate::VirtualHosts`]。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct HostConfig {
+    /// 配置文件中 `[host.<站点>]` 的站点名，只用于错误信息。
+    pub label: String,
+    /// 这个站点的主机名，可以是 `*.example.com` 这样的通配符。
+    pub names: Vec<String>,
+    /// 这个站点的静态文件根目录。
+    pub root: PathBuf,
+    /// 文件不存在时作为 404 响应体的页面，相对于 `root`。
+    pub not_found_page: Option<String>,
+}
+
+/// 一个反向代理，见 [`crate::Proxy`]。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ProxyConfig {
+    /// 配置文件中 `[proxy.<名字>]` 的名字，只用于错误信息。
+    pub label: String,
+    /// 转发的路径前缀，以 `/` 开头。
+    pub prefix: String,
+    /// 轮流转发的上游服务器。
+    pub upstreams: Vec<SocketAddr>,
+    /// 转发时是否去掉路径前缀。
+    pub strip_prefix: bool,
+    /// 是否把客户端的 `Host` 原样发给上游。
+    pub preserve_host: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            listen: vec![SocketAddr::from(([127, 0, 0, 1], 7878))],
+            tls_listen: Vec::new(),
+            tls_cert: None,
+            tls_key: None,
+            mode: ServerMode::Threaded,
+            threads: 4,
+            max_threads: 16,
+            queue_capacity: 64,
+            root: PathBuf::from("public"),
+            not_found_page: Some(String::from("404.html")),
+            file_cache_size: 32 * 1024 * 1024,
+            max_header_size: 64 * 1024,
+            max_request_size: 1024 * 1024,
+            idle_timeout: Duration::from_secs(5),
+            header_timeout: Duration::from_secs(10),
+            body_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            drain_timeout: Duration::from_secs(10),
+            queue_timeout: Duration::from_millis(500),
+            log_level: Level::Info,
+            access_log: AccessLogTarget::Stderr,
+            access_log_format: LogFormat::Combined,
+            hosts: Vec::new(),
+            proxies: Vec::new(),
+        }
+    }
+}
+
+/// 加载或校验配置时的错误。
+#[derive(Debug)]
+pub enum ConfigError {
+    /// 无法读取配置文件。
+    Io { path: PathBuf, error: io::Error },
+    /// 配置文件的语法错误或无效取值，`line` 从 1 开始。
+    File {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+    /// 命令行参数错误。
+    Flag { flag: String, message: String },
+    /// 各项单独看都有效，合在一起却不成立，或者引用的路径不存在。
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { path, error } => {
+                write!(f, "无法读取配置文件 {}：{error}", path.display())
+            }
+            ConfigError::File {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{line}：{message}", path.display()),
+            ConfigError::Flag { flag, message } => write!(f, "参数 {flag}：{message}"),
+            ConfigError::Invalid(message) => write!(f, "配置无效：{message}"),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { error, .. } => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl Config {
+    /// 读取并校验 `path` 处的配置文件，未出现的设置取默认值。
+    pub fn load(path: impl AsRef<Path>) -> Result<Config, ConfigError> {
+        let mut config = Config::default();
+        config.merge_file(path.as_ref())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 解析命令行参数（不含程序名）并校验结果。
+    ///
+    /// `--config <文件>` 先被读取，其余参数不论出现在它之前还是之后都覆盖文件中的
+    /// 设置。参数值可以写作 `--threads 8`，也可以写作 `--threads=8`；`--listen`
+    /// 与 `--tls-listen` 可以重复，出现时取代文件中的全部地址。
+    pub fn from_args<I>(args: I) -> Result<Config, ConfigError>
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        let flags = parse_flags(args)?;
+        let mut config = Config::default();
+
+        if let Some((_, path)) = flags.iter().rev().find(|(flag, _)| flag == "--config") {
+            config.merge_file(Path::new(path))?;
+        }
+
+        // 可以重复的参数先收集起来，最后作为一个数组设置。
+        let mut repeated: Vec<(&Setting, Vec<Value>)> = Vec::new();
+        for (flag, text) in &flags {
+            if flag == "--config" {
+                continue;
+            }
+            let setting = SETTINGS.iter().find(|s| s.flag == flag).unwrap();
+            let value = Value::from_flag(text);
+
+            if REPEATABLE.contains(&setting.key) {
+                match repeated.iter_mut().find(|(s, _)| s.key == setting.key) {
+                    Some((_, values)) => values.push(value),
+                    None => repeated.push((setting, vec![value])),
+                }
+                continue;
+            }
+            (setting.apply)(&mut config, value, Path::new("")).map_err(|message| {
+                ConfigError::Flag {
+                    flag: flag.clone(),
+                    message,
+                }
+            })?;
+        }
+        for (setting, values) in repeated {
+            (setting.apply)(&mut config, Value::Array(values), Path::new("")).map_err(
+                |message| ConfigError::Flag {
+                    flag: setting.flag.to_string(),
+                    message,
+                },
+            )?;
+        }
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 检查各项设置之间的关系，以及根目录等路径是否存在。
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let invalid = |message: String| Err(ConfigError::Invalid(message));
+
+        let addrs: Vec<_> = self.listen.iter().chain(&self.tls_listen).collect();
+        if addrs.is_empty() {
+            return invalid(String::from("至少需要一个监听地址"));
+        }
+        for (i, addr) in addrs.iter().enumerate() {
+            if addrs[..i].contains(addr) {
+                return invalid(format!("监听地址 {addr} 重复"));
+            }
+        }
+        if !self.tls_listen.is_empty() {
+            if !cfg!(feature = "tls") {
+                return invalid(String::from(
+                    "监听 HTTPS 需要以 tls 特性编译：cargo build --features tls",
+                ));
+            }
+            let (Some(cert), Some(key)) = (&self.tls_cert, &self.tls_key) else {
+                return invalid(String::from("监听 HTTPS 需要同时设置 tls.cert 与 tls.key"));
+            };
+            for (name, path) in [("证书", cert), ("私钥", key)] {
+                if !path.is_file() {
+                    return invalid(format!("{name} {} 不存在", path.display()));
+                }
+            }
+        }
+        if self.max_threads < self.threads {
+            return invalid(format!(
+                "max_threads（{}）不能小于 threads（{}）",
+                self.max_threads, self.threads
+            ));
+        }
+        let timeouts = [
+            ("空闲", self.idle_timeout),
+            ("读取头部", self.header_timeout),
+            ("读取请求体", self.body_timeout),
+            ("写出响应", self.write_timeout),
+        ];
+        for (name, timeout) in timeouts {
+            if timeout.is_zero() {
+                return invalid(format!("{name}超时必须大于 0"));
+            }
+        }
+        if !self.root.is_dir() {
+            return invalid(format!(
+                "文档根目录 {} 不存在或不是目录",
+                self.root.display()
+            ));
+        }
+        if let Some(page) = &self.not_found_page {
+            let path = self.root.join(page);
+            if !path.is_file() {
+                return invalid(format!("404 页面 {} 不存在", path.display()));
+            }
+        }
+
+        let mut names = Vec::new();
+        for host in &self.hosts {
+            let label = &host.label;
+            if host.names.is_empty() {
+                return invalid(format!("虚拟主机 {label} 需要设置 names"));
+            }
+            for name in &host.names {
+                let name = name.to_ascii_lowercase();
+                if names.contains(&name) {
+                    return invalid(format!("主机名 {name} 重复"));
+                }
+                names.push(name);
+            }
+            if !host.root.is_dir() {
+                return invalid(format!(
+                    "虚拟主机 {label} 的根目录 {} 不存在或不是目录",
+                    host.root.display()
+                ));
+            }
+            if let Some(page) = &host.not_found_page {
+                let path = host.root.join(page);
+                if !path.is_file() {
+                    return invalid(format!("404 页面 {} 不存在", path.display()));
+                }
+            }
+        }
+
+        let mut prefixes = Vec::new();
+        for proxy in &self.proxies {
+            let label = &proxy.label;
+            if !proxy.prefix.starts_with('/') {
+                return invalid(format!("反向代理 {label} 的 prefix 应以 / 开头"));
+            }
+            if proxy.upstreams.is_empty() {
+                return invalid(format!("反向代理 {label} 需要设置 upstreams"));
+            }
+            let prefix = proxy.prefix.trim_end_matches('/');
+            if prefixes.contains(&prefix) {
+                return invalid(format!("路径前缀 {} 重复", proxy.prefix));
+            }
+            prefixes.push(prefix);
+        }
+
+        Ok(())
+    }
+
+    /// 用配置文件中出现的设置覆盖当前的值。
+    fn merge_file(&mut self, path: &Path) -> Result<(), ConfigError> {
+        let source = fs::read_to_string(path).map_err(|error| ConfigError::Io {
+            path: path.to_path_buf(),
+            error,
+        })?;
+        let base = path.parent().unwrap_or(Path::new(""));
+        let error = |line, message| ConfigError::File {
+            path: path.to_path_buf(),
+            line,
+            message,
+        };
+
+        let entries = Parser::new(&source)
+            .parse()
+            .map_err(|(line, message)| error(line, message))?;
+
+        for (i, entry) in entries.iter().enumerate() {
+            if entries[..i].iter().any(|e| e.key == entry.key) {
+                return Err(error(entry.line, format!("{} 重复设置", entry.key)));
+            }
+
+            let host_setting = entry
+                .key
+                .strip_prefix("host.")
+                .and_then(|rest| rest.split_once('.'))
+                .and_then(|(label, key)| {
+                    let setting = HOST_SETTINGS.iter().find(|s| s.key == key)?;
+                    Some((label, setting))
+                });
+            if let Some((label, setting)) = host_setting {
+                let host = match self.hosts.iter().position(|host| host.label == label) {
+                    Some(i) => &mut self.hosts[i],
+                    None => {
+                        self.hosts.push(HostConfig {
+                            label: label.to_string(),
+                            ..HostConfig::default()
+                        });
+                        self.hosts.last_mut().unwrap()
+                    }
+                };
+                (setting.apply)(host, entry.value.clone(), base)
+                    .map_err(|message| error(entry.line, format!("{}：{message}", entry.key)))?;
+                continue;
+            }
+
+            let proxy_setting = entry
+                .key
+                .strip_prefix("proxy.")
+                .and_then(|rest| rest.split_once('.'))
+                .and_then(|(label, key)| {
+                    let setting = PROXY_SETTINGS.iter().find(|s| s.key == key)?;
+                    Some((label, setting))
+                });
+            if let Some((label, setting)) = proxy_setting {
+                let proxy = match self.proxies.iter().position(|proxy| proxy.label == label) {
+                    Some(i) => &mut self.proxies[i],
+                    None => {
+                        self.proxies.push(ProxyConfig {
+                            label: label.to_string(),
+                            ..ProxyConfig::default()
+                        });
+                        self.proxies.last_mut().unwrap()
+                    }
+                };
+                (setting.apply)(proxy, entry.value.clone())
+                    .map_err(|message| error(entry.line, format!("{}：{message}", entry.key)))?;
+                continue;
+            }
+
+            let setting = SETTINGS
+                .iter()
+                .find(|s| s.key == entry.key)
+                .ok_or_else(|| error(entry.line, format!("未知的设置 {}", entry.key)))?;
+
+            (setting.apply)(self, entry.value.clone(), base)
+                .map_err(|message| error(entry.line, format!("{}：{message}", entry.key)))?;
+        }
+
+        Ok(())
+    }
+}
+
+/// 命令行的用法说明。
+pub fn usage() -> String {
+    let mut rows = vec![(String::from("--config <文件>"), "从 TOML 文件读取设置")];
+    for setting in SETTINGS {
+        rows.push((
+            format!("{} <{}>", setting.flag, setting.placeholder),
+            setting.help,
+        ));
+    }
+    rows.push((String::from("-h, --help"), "显示本说明"));
+
+    // 按终端上的显示宽度对齐，汉字占两列。
+    let width = |s: &str| {
+        s.chars()
+            .map(|c| if c.is_ascii() { 1 } else { 2 })
+            .sum::<usize>()
+    };
+    let mut out = String::from("用法：hello [选项]\n\n选项：\n");
+    for (flag, help) in rows {
+        let pad = 32usize.saturating_sub(width(&flag));
+        out.push_str(&format!("  {flag}{:pad$}{help}\n", ""));
+    }
+    out
+}
+
+/// 把参数拆成 `(参数名, 值)`，并确认每个参数名都是已知的。
+fn parse_flags<I>(args: I) -> Result<Vec<(String, String)>, ConfigError>
+where
+    I: IntoIterator,
+    I::Item: Into<String>,
+{
+    let mut args = args.into_iter().map(Into::into);
+    let mut flags = Vec::new();
+
+    while let Some(arg) = args.next() {
+        let (flag, inline) = match arg.split_once('=') {
+            Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+            None => (arg, None),
+        };
+        let error = |message: &str| ConfigError::Flag {
+            flag: flag.clone(),
+            message: message.to_string(),
+        };
+
+        if !flag.starts_with("--") {
+            return Err(error("不是一个选项"));
+        }
+        if flag != "--config" && !SETTINGS.iter().any(|s| s.flag == flag) {
+            return Err(error("未知的选项"));
+        }
+
+        let value = match inline {
+            Some(value) => value,
+            None => args.next().ok_or_else(|| error("缺少取值"))?,
+        };
+        flags.push((flag, value));
+    }
+
+    Ok(flags)
+}
+
+/// 在命令行中可以重复的设置。
+const REPEATABLE: &[&str] = &["server.listen", "tls.listen"];
+
+/// 一项可以在文件和命令行中设置的配置。
+struct Setting {
+    /// 文件中的 `节.键`。
+    key: &'static str,
+    flag: &'static str,
+    placeholder: &'static str,
+    help: &'static str,
+    apply: fn(&mut Config, Value, &Path) -> Result<(), String>,
+}
+
+const SETTINGS: &[Setting] = &[
+    Setting {
+        key: "server.listen",
+        flag: "--listen",
+        placeholder: "地址",
+        help: "监听地址，比如 0.0.0.0:80 或 [::]:80；可以重复",
+        apply: |config, value, _| {
+            config.listen = parse_addrs(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.mode",
+        flag: "--mode",
+        placeholder: "方式",
+        help: "threaded：每个连接占一个线程；async：每个连接一个异步任务",
+        apply: |config, value, _| {
+            config.mode = match value.into_string()?.as_str() {
+                "threaded" => ServerMode::Threaded,
+                "async" => ServerMode::Async,
+                other => return Err(format!("未知的运行方式 {other:?}，可用 threaded 或 async")),
+            };
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.root",
+        flag: "--root",
+        placeholder: "目录",
+        help: "静态文件的根目录",
+        apply: |config, value, base| {
+            config.root = base.join(value.into_string()?);
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.not_found_page",
+        flag: "--not-found-page",
+        placeholder: "文件",
+        help: "404 页面，相对于根目录；空字符串表示不用",
+        apply: |config, value, _| {
+            let page = value.into_string()?;
+            config.not_found_page = Some(page).filter(|page| !page.is_empty());
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.file_cache_size",
+        flag: "--file-cache-size",
+        placeholder: "大小",
+        help: "静态文件缓存的容量，0 表示不缓存",
+        apply: |config, value, _| {
+            config.file_cache_size = value.into_bytes()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.max_header_size",
+        flag: "--max-header-size",
+        placeholder: "大小",
+        help: "请求行与头部合计的最大长度",
+        apply: |config, value, _| {
+            config.max_header_size =
+                usize::try_from(value.into_size()?).map_err(|_| String::from("太大"))?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "server.max_request_size",
+        flag: "--max-request-size",
+        placeholder: "大小",
+        help: "请求体的最大长度，比如 65536 或 \"1MiB\"",
+        apply: |config, value, _| {
+            config.max_request_size = value.into_size()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "pool.threads",
+        flag: "--threads",
+        placeholder: "数量",
+        help: "常驻的 worker 线程数",
+        apply: |config, value, _| {
+            config.threads = value.into_count()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "pool.max_threads",
+        flag: "--max-threads",
+        placeholder: "数量",
+        help: "繁忙时最多的 worker 线程数",
+        apply: |config, value, _| {
+            config.max_threads = value.into_count()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "pool.queue_capacity",
+        flag: "--queue-capacity",
+        placeholder: "数量",
+        help: "最多排队等待 worker 的连接数",
+        apply: |config, value, _| {
+            config.queue_capacity = value.into_count()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.idle",
+        flag: "--idle-timeout",
+        placeholder: "时长",
+        help: "持久连接的空闲超时，比如 5 或 \"500ms\"",
+        apply: |config, value, _| {
+            config.idle_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.header",
+        flag: "--header-timeout",
+        placeholder: "时长",
+        help: "读完请求头部的期限，超时以 408 回应",
+        apply: |config, value, _| {
+            config.header_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.body",
+        flag: "--body-timeout",
+        placeholder: "时长",
+        help: "读完请求体的期限，超时以 408 回应",
+        apply: |config, value, _| {
+            config.body_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.write",
+        flag: "--write-timeout",
+        placeholder: "时长",
+        help: "单次写出响应最长阻塞的时长",
+        apply: |config, value, _| {
+            config.write_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.drain",
+        flag: "--drain-timeout",
+        placeholder: "时长",
+        help: "关闭时等待进行中请求的时长",
+        apply: |config, value, _| {
+            config.drain_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "timeouts.queue",
+        flag: "--queue-timeout",
+        placeholder: "时长",
+        help: "队列已满时新连接的等待时长",
+        apply: |config, value, _| {
+            config.queue_timeout = value.into_duration()?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "log.level",
+        flag: "--log-level",
+        placeholder: "级别",
+        help: "error、warn、info、debug 或 trace",
+        apply: |config, value, _| {
+            config.log_level = value.into_string()?.parse().map_err(|e| format!("{e}"))?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "log.access_log",
+        flag: "--access-log",
+        placeholder: "去向",
+        help: "访问日志：off、stderr 或文件路径",
+        apply: |config, value, base| {
+            config.access_log = match value.into_string()?.as_str() {
+                "off" => AccessLogTarget::Off,
+                "stderr" => AccessLogTarget::Stderr,
+                "" => return Err(String::from("不能为空")),
+                path => AccessLogTarget::File(base.join(path)),
+            };
+            Ok(())
+        },
+    },
+    Setting {
+        key: "log.access_format",
+        flag: "--access-format",
+        placeholder: "格式",
+        help: "访问日志格式：common 或 combined",
+        apply: |config, value, _| {
+            config.access_log_format = value.into_string()?.parse().map_err(|e| format!("{e}"))?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "tls.listen",
+        flag: "--tls-listen",
+        placeholder: "地址",
+        help: "HTTPS 监听地址，需要 tls 特性；可以重复",
+        apply: |config, value, _| {
+            config.tls_listen = parse_addrs(value)?;
+            Ok(())
+        },
+    },
+    Setting {
+        key: "tls.cert",
+        flag: "--tls-cert",
+        placeholder: "文件",
+        help: "PEM 格式的证书链",
+        apply: |config, value, base| {
+            config.tls_cert = Some(base.join(value.into_string()?));
+            Ok(())
+        },
+    },
+    Setting {
+        key: "tls.key",
+        flag: "--tls-key",
+        placeholder: "文件",
+        help: "PEM 格式的私钥",
+        apply: |config, value, base| {
+            config.tls_key = Some(base.join(value.into_string()?));
+            Ok(())
+        },
+    },
+];
+
+/// 虚拟主机 `[host.<站点>]` 中的一项设置。
+struct HostSetting {
+    key: &'static str,
+    apply: fn(&mut HostConfig, Value, &Path) -> Result<(), String>,
+}
+
+const HOST_SETTINGS: &[HostSetting] = &[
+    HostSetting {
+        key: "names",
+        apply: |host, value, _| {
+            let values = match value {
+                Value::Array(values) => values,
+                value => vec![value],
+            };
+            host.names = values
+                .into_iter()
+                .map(|value| match value.into_string()? {
+                    name if name.is_empty() => Err(String::from("主机名不能为空")),
+                    name => Ok(name),
+                })
+                .collect::<Result<_, _>>()?;
+            Ok(())
+        },
+    },
+    HostSetting {
+        key: "root",
+        apply: |host, value, base| {
+            host.root = base.join(value.into_string()?);
+            Ok(())
+        },
+    },
+    HostSetting {
+        key: "not_found_page",
+        apply: |host, value, _| {
+            let page = value.into_string()?;
+            host.not_found_page = Some(page).filter(|page| !page.is_empty());
+            Ok(())
+        },
+    },
+];
+
+/// 反向代理 `[proxy.<名字>]` 中的一项设置。
+struct ProxySetting {
+    key: &'static str,
+    apply: fn(&mut ProxyConfig, Value) -> Result<(), String>,
+}
+
+const PROXY_SETTINGS: &[ProxySetting] = &[
+    ProxySetting {
+        key: "prefix",
+        apply: |proxy, value| {
+            proxy.prefix = value.into_string()?;
+            Ok(())
+        },
+    },
+    ProxySetting {
+        key: "upstreams",
+        apply: |proxy, value| {
+            proxy.upstreams = parse_addrs(value)?;
+            Ok(())
+        },
+    },
+    ProxySetting {
+        key: "strip_prefix",
+        apply: |proxy, value| {
+            proxy.strip_prefix = value.into_bool()?;
+            Ok(())
+        },
+    },
+    ProxySetting {
+        key: "preserve_host",
+        apply: |proxy, value| {
+            proxy.preserve_host = value.into_bool()?;
+            Ok(())
+        },
+    },
+];
+
+/// 一个或一组地址。
+fn parse_addrs(value: Value) -> Result<Vec<SocketAddr>, String> {
+    let values = match value {
+        Value::Array(values) => values,
+        value => vec![value],
+    };
+
+    values
+        .into_iter()
+        .map(|value| {
+            let text = value.into_string()?;
+            text.parse::<SocketAddr>().map_err(|_| {
+                format!("{text:?} 不是有效的地址，应形如 127.0.0.1:7878 或 [::1]:7878")
+            })
+        })
+        .collect()
+}
+
+/// 配置文件中的值。
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Value {
+    String(String),
+    Integer(i64),
+    Bool(bool),
+    Array(Vec<Value>),
+    /// 命令行中的原始文本，按设置需要的类型解释。
+    Flag(String),
+}
+
+impl Value {
+    fn from_flag(text: &str) -> Value {
+        Value::Flag(text.to_string())
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Value::String(_) => "字符串",
+            Value::Integer(_) => "整数",
+            Value::Bool(_) => "布尔值",
+            Value::Array(_) => "数组",
+            Value::Flag(_) => "参数",
+        }
+    }
+
+    fn into_string(self) -> Result<String, String> {
+        match self {
+            Value::String(s) | Value::Flag(s) => Ok(s),
+            other => Err(format!("应为字符串，实际是{}", other.kind())),
+        }
+    }
+
+    fn into_bool(self) -> Result<bool, String> {
+        match self {
+            Value::Bool(b) => Ok(b),
+            Value::Flag(text) => text
+                .parse()
+                .map_err(|_| format!("{text:?} 应为 true 或 false")),
+            other => Err(format!("应为布尔值，实际是{}", other.kind())),
+        }
+    }
+
+    /// 大于 0 的数量。
+    fn into_count(self) -> Result<usize, String> {
+        let value = match self {
+            Value::Flag(text) => match text.parse() {
+                Ok(n) => Value::Integer(n),
+                Err(_) => return Err(format!("{text:?} 不是整数")),
+            },
+            value => value,
+        };
+        match value {
+            Value::Integer(n) if n > 0 => usize::try_from(n).map_err(|_| format!("{n} 太大")),
+            Value::Integer(n) => Err(format!("必须大于 0，实际是 {n}")),
+            other => Err(format!("应为整数，实际是{}", other.kind())),
+        }
+    }
+
+    /// 整数表示秒，字符串可以带 `ms`、`s`、`m` 或 `h` 单位。
+    fn into_duration(self) -> Result<Duration, String> {
+        let (n, unit) = self.into_quantity("时长")?;
+        let scale = match unit.as_str() {
+            "" | "s" => 1000,
+            "ms" => 1,
+            "m" => 60 * 1000,
+            "h" => 60 * 60 * 1000,
+            _ => return Err(format!("未知的时间单位 {unit:?}，可用 ms、s、m、h")),
+        };
+        n.checked_mul(scale)
+            .map(Duration::from_millis)
+            .ok_or_else(|| String::from("时长太长"))
+    }
+
+    /// 大于 0 的字节数，写法见 [`Value::into_bytes`]。
+    fn into_size(self) -> Result<u64, String> {
+        match self.into_bytes()? {
+            0 => Err(String::from("必须大于 0")),
+            size => Ok(size),
+        }
+    }
+
+    /// 整数表示字节，字符串可以带 `B`、`KB`、`KiB`、`MB`、`MiB`、`GB` 或 `GiB` 单位。
+    fn into_bytes(self) -> Result<u64, String> {
+        let (n, unit) = self.into_quantity("大小")?;
+        let scale: u64 = match unit.as_str() {
+            "" | "B" => 1,
+            "KB" => 1000,
+            "KiB" => 1 << 10,
+            "MB" => 1000 * 1000,
+            "MiB" => 1 << 20,
+            "GB" => 1000 * 1000 * 1000,
+            "GiB" => 1 << 30,
+            _ => return Err(format!("未知的大小单位 {unit:?}")),
+        };
+        n.checked_mul(scale).ok_or_else(|| String::from("太大"))
+    }
+
+    /// 拆出非负的数值与单位（可能为空）。
+    fn into_quantity(self, what: &str) -> Result<(u64, String), String> {
+        let (digits, unit) = match self {
+            Value::Integer(n) => {
+                return u64::try_from(n)
+                    .map(|n| (n, String::new()))
+                    .map_err(|_| format!("{what}不能为负"))
+            }
+            Value::String(s) | Value::Flag(s) => {
+                let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+                let (digits, unit) = s.split_at(split);
+                (digits.to_string(), unit.trim().to_string())
+            }
+            other => return Err(format!("应为{what}，实际是{}", other.kind())),
+        };
+        let n = digits
+            .parse()
+            .map_err(|_| format!("{what} {:?} 应以数字开头", format!("{digits}{unit}")))?;
+        Ok((n, unit))
+    }
+}
+
+/// 文件中的一项设置。
+#[derive(Debug)]
+struct Entry {
+    /// 带节名的键，比如 `pool.threads`。
+    key: String,
+    value: Value,
+    line: usize,
+}
+
+/// 只支持本文件需要的 TOML 子集：`[节]`（可以是 `[host.blog]` 这样带点的名字）、
+/// `键 = 值`、`#` 注释，值可以是字符串、整数、布尔值或（可以跨行的）数组。
+struct Parser<'a> {
+    rest: &'a str,
+    line: usize,
+}
+
+type ParseResult<T> = Result<T, (usize, String)>;
+
+impl<'a> Parser<'a> {
+    fn new(source: &'a str) -> Parser<'a> {
+        Parser {
+            rest: source,
+            line: 1,
+        }
+    }
+
+    fn parse(mut self) -> ParseResult<Vec<Entry>> {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+
+        loop {
+            self.skip_blank(true);
+            match self.peek() {
+                None => return Ok(entries),
+                Some('[') => {
+                    self.bump();
+                    self.skip_blank(false);
+                    section = self.key()?;
+                    while self.peek() == Some('.') {
+                        self.bump();
+                        section.push('.');
+                        section.push_str(&self.key()?);
+                    }
+                    self.skip_blank(false);
+                    self.expect(']')?;
+                }
+                Some(_) => {
+                    let line = self.line;
+                    let key = self.key()?;
+                    self.skip_blank(false);
+                    self.expect('=')?;
+                    self.skip_blank(false);
+                    let value = self.value()?;
+
+                    let key = match section.as_str() {
+                        "" => key,
+                        section => format!("{section}.{key}"),
+                    };
+                    entries.push(Entry { key, value, line });
+                }
+            }
+            self.end_of_line()?;
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.rest.chars().next()
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.peek()?;
+        self.rest = &self.rest[c.len_utf8()..];
+        if c == '\n' {
+            self.line += 1;
+        }
+        Some(c)
+    }
+
+    fn error<T>(&self, message: impl Into<String>) -> ParseResult<T> {
+        Err((self.line, message.into()))
+    }
+
+    fn expect(&mut self, expected: char) -> ParseResult<()> {
+        match self.peek() {
+            Some(c) if c == expected => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("应为 {expected:?}，实际是 {c:?}")),
+            None => self.error(format!("应为 {expected:?}，文件却结束了")),
+        }
+    }
+
+    /// 跳过空白与注释；`newlines` 为真时也跳过换行。
+    fn skip_blank(&mut self, newlines: bool) {
+        while let Some(c) = self.peek() {
+            match c {
+                ' ' | '\t' | '\r' => {}
+                '\n' if newlines => {}
+                '#' => {
+                    while self.peek().is_some_and(|c| c != '\n') {
+                        self.bump();
+                    }
+                    continue;
+                }
+                _ => return,
+            }
+            self.bump();
+        }
+    }
+
+    /// 一项设置或节名之后，同一行只能有注释。
+    fn end_of_line(&mut self) -> ParseResult<()> {
+        self.skip_blank(false);
+        match self.peek() {
+            None => Ok(()),
+            Some('\n') => {
+                self.bump();
+                Ok(())
+            }
+            Some(c) => self.error(format!("多余的内容 {c:?}")),
+        }
+    }
+
+    /// 由字母、数字、`_` 与 `-` 组成的键。
+    fn key(&mut self) -> ParseResult<String> {
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_' || c == '-'))
+            .unwrap_or(self.rest.len());
+        if end == 0 {
+            return match self.peek() {
+                Some(c) => self.error(format!("应为键名，实际是 {c:?}")),
+                None => self.error("应为键名，文件却结束了"),
+            };
+        }
+
+        let key = self.rest[..end].to_string();
+        self.rest = &self.rest[end..];
+        Ok(key)
+    }
+
+    fn value(&mut self) -> ParseResult<Value> {
+        match self.peek() {
+            Some('"') => self.basic_string().map(Value::String),
+            Some('\'') => self.literal_string().map(Value::String),
+            Some('[') => self.array(),
+            Some(c) if c == '-' || c == '+' || c.is_ascii_digit() => self.integer(),
+            Some(c) if c.is_ascii_alphabetic() => {
+                let word = self.key()?;
+                match word.as_str() {
+                    "true" => Ok(Value::Bool(true)),
+                    "false" => Ok(Value::Bool(false)),
+                    _ => self.error(format!("无法识别的值 {word:?}，字符串需要加引号")),
+                }
+            }
+            Some(c) => self.error(format!("无法识别的值，以 {c:?} 开头")),
+            None => self.error("缺少值"),
+        }
+    }
+
+    fn integer(&mut self) -> ParseResult<Value> {
+        let end = self
+            .rest
+            .find(|c: char| !(c.is_ascii_alphanumeric() || "+-_".contains(c)))
+            .unwrap_or(self.rest.len());
+        let text = &self.rest[..end];
+
+        // TOML 允许用下划线分隔数字，但下划线两边都必须是数字。
+        let digits = text.trim_start_matches(['+', '-']);
+        let valid = !digits.is_empty()
+            && !digits.starts_with('_')
+            && !digits.ends_with('_')
+            && !digits.contains("__")
+            && digits.chars().all(|c| c.is_ascii_digit() || c == '_');
+        let n = text.replace('_', "").parse().ok().filter(|_| valid);
+
+        match n {
+            Some(n) => {
+                self.rest = &self.rest[end..];
+                Ok(Value::Integer(n))
+            }
+            None => self.error(format!("无效的整数 {text:?}")),
+        }
+    }
+
+    fn basic_string(&mut self) -> ParseResult<String> {
+        self.bump();
+        let mut s = String::new();
+
+        loop {
+            if self.peek() == Some('\n') {
+                return self.error("字符串没有结束");
+            }
+            match self.bump() {
+                Some('"') => return Ok(s),
+                Some('\\') => {
+                    let c = match self.bump() {
+                        Some('"') => '"',
+                        Some('\\') => '\\',
+                        Some('n') => '\n',
+                        Some('t') => '\t',
+                        Some('r') => '\r',
+                        Some(c) => return self.error(format!("不支持的转义 \\{c}")),
+                        None => return self.error("字符串没有结束"),
+                    };
+                    s.push(c);
+                }
+                None => return self.error("字符串没有结束"),
+                Some(c) => s.push(c),
+            }
+        }
+    }
+
+    fn literal_string(&mut self) -> ParseResult<String> {
+        self.bump();
+        let end = self.rest.find(['\'', '\n']);
+
+        match end {
+            Some(end) if self.rest[end..].starts_with('\'') => {
+                let s = self.rest[..end].to_string();
+                self.rest = &self.rest[end + 1..];
+                Ok(s)
+            }
+            _ => self.error("字符串没有结束"),
+        }
+    }
+
+    fn array(&mut self) -> ParseResult<Value> {
+        self.bump();
+        let mut values = Vec::new();
+
+        loop {
+            self.skip_blank(true);
+            if self.peek() == Some(']') {
+                self.bump();
+                return Ok(Value::Array(values));
+            }
+
+            values.push(self.value()?);
+            self.skip_blank(true);
+            match self.peek() {
+                Some(',') => {
+                    self.bump();
+                }
+                Some(']') => {}
+                Some(c) => return self.error(format!("数组中应为 ',' 或 ']'，实际是 {c:?}")),
+                None => return self.error("数组没有结束"),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::ops::Deref;
+
+    /// 临时目录中的配置文件；离开作用域时删除整个目录。
+    struct Fixture(PathBuf);
+
+    impl Deref for Fixture {
+        type Target = Path;
+
+        fn deref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl AsRef<Path> for Fixture {
+        fn as_ref(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            if let Some(dir) = self.0.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    /// 在临时目录中建立带有 `public/404.html` 的文档根目录与配置文件。
+    fn fixture(name: &str, toml: &str) -> Fixture {
+        let dir = std::env::temp_dir().join(format!("hello-config-{name}-{}", std::process::id()));
+        fs::create_dir_all(dir.join("public")).unwrap();
+        fs::write(dir.join("public/404.html"), "404").unwrap();
+
+        let path = dir.join("hello.toml");
+        fs::write(&path, toml).unwrap();
+        Fixture(path)
+    }
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn loads_every_setting_from_a_file() {
+        let path = fixture(
+            "full",
+            r#"
+# 示例配置
+[server]
+listen = [
+    "127.0.0.1:8080",  # IPv4
+    "[::1]:8080",
+]
+mode = "async"
+root = 'public'
+not_found_page = "404.html"
+file_cache_size = "4MiB"
+max_header_size = "8KiB"
+max_request_size = "64KiB"
+
+[pool]
+threads = 2
+max_threads = 1_000
+queue_capacity = 8
+
+[timeouts]
+idle = "250ms"
+header = "2s"
+body = 20
+write = "1m"
+drain = 3
+queue = "1m"
+
+[log]
+level = "debug"
+access_log = "access.log"
+access_format = "common"
+"#,
+        );
+        let dir = path.parent().unwrap();
+        let config = Config::load(&path).unwrap();
+
+        assert_eq!(
+            config.listen,
+            vec![
+                "127.0.0.1:8080".parse().unwrap(),
+                "[::1]:8080".parse().unwrap()
+            ]
+        );
+        assert_eq!(config.mode, ServerMode::Async);
+        assert_eq!(config.root, dir.join("public"));
+        assert_eq!(config.file_cache_size, 4 * 1024 * 1024);
+        assert_eq!(config.max_header_size, 8 * 1024);
+        assert_eq!(config.max_request_size, 64 * 1024);
+        assert_eq!(
+            (config.threads, config.max_threads, config.queue_capacity),
+            (2, 1000, 8)
+        );
+        assert_eq!(config.idle_timeout, Duration::from_millis(250));
+        assert_eq!(config.header_timeout, Duration::from_secs(2));
+        assert_eq!(config.body_timeout, Duration::from_secs(20));
+        assert_eq!(config.write_timeout, Duration::from_secs(60));
+        assert_eq!(config.drain_timeout, Duration::from_secs(3));
+        assert_eq!(config.queue_timeout, Duration::from_secs(60));
+        assert_eq!(config.log_level, Level::Debug);
+        assert_eq!(
+            config.access_log,
+            AccessLogTarget::File(dir.join("access.log"))
+        );
+        assert_eq!(config.access_log_format, LogFormat::Common);
+    }
+
+    #[test]
+    fn reports_the_offending_line() {
+        let cases = [
+            ("[pool]\nthreads = 0\n", 2, "必须大于 0"),
+            ("[pool]\n\nthreads = \"four\"\n", 3, "应为整数"),
+            ("[server]\nport = 80\n", 2, "未知的设置 server.port"),
+            ("[server]\nroot = public\n", 2, "需要加引号"),
+            ("[server]\nroot = \"public\n", 2, "字符串没有结束"),
+            ("[server]\nlisten = [\"localhost\"]\n", 2, "不是有效的地址"),
+            ("[pool]\nthreads = 2\nthreads = 3\n", 3, "重复"),
+            ("[timeouts]\nidle = \"5 days\"\n", 2, "未知的时间单位"),
+            ("[pool] threads = 2\n", 1, "多余的内容"),
+            ("[host.blog]\nport = 80\n", 2, "未知的设置 host.blog.port"),
+            ("[proxy.api]\nport = 80\n", 2, "未知的设置 proxy.api.port"),
+            ("[host.blog]\nnames = [\"\"]\n", 2, "主机名不能为空"),
+            ("[host.]\n", 1, "应为键名"),
+        ];
+
+        for (i, (toml, line, message)) in cases.into_iter().enumerate() {
+            let path = fixture(&format!("bad{i}"), toml);
+            match Config::load(&path) {
+                Err(ConfigError::File {
+                    line: actual,
+                    message: actual_message,
+                    ..
+                }) => {
+                    assert_eq!(actual, line, "{toml:?}");
+                    assert!(actual_message.contains(message), "{actual_message}");
+                }
+                other => panic!("{toml:?} 得到 {other:?}"),
+            }
+        }
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let path = fixture("flags", "[pool]\nthreads = 2\nmax_threads = 4\n");
+        let root = path.parent().unwrap().join("public");
+
+        let config = Config::from_args(args(&[
+            "--threads=3",
+            "--config",
+            path.to_str().unwrap(),
+            "--root",
+            root.to_str().unwrap(),
+            "--listen",
+            "0.0.0.0:80",
+            "--listen",
+            "[::]:80",
+            "--idle-timeout",
+            "2",
+            "--file-cache-size=0",
+        ]))
+        .unwrap();
+
+        assert_eq!((config.threads, config.max_threads), (3, 4));
+        assert_eq!(config.listen.len(), 2);
+        assert_eq!(config.idle_timeout, Duration::from_secs(2));
+        assert_eq!(config.file_cache_size, 0);
+    }
+
+    #[test]
+    fn rejects_bad_flags_and_inconsistent_settings() {
+        let path = fixture("invalid", "");
+        let root = path.parent().unwrap().join("public");
+        let root = root.to_str().unwrap();
+
+        let error = Config::from_args(args(&["--threads"])).unwrap_err();
+        assert_eq!(error.to_string(), "参数 --threads：缺少取值");
+        assert!(matches!(
+            Config::from_args(args(&["--port", "80"])),
+            Err(ConfigError::Flag { .. })
+        ));
+        assert!(matches!(
+            Config::from_args(args(&["--root", root, "--max-request-size", "0"])),
+            Err(ConfigError::Flag { .. })
+        ));
+
+        let error = Config::from_args(args(&[
+            "--root",
+            root,
+            "--threads",
+            "8",
+            "--max-threads",
+            "4",
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "配置无效：max_threads（4）不能小于 threads（8）"
+        );
+
+        let error =
+            Config::from_args(args(&["--root", root, "--header-timeout", "0ms"])).unwrap_err();
+        assert_eq!(error.to_string(), "配置无效：读取头部超时必须大于 0");
+
+        let missing = Config::from_args(args(&["--root", "/nonexistent/hello"])).unwrap_err();
+        assert!(matches!(missing, ConfigError::Invalid(_)));
+
+        let unreadable = Config::from_args(args(&["--config", "/nonexistent/hello.toml"]));
+        assert!(matches!(unreadable, Err(ConfigError::Io { .. })));
+    }
+
+    #[test]
+    fn loads_virtual_hosts() {
+        let path = fixture(
+            "hosts",
+            r#"
+[host.blog]
+names = ["blog.example.com", "*.blog.example.com"]
+root = "public"
+not_found_page = "404.html"
+
+[ host.docs ]
+names = "docs.example.com"
+root = "public"
+"#,
+        );
+        let dir = path.parent().unwrap();
+        let config = Config::load(&path).unwrap();
+
+        assert_eq!(
+            config.hosts,
+            vec![
+                HostConfig {
+                    label: String::from("blog"),
+                    names: vec![
+                        String::from("blog.example.com"),
+                        String::from("*.blog.example.com")
+                    ],
+                    root: dir.join("public"),
+                    not_found_page: Some(String::from("404.html")),
+                },
+                HostConfig {
+                    label: String::from("docs"),
+                    names: vec![String::from("docs.example.com")],
+                    root: dir.join("public"),
+                    not_found_page: None,
+                },
+            ]
+        );
+
+        let cases = [
+            ("[host.a]\nroot = \"public\"\n", "虚拟主机 a 需要设置 names"),
+            ("[host.a]\nnames = \"a\"\n", "虚拟主机 a 的根目录"),
+            (
+                "[host.a]\nnames = \"a\"\nroot = \"public\"\n\
+                 [host.b]\nnames = \"A\"\nroot = \"public\"\n",
+                "主机名 a 重复",
+            ),
+            (
+                "[host.a]\nnames = \"a\"\nroot = \"public\"\nnot_found_page = \"x.html\"\n",
+                "x.html 不存在",
+            ),
+        ];
+        for (i, (toml, message)) in cases.into_iter().enumerate() {
+            let path = fixture(&format!("hosts{i}"), toml);
+            let error = Config::load(&path).unwrap_err().to_string();
+            assert!(error.contains(message), "{toml:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn loads_reverse_proxies() {
+        let path = fixture(
+            "proxies",
+            r#"
+[proxy.api]
+prefix = "/api"
+upstreams = ["127.0.0.1:9001", "[::1]:9002"]
+strip_prefix = true
+
+[proxy.legacy]
+prefix = "/old/"
+upstreams = "127.0.0.1:9003"
+preserve_host = true
+"#,
+        );
+        let config = Config::load(&path).unwrap();
+
+        assert_eq!(
+            config.proxies,
+            vec![
+                ProxyConfig {
+                    label: String::from("api"),
+                    prefix: String::from("/api"),
+                    upstreams: vec![
+                        SocketAddr::from(([127, 0, 0, 1], 9001)),
+                        "[::1]:9002".parse().unwrap(),
+                    ],
+                    strip_prefix: true,
+                    preserve_host: false,
+                },
+                ProxyConfig {
+                    label: String::from("legacy"),
+                    prefix: String::from("/old/"),
+                    upstreams: vec![SocketAddr::from(([127, 0, 0, 1], 9003))],
+                    strip_prefix: false,
+                    preserve_host: true,
+                },
+            ]
+        );
+
+        let cases = [
+            ("[proxy.a]\nupstreams = \"127.0.0.1:1\"\n", "prefix 应以 / 开头"),
+            ("[proxy.a]\nprefix = \"/a\"\n", "反向代理 a 需要设置 upstreams"),
+            (
+                "[proxy.a]\nprefix = \"/a\"\nupstreams = \"127.0.0.1:1\"\n\
+                 [proxy.b]\nprefix = \"/a/\"\nupstreams = \"127.0.0.1:2\"\n",
+                "路径前缀 /a/ 重复",
+            ),
+            (
+                "[proxy.a]\nprefix = \"/a\"\nstrip_prefix = \"yes\"\n",
+                "应为布尔值",
+            ),
+        ];
+        for (i, (toml, message)) in cases.into_iter().enumerate() {
+            let path = fixture(&format!("proxies{i}"), toml);
+            let error = Config::load(&path).unwrap_err().to_string();
+            assert!(error.contains(message), "{toml:?}: {error}");
+        }
+    }
+
+    #[test]
+    fn checks_tls_settings() {
+        let path = fixture(
+            "tls",
+            r#"
+[server]
+listen = []
+
+[tls]
+listen = ["[::1]:7443"]
+cert = "cert.pem"
+key = "key.pem"
+"#,
+        );
+        let dir = path.parent().unwrap();
+        fs::write(dir.join("cert.pem"), "").unwrap();
+        fs::write(dir.join("key.pem"), "").unwrap();
+
+        let config = Config::load(&path);
+        if cfg!(feature = "tls") {
+            let config = config.unwrap();
+            assert!(config.listen.is_empty());
+            assert_eq!(config.tls_listen, vec!["[::1]:7443".parse().unwrap()]);
+            assert_eq!(config.tls_cert, Some(dir.join("cert.pem")));
+            assert_eq!(config.tls_key, Some(dir.join("key.pem")));
+        } else {
+            let error = config.unwrap_err().to_string();
+            assert!(error.contains("tls 特性"), "{error}");
+            return;
+        }
+
+        let path = path.to_str().unwrap();
+        let error =
+            Config::from_args(args(&["--config", path, "--tls-key", "/nonexistent/key.pem"]))
+                .unwrap_err();
+        assert_eq!(error.to_string(), "配置无效：私钥 /nonexistent/key.pem 不存在");
+
+        let error = Config::from_args(args(&[
+            "--config",
+            path,
+            "--listen",
+            "[::1]:7443",
+            "--tls-listen=127.0.0.1:7443",
+            "--tls-listen=[::1]:7443",
+        ]))
+        .unwrap_err();
+        assert_eq!(error.to_string(), "配置无效：监听地址 [::1]:7443 重复");
+
+        let root = dir.join("public");
+        let error = Config::from_args(args(&[
+            "--root",
+            root.to_str().unwrap(),
+            "--tls-listen",
+            "127.0.0.1:7443",
+        ]))
+        .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "配置无效：监听 HTTPS 需要同时设置 tls.cert 与 tls.key"
+        );
+    }
+}
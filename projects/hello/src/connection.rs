@@ -0,0 +1,875 @@
+use std::{
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    mem,
+    net::{Shutdown, SocketAddr, TcpStream},
+    sync::{Arc, Mutex, MutexGuard},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::{
+    access_log::{AccessEntry, AccessLog},
+    request::{Limits, Method, ParseError, Request, Version},
+    response::Response,
+    router::Handler,
+    server::ShutdownHandle,
+    status::StatusCode,
+    upgrade::{OnUpgrade, Upgraded},
+};
+
+/// 空闲等待下一个请求时检查关闭信号的间隔。
+pub(crate) const SHUTDOWN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// 拒绝请求后，关闭连接前最多花多久读掉对端已经发出的数据。
+pub(crate) const LINGER_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// 拒绝请求后，关闭连接前最多读掉多少字节。
+pub(crate) const LINGER_LIMIT: u64 = 1024 * 1024;
+
+/// 单个连接上的行为选项。
+#[derive(Clone, Debug)]
+pub struct ConnectionOptions {
+    /// 两个请求之间允许的最长空闲时间，超时后关闭连接。`None` 表示一直等待。
+    pub idle_timeout: Option<Duration>,
+    /// 从请求的第一个字节到头部读完的最长时间，超时后以 408 回应。
+    ///
+    /// 这是整个头部的期限而不是单次读取的超时，每隔一会儿发一个字节的客户端也会超时。
+    pub header_timeout: Option<Duration>,
+    /// 读完请求体的最长时间，超时后以 408 回应。
+    pub body_timeout: Option<Duration>,
+    /// 写响应时单次写操作最长阻塞多久；一直不读取响应的客户端会在超时后被断开。
+    pub write_timeout: Option<Duration>,
+    /// 一个连接上最多处理的请求数，达到后在最后一个响应中要求关闭连接。
+    pub max_requests: Option<usize>,
+    /// 触发后，空闲的连接立即关闭，正在处理的请求得到响应后关闭。
+    pub shutdown: ShutdownHandle,
+    /// 为每个请求写一行访问日志；无法解析的请求也会记录，请求行记为 `-`。
+    pub access_log: Option<Arc<AccessLog>>,
+    /// 对请求头部与请求体大小的限制；超出时以 413 回应并关闭连接。
+    pub limits: Limits,
+}
+
+impl Default for ConnectionOptions {
+    fn default() -> ConnectionOptions {
+        ConnectionOptions {
+            idle_timeout: Some(Duration::from_secs(5)),
+            header_timeout: Some(Duration::from_secs(10)),
+            body_timeout: Some(Duration::from_secs(30)),
+            write_timeout: Some(Duration::from_secs(30)),
+            max_requests: None,
+            shutdown: ShutdownHandle::new(),
+            access_log: None,
+            limits: Limits {
+                max_header_size: Some(64 * 1024),
+                max_body_size: Some(1024 * 1024),
+            },
+        }
+    }
+}
+
+/// [`serve_connection`] 读写的连接：明文的 [`TcpStream`]，或者启用 `tls` 特性时的
+/// `TlsStream`。
+///
+/// 读写都只需要共享引用，同一个连接才能同时交给读缓冲区与写缓冲区。
+pub trait Transport {
+    /// 底层的套接字，用来设置超时、查询对端地址。
+    fn socket(&self) -> &TcpStream;
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize>;
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize>;
+
+    fn flush(&self) -> io::Result<()>;
+
+    /// 告诉对端不会再发送数据。
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.socket().shutdown(Shutdown::Write)
+    }
+
+    /// 连接是否经过 TLS 加密，见 [`Request::is_secure`]。
+    fn is_tls(&self) -> bool {
+        false
+    }
+}
+
+impl Transport for TcpStream {
+    fn socket(&self) -> &TcpStream {
+        self
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        <&TcpStream as Read>::read(&mut &*self, buf)
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        <&TcpStream as Write>::write(&mut &*self, buf)
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        <&TcpStream as Write>::flush(&mut &*self)
+    }
+}
+
+/// 在一个持久连接上循环读取请求、交给 `handler` 处理并写回响应。
+///
+/// 请求按到达顺序逐个处理，因此流水线（pipelining）发来的多个请求会按顺序得到
+/// 响应。HTTP/1.1 默认保持连接，除非请求带有 `Connection: close`；HTTP/1.0
+/// 默认关闭连接，除非请求带有 `Connection: keep-alive`。
+///
+/// 处理器以 [`Response::with_upgrade`] 升级连接时，101 响应发出之后连接交给升级回调，
+/// 回调在当前线程上运行。处理器的 [`streams_body`](Handler::streams_body) 返回真时，
+/// 请求体留在连接上由处理器读取，这个请求之后连接关闭。
+pub fn serve_connection(
+    stream: impl Transport + Send + 'static,
+    handler: &dyn Handler,
+    options: &ConnectionOptions,
+) -> io::Result<()> {
+    serve_pooled(stream, handler, options, &|| false)
+}
+
+/// 与 [`serve_connection`] 相同，但连接空闲时若 `others_waiting` 返回真，就关闭连接，
+/// 把线程让给排队等待的其他连接。
+pub(crate) fn serve_pooled(
+    stream: impl Transport + Send + 'static,
+    handler: &dyn Handler,
+    options: &ConnectionOptions,
+    others_waiting: &dyn Fn() -> bool,
+) -> io::Result<()> {
+    let stream: Shared = Arc::new(Mutex::new(Box::new(stream)));
+    if let Some((upgrade, buffered)) = serve(&stream, handler, options, others_waiting)? {
+        // 读端与写端都已放下，只有处理器留着请求体时才拿不回连接。
+        let Ok(stream) = Arc::try_unwrap(stream) else {
+            return Err(io::Error::other("请求体仍在使用，无法升级连接"));
+        };
+        let stream = stream.into_inner().unwrap_or_else(|e| e.into_inner());
+        stream.socket().set_read_timeout(None)?;
+        upgrade.run(Upgraded::stream(buffered, stream));
+    }
+    Ok(())
+}
+
+/// 读缓冲区、写缓冲区与交给处理器的请求体共用的连接。
+///
+/// 它们在同一个线程上轮流读写，锁只是让它们都能拥有连接。
+type Shared = Arc<Mutex<Box<dyn Transport + Send>>>;
+
+fn lock(stream: &Shared) -> MutexGuard<'_, Box<dyn Transport + Send>> {
+    stream.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// 处理连接上的请求，直到连接关闭或者被升级。
+///
+/// 升级时返回升级回调，以及已经读进缓冲区、属于新协议的数据。
+fn serve(
+    stream: &Shared,
+    handler: &dyn Handler,
+    options: &ConnectionOptions,
+    others_waiting: &dyn Fn() -> bool,
+) -> io::Result<Option<(OnUpgrade, Vec<u8>)>> {
+    let mut reader = BufReader::new(DeadlineReader {
+        stream: Arc::clone(stream),
+        deadline: None,
+    });
+    // 状态行、头部与较小的响应体合并为一次写入；分开写时，Nagle 算法与对端的
+    // 延迟确认会让持久连接上的每个响应多等几十毫秒。`send` 写完会刷新缓冲区。
+    let mut writer = BufWriter::new(Writer(Arc::clone(stream)));
+    let mut served = 0;
+    let (peer, secure) = {
+        let stream = lock(stream);
+        stream.socket().set_write_timeout(options.write_timeout)?;
+        (stream.socket().peer_addr().ok(), stream.is_tls())
+    };
+
+    loop {
+        if !wait_for_request(&mut reader, options, others_waiting)? {
+            break;
+        }
+        let start = Instant::now();
+        let time = SystemTime::now();
+
+        let mut request = match read_request(&mut reader, handler, options) {
+            Ok(request) => request,
+            Err(ParseError::ConnectionClosed) => break,
+            Err(ParseError::Io(e)) if !is_timeout(&e) => return Err(e),
+            Err(e) => {
+                let mut response = error_response(&e).with_header("Connection", "close");
+                response.stamp();
+                let status = response.status();
+                let bytes = response.send(&mut writer, Version::Http11, false)?;
+                Exchange::rejected(start, time, options).log(options, peer, status, bytes);
+                linger(&mut reader);
+                break;
+            }
+        };
+
+        served += 1;
+        request.set_connection(peer, secure);
+        let streamed = request.body_stream().is_some();
+        let mut exchange = Exchange::new(&request, served, start, time, options);
+
+        let mut response = handler.handle(request);
+        exchange.prepare(&mut response, options);
+        let upgrade = response.take_upgrade();
+        let status = response.status();
+        let bytes = response.send(&mut writer, exchange.version, exchange.head_only)?;
+        exchange.log(options, peer, status, bytes);
+
+        if let Some(upgrade) = upgrade {
+            return Ok(Some((upgrade, reader.buffer().to_vec())));
+        }
+        if !exchange.keep_alive {
+            // 处理器不一定读完了请求体。
+            if streamed {
+                linger(&mut reader);
+            }
+            break;
+        }
+    }
+
+    // 对端可能已经先关闭了连接，此时关闭失败无关紧要。
+    let _ = lock(stream).shutdown_write();
+    Ok(None)
+}
+
+/// 一次请求与响应之间需要记住的信息。
+///
+/// 请求会被处理器取走，所以在交给处理器之前记下协议版本、是否保持连接与访问日志
+/// 需要的部分。
+pub(crate) struct Exchange {
+    pub(crate) version: Version,
+    pub(crate) head_only: bool,
+    pub(crate) keep_alive: bool,
+    start: Instant,
+    time: SystemTime,
+    /// 请求行、Referer 与 User-Agent；没有访问日志时为 `None`。
+    logged: Option<(String, Option<String>, Option<String>)>,
+}
+
+impl Exchange {
+    /// `served` 是连接上已经读到的请求数，包括这一个。
+    pub(crate) fn new(
+        request: &Request,
+        served: usize,
+        start: Instant,
+        time: SystemTime,
+        options: &ConnectionOptions,
+    ) -> Exchange {
+        let version = request.version();
+        let logged = options.access_log.as_ref().map(|_| {
+            let header = |name| request.header(name).map(str::to_string);
+            let line = format!("{} {} {}", request.method(), request.target(), version);
+            (line, header("Referer"), header("User-Agent"))
+        });
+
+        // 边读边取的请求体之后，连接上剩下多少数据不得而知，不能再读下一个请求。
+        Exchange {
+            version,
+            head_only: request.method() == Method::Head,
+            keep_alive: wants_keep_alive(request)
+                && request.body_stream().is_none()
+                && options.max_requests.is_none_or(|max| served < max),
+            start,
+            time,
+            logged,
+        }
+    }
+
+    /// 无法解析的请求：协议版本按 HTTP/1.1 回应，访问日志中的请求行记为 `-`。
+    pub(crate) fn rejected(
+        start: Instant,
+        time: SystemTime,
+        options: &ConnectionOptions,
+    ) -> Exchange {
+        Exchange {
+            version: Version::Http11,
+            head_only: false,
+            keep_alive: false,
+            start,
+            time,
+            logged: options
+                .access_log
+                .as_ref()
+                .map(|_| ("-".to_string(), None, None)),
+        }
+    }
+
+    /// 根据响应决定是否保持连接，并相应地设置 `Connection` 头部。
+    pub(crate) fn prepare(&mut self, response: &mut Response, options: &ConnectionOptions) {
+        response.stamp();
+
+        // 升级之后连接不再使用 HTTP，`Connection: Upgrade` 要原样保留。
+        if response.is_upgrade() {
+            self.keep_alive = false;
+            return;
+        }
+
+        if response.headers().has_token("Connection", "close") || options.shutdown.is_shutdown() {
+            self.keep_alive = false;
+        }
+
+        // HTTP/1.0 客户端只能通过关闭连接得知流式响应体的结束。
+        if self.version == Version::Http10 && response.body().content_length().is_none() {
+            self.keep_alive = false;
+        }
+
+        if !self.keep_alive {
+            response.headers_mut().insert("Connection", "close");
+        } else if self.version == Version::Http10 {
+            response.headers_mut().insert("Connection", "keep-alive");
+        }
+    }
+
+    /// 响应发送完毕后写一行访问日志。
+    pub(crate) fn log(
+        &self,
+        options: &ConnectionOptions,
+        peer: Option<SocketAddr>,
+        status: StatusCode,
+        bytes: u64,
+    ) {
+        if let (Some(log), Some((request_line, referer, user_agent))) =
+            (&options.access_log, &self.logged)
+        {
+            log.log(&AccessEntry {
+                peer,
+                time: self.time,
+                request_line,
+                status: status.as_u16(),
+                bytes,
+                duration: self.start.elapsed(),
+                referer: referer.as_deref(),
+                user_agent: user_agent.as_deref(),
+            });
+        }
+    }
+}
+
+/// 带有截止时间的读端。
+///
+/// 每次读取前都把套接字的读超时设为剩余的时间，因此截止时间限制的是一连串读取的
+/// 总时长，而不只是单次读取。
+struct DeadlineReader {
+    stream: Shared,
+    deadline: Option<Instant>,
+}
+
+impl Read for DeadlineReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timeout = match self.deadline {
+            Some(deadline) => {
+                let now = Instant::now();
+                if deadline <= now {
+                    return Err(io::ErrorKind::TimedOut.into());
+                }
+                Some(deadline - now)
+            }
+            None => None,
+        };
+
+        let stream = lock(&self.stream);
+        stream.socket().set_read_timeout(timeout)?;
+        stream.read(buf)
+    }
+}
+
+/// 写缓冲区的写端。
+struct Writer(Shared);
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        lock(&self.0).write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        lock(&self.0).flush()
+    }
+}
+
+/// 在各自的期限内读取请求头部与请求体。
+///
+/// 请求体由处理器边读边取时，整个读缓冲区连同请求体的期限一起交给请求，`reader`
+/// 换成一个新的。
+fn read_request(
+    reader: &mut BufReader<DeadlineReader>,
+    handler: &dyn Handler,
+    options: &ConnectionOptions,
+) -> Result<Request, ParseError> {
+    let deadline = |timeout: Option<Duration>| timeout.map(|t| Instant::now() + t);
+
+    reader.get_mut().deadline = deadline(options.header_timeout);
+    let mut request = Request::parse_head(reader, &options.limits)?;
+
+    reader.get_mut().deadline = deadline(options.body_timeout);
+    if request.has_body() && handler.streams_body(&request) {
+        let stream = Arc::clone(&reader.get_ref().stream);
+        let fresh = BufReader::new(DeadlineReader {
+            stream,
+            deadline: None,
+        });
+        request.stream_body(mem::replace(reader, fresh), &options.limits)?;
+        return Ok(request);
+    }
+    request.read_body(reader, &options.limits)?;
+
+    reader.get_mut().deadline = None;
+    Ok(request)
+}
+
+/// 在拒绝请求、或者处理器没有读完请求体时，关闭连接之前读掉对端已经发出的数据。
+///
+/// 关闭时若还有未读的数据，内核会发送 RST，客户端可能因此收不到响应。
+/// 读取受 `LINGER_TIMEOUT` 与 `LINGER_LIMIT` 限制，以免被恶意客户端拖住。
+fn linger(reader: &mut BufReader<DeadlineReader>) {
+    let _ = lock(&reader.get_ref().stream).shutdown_write();
+    reader.get_mut().deadline = Some(Instant::now() + LINGER_TIMEOUT);
+    let _ = io::copy(&mut reader.take(LINGER_LIMIT), &mut io::sink());
+}
+
+/// 等待下一个请求的第一个字节。
+///
+/// 连接空闲超过 `idle_timeout`、对端关闭连接或服务器请求关闭时返回 `false`；
+/// 空闲了一个检查间隔之后，`others_waiting` 返回真时也返回 `false`。
+/// 流水线请求已经在缓冲区中时立即返回 `true`。
+fn wait_for_request(
+    reader: &mut BufReader<DeadlineReader>,
+    options: &ConnectionOptions,
+    others_waiting: &dyn Fn() -> bool,
+) -> io::Result<bool> {
+    let deadline = options.idle_timeout.map(|t| Instant::now() + t);
+
+    loop {
+        if !reader.buffer().is_empty() {
+            return Ok(true);
+        }
+        if options.shutdown.is_shutdown() {
+            return Ok(false);
+        }
+
+        let now = Instant::now();
+        let slice = match deadline {
+            Some(deadline) if deadline <= now => return Ok(false),
+            Some(deadline) => (deadline - now).min(SHUTDOWN_POLL_INTERVAL),
+            None => SHUTDOWN_POLL_INTERVAL,
+        };
+        reader.get_mut().deadline = Some(now + slice);
+
+        match reader.fill_buf() {
+            Ok(buf) => return Ok(!buf.is_empty()),
+            Err(e) if is_timeout(&e) => {
+                if others_waiting() {
+                    return Ok(false);
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn wants_keep_alive(request: &Request) -> bool {
+    let headers = request.headers();
+
+    match request.version() {
+        Version::Http11 => !headers.has_token("Connection", "close"),
+        Version::Http10 => headers.has_token("Connection", "keep-alive"),
+    }
+}
+
+pub(crate) fn is_timeout(e: &io::Error) -> bool {
+    matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut)
+}
+
+/// 为无法解析的请求选择响应。
+///
+/// 读取超时对应 408；请求头部或请求体超过上限都对应 413。
+pub fn error_response(e: &ParseError) -> Response {
+    let status = match e {
+        ParseError::Io(e) if is_timeout(e) => StatusCode::RequestTimeout,
+        ParseError::UnsupportedMethod(_) => StatusCode::NotImplemented,
+        ParseError::UnsupportedVersion(_) => StatusCode::HttpVersionNotSupported,
+        ParseError::UnsupportedTransferEncoding(_) => StatusCode::NotImplemented,
+        ParseError::HeaderTooLarge { .. } | ParseError::BodyTooLarge { .. } => {
+            StatusCode::ContentTooLarge
+        }
+        _ => StatusCode::BadRequest,
+    };
+
+    Response::text(status, format!("{status}\n{e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{Read, Write},
+        net::TcpListener,
+        sync::mpsc,
+        thread,
+        time::Instant,
+    };
+
+    /// 边读边取 POST 请求体的处理器：每读到一段就经由通道报告，最后回显整个请求体。
+    struct Upload(mpsc::Sender<String>);
+
+    impl Handler for Upload {
+        fn handle(&self, mut request: Request) -> Response {
+            let mut body = request.take_body_stream().unwrap();
+            let mut received = Vec::new();
+            let mut buf = [0; 1024];
+            loop {
+                match body.read(&mut buf) {
+                    Ok(0) => break,
+                    Ok(n) => {
+                        received.extend_from_slice(&buf[..n]);
+                        let _ = self.0.send(String::from_utf8_lossy(&buf[..n]).into_owned());
+                    }
+                    Err(e) => {
+                        let e = e.downcast::<ParseError>().unwrap_or_else(ParseError::from);
+                        return error_response(&e);
+                    }
+                }
+            }
+            Response::new(StatusCode::Ok).with_body(received)
+        }
+
+        fn streams_body(&self, request: &Request) -> bool {
+            request.method() == Method::Post
+        }
+    }
+
+    /// 在后台线程中接受一个连接，用回显路径的处理器为它服务。
+    fn spawn_server(options: ConnectionOptions) -> std::net::SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let handler = |request: Request| {
+                Response::new(StatusCode::Ok).with_body(request.path().to_string())
+            };
+            serve_connection(stream, &handler, &options).unwrap();
+        });
+
+        addr
+    }
+
+    fn read_all(stream: &mut TcpStream) -> String {
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn pipelined_requests_are_answered_in_order() {
+        let addr = spawn_server(ConnectionOptions::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream
+            .write_all(
+                b"GET /one HTTP/1.1\r\nHost: x\r\n\r\n\
+                  HEAD /two HTTP/1.1\r\nHost: x\r\n\r\n\
+                  GET /three HTTP/1.1\r\nHost: x\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+
+        let out = read_all(&mut stream);
+
+        assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 3);
+        let one = out.find("/one").unwrap();
+        let three = out.find("/three").unwrap();
+        assert!(one < three);
+        assert!(!out.contains("/two"));
+        assert!(out.ends_with("Connection: close\r\nContent-Length: 6\r\n\r\n/three"));
+    }
+
+    #[test]
+    fn responses_carry_date_and_server() {
+        let addr = spawn_server(ConnectionOptions::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let out = read_all(&mut stream);
+
+        let date = out
+            .lines()
+            .find_map(|line| line.strip_prefix("Date: "))
+            .unwrap();
+        assert!(date.ends_with(" GMT"), "{date}");
+        assert_eq!(date.len(), "Sun, 06 Nov 1994 08:49:37 GMT".len());
+        assert!(out.contains(&format!("Server: {}\r\n", crate::response::SERVER)));
+    }
+
+    #[test]
+    fn http10_closes_unless_keep_alive() {
+        let addr = spawn_server(ConnectionOptions::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream
+            .write_all(
+                b"GET /a HTTP/1.0\r\nConnection: keep-alive\r\n\r\n\
+                  GET /b HTTP/1.0\r\n\r\n\
+                  GET /c HTTP/1.0\r\n\r\n",
+            )
+            .unwrap();
+
+        let out = read_all(&mut stream);
+
+        assert!(out.contains("Connection: keep-alive"));
+        assert!(out.contains("/b"));
+        assert!(!out.contains("/c"));
+    }
+
+    #[test]
+    fn streams_chunked_to_http11_and_raw_to_http10() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        thread::spawn(move || {
+            let handler = |_: Request| {
+                Response::new(StatusCode::Ok).with_chunks(vec![b"hello, ".to_vec(), b"world".to_vec()])
+            };
+            for stream in listener.incoming().take(2) {
+                serve_connection(stream.unwrap(), &handler, &ConnectionOptions::default()).unwrap();
+            }
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n\
+                         GET / HTTP/1.1\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let out = read_all(&mut stream);
+
+        assert_eq!(out.matches("Transfer-Encoding: chunked").count(), 2);
+        assert!(out.ends_with("\r\n\r\n7\r\nhello, \r\n5\r\nworld\r\n0\r\n\r\n"));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.0\r\nConnection: keep-alive\r\n\r\n")
+            .unwrap();
+        let out = read_all(&mut stream);
+
+        assert!(!out.contains("Transfer-Encoding"));
+        assert!(out.contains("Connection: close"));
+        assert!(out.ends_with("\r\n\r\nhello, world"));
+    }
+
+    #[test]
+    fn idle_connections_time_out() {
+        let addr = spawn_server(ConnectionOptions {
+            idle_timeout: Some(Duration::from_millis(100)),
+            ..ConnectionOptions::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+
+        stream.write_all(b"GET /a HTTP/1.1\r\n\r\n").unwrap();
+        let out = read_all(&mut stream);
+
+        assert!(out.ends_with("/a"));
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn malformed_request_gets_400_and_close() {
+        let addr = spawn_server(ConnectionOptions::default());
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream.write_all(b"GARBAGE\r\n\r\n").unwrap();
+        let out = read_all(&mut stream);
+
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(out.contains("Connection: close"));
+    }
+
+    /// 每隔 `interval` 发送 `data` 中的一个字节，模拟慢速发送的客户端。
+    fn dribble(stream: &TcpStream, data: &'static [u8], interval: Duration) {
+        let mut stream = stream.try_clone().unwrap();
+        thread::spawn(move || {
+            for byte in data {
+                if stream.write_all(&[*byte]).is_err() {
+                    return;
+                }
+                thread::sleep(interval);
+            }
+        });
+    }
+
+    #[test]
+    fn slow_headers_get_408() {
+        let addr = spawn_server(ConnectionOptions {
+            header_timeout: Some(Duration::from_millis(300)),
+            ..ConnectionOptions::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let start = Instant::now();
+
+        // 每个字节都来得比空闲超时快，但整个头部要好几秒才能发完。
+        dribble(
+            &stream,
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nUser-Agent: slowloris\r\n\r\n",
+            Duration::from_millis(50),
+        );
+        let out = read_all(&mut stream);
+
+        assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(out.contains("Connection: close"));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn slow_body_gets_408() {
+        let addr = spawn_server(ConnectionOptions {
+            body_timeout: Some(Duration::from_millis(300)),
+            ..ConnectionOptions::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 100\r\n\r\n")
+            .unwrap();
+        dribble(&stream, &[b'x'; 100], Duration::from_millis(50));
+        let out = read_all(&mut stream);
+
+        assert!(out.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn oversized_requests_get_413() {
+        let options = ConnectionOptions {
+            limits: Limits {
+                max_header_size: Some(1024),
+                max_body_size: Some(1024),
+            },
+            ..ConnectionOptions::default()
+        };
+
+        let addr = spawn_server(options.clone());
+        let mut stream = TcpStream::connect(addr).unwrap();
+        let cookie = "a".repeat(4096);
+        write!(stream, "GET / HTTP/1.1\r\nCookie: {cookie}\r\n\r\n").unwrap();
+        let out = read_all(&mut stream);
+        assert!(out.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+        assert!(out.contains("Connection: close"));
+
+        // 声明的长度超限时不等请求体发完就回应。
+        let addr = spawn_server(options);
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 1048576\r\n\r\npartial")
+            .unwrap();
+        let out = read_all(&mut stream);
+        assert!(out.starts_with("HTTP/1.1 413 Content Too Large\r\n"));
+    }
+
+    #[test]
+    fn clients_that_stop_reading_hit_the_write_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (done, result) = std::sync::mpsc::channel();
+
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // 远大于套接字的收发缓冲区，客户端不读取就一定写不完。
+            let handler = |_: Request| Response::new(StatusCode::Ok).with_body(vec![0; 64 << 20]);
+            let options = ConnectionOptions {
+                write_timeout: Some(Duration::from_millis(200)),
+                ..ConnectionOptions::default()
+            };
+            done.send(serve_connection(stream, &handler, &options)).unwrap();
+        });
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+
+        let error = result.recv_timeout(Duration::from_secs(5)).unwrap().unwrap_err();
+        assert!(is_timeout(&error));
+    }
+
+    #[test]
+    fn writes_access_log_lines() {
+        let path = std::env::temp_dir().join(format!("hello-access-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AccessLog::file(&path, crate::LogFormat::Combined).unwrap();
+
+        let addr = spawn_server(ConnectionOptions {
+            access_log: Some(Arc::new(log)),
+            ..ConnectionOptions::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"GET /logged?x=1 HTTP/1.1\r\nUser-Agent: test/1.0\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        read_all(&mut stream);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(contents.starts_with("127.0.0.1 - - ["));
+        assert!(contents.contains("] \"GET /logged?x=1 HTTP/1.1\" 200 7 \"-\" \"test/1.0\" "));
+        assert_eq!(contents.lines().count(), 1);
+    }
+
+    #[test]
+    fn logs_rejected_requests() {
+        let path = std::env::temp_dir().join(format!("hello-rejected-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = AccessLog::file(&path, crate::LogFormat::Common).unwrap();
+
+        let addr = spawn_server(ConnectionOptions {
+            access_log: Some(Arc::new(log)),
+            ..ConnectionOptions::default()
+        });
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(b"GET / HTTP/1.1\r\nHost : x\r\n\r\n").unwrap();
+        let response = read_all(&mut stream);
+        let body = response.split_once("\r\n\r\n").unwrap().1;
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(contents.contains(&format!("] \"-\" 400 {} ", body.len())));
+    }
+
+    #[test]
+    fn streams_request_bodies_to_handlers_that_ask() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (reads, read) = mpsc::channel();
+        thread::spawn(move || {
+            let mut options = ConnectionOptions::default();
+            options.limits.max_body_size = Some(10);
+            for stream in listener.incoming().take(2) {
+                serve_connection(stream.unwrap(), &Upload(reads.clone()), &options).unwrap();
+            }
+        });
+
+        // 处理器在请求体到齐之前就读到了第一段，之后连接关闭。
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        assert_eq!(read.recv_timeout(Duration::from_secs(5)).unwrap(), "hello");
+        stream.write_all(b"world").unwrap();
+        let out = read_all(&mut stream);
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+        assert!(out.contains("Connection: close\r\n"));
+        assert!(out.ends_with("\r\n\r\nhelloworld"));
+
+        // 分块编码的请求体读到超出上限时出错。
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+                  5\r\nhello\r\n6\r\nworld!\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        let out = read_all(&mut stream);
+        assert!(
+            out.starts_with("HTTP/1.1 413 Content Too Large\r\n"),
+            "{out}"
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// 精确到秒的 UTC 日历时间。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct DateTime {
+    pub year: i64,
+    /// 1 到 12。
+    pub month: u8,
+    /// 1 到 31。
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    pub fn now() -> DateTime {
+        DateTime::from(SystemTime::now())
+    }
+
+    /// 以 Unix 时间戳（秒）创建。
+    pub fn from_unix(secs: i64) -> DateTime {
+        let days = secs.div_euclid(86_400);
+        let rem = secs.rem_euclid(86_400);
+        let (year, month, day) = civil_from_days(days);
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: (rem / 3600) as u8,
+            minute: (rem % 3600 / 60) as u8,
+            second: (rem % 60) as u8,
+        }
+    }
+
+    /// 换算回 Unix 时间戳（秒）；超出 `i64` 的范围时取最小值或最大值。
+    pub fn unix(&self) -> i64 {
+        self.checked_unix()
+            .unwrap_or(if self.year < 0 { i64::MIN } else { i64::MAX })
+    }
+
+    /// 换算回 Unix 时间戳（秒），超出 `i64` 的范围时返回 `None`。
+    pub fn checked_unix(&self) -> Option<i64> {
+        let secs =
+            i64::from(self.hour) * 3600 + i64::from(self.minute) * 60 + i64::from(self.second);
+        days_from_civil(self.year, self.month, self.day)?
+            .checked_mul(86_400)?
+            .checked_add(secs)
+    }
+
+    /// HTTP 头部使用的 IMF-fixdate 格式，比如 `Tue, 10 Oct 2000 13:55:36 GMT`。
+    pub fn http(&self) -> impl fmt::Display + '_ {
+        Http(self)
+    }
+
+    /// 解析 HTTP 头部中的日期。
+    ///
+    /// 除了 IMF-fixdate，还按 RFC 9110 的要求接受过时的 RFC 850 与 asctime 格式，
+    /// 比如 `Sunday, 06-Nov-94 08:49:37 GMT` 与 `Sun Nov  6 08:49:37 1994`。
+    pub fn parse_http(s: &str) -> Option<DateTime> {
+        let parts: Vec<&str> = s.split_whitespace().collect();
+
+        // 年份只接受四位数字（RFC 850 格式还可以是两位），以免巨大的年份在换算时溢出。
+        let (day, month, year, time) = match parts[..] {
+            // Sun, 06 Nov 1994 08:49:37 GMT
+            [_, day, month, year, time, "GMT"] => (day, month, digits(year, 4)?, time),
+            // Sunday, 06-Nov-94 08:49:37 GMT
+            [_, date, time, "GMT"] => {
+                let mut date = date.split('-');
+                let (day, month, year) = (date.next()?, date.next()?, date.next()?);
+                let year = match digits(year, 2) {
+                    Some(year) => expand_year(year, DateTime::now().year),
+                    None => digits(year, 4)?,
+                };
+                (day, month, year, time)
+            }
+            // Sun Nov  6 08:49:37 1994
+            [_, month, day, time, year] => (day, month, digits(year, 4)?, time),
+            _ => return None,
+        };
+
+        let month = MONTHS.iter().position(|&m| m == month)? as u8 + 1;
+        let day: u8 = day.parse().ok()?;
+
+        let mut time = time.split(':').map(|part| part.parse::<u8>().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+        let t = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        // 拒绝 2 月 30 日、25 点这样的日期：换算一个来回后应当不变。
+        (DateTime::from_unix(t.checked_unix()?) == t).then_some(t)
+    }
+
+    /// Common Log Format 使用的时间，比如 `10/Oct/2000:13:55:36 +0000`。
+    pub fn clf(&self) -> impl fmt::Display + '_ {
+        Clf(self)
+    }
+
+    /// RFC 3339 格式的时间，比如 `2000-10-10T13:55:36Z`。
+    pub fn rfc3339(&self) -> impl fmt::Display + '_ {
+        Rfc3339(self)
+    }
+}
+
+impl From<SystemTime> for DateTime {
+    fn from(time: SystemTime) -> DateTime {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(d) => d.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs_f64().ceil() as i64),
+        };
+        DateTime::from_unix(secs)
+    }
+}
+
+struct Clf<'a>(&'a DateTime);
+
+impl fmt::Display for Clf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+        write!(
+            f,
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            t.day,
+            MONTHS[usize::from(t.month - 1)],
+            t.year,
+            t.hour,
+            t.minute,
+            t.second
+        )
+    }
+}
+
+struct Rfc3339<'a>(&'a DateTime);
+
+impl fmt::Display for Rfc3339<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            t.year, t.month, t.day, t.hour, t.minute, t.second
+        )
+    }
+}
+
+struct Http<'a>(&'a DateTime);
+
+impl fmt::Display for Http<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let t = self.0;
+        // 1970-01-01 是星期四。公历每 400 年恰好是整数个星期，先把年份挪到这个范围内，
+        // 免得很大的年份在换算时溢出。
+        let days = days_from_civil(2000 + t.year.rem_euclid(400), t.month, t.day).unwrap_or(0);
+        let weekday = (days + 4).rem_euclid(7);
+        write!(
+            f,
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            WEEKDAYS[weekday as usize],
+            t.day,
+            MONTHS[usize::from(t.month - 1)],
+            t.year,
+            t.hour,
+            t.minute,
+            t.second
+        )
+    }
+}
+
+/// 把自 1970-01-01 起的天数换算为公历的年、月、日。
+///
+/// 算法见 Howard Hinnant 的 “chrono-Compatible Low-Level Date Algorithms”。
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u8;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// [`civil_from_days`] 的逆运算；天数超出 `i64` 的范围时返回 `None`。
+fn days_from_civil(year: i64, month: u8, day: u8) -> Option<i64> {
+    let year = year.checked_sub(i64::from(month <= 2))?;
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let mp = if month > 2 { month - 3 } else { month + 9 };
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era.checked_mul(146_097)?.checked_add(doe - 719_468)
+}
+
+/// 恰好 `len` 位十进制数字组成的年份。
+fn digits(s: &str, len: usize) -> Option<i64> {
+    if s.len() != len || !s.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    s.parse().ok()
+}
+
+/// 按 RFC 9110 第 5.6.7 节把 RFC 850 格式的两位数年份展开为离 `this_year` 不超过
+/// 50 年的那一年：看起来在 50 年以后的，取过去最近的同尾数年份。
+fn expand_year(two_digits: i64, this_year: i64) -> i64 {
+    let year = this_year - this_year.rem_euclid(100) + two_digits;
+    if year > this_year + 50 {
+        year - 100
+    } else if year < this_year - 50 {
+        year + 100
+    } else {
+        year
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_unix_timestamps() {
+        let t = DateTime::from_unix(971_185_736);
+        assert_eq!(t.clf().to_string(), "10/Oct/2000:13:48:56 +0000");
+        assert_eq!(t.rfc3339().to_string(), "2000-10-10T13:48:56Z");
+
+        assert_eq!(
+            DateTime::from_unix(0).rfc3339().to_string(),
+            "1970-01-01T00:00:00Z"
+        );
+        assert_eq!(
+            DateTime::from_unix(951_782_400).rfc3339().to_string(),
+            "2000-02-29T00:00:00Z"
+        );
+        assert_eq!(
+            DateTime::from_unix(-1).rfc3339().to_string(),
+            "1969-12-31T23:59:59Z"
+        );
+    }
+
+    #[test]
+    fn formats_http_dates() {
+        assert_eq!(
+            DateTime::from_unix(784_111_777).http().to_string(),
+            "Sun, 06 Nov 1994 08:49:37 GMT"
+        );
+        assert_eq!(
+            DateTime::from_unix(971_185_736).http().to_string(),
+            "Tue, 10 Oct 2000 13:48:56 GMT"
+        );
+
+        for secs in [-86_401, -1, 0, 951_782_400, 971_185_736, 4_102_444_800] {
+            assert_eq!(DateTime::from_unix(secs).unix(), secs);
+        }
+    }
+
+    #[test]
+    fn parses_http_dates() {
+        let expected = Some(DateTime::from_unix(784_111_777));
+        assert_eq!(
+            DateTime::parse_http("Sun, 06 Nov 1994 08:49:37 GMT"),
+            expected
+        );
+        assert_eq!(
+            DateTime::parse_http("Sunday, 06-Nov-94 08:49:37 GMT"),
+            expected
+        );
+        assert_eq!(DateTime::parse_http("Sun Nov  6 08:49:37 1994"), expected);
+
+        for bad in [
+            "",
+            "Sun, 06 Nov 1994 08:49:37 PST",
+            "Sun, 30 Feb 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 25:49:37 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+            "1994-11-06T08:49:37Z",
+            // 年份过大或位数不对，曾经在换算时溢出。
+            "Sun, 06 Nov 99999 08:49:37 GMT",
+            "Sunday, 06-Nov-9223372036854775807 08:49:37 GMT",
+            "Sunday, 06-Nov-994 08:49:37 GMT",
+            "Sunday, 06-Nov-+4 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 99999999999999999",
+            "Sun Nov  6 08:49:37 -994",
+        ] {
+            assert_eq!(DateTime::parse_http(bad), None, "{bad}");
+        }
+        assert_eq!(
+            DateTime::parse_http("Sunday, 06-Nov-1994 08:49:37 GMT"),
+            expected
+        );
+    }
+
+    #[test]
+    fn expands_two_digit_years() {
+        assert_eq!(expand_year(94, 2026), 1994);
+        assert_eq!(expand_year(76, 2026), 2076);
+        assert_eq!(expand_year(77, 2026), 1977);
+        assert_eq!(expand_year(5, 2026), 2005);
+        assert_eq!(expand_year(10, 2090), 2110);
+    }
+
+    #[test]
+    fn does_not_overflow_on_huge_years() {
+        let t = DateTime {
+            year: i64::MAX,
+            month: 1,
+            day: 1,
+            hour: 0,
+            minute: 0,
+            second: 0,
+        };
+        assert_eq!(t.checked_unix(), None);
+        assert_eq!(t.unix(), i64::MAX);
+        let min = DateTime {
+            year: i64::MIN,
+            ..t
+        };
+        assert_eq!(min.unix(), i64::MIN);
+        assert!(t.http().to_string().ends_with(" 00:00:00 GMT"));
+    }
+}
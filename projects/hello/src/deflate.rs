@@ -0,0 +1,859 @@
+//! DEFLATE（RFC 1951）压缩器，以及 gzip（RFC 1952）与 zlib（RFC 1950）两种封装。
+//!
+//! 用哈希链查找重复串，并做一步惰性匹配；每个块在存储、固定 Huffman 编码与动态
+//! Huffman 编码三种方式中选输出最短的一种。只实现了压缩，解压交给客户端。
+
+use std::{
+    cmp::Reverse,
+    collections::BinaryHeap,
+    io::{self, Write},
+};
+
+/// 回看窗口的大小；匹配距离必须小于它。
+const WINDOW: usize = 32 * 1024;
+/// 攒够这么多输入就压缩成一个块。
+const BLOCK: usize = 64 * 1024;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const HASH_BITS: u32 = 15;
+/// 每个位置最多沿哈希链比较多少个候选。
+const MAX_CHAIN: usize = 128;
+/// 找到这么长的匹配就不再继续找，也不做惰性匹配。
+const NICE_MATCH: usize = 128;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// 码长码的码长按这个顺序写出。
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB8_8320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+};
+
+/// 压缩数据外面的封装。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `Content-Encoding: gzip`。
+    Gzip,
+    /// `Content-Encoding: deflate`，即 zlib 格式，而不是裸的 DEFLATE 数据。
+    Zlib,
+}
+
+/// 一次性压缩 `data`。
+pub fn compress(data: &[u8], format: Format) -> Vec<u8> {
+    let mut encoder = Encoder::new(Vec::new(), format);
+    encoder.write_all(data).expect("写入 Vec 不会失败");
+    encoder.finish().expect("写入 Vec 不会失败")
+}
+
+/// 把写入的数据压缩后写给 `inner`。
+///
+/// 输入攒够一个块才压缩输出；[`flush`](Write::flush) 会把已有的输入全部压缩并按字节
+/// 对齐输出（即 zlib 的 `Z_SYNC_FLUSH`），让对端马上能解出这些数据。写完后必须调用
+/// [`finish`](Encoder::finish) 写出结尾。
+pub struct Encoder<W: Write> {
+    inner: W,
+    format: Format,
+    matcher: Matcher,
+    bits: BitWriter,
+    checksum: Checksum,
+    /// 输入的总长度对 2^32 取模，gzip 结尾要用。
+    size: u32,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, format: Format) -> Encoder<W> {
+        let mut bits = BitWriter::new();
+        let checksum = match format {
+            // 不记录文件名与修改时间，操作系统记为“未知”。
+            Format::Gzip => {
+                bits.out
+                    .extend_from_slice(&[0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255]);
+                Checksum::Crc32(!0)
+            }
+            // 32 KiB 窗口、默认压缩级别，0x789c 是 31 的倍数。
+            Format::Zlib => {
+                bits.out.extend_from_slice(&[0x78, 0x9c]);
+                Checksum::Adler32(1, 0)
+            }
+        };
+
+        Encoder {
+            inner,
+            format,
+            matcher: Matcher::new(),
+            bits,
+            checksum,
+            size: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut W {
+        &mut self.inner
+    }
+
+    /// 压缩剩下的输入，写出结尾，返回 `inner`。
+    pub fn finish(mut self) -> io::Result<W> {
+        self.compress(true);
+        self.bits.align();
+
+        match self.checksum {
+            Checksum::Crc32(crc) => {
+                self.bits.out.extend_from_slice(&(!crc).to_le_bytes());
+                self.bits.out.extend_from_slice(&self.size.to_le_bytes());
+            }
+            Checksum::Adler32(a, b) => {
+                self.bits
+                    .out
+                    .extend_from_slice(&(b << 16 | a).to_be_bytes());
+            }
+        }
+
+        self.inner.write_all(&self.bits.out)?;
+        Ok(self.inner)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+    }
+
+    /// 把尚未压缩的输入压缩成一个块，追加到 `self.bits`。
+    fn compress(&mut self, last: bool) {
+        let start = self.matcher.start;
+        let tokens = self.matcher.tokenize();
+        write_block(&mut self.bits, &tokens, &self.matcher.data[start..], last);
+        self.matcher.slide();
+    }
+
+    fn drain(&mut self) -> io::Result<()> {
+        self.inner.write_all(&self.bits.out)?;
+        self.bits.out.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = buf.len().min(BLOCK - self.matcher.pending());
+        let buf = &buf[..n];
+
+        self.checksum.update(buf);
+        self.size = self.size.wrapping_add(n as u32);
+        self.matcher.data.extend_from_slice(buf);
+
+        if self.matcher.pending() == BLOCK {
+            self.compress(false);
+            self.drain()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if self.matcher.pending() > 0 {
+            self.compress(false);
+        }
+        // 一个空的存储块，把输出补齐到字节边界。
+        self.bits.bits(0, 3);
+        self.bits.align();
+        self.bits.out.extend_from_slice(&[0, 0, 0xff, 0xff]);
+
+        self.drain()?;
+        self.inner.flush()
+    }
+}
+
+enum Checksum {
+    /// 取反前的 CRC-32 寄存器。
+    Crc32(u32),
+    /// Adler-32 的 `a` 与 `b`。
+    Adler32(u32, u32),
+}
+
+impl Checksum {
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Checksum::Crc32(crc) => {
+                for &byte in data {
+                    *crc = CRC_TABLE[((*crc ^ u32::from(byte)) & 0xff) as usize] ^ (*crc >> 8);
+                }
+            }
+            Checksum::Adler32(a, b) => {
+                // 5552 是在 u32 不溢出的前提下可以推迟取模的最大字节数。
+                for chunk in data.chunks(5552) {
+                    for &byte in chunk {
+                        *a += u32::from(byte);
+                        *b += *a;
+                    }
+                    *a %= 65521;
+                    *b %= 65521;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Token {
+    Literal(u8),
+    Match { len: u16, dist: u16 },
+}
+
+/// LZ77 匹配器：保存最多一个窗口的历史与尚未压缩的输入。
+struct Matcher {
+    data: Vec<u8>,
+    /// 尚未压缩的输入在 `data` 中的起点。
+    start: usize,
+    /// `data[0]` 在整个输入流中的位置。
+    base: usize,
+    /// 每个哈希值最近一次出现的位置加一，0 表示没有。
+    head: Vec<usize>,
+    /// 以位置对窗口大小取模为下标，同一哈希值上一次出现的位置加一。
+    prev: Vec<usize>,
+}
+
+impl Matcher {
+    fn new() -> Matcher {
+        Matcher {
+            data: Vec::new(),
+            start: 0,
+            base: 0,
+            head: vec![0; 1 << HASH_BITS],
+            prev: vec![0; WINDOW],
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.data.len() - self.start
+    }
+
+    fn hash(&self, i: usize) -> usize {
+        let d = &self.data;
+        let v = u32::from(d[i]) << 16 | u32::from(d[i + 1]) << 8 | u32::from(d[i + 2]);
+        (v.wrapping_mul(0x9E37_79B1) >> (32 - HASH_BITS)) as usize
+    }
+
+    fn insert(&mut self, i: usize) {
+        if i + MIN_MATCH > self.data.len() {
+            return;
+        }
+        let h = self.hash(i);
+        let pos = self.base + i;
+        self.prev[pos % WINDOW] = self.head[h];
+        self.head[h] = pos + 1;
+    }
+
+    /// 在窗口中为 `data[i..]` 找最长的匹配，返回长度与距离；没有足够长的匹配时长度为 0。
+    fn longest_match(&self, i: usize) -> (usize, usize) {
+        let end = self.data.len();
+        if i + MIN_MATCH > end {
+            return (0, 0);
+        }
+
+        let max_len = (end - i).min(MAX_MATCH);
+        let pos = self.base + i;
+        let target = &self.data[i..i + max_len];
+        let (mut best_len, mut best_dist) = (0, 0);
+        let mut candidate = self.head[self.hash(i)];
+
+        for _ in 0..MAX_CHAIN {
+            let Some(c) = candidate.checked_sub(1) else {
+                break;
+            };
+            if c >= pos || pos - c >= WINDOW || c < self.base {
+                break;
+            }
+
+            let from = c - self.base;
+            // 先比较能让匹配变长的那个字节，大多数候选在这里就被排除。
+            if self.data[from + best_len.min(max_len - 1)] == target[best_len.min(max_len - 1)] {
+                let len = self.data[from..]
+                    .iter()
+                    .zip(target)
+                    .take_while(|(a, b)| a == b)
+                    .count();
+                if len > best_len {
+                    best_len = len;
+                    best_dist = pos - c;
+                    if len >= max_len || len >= NICE_MATCH {
+                        break;
+                    }
+                }
+            }
+
+            candidate = self.prev[c % WINDOW];
+        }
+
+        if best_len >= MIN_MATCH {
+            (best_len, best_dist)
+        } else {
+            (0, 0)
+        }
+    }
+
+    /// 把尚未压缩的输入全部转换为字面量与匹配。
+    fn tokenize(&mut self) -> Vec<Token> {
+        let end = self.data.len();
+        let mut tokens = Vec::new();
+        let mut i = self.start;
+
+        while i < end {
+            let (len, dist) = self.longest_match(i);
+            self.insert(i);
+
+            if len == 0 {
+                tokens.push(Token::Literal(self.data[i]));
+                i += 1;
+                continue;
+            }
+
+            // 惰性匹配：下一个位置的匹配更长时，这里只输出一个字面量。
+            if len < NICE_MATCH && self.longest_match(i + 1).0 > len {
+                tokens.push(Token::Literal(self.data[i]));
+                i += 1;
+                continue;
+            }
+
+            tokens.push(Token::Match {
+                len: len as u16,
+                dist: dist as u16,
+            });
+            for j in i + 1..i + len {
+                self.insert(j);
+            }
+            i += len;
+        }
+
+        self.start = end;
+        tokens
+    }
+
+    /// 丢掉窗口以外的历史。
+    fn slide(&mut self) {
+        if self.data.len() > WINDOW {
+            let drop = self.data.len() - WINDOW;
+            self.data.drain(..drop);
+            self.base += drop;
+            self.start -= drop;
+        }
+    }
+}
+
+/// 从低位开始逐位写出。
+struct BitWriter {
+    out: Vec<u8>,
+    acc: u64,
+    count: u32,
+}
+
+impl BitWriter {
+    fn new() -> BitWriter {
+        BitWriter {
+            out: Vec::new(),
+            acc: 0,
+            count: 0,
+        }
+    }
+
+    fn bits(&mut self, value: u32, count: u32) {
+        self.acc |= u64::from(value) << self.count;
+        self.count += count;
+        while self.count >= 8 {
+            self.out.push(self.acc as u8);
+            self.acc >>= 8;
+            self.count -= 8;
+        }
+    }
+
+    fn align(&mut self) {
+        if self.count > 0 {
+            self.out.push(self.acc as u8);
+            self.acc = 0;
+            self.count = 0;
+        }
+    }
+}
+
+/// 一组 Huffman 码：每个符号的码长与已按位反转、可直接写出的码字。
+struct Codes {
+    lengths: Vec<u8>,
+    codes: Vec<u16>,
+}
+
+impl Codes {
+    /// 按 RFC 1951 第 3.2.2 节由码长得到规范 Huffman 码。
+    fn new(lengths: Vec<u8>) -> Codes {
+        let mut count = [0u16; 16];
+        for &l in &lengths {
+            if l > 0 {
+                count[usize::from(l)] += 1;
+            }
+        }
+
+        let mut next = [0u16; 16];
+        let mut code = 0;
+        for bits in 1..16 {
+            code = (code + count[bits - 1]) << 1;
+            next[bits] = code;
+        }
+
+        let codes = lengths
+            .iter()
+            .map(|&l| {
+                if l == 0 {
+                    return 0;
+                }
+                let code = next[usize::from(l)];
+                next[usize::from(l)] += 1;
+                code.reverse_bits() >> (16 - l)
+            })
+            .collect();
+
+        Codes { lengths, codes }
+    }
+
+    fn fixed_literals() -> Codes {
+        let mut lengths = vec![8; 288];
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        Codes::new(lengths)
+    }
+
+    fn fixed_distances() -> Codes {
+        Codes::new(vec![5; 30])
+    }
+
+    fn write(&self, out: &mut BitWriter, symbol: usize) {
+        out.bits(
+            u32::from(self.codes[symbol]),
+            u32::from(self.lengths[symbol]),
+        );
+    }
+
+    /// 按这组码写出给定频率的符号共需多少位，含长度与距离的额外位。
+    fn cost(&self, freqs: &[u32], extra: impl Fn(usize) -> u8) -> u64 {
+        freqs
+            .iter()
+            .enumerate()
+            .map(|(s, &f)| u64::from(f) * u64::from(self.lengths[s] + extra(s)))
+            .sum()
+    }
+}
+
+fn length_symbol(len: u16) -> usize {
+    LENGTH_BASE.partition_point(|&base| base <= len) - 1
+}
+
+fn dist_symbol(dist: u16) -> usize {
+    DIST_BASE.partition_point(|&base| base <= dist) - 1
+}
+
+fn literal_extra(symbol: usize) -> u8 {
+    if symbol > 256 {
+        LENGTH_EXTRA[symbol - 257]
+    } else {
+        0
+    }
+}
+
+fn dist_extra(symbol: usize) -> u8 {
+    DIST_EXTRA[symbol]
+}
+
+/// 由符号频率计算码长不超过 `limit` 的 Huffman 码长。
+///
+/// 超过上限时把频率减半再算，直到满足为止：频率越平均，树越矮。出现的符号少于两个时
+/// 补足两个，使码树总是完整的，有些解码器不接受不完整的码树。
+fn huffman_lengths(freqs: &[u32], limit: u8) -> Vec<u8> {
+    let mut freqs = freqs.to_vec();
+    let used = freqs.iter().filter(|&&f| f > 0).count();
+    for f in freqs.iter_mut().filter(|f| **f == 0).take(2 - used.min(2)) {
+        *f = 1;
+    }
+
+    loop {
+        let lengths = build_huffman(&freqs);
+        if lengths.iter().all(|&l| l <= limit) {
+            return lengths;
+        }
+        for f in freqs.iter_mut().filter(|f| **f > 1) {
+            *f = f.div_ceil(2);
+        }
+    }
+}
+
+fn build_huffman(freqs: &[u32]) -> Vec<u8> {
+    let symbols: Vec<usize> = (0..freqs.len()).filter(|&s| freqs[s] > 0).collect();
+    let n = symbols.len();
+
+    // 结点 0..n 是叶子，之后是内部结点；父结点的编号总是大于子结点。
+    let mut parent = vec![0; 2 * n - 1];
+    let mut heap: BinaryHeap<_> = symbols
+        .iter()
+        .enumerate()
+        .map(|(node, &s)| Reverse((u64::from(freqs[s]), node)))
+        .collect();
+    let mut next = n;
+
+    while heap.len() > 1 {
+        let Reverse((w1, a)) = heap.pop().unwrap();
+        let Reverse((w2, b)) = heap.pop().unwrap();
+        parent[a] = next;
+        parent[b] = next;
+        heap.push(Reverse((w1 + w2, next)));
+        next += 1;
+    }
+
+    let mut depth = vec![0u32; 2 * n - 1];
+    for node in (0..2 * n - 2).rev() {
+        depth[node] = depth[parent[node]] + 1;
+    }
+
+    let mut lengths = vec![0; freqs.len()];
+    for (node, &s) in symbols.iter().enumerate() {
+        lengths[s] = depth[node].min(255) as u8;
+    }
+    lengths
+}
+
+/// 把码长序列按 RFC 1951 第 3.2.7 节做游程编码，返回码长码的符号与额外位的值。
+fn run_lengths(lengths: &[u8]) -> Vec<(u8, u8)> {
+    let mut out = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let l = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&x| x == l).count();
+
+        if l == 0 && run >= 3 {
+            let n = run.min(138);
+            if n >= 11 {
+                out.push((18, (n - 11) as u8));
+            } else {
+                out.push((17, (n - 3) as u8));
+            }
+            i += n;
+        } else if l != 0 && run >= 4 {
+            let n = (run - 1).min(6);
+            out.push((l, 0));
+            out.push((16, (n - 3) as u8));
+            i += 1 + n;
+        } else {
+            out.push((l, 0));
+            i += 1;
+        }
+    }
+
+    out
+}
+
+fn run_length_extra(symbol: usize) -> u8 {
+    match symbol {
+        16 => 2,
+        17 => 3,
+        18 => 7,
+        _ => 0,
+    }
+}
+
+/// 动态 Huffman 块的码表与描述它们的块头。
+struct Dynamic {
+    literals: Codes,
+    distances: Codes,
+    hlit: usize,
+    hdist: usize,
+    code_lengths: Codes,
+    hclen: usize,
+    runs: Vec<(u8, u8)>,
+}
+
+impl Dynamic {
+    fn new(literal_freqs: &[u32], dist_freqs: &[u32]) -> Dynamic {
+        let literals = huffman_lengths(literal_freqs, 15);
+        let distances = huffman_lengths(dist_freqs, 15);
+        let hlit = last_used(&literals).max(257);
+        let hdist = last_used(&distances).max(1);
+
+        let runs = run_lengths(&[&literals[..hlit], &distances[..hdist]].concat());
+        let mut freqs = [0u32; 19];
+        for &(symbol, _) in &runs {
+            freqs[usize::from(symbol)] += 1;
+        }
+        let code_lengths = huffman_lengths(&freqs, 7);
+        let hclen = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&s| code_lengths[s] > 0)
+            .map_or(0, |i| i + 1)
+            .max(4);
+
+        Dynamic {
+            literals: Codes::new(literals),
+            distances: Codes::new(distances),
+            hlit,
+            hdist,
+            code_lengths: Codes::new(code_lengths),
+            hclen,
+            runs,
+        }
+    }
+
+    fn header_cost(&self) -> u64 {
+        let runs: u64 = self
+            .runs
+            .iter()
+            .map(|&(s, _)| {
+                let s = usize::from(s);
+                u64::from(self.code_lengths.lengths[s] + run_length_extra(s))
+            })
+            .sum();
+        14 + 3 * self.hclen as u64 + runs
+    }
+
+    fn write_header(&self, out: &mut BitWriter) {
+        out.bits((self.hlit - 257) as u32, 5);
+        out.bits((self.hdist - 1) as u32, 5);
+        out.bits((self.hclen - 4) as u32, 4);
+        for &s in &CODE_LENGTH_ORDER[..self.hclen] {
+            out.bits(u32::from(self.code_lengths.lengths[s]), 3);
+        }
+        for &(s, extra) in &self.runs {
+            let s = usize::from(s);
+            self.code_lengths.write(out, s);
+            out.bits(u32::from(extra), u32::from(run_length_extra(s)));
+        }
+    }
+}
+
+fn last_used(lengths: &[u8]) -> usize {
+    lengths.iter().rposition(|&l| l > 0).map_or(0, |i| i + 1)
+}
+
+/// 把一个块写为存储、固定 Huffman 或动态 Huffman 三种之中最短的一种。
+fn write_block(out: &mut BitWriter, tokens: &[Token], raw: &[u8], last: bool) {
+    let mut literal_freqs = [0u32; 286];
+    let mut dist_freqs = [0u32; 30];
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literal_freqs[usize::from(byte)] += 1,
+            Token::Match { len, dist } => {
+                literal_freqs[257 + length_symbol(len)] += 1;
+                dist_freqs[dist_symbol(dist)] += 1;
+            }
+        }
+    }
+    literal_freqs[256] = 1;
+
+    let fixed = (Codes::fixed_literals(), Codes::fixed_distances());
+    let dynamic = Dynamic::new(&literal_freqs, &dist_freqs);
+
+    let data_cost = |literals: &Codes, distances: &Codes| {
+        literals.cost(&literal_freqs, literal_extra) + distances.cost(&dist_freqs, dist_extra)
+    };
+    let fixed_cost = data_cost(&fixed.0, &fixed.1);
+    let dynamic_cost = dynamic.header_cost() + data_cost(&dynamic.literals, &dynamic.distances);
+    // 每个存储块有 3 位块头、最多 7 位填充与 4 字节的长度。
+    let stored_cost = raw.len().div_ceil(65535).max(1) as u64 * 42 + 8 * raw.len() as u64;
+
+    if stored_cost <= fixed_cost.min(dynamic_cost) {
+        write_stored(out, raw, last);
+        return;
+    }
+
+    out.bits(u32::from(last), 1);
+    if fixed_cost <= dynamic_cost {
+        out.bits(1, 2);
+        write_tokens(out, tokens, &fixed.0, &fixed.1);
+    } else {
+        out.bits(2, 2);
+        dynamic.write_header(out);
+        write_tokens(out, tokens, &dynamic.literals, &dynamic.distances);
+    }
+}
+
+fn write_stored(out: &mut BitWriter, raw: &[u8], last: bool) {
+    let count = raw.len().div_ceil(65535).max(1);
+
+    for i in 0..count {
+        let chunk = &raw[i * 65535..raw.len().min((i + 1) * 65535)];
+        out.bits(u32::from(last && i == count - 1), 1);
+        out.bits(0, 2);
+        out.align();
+
+        let len = chunk.len() as u16;
+        out.out.extend_from_slice(&len.to_le_bytes());
+        out.out.extend_from_slice(&(!len).to_le_bytes());
+        out.out.extend_from_slice(chunk);
+    }
+}
+
+fn write_tokens(out: &mut BitWriter, tokens: &[Token], literals: &Codes, distances: &Codes) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => literals.write(out, usize::from(byte)),
+            Token::Match { len, dist } => {
+                let l = length_symbol(len);
+                literals.write(out, 257 + l);
+                out.bits(u32::from(len - LENGTH_BASE[l]), u32::from(LENGTH_EXTRA[l]));
+
+                let d = dist_symbol(dist);
+                distances.write(out, d);
+                out.bits(u32::from(dist - DIST_BASE[d]), u32::from(DIST_EXTRA[d]));
+            }
+        }
+    }
+    literals.write(out, 256);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Read;
+
+    use flate2::read::{GzDecoder, ZlibDecoder};
+
+    fn decompress(data: &[u8], format: Format) -> Vec<u8> {
+        let mut out = Vec::new();
+        match format {
+            Format::Gzip => GzDecoder::new(data).read_to_end(&mut out).unwrap(),
+            Format::Zlib => ZlibDecoder::new(data).read_to_end(&mut out).unwrap(),
+        };
+        out
+    }
+
+    /// 一个简单的线性同余生成器，产生不可压缩的字节。
+    fn noise(len: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (state >> 16) as u8
+            })
+            .collect()
+    }
+
+    fn html(len: usize) -> Vec<u8> {
+        let mut page = String::from("<!DOCTYPE html>\n<html lang=\"en\">\n<body>\n");
+        let mut i = 0;
+        while page.len() < len {
+            page.push_str(&format!(
+                "  <li class=\"item\"><a href=\"/items/{i}\">Item number {i}</a></li>\n"
+            ));
+            i += 1;
+        }
+        page.truncate(len);
+        page.into_bytes()
+    }
+
+    #[test]
+    fn round_trips_through_flate2() {
+        let inputs = [
+            Vec::new(),
+            b"a".to_vec(),
+            b"hello, hello, hello, world".to_vec(),
+            vec![b'x'; 100_000],
+            html(300 * 1024),
+            noise(70_000),
+            [html(40_000), noise(40_000), html(40_000)].concat(),
+        ];
+
+        for input in &inputs {
+            for format in [Format::Gzip, Format::Zlib] {
+                let compressed = compress(input, format);
+                assert_eq!(
+                    decompress(&compressed, format),
+                    *input,
+                    "{format:?}, {} bytes",
+                    input.len()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn compresses_text_and_barely_grows_noise() {
+        let page = html(256 * 1024);
+        let compressed = compress(&page, Format::Gzip);
+        assert!(
+            compressed.len() < page.len() / 5,
+            "{} -> {}",
+            page.len(),
+            compressed.len()
+        );
+
+        let random = noise(256 * 1024);
+        let compressed = compress(&random, Format::Gzip);
+        assert!(compressed.len() < random.len() + 64);
+    }
+
+    #[test]
+    fn flush_makes_written_data_decodable() {
+        let mut encoder = Encoder::new(Vec::new(), Format::Zlib);
+        encoder.write_all(b"first part, ").unwrap();
+        encoder.flush().unwrap();
+        let partial = encoder.get_ref().clone();
+
+        let mut inflate = flate2::Decompress::new(true);
+        let mut out = Vec::with_capacity(64);
+        inflate
+            .decompress_vec(&partial, &mut out, flate2::FlushDecompress::Sync)
+            .unwrap();
+        assert_eq!(out, b"first part, ");
+
+        encoder.write_all(b"second part").unwrap();
+        let all = encoder.finish().unwrap();
+        assert_eq!(decompress(&all, Format::Zlib), b"first part, second part");
+    }
+
+    #[test]
+    fn limits_code_lengths() {
+        // 斐波那契频率会让不加限制的 Huffman 树深度超过 15。
+        let mut freqs = vec![1u32, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+
+        let lengths = huffman_lengths(&freqs, 15);
+        assert!(lengths.iter().all(|&l| (1..=15).contains(&l)));
+        let kraft: f64 = lengths.iter().map(|&l| 0.5f64.powi(i32::from(l))).sum();
+        assert_eq!(kraft, 1.0);
+
+        assert_eq!(huffman_lengths(&[0, 7, 0], 7), [1, 1, 0]);
+        assert_eq!(huffman_lengths(&[0, 0, 0], 7), [1, 1, 0]);
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+/// 按修改时间失效的内存文件缓存。
+///
+/// 每次读取仍会查询文件的元数据；修改时间或长度与缓存不同时重新读入。不超过
+/// [`max_file_size`](FileCache::max_file_size) 的文件才会被缓存，总量超过容量时
+/// 淘汰最久没有用到的文件。
+pub struct FileCache {
+    capacity: u64,
+    max_file_size: u64,
+    state: Mutex<State>,
+}
+
+#[derive(Default)]
+struct State {
+    entries: HashMap<PathBuf, Entry>,
+    size: u64,
+    /// 每次命中加一，用来找出最久没有用到的文件。
+    clock: u64,
+}
+
+struct Entry {
+    bytes: Arc<[u8]>,
+    modified: SystemTime,
+    used: u64,
+}
+
+/// 一个已打开的文件：内容要么在缓存里，要么还在磁盘上。
+pub struct CachedFile {
+    pub len: u64,
+    pub modified: SystemTime,
+    source: Source,
+}
+
+enum Source {
+    Memory(Arc<[u8]>),
+    Disk(File),
+}
+
+impl FileCache {
+    /// 创建最多缓存 `capacity` 字节的缓存；容量为 0 时什么也不缓存。
+    pub fn new(capacity: u64) -> FileCache {
+        FileCache {
+            capacity,
+            max_file_size: (capacity / 8).min(1024 * 1024),
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    /// 设置可以缓存的单个文件的最大长度，默认为容量的八分之一与 1 MiB 中较小的一个。
+    pub fn max_file_size(mut self, bytes: u64) -> FileCache {
+        self.max_file_size = bytes.min(self.capacity);
+        self
+    }
+
+    /// 当前缓存的文件数与总字节数。
+    pub fn usage(&self) -> (usize, u64) {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        (state.entries.len(), state.size)
+    }
+
+    /// 打开 `path` 处的普通文件，缓存仍然有效时不读磁盘。
+    pub fn open(&self, path: &Path) -> io::Result<CachedFile> {
+        let metadata = fs::metadata(path)?;
+        if !metadata.is_file() {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let modified = metadata.modified()?;
+
+        if let Some(bytes) = self.lookup(path, modified, metadata.len()) {
+            return Ok(CachedFile {
+                len: bytes.len() as u64,
+                modified,
+                source: Source::Memory(bytes),
+            });
+        }
+
+        let mut file = File::open(path)?;
+        // 以打开后的元数据为准，文件可能在两次查询之间被替换。
+        let metadata = file.metadata()?;
+        if !metadata.is_file() {
+            return Err(io::Error::from(io::ErrorKind::NotFound));
+        }
+        let (len, modified) = (metadata.len(), metadata.modified()?);
+
+        if len > self.max_file_size {
+            return Ok(CachedFile {
+                len,
+                modified,
+                source: Source::Disk(file),
+            });
+        }
+
+        let mut bytes = Vec::with_capacity(len as usize);
+        file.read_to_end(&mut bytes)?;
+        let bytes: Arc<[u8]> = bytes.into();
+        self.insert(path, Arc::clone(&bytes), modified);
+
+        Ok(CachedFile {
+            len: bytes.len() as u64,
+            modified,
+            source: Source::Memory(bytes),
+        })
+    }
+
+    fn lookup(&self, path: &Path, modified: SystemTime, len: u64) -> Option<Arc<[u8]>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+
+        let entry = state.entries.get_mut(path)?;
+        if entry.modified == modified && entry.bytes.len() as u64 == len {
+            entry.used = clock;
+            return Some(Arc::clone(&entry.bytes));
+        }
+
+        // 文件变了，丢掉旧内容。
+        let stale = state.entries.remove(path)?;
+        state.size -= stale.bytes.len() as u64;
+        None
+    }
+
+    fn insert(&self, path: &Path, bytes: Arc<[u8]>, modified: SystemTime) {
+        let len = bytes.len() as u64;
+        if len > self.max_file_size {
+            return;
+        }
+
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(old) = state.entries.remove(path) {
+            state.size -= old.bytes.len() as u64;
+        }
+
+        while state.size + len > self.capacity {
+            let Some(oldest) = state
+                .entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.used)
+                .map(|(path, _)| path.clone())
+            else {
+                break;
+            };
+            let evicted = state.entries.remove(&oldest).unwrap();
+            state.size -= evicted.bytes.len() as u64;
+        }
+
+        state.size += len;
+        let used = state.clock;
+        state.entries.insert(
+            path.to_path_buf(),
+            Entry {
+                bytes,
+                modified,
+                used,
+            },
+        );
+    }
+}
+
+impl CachedFile {
+    /// 读取 `start` 起的 `len` 个字节的读取器。
+    pub fn reader(self, start: u64, len: u64) -> io::Result<Box<dyn Read + Send>> {
+        match self.source {
+            Source::Memory(bytes) => {
+                let mut cursor = io::Cursor::new(bytes);
+                cursor.set_position(start);
+                Ok(Box::new(cursor.take(len)))
+            }
+            Source::Disk(mut file) => {
+                file.seek(SeekFrom::Start(start))?;
+                Ok(Box::new(file.take(len)))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, process, time::Duration};
+
+    fn read(file: CachedFile) -> Vec<u8> {
+        let len = file.len;
+        let mut out = Vec::new();
+        file.reader(0, len).unwrap().read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn caches_until_the_file_changes() {
+        let dir = env::temp_dir().join(format!("hello-cache-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (a, b, big) = (dir.join("a.txt"), dir.join("b.txt"), dir.join("big.txt"));
+        fs::write(&a, "first").unwrap();
+        fs::write(&b, "bbbbbbbbbb").unwrap();
+        fs::write(&big, vec![b'x'; 64]).unwrap();
+
+        let cache = FileCache::new(12).max_file_size(12);
+        assert_eq!(read(cache.open(&a).unwrap()), b"first");
+        assert_eq!(cache.usage(), (1, 5));
+
+        // 长度相同、修改时间不同的内容也会被重新读入。
+        fs::write(&a, "again").unwrap();
+        let later = SystemTime::now() + Duration::from_secs(10);
+        File::options()
+            .write(true)
+            .open(&a)
+            .unwrap()
+            .set_modified(later)
+            .unwrap();
+        assert_eq!(read(cache.open(&a).unwrap()), b"again");
+        assert_eq!(cache.usage(), (1, 5));
+
+        // 放不下 b 时淘汰 a；太大的文件直接从磁盘读取。
+        assert_eq!(read(cache.open(&b).unwrap()), b"bbbbbbbbbb");
+        assert_eq!(cache.usage(), (1, 10));
+        assert_eq!(read(cache.open(&big).unwrap()), vec![b'x'; 64]);
+        assert_eq!(cache.usage(), (1, 10));
+
+        let file = cache.open(&b).unwrap();
+        let mut tail = String::new();
+        file.reader(7, 3)
+            .unwrap()
+            .read_to_string(&mut tail)
+            .unwrap();
+        assert_eq!(tail, "bbb");
+
+        assert_eq!(
+            cache.open(&dir).err().map(|e| e.kind()),
+            Some(io::ErrorKind::NotFound)
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,94 @@
+use std::fmt;
+
+/// HTTP 头部的有序集合。
+///
+/// 头部名字的比较不区分大小写，同名头部会按出现顺序全部保留。
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    pub fn new() -> Headers {
+        Headers { entries: Vec::new() }
+    }
+
+    /// 返回名为 `name` 的第一个头部的值。
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// 按出现顺序返回名为 `name` 的全部头部的值。
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// 追加一个头部，不影响已有的同名头部。
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// 设置一个头部，先移除全部同名头部。
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// 移除全部同名头部，返回第一个被移除的值。
+    pub fn remove(&mut self, name: &str) -> Option<String> {
+        let mut removed = None;
+
+        self.entries.retain_mut(|(n, v)| {
+            if n.eq_ignore_ascii_case(name) {
+                if removed.is_none() {
+                    removed = Some(std::mem::take(v));
+                }
+                false
+            } else {
+                true
+            }
+        });
+
+        removed
+    }
+
+    /// 检查某个以逗号分隔的头部（比如 `Connection`）中是否含有 `token`。
+    pub fn has_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries.iter().map(|(n, v)| (n.as_str(), v.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Headers {
+    /// 以 `Name: value\r\n` 的线路格式输出全部头部。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.entries {
+            write!(f, "{name}: {value}\r\n")?;
+        }
+        Ok(())
+    }
+}
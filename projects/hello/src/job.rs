@@ -0,0 +1,198 @@
+use std::{
+    any::Any,
+    error::Error,
+    fmt,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+};
+
+use crate::pool::Job;
+
+enum State<T> {
+    /// 已入队，尚未开始执行。
+    Pending,
+    Running,
+    /// 已结束；结果被 `join` 取走后为 `None`。
+    Finished(Option<thread::Result<T>>),
+    /// 被取消，或者作业在执行前就被丢弃了。
+    Cancelled,
+}
+
+struct Slot<T> {
+    state: Mutex<State<T>>,
+    changed: Condvar,
+}
+
+impl<T> Slot<T> {
+    fn lock(&self) -> MutexGuard<'_, State<T>> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set(&self, state: State<T>) {
+        *self.lock() = state;
+        self.changed.notify_all();
+    }
+}
+
+/// [`crate::ThreadPool::spawn`] 所提交作业的句柄，用于取回返回值或取消作业。
+pub struct JobHandle<T> {
+    slot: Arc<Slot<T>>,
+}
+
+impl<T> JobHandle<T> {
+    /// 阻塞到作业结束，返回其返回值。
+    ///
+    /// 作业 panic 时返回 [`JoinError::Panicked`] 及其载荷；作业在开始前被取消时返回
+    /// [`JoinError::Cancelled`]。
+    pub fn join(self) -> Result<T, JoinError> {
+        let mut state = self.slot.lock();
+
+        loop {
+            match &mut *state {
+                State::Pending | State::Running => {
+                    state = self.slot.changed.wait(state).unwrap_or_else(|e| e.into_inner());
+                }
+                State::Finished(result) => {
+                    return match result.take() {
+                        Some(Ok(value)) => Ok(value),
+                        Some(Err(payload)) => Err(JoinError::Panicked(payload)),
+                        None => unreachable!("JobHandle::join 消耗了句柄，结果只会被取走一次"),
+                    };
+                }
+                State::Cancelled => return Err(JoinError::Cancelled),
+            }
+        }
+    }
+
+    /// 取消尚未开始执行的作业，成功时返回 `true`。
+    ///
+    /// 已经开始或已经结束的作业无法取消。被取消的作业仍占着队列中的位置，直到某个
+    /// worker 取出并跳过它。
+    pub fn cancel(&self) -> bool {
+        let mut state = self.slot.lock();
+
+        if matches!(*state, State::Pending) {
+            *state = State::Cancelled;
+            drop(state);
+            self.slot.changed.notify_all();
+            true
+        } else {
+            false
+        }
+    }
+
+    /// 作业已经结束（包括 panic 与被取消）时返回 `true`。
+    pub fn is_finished(&self) -> bool {
+        matches!(*self.slot.lock(), State::Finished(_) | State::Cancelled)
+    }
+}
+
+impl<T> fmt::Debug for JobHandle<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match *self.slot.lock() {
+            State::Pending => "Pending",
+            State::Running => "Running",
+            State::Finished(_) => "Finished",
+            State::Cancelled => "Cancelled",
+        };
+        f.debug_struct("JobHandle").field("state", &state).finish()
+    }
+}
+
+/// [`JobHandle::join`] 没能取回返回值的原因。
+pub enum JoinError {
+    /// 作业在开始前被取消，或者在执行前就被丢弃了。
+    Cancelled,
+    /// 作业 panic 了，附带 `panic!` 的载荷。
+    Panicked(Box<dyn Any + Send + 'static>),
+}
+
+impl fmt::Debug for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => f.write_str("Cancelled"),
+            JoinError::Panicked(_) => f.write_str("Panicked(..)"),
+        }
+    }
+}
+
+impl fmt::Display for JoinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JoinError::Cancelled => write!(f, "作业已被取消"),
+            JoinError::Panicked(_) => write!(f, "作业 panic 了"),
+        }
+    }
+}
+
+impl Error for JoinError {}
+
+/// 作业闭包被丢弃而没有运行时（比如线程池在执行前被强行拆除），把句柄标记为已取消，
+/// 以免 `join` 永远等待。
+struct Completion<T> {
+    slot: Arc<Slot<T>>,
+    finished: bool,
+}
+
+impl<T> Completion<T> {
+    /// 作业已经运行（或者因被取消而跳过）；`state` 为需要写入句柄的最终状态。
+    fn complete(mut self, state: Option<State<T>>) {
+        self.finished = true;
+        if let Some(state) = state {
+            self.slot.set(state);
+        }
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.slot.set(State::Cancelled);
+        }
+    }
+}
+
+/// 把 `f` 包装为一个线程池作业与对应的句柄。
+///
+/// `on_panic` 在 `f` panic 时、把载荷交给句柄之前调用。
+pub(crate) fn wrap<F, T>(f: F, on_panic: impl FnOnce() + Send + 'static) -> (Job, JobHandle<T>)
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let slot = Arc::new(Slot {
+        state: Mutex::new(State::Pending),
+        changed: Condvar::new(),
+    });
+
+    let completion = Completion {
+        slot: Arc::clone(&slot),
+        finished: false,
+    };
+
+    let job = Box::new(move || {
+        let start = {
+            let mut state = completion.slot.lock();
+            let pending = matches!(*state, State::Pending);
+            if pending {
+                *state = State::Running;
+            }
+            pending
+        };
+
+        if !start {
+            completion.complete(None);
+            return;
+        }
+
+        let result = panic::catch_unwind(AssertUnwindSafe(f));
+        if result.is_err() {
+            on_panic();
+        }
+
+        completion.complete(Some(State::Finished(Some(result))));
+    });
+
+    (job, JobHandle { slot })
+}
@@ -1,89 +1,60 @@
-use std::{
-    sync::{mpsc, Arc, Mutex},
-    thread,
+pub mod access_log;
+mod async_server;
+pub mod base64;
+pub mod chunked;
+pub mod client;
+pub mod compression;
+pub mod config;
+pub mod connection;
+pub mod date;
+pub mod deflate;
+pub mod file_cache;
+pub mod headers;
+mod job;
+pub mod log;
+pub mod metrics;
+pub mod middleware;
+pub mod mime;
+pub mod proxy;
+mod pool;
+mod queue;
+pub mod request;
+pub mod response;
+pub mod router;
+pub mod server;
+pub mod sha1;
+pub mod static_files;
+pub mod status;
+#[cfg(feature = "tls")]
+pub mod tls;
+pub mod upgrade;
+pub mod virtual_host;
+pub mod websocket;
+
+pub use access_log::{AccessLog, LogFormat};
+pub use client::{BodyReader, Client, ClientError, ClientResponse};
+pub use compression::Compression;
+pub use config::{Config, ConfigError};
+pub use connection::{serve_connection, ConnectionOptions, Transport};
+pub use file_cache::FileCache;
+pub use headers::Headers;
+pub use job::{JobHandle, JoinError};
+pub use log::{Level, Logger, NullLogger, WriterLogger};
+pub use metrics::Metrics;
+pub use middleware::{BasicAuth, Cors, Middleware, RequestId, Stack, Timing};
+pub use proxy::Proxy;
+pub use pool::{
+    ExecuteError, JobPanic, PoolCreationError, PoolMonitor, PoolStats, ShutdownReport, ThreadPool,
+    ThreadPoolBuilder,
 };
-
-pub struct ThreadPool {
-    workers: Vec<Worker>,
-    sender: Option<mpsc::Sender<Job>>,
-}
-
-type Job = Box<dyn FnOnce() + Send + 'static>;
-
-impl ThreadPool {
-    /// 创建一个新的 ThreadPool。
-    ///
-    /// 其中 size 为线程池中线程的数量。
-    ///
-    /// # Panics
-    ///
-    /// `new` 函数将在 size 为零时终止运行。
-    pub fn new(size: usize) -> ThreadPool {
-        assert! (size > 0);
-
-        let (sender, receiver) = mpsc::channel();
-
-        let receiver = Arc::new(Mutex::new(receiver));
-
-        let mut workers = Vec::with_capacity(size);
-
-        for id in 0..size {
-            workers.push(Worker::new(id, Arc::clone(&receiver)));
-        }
-
-        ThreadPool {
-            workers,
-            sender: Some(sender),
-        }
-    }
-
-    pub fn execute<F>(&self, f: F)
-    where
-        F: FnOnce() + Send + 'static,
-    {
-        let job = Box::new(f);
-
-        self.sender.as_ref().unwrap().send(job).unwrap();
-    }
-}
-
-impl Drop for ThreadPool {
-    fn drop(&mut self) {
-        drop(self.sender.take());
-
-        for worker in &mut self.workers.drain(..) {
-            println! ("关闭 worker {}", worker.id);
-
-            worker.thread.join().unwrap();
-        }
-    }
-}
-
-struct Worker {
-    id: usize,
-    thread: thread::JoinHandle<()>,
-}
-
-impl Worker {
-    fn new(id: usize, receiver: Arc<Mutex<mpsc::Receiver<Job>>>) -> Worker {
-        let thread = thread::spawn(move || {
-            loop {
-                let message = receiver.lock().unwrap().recv();
-
-                match message {
-                    Ok(job) => {
-                        println!("Worker {id} 得到作业；执行中。");
-
-                        job();
-                    }
-                    Err(_) => {
-                        println!("Worker {id} 已断开；关闭中。");
-                        break;
-                    }
-                }
-            }
-        });
-
-        Worker { id, thread }
-    }
-}
+pub use request::{Limits, Method, ParseError, Request, RequestBody, Version};
+pub use response::{Body, Response};
+pub use router::{Handler, Router};
+pub use server::{Listener, Server, ShutdownHandle};
+pub use static_files::StaticFiles;
+pub use status::StatusCode;
+#[cfg(feature = "tls")]
+pub use tls::{Tls, TlsError};
+pub use upgrade::Upgraded;
+pub use virtual_host::VirtualHosts;
+pub use websocket::{WebSocket, WebSocketError};
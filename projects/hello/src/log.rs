@@ -0,0 +1,262 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, Write},
+    str::FromStr,
+    sync::Mutex,
+};
+
+use crate::date::DateTime;
+
+/// 日志级别，越靠前越严重。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "ERROR",
+            Level::Warn => "WARN",
+            Level::Info => "INFO",
+            Level::Debug => "DEBUG",
+            Level::Trace => "TRACE",
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(self.as_str())
+    }
+}
+
+/// 无法识别的日志级别名称。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ParseLevelError(pub String);
+
+impl fmt::Display for ParseLevelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "未知的日志级别：{:?}", self.0)
+    }
+}
+
+impl Error for ParseLevelError {}
+
+impl FromStr for Level {
+    type Err = ParseLevelError;
+
+    /// 不区分大小写地解析 `error`、`warn`、`info`、`debug`、`trace`。
+    fn from_str(s: &str) -> Result<Level, ParseLevelError> {
+        [
+            Level::Error,
+            Level::Warn,
+            Level::Info,
+            Level::Debug,
+            Level::Trace,
+        ]
+        .into_iter()
+        .find(|level| level.as_str().eq_ignore_ascii_case(s))
+        .ok_or_else(|| ParseLevelError(s.to_string()))
+    }
+}
+
+/// 一个结构化字段：名称与值。
+pub type Field<'a> = (&'a str, &'a dyn fmt::Display);
+
+/// 一条日志记录。
+pub struct Record<'a> {
+    pub level: Level,
+    /// 产生记录的组件，比如 `"pool"` 或 `"server"`。
+    pub target: &'a str,
+    pub message: &'a str,
+    pub fields: &'a [Field<'a>],
+}
+
+/// 可替换的日志后端。
+pub trait Logger: Send + Sync {
+    /// 该级别的记录是否需要写出；返回 `false` 时调用方不会构造记录。
+    fn enabled(&self, level: Level) -> bool {
+        let _ = level;
+        true
+    }
+
+    fn log(&self, record: &Record<'_>);
+}
+
+impl dyn Logger {
+    /// 级别已启用时构造一条记录并交给 [`Logger::log`]。
+    pub fn record(&self, level: Level, target: &str, message: &str, fields: &[Field<'_>]) {
+        if self.enabled(level) {
+            self.log(&Record {
+                level,
+                target,
+                message,
+                fields,
+            });
+        }
+    }
+
+    pub fn error(&self, target: &str, message: &str, fields: &[Field<'_>]) {
+        self.record(Level::Error, target, message, fields);
+    }
+
+    pub fn warn(&self, target: &str, message: &str, fields: &[Field<'_>]) {
+        self.record(Level::Warn, target, message, fields);
+    }
+
+    pub fn info(&self, target: &str, message: &str, fields: &[Field<'_>]) {
+        self.record(Level::Info, target, message, fields);
+    }
+
+    pub fn debug(&self, target: &str, message: &str, fields: &[Field<'_>]) {
+        self.record(Level::Debug, target, message, fields);
+    }
+}
+
+/// 丢弃所有记录。
+#[derive(Clone, Copy, Debug, Default)]
+pub struct NullLogger;
+
+impl Logger for NullLogger {
+    fn enabled(&self, _level: Level) -> bool {
+        false
+    }
+
+    fn log(&self, _record: &Record<'_>) {}
+}
+
+/// 把不低于某个级别的记录逐行写入一个 [`Write`]，格式为
+/// `时间 级别 组件: 消息 名称=值 ...`。
+pub struct WriterLogger {
+    level: Level,
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl WriterLogger {
+    pub fn new(writer: impl Write + Send + 'static, level: Level) -> WriterLogger {
+        WriterLogger {
+            level,
+            out: Mutex::new(Box::new(writer)),
+        }
+    }
+
+    pub fn stderr(level: Level) -> WriterLogger {
+        WriterLogger::new(io::stderr(), level)
+    }
+
+    /// 把一条记录格式化为一行，不含换行符。
+    pub fn format(record: &Record<'_>) -> String {
+        let mut line = format!(
+            "{} {:<5} {}: {}",
+            DateTime::now().rfc3339(),
+            record.level,
+            record.target,
+            record.message
+        );
+        for (name, value) in record.fields {
+            line.push(' ');
+            line.push_str(name);
+            line.push('=');
+            push_value(&mut line, &value.to_string());
+        }
+        line
+    }
+}
+
+impl fmt::Debug for WriterLogger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriterLogger")
+            .field("level", &self.level)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Logger for WriterLogger {
+    fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        let mut line = WriterLogger::format(record);
+        line.push('\n');
+
+        let mut out = self.out.lock().unwrap_or_else(|e| e.into_inner());
+        // 日志写不出去时没有更好的地方报告，只能忽略。
+        let _ = out.write_all(line.as_bytes()).and_then(|()| out.flush());
+    }
+}
+
+/// 含有空白、引号或等号的值加上引号，以便按空格切分字段。
+fn push_value(line: &mut String, value: &str) {
+    let plain = !value.is_empty()
+        && !value
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '=');
+
+    if plain {
+        line.push_str(value);
+    } else {
+        line.push_str(&format!("{value:?}"));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    /// 可以在写入后取回内容的缓冲区。
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedBuf {
+        fn contents(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writes_levels_and_fields() {
+        let buf = SharedBuf::default();
+        let logger: Arc<dyn Logger> = Arc::new(WriterLogger::new(buf.clone(), Level::Info));
+
+        logger.info(
+            "pool",
+            "worker 退出",
+            &[("worker", &3), ("reason", &"空闲 过久")],
+        );
+        logger.debug("pool", "不该出现", &[]);
+        logger.error("server", "出错", &[("error", &"")]);
+
+        let out = buf.contents();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].ends_with(" INFO  pool: worker 退出 worker=3 reason=\"空闲 过久\""));
+        assert!(lines[1].ends_with(" ERROR server: 出错 error=\"\""));
+    }
+
+    #[test]
+    fn parses_levels() {
+        assert_eq!("warn".parse(), Ok(Level::Warn));
+        assert_eq!("DEBUG".parse(), Ok(Level::Debug));
+        assert!("loud".parse::<Level>().is_err());
+        assert!(Level::Error < Level::Info);
+    }
+}
@@ -1,47 +1,226 @@
 use std::{
-    fs,
-    io::{prelude::*, BufReader},
-    net::{TcpListener, TcpStream},
+    env,
+    net::TcpListener,
+    process,
+    sync::Arc,
     thread,
     time::Duration,
 };
 
-use hello::ThreadPool;
+use hello::{
+    config::{self, AccessLogTarget, ServerMode},
+    websocket::{self, Message, WebSocket},
+    AccessLog, Compression, Config, ConnectionOptions, FileCache, Handler, Limits, Listener, Logger,
+    Metrics, Proxy, RequestId, Router, Server, Stack, StaticFiles, ThreadPool, Timing,
+    VirtualHosts, WriterLogger,
+};
+
+#[cfg(feature = "tls")]
+use hello::Tls;
 
 fn main() {
-    let listener = TcpListener::bind("127.0.0.1:7878").unwrap();
-    let pool = ThreadPool::new(4);
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        print!("{}", config::usage());
+        return;
+    }
+
+    let config = match Config::from_args(args) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("{e}\n\n{}", config::usage());
+            process::exit(2);
+        }
+    };
 
-    for stream in listener.incoming().take(2) {
-        let stream = stream.unwrap();
+    let logger: Arc<dyn Logger> = Arc::new(WriterLogger::stderr(config.log_level));
+    let fail = |message: &str, error: &dyn std::fmt::Display| -> ! {
+        logger.error("main", message, &[("error", error)]);
+        process::exit(1);
+    };
 
-        pool.execute(|| {
-            handle_connection(stream);
-        });
+    let mut listeners = Vec::new();
+    for addr in &config.listen {
+        match TcpListener::bind(addr) {
+            Ok(listener) => listeners.push(Listener::new(listener)),
+            Err(e) => fail(&format!("无法监听 {addr}"), &e),
+        }
+    }
+    #[cfg(feature = "tls")]
+    if let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) {
+        let tls = Tls::from_pem_files(cert, key).unwrap_or_else(|e| fail("无法加载证书", &e));
+        for addr in &config.tls_listen {
+            match TcpListener::bind(addr) {
+                Ok(listener) => listeners.push(Listener::tls(listener, tls.clone())),
+                Err(e) => fail(&format!("无法监听 {addr}"), &e),
+            }
+        }
     }
+    let bound: Vec<_> = listeners
+        .iter()
+        .filter_map(|listener| {
+            let scheme = if listener.is_tls() { "https" } else { "http" };
+            Some((listener.local_addr().ok()?, scheme))
+        })
+        .collect();
 
-    println! ("关闭。");
-}
+    // /sleep 这样的慢请求会占住 worker；忙时扩容到 max_threads，空闲后缩回 threads。
+    let pool = ThreadPool::builder(config.threads)
+        .max_threads(config.max_threads)
+        .queue_capacity(config.queue_capacity)
+        .logger(Arc::clone(&logger))
+        .build()
+        .unwrap_or_else(|e| fail("无法创建线程池", &e));
 
-fn handle_connection(mut stream: TcpStream) {
-    let buf_reader = BufReader::new(&stream);
-    let request_line = buf_reader.lines().next().unwrap().unwrap();
+    let options = ConnectionOptions {
+        idle_timeout: Some(config.idle_timeout),
+        header_timeout: Some(config.header_timeout),
+        body_timeout: Some(config.body_timeout),
+        write_timeout: Some(config.write_timeout),
+        limits: Limits {
+            max_header_size: Some(config.max_header_size),
+            max_body_size: Some(config.max_request_size),
+        },
+        ..ConnectionOptions::default()
+    };
 
-    let (status_line, filename) = match &request_line[..] {
-        "GET / HTTP/1.1" => ( "HTTP/1.1 200 OK", "hello.html"),
-        "GET /sleep HTTP/1.1" => {
-            thread::sleep(Duration::from_secs(5));
-            ("HTTP/1.1 200 0K", "hello.html")
+    let metrics = Metrics::new(pool.monitor());
+    let mut listeners = listeners.into_iter();
+    let mut server = Server::new(listeners.next().unwrap(), pool, app(&config, metrics, &logger))
+        .options(options)
+        .drain_timeout(config.drain_timeout)
+        .queue_timeout(config.queue_timeout)
+        .logger(Arc::clone(&logger));
+    for listener in listeners {
+        server = server.listener(listener);
+    }
+
+    let access_log = match &config.access_log {
+        AccessLogTarget::Off => None,
+        AccessLogTarget::Stderr => Some(AccessLog::stderr(config.access_log_format)),
+        AccessLogTarget::File(path) => match AccessLog::file(path, config.access_log_format) {
+            Ok(log) => Some(log),
+            Err(e) => fail(&format!("无法打开访问日志 {}", path.display()), &e),
+        },
+    };
+    if let Some(log) = access_log {
+        server = server.access_log(log);
+    }
+    let shutdown = server.shutdown_handle();
+
+    let mode = match config.mode {
+        ServerMode::Threaded => "threaded",
+        ServerMode::Async => "async",
+    };
+    for (addr, scheme) in &bound {
+        logger.info(
+            "main",
+            "开始监听",
+            &[("addr", addr), ("scheme", scheme), ("mode", &mode)],
+        );
+    }
+
+    // 收到 SIGINT（Ctrl-C）或 SIGTERM 时开始优雅关闭；再次收到则立即退出。
+    let signal_logger = Arc::clone(&logger);
+    ctrlc::set_handler(move || {
+        if shutdown.is_shutdown() {
+            process::exit(130);
         }
-        _ => ("HTTP/1.1 404 NOT FOUND", "404.html"),
+        signal_logger.info("main", "收到关闭信号，正在等待进行中的请求完成", &[]);
+        shutdown.shutdown();
+    })
+    .expect("无法注册信号处理器");
+
+    let report = match config.mode {
+        ServerMode::Threaded => server.run(),
+        ServerMode::Async => server.run_async(),
     };
+    match report {
+        Ok(report) if report.is_clean() => {
+            logger.info(
+                "main",
+                "关闭，全部 worker 已结束",
+                &[("workers", &report.joined.len()), ("elapsed", &format!("{:?}", report.elapsed))],
+            );
+        }
+        Ok(report) => {
+            logger.warn(
+                "main",
+                "关闭，仍有 worker 未结束",
+                &[
+                    ("unfinished", &format!("{:?}", report.unfinished)),
+                    ("elapsed", &format!("{:?}", report.elapsed)),
+                ],
+            );
+        }
+        Err(e) => {
+            fail("服务器出错", &e);
+        }
+    }
+}
 
+fn app(config: &Config, metrics: Metrics, logger: &Arc<dyn Logger>) -> Stack {
+    let mut files = StaticFiles::new(&config.root)
+        .cache(FileCache::new(config.file_cache_size))
+        .logger(Arc::clone(logger));
+    if let Some(page) = &config.not_found_page {
+        files = files.not_found_page(page);
+    }
+    let files = Arc::new(files);
 
-    let contents = fs::read_to_string(filename).unwrap();
-    let length = contents.len();
+    let home = Arc::clone(&files);
+    let sleep = Arc::clone(&files);
 
-    let response =
-        format!("{status_line}\r\nContent-Length: {length}\r\n\r\n{contents}");
+    let router = Router::new()
+        .get("/", move |request| home.serve_request(&request, "hello.html"))
+        .get("/sleep", move |request| {
+            thread::sleep(Duration::from_secs(5));
+            sleep.serve_request(&request, "hello.html")
+        })
+        .get("/metrics", metrics)
+        .get("/ws", |request| websocket::upgrade(&request, echo))
+        .not_found(move |request| files.handle(request));
 
-    stream.write_all(response.as_bytes()).unwrap();
+    // 其它站点只提供各自根目录下的静态文件，文件缓存也各自独立。
+    let mut hosts = VirtualHosts::new(router);
+    for host in &config.hosts {
+        let mut files = StaticFiles::new(&host.root)
+            .cache(FileCache::new(config.file_cache_size))
+            .logger(Arc::clone(logger));
+        if let Some(page) = &host.not_found_page {
+            files = files.not_found_page(page);
+        }
+        let files = Arc::new(files);
+        for name in &host.names {
+            let files = Arc::clone(&files);
+            hosts = hosts.host(name, move |request| files.handle(request));
+        }
+    }
+
+    // 反向代理在选择站点之前，对全部主机名生效。
+    let mut stack = Stack::new(hosts);
+    for proxy in &config.proxies {
+        stack = stack.wrap(
+            Proxy::new(&proxy.prefix, proxy.upstreams.iter().copied())
+                .strip_prefix(proxy.strip_prefix)
+                .preserve_host(proxy.preserve_host)
+                .logger(Arc::clone(logger)),
+        );
+    }
+
+    stack
+        .wrap(Compression::new())
+        .wrap(Timing::new().logger(Arc::clone(logger)))
+        .wrap(RequestId::new())
+}
+
+/// 把收到的文本和二进制消息原样发回去，直到对端关闭连接。
+fn echo(mut socket: WebSocket) {
+    while let Ok(message) = socket.recv() {
+        if let Message::Text(_) | Message::Binary(_) = message
+            && socket.send(message).is_err()
+        {
+            break;
+        }
+    }
 }
@@ -0,0 +1,238 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{
+    pool::{PoolMonitor, PoolStats},
+    request::Request,
+    response::Response,
+    router::Handler,
+    status::StatusCode,
+};
+
+/// 作业延迟直方图各个桶的上限（不含 `+Inf`）。
+pub const LATENCY_BUCKETS: [Duration; 11] = [
+    Duration::from_micros(100),
+    Duration::from_micros(500),
+    Duration::from_millis(1),
+    Duration::from_millis(5),
+    Duration::from_millis(10),
+    Duration::from_millis(50),
+    Duration::from_millis(100),
+    Duration::from_millis(500),
+    Duration::from_secs(1),
+    Duration::from_secs(5),
+    Duration::from_secs(10),
+];
+
+/// 可以被多个线程同时记录的延迟直方图。
+pub(crate) struct Recorder {
+    /// 每个桶（最后一个为 `+Inf`）中的样本数，不累计。
+    counts: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    sum_nanos: AtomicU64,
+}
+
+impl Recorder {
+    pub(crate) fn new() -> Recorder {
+        Recorder {
+            counts: std::array::from_fn(|_| AtomicU64::new(0)),
+            sum_nanos: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn record(&self, latency: Duration) {
+        let i = LATENCY_BUCKETS.partition_point(|&bound| bound < latency);
+        self.counts[i].fetch_add(1, Ordering::Relaxed);
+
+        let nanos = latency.as_nanos().try_into().unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> Histogram {
+        let mut total = 0;
+        let buckets = LATENCY_BUCKETS
+            .iter()
+            .zip(&self.counts)
+            .map(|(&bound, count)| {
+                total += count.load(Ordering::Relaxed);
+                (bound, total)
+            })
+            .collect();
+        let count = total + self.counts[LATENCY_BUCKETS.len()].load(Ordering::Relaxed);
+
+        Histogram {
+            buckets,
+            count,
+            sum: Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+/// 某一时刻的延迟直方图。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Histogram {
+    /// `(上限, 不超过该上限的样本数)`，与 Prometheus 一样是累计的。
+    pub buckets: Vec<(Duration, u64)>,
+    /// 样本总数，包括超过最大上限的样本。
+    pub count: u64,
+    /// 所有样本之和。
+    pub sum: Duration,
+}
+
+impl Histogram {
+    /// 样本的平均值；没有样本时返回 `None`。
+    pub fn mean(&self) -> Option<Duration> {
+        let count = u32::try_from(self.count).ok().filter(|&n| n > 0)?;
+        Some(self.sum / count)
+    }
+}
+
+/// 以 Prometheus 文本格式（0.0.4）输出线程池统计。
+pub fn render(stats: &PoolStats) -> String {
+    let mut out = String::new();
+
+    let gauges = [
+        ("threads", "当前的 worker 线程数。", stats.threads),
+        (
+            "busy_workers",
+            "正在执行作业的 worker 数。",
+            stats.busy_workers,
+        ),
+        ("idle_workers", "空闲的 worker 数。", stats.idle_workers),
+        ("queued_jobs", "尚未开始执行的作业数。", stats.queued_jobs),
+    ];
+    let counters = [
+        (
+            "jobs_completed_total",
+            "执行完毕的作业数。",
+            stats.completed_jobs,
+        ),
+        (
+            "jobs_panicked_total",
+            "panic 了的作业数。",
+            stats.panicked_jobs,
+        ),
+        (
+            "workers_respawned_total",
+            "重新创建的 worker 数。",
+            stats.respawned_workers,
+        ),
+    ];
+
+    for (kind, samples) in [("gauge", gauges.as_slice()), ("counter", &counters)] {
+        for &(name, help, value) in samples {
+            let name = format!("hello_pool_{name}");
+            metric(&mut out, &name, help, kind);
+            let _ = writeln!(out, "{name} {value}");
+        }
+    }
+
+    histogram(
+        &mut out,
+        "hello_pool_job_wait_seconds",
+        "作业从提交到开始执行所等待的时间。",
+        &stats.wait_time,
+    );
+    histogram(
+        &mut out,
+        "hello_pool_job_run_seconds",
+        "作业执行所用的时间。",
+        &stats.run_time,
+    );
+
+    out
+}
+
+fn metric(out: &mut String, name: &str, help: &str, kind: &str) {
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+}
+
+fn histogram(out: &mut String, name: &str, help: &str, histogram: &Histogram) {
+    metric(out, name, help, "histogram");
+    for (bound, count) in &histogram.buckets {
+        let _ = writeln!(
+            out,
+            "{name}_bucket{{le=\"{}\"}} {count}",
+            bound.as_secs_f64()
+        );
+    }
+    let _ = writeln!(out, "{name}_bucket{{le=\"+Inf\"}} {}", histogram.count);
+    let _ = writeln!(out, "{name}_sum {}", histogram.sum.as_secs_f64());
+    let _ = writeln!(out, "{name}_count {}", histogram.count);
+}
+
+/// 以 Prometheus 文本格式回应线程池统计的处理器，通常挂在 `/metrics` 上。
+#[derive(Clone, Debug)]
+pub struct Metrics {
+    monitor: PoolMonitor,
+}
+
+impl Metrics {
+    pub fn new(monitor: PoolMonitor) -> Metrics {
+        Metrics { monitor }
+    }
+}
+
+impl Handler for Metrics {
+    fn handle(&self, _request: Request) -> Response {
+        Response::new(StatusCode::Ok)
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_body(render(&self.monitor.stats()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn records_into_cumulative_buckets() {
+        let recorder = Recorder::new();
+        recorder.record(Duration::from_micros(50));
+        recorder.record(Duration::from_micros(100));
+        recorder.record(Duration::from_millis(3));
+        recorder.record(Duration::from_secs(60));
+
+        let histogram = recorder.snapshot();
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.buckets[0], (Duration::from_micros(100), 2));
+        assert_eq!(histogram.buckets[3], (Duration::from_millis(5), 3));
+        assert_eq!(histogram.buckets.last().unwrap().1, 3);
+        assert_eq!(
+            histogram.sum,
+            Duration::from_micros(3150) + Duration::from_secs(60)
+        );
+        assert_eq!(Recorder::new().snapshot().mean(), None);
+    }
+
+    #[test]
+    fn renders_prometheus_text() {
+        let recorder = Recorder::new();
+        recorder.record(Duration::from_millis(2));
+
+        let stats = PoolStats {
+            threads: 4,
+            busy_workers: 1,
+            idle_workers: 3,
+            queued_jobs: 2,
+            completed_jobs: 10,
+            panicked_jobs: 1,
+            respawned_workers: 0,
+            wait_time: recorder.snapshot(),
+            run_time: Recorder::new().snapshot(),
+        };
+        let text = render(&stats);
+
+        assert!(text.contains("# TYPE hello_pool_threads gauge\nhello_pool_threads 4\n"));
+        assert!(text.contains("hello_pool_jobs_completed_total 10\n"));
+        assert!(text.contains("# TYPE hello_pool_job_wait_seconds histogram\n"));
+        assert!(text.contains("hello_pool_job_wait_seconds_bucket{le=\"0.001\"} 0\n"));
+        assert!(text.contains("hello_pool_job_wait_seconds_bucket{le=\"0.005\"} 1\n"));
+        assert!(text.contains("hello_pool_job_wait_seconds_bucket{le=\"+Inf\"} 1\n"));
+        assert!(text.contains("hello_pool_job_wait_seconds_sum 0.002\n"));
+        assert!(text.contains("hello_pool_job_run_seconds_count 0\n"));
+    }
+}
@@ -0,0 +1,673 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    base64,
+    log::{Logger, NullLogger},
+    request::{Method, Request},
+    response::Response,
+    router::Handler,
+    status::StatusCode,
+};
+
+/// 包裹在处理器外面的一层。
+///
+/// 中间件拿到请求与内层的处理器 `next`：可以改写请求后交给 `next`，可以对 `next`
+/// 返回的响应再做处理，也可以不调用 `next` 而直接返回响应。
+///
+/// 任何 `Fn(Request, &dyn Handler) -> Response` 闭包都自动实现了该特征。
+pub trait Middleware: Send + Sync {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response;
+
+    /// 见 [`Handler::streams_body`]；默认由内层决定。
+    fn streams_body(&self, request: &Request, next: &dyn Handler) -> bool {
+        next.streams_body(request)
+    }
+}
+
+impl<F> Middleware for F
+where
+    F: Fn(Request, &dyn Handler) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        self(request, next)
+    }
+}
+
+/// 一个处理器加上包裹它的若干层中间件，本身也是处理器。
+///
+/// 后加入的中间件在外层：`Stack::new(h).wrap(a).wrap(b)` 处理请求的顺序是
+/// `b`、`a`、`h`，响应则按相反的顺序经过它们。
+pub struct Stack {
+    /// 由内向外。
+    layers: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Stack {
+    pub fn new(handler: impl Handler + 'static) -> Stack {
+        Stack {
+            layers: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// 在现有各层之外再包一层。
+    pub fn wrap(mut self, middleware: impl Middleware + 'static) -> Stack {
+        self.layers.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Stack {
+    fn handle(&self, request: Request) -> Response {
+        Next {
+            layers: &self.layers,
+            handler: &*self.handler,
+        }
+        .handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        Next {
+            layers: &self.layers,
+            handler: &*self.handler,
+        }
+        .streams_body(request)
+    }
+}
+
+/// 某一层中间件看到的内层：剩下的各层加上最终的处理器。
+struct Next<'a> {
+    layers: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Handler for Next<'_> {
+    fn handle(&self, request: Request) -> Response {
+        match self.layers.split_last() {
+            Some((outer, inner)) => outer.handle(
+                request,
+                &Next {
+                    layers: inner,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.handle(request),
+        }
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        match self.layers.split_last() {
+            Some((outer, inner)) => outer.streams_body(
+                request,
+                &Next {
+                    layers: inner,
+                    handler: self.handler,
+                },
+            ),
+            None => self.handler.streams_body(request),
+        }
+    }
+}
+
+/// 为每个请求分配一个 ID，写入请求头与响应头（默认 `X-Request-Id`）。
+///
+/// 客户端或上游代理已经带了格式合理的 ID 时沿用它，否则生成一个新的。
+pub struct RequestId {
+    header: String,
+    prefix: u64,
+    next: AtomicU64,
+}
+
+impl RequestId {
+    pub fn new() -> RequestId {
+        RequestId {
+            header: "X-Request-Id".to_string(),
+            // 每个进程一个随机前缀，重启后的 ID 不会与之前的重复。
+            prefix: RandomState::new().build_hasher().finish(),
+            next: AtomicU64::new(1),
+        }
+    }
+
+    /// 改用另一个头部传递 ID。
+    pub fn header(mut self, name: impl Into<String>) -> RequestId {
+        self.header = name.into();
+        self
+    }
+
+    fn generate(&self) -> String {
+        let n = self.next.fetch_add(1, Ordering::Relaxed);
+        format!("{:016x}-{n}", self.prefix)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> RequestId {
+        RequestId::new()
+    }
+}
+
+/// 只接受不太长、由字母数字与 `-_.:` 组成的 ID，以免把任意内容回显到响应里。
+fn is_valid_id(id: &str) -> bool {
+    (1..=128).contains(&id.len())
+        && id
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_.:".contains(&b))
+}
+
+impl Middleware for RequestId {
+    fn handle(&self, mut request: Request, next: &dyn Handler) -> Response {
+        let id = match request.header(&self.header) {
+            Some(id) if is_valid_id(id) => id.to_string(),
+            _ => self.generate(),
+        };
+        request
+            .headers_mut()
+            .insert(self.header.clone(), id.clone());
+
+        let mut response = next.handle(request);
+        response.headers_mut().insert(self.header.clone(), id);
+        response
+    }
+}
+
+/// 测量内层处理请求所花的时间，写入 `Server-Timing` 响应头，并可记一条调试日志。
+pub struct Timing {
+    logger: Arc<dyn Logger>,
+}
+
+impl Timing {
+    pub fn new() -> Timing {
+        Timing {
+            logger: Arc::new(NullLogger),
+        }
+    }
+
+    pub fn logger(mut self, logger: Arc<dyn Logger>) -> Timing {
+        self.logger = logger;
+        self
+    }
+}
+
+impl Default for Timing {
+    fn default() -> Timing {
+        Timing::new()
+    }
+}
+
+impl Middleware for Timing {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let method = request.method();
+        let path = request.path().to_string();
+        let start = Instant::now();
+
+        let mut response = next.handle(request);
+        let elapsed = start.elapsed();
+
+        response.headers_mut().append(
+            "Server-Timing",
+            format!("app;dur={:.3}", elapsed.as_secs_f64() * 1000.0),
+        );
+        self.logger.debug(
+            "timing",
+            "请求处理完毕",
+            &[
+                ("method", &method.as_str()),
+                ("path", &path),
+                ("status", &response.status().as_u16()),
+                ("elapsed", &format!("{elapsed:?}")),
+            ],
+        );
+        response
+    }
+}
+
+/// 允许哪些来源跨域访问。
+enum Origins {
+    Any,
+    List(Vec<String>),
+}
+
+/// 跨域资源共享（CORS）。
+///
+/// 不带 `Origin` 的请求与来自不被允许的来源的请求原样交给内层；预检请求
+/// （带 `Access-Control-Request-Method` 的 `OPTIONS`）在这里直接以 204 回应。
+/// 与 [`BasicAuth`] 一起使用时应包在它外面，因为浏览器的预检请求不带凭据。
+pub struct Cors {
+    origins: Origins,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    expose: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// 允许任何来源以 `GET`、`HEAD` 与 `POST` 访问。
+    pub fn new() -> Cors {
+        Cors {
+            origins: Origins::Any,
+            methods: vec![Method::Get, Method::Head, Method::Post],
+            headers: Vec::new(),
+            expose: Vec::new(),
+            credentials: false,
+            max_age: None,
+        }
+    }
+
+    /// 只允许列出的来源，比如 `https://example.com`；可多次调用。
+    pub fn allow_origin(mut self, origin: impl Into<String>) -> Cors {
+        let origin = origin.into();
+        match &mut self.origins {
+            Origins::List(list) => list.push(origin),
+            Origins::Any => self.origins = Origins::List(vec![origin]),
+        }
+        self
+    }
+
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+        self.methods = methods.into_iter().collect();
+        self
+    }
+
+    /// 预检请求中允许的请求头；不设置时允许预检请求列出的全部头部。
+    pub fn allow_headers<I, S>(mut self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.headers = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 允许页面脚本读取的响应头。
+    pub fn expose_headers<I, S>(mut self, headers: I) -> Cors
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.expose = headers.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// 允许携带 Cookie 等凭据；此时 `Access-Control-Allow-Origin` 总是回显具体的来源。
+    pub fn allow_credentials(mut self, allow: bool) -> Cors {
+        self.credentials = allow;
+        self
+    }
+
+    /// 浏览器可以缓存预检结果多久。
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows(&self, origin: &str) -> bool {
+        match &self.origins {
+            Origins::Any => true,
+            Origins::List(list) => list.iter().any(|o| o.eq_ignore_ascii_case(origin)),
+        }
+    }
+
+    /// 响应是否随 `Origin` 而不同。是的话每个响应都要带上 `Vary: Origin`，包括没有
+    /// 加上 CORS 头部的响应，否则共享缓存可能把一个来源的响应交给另一个来源。
+    fn varies_by_origin(&self) -> bool {
+        !matches!(self.origins, Origins::Any) || self.credentials
+    }
+
+    /// 加上预检请求与实际请求都需要的头部。
+    fn decorate(&self, response: &mut Response, origin: &str) {
+        let headers = response.headers_mut();
+
+        if !self.varies_by_origin() {
+            headers.insert("Access-Control-Allow-Origin", "*");
+        } else {
+            headers.insert("Access-Control-Allow-Origin", origin);
+            headers.append("Vary", "Origin");
+        }
+        if self.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+    }
+
+    fn preflight(&self, request: &Request, origin: &str) -> Response {
+        let mut response = Response::new(StatusCode::NoContent);
+        self.decorate(&mut response, origin);
+
+        let methods = self
+            .methods
+            .iter()
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ");
+        let allowed_headers = if self.headers.is_empty() {
+            request
+                .header("Access-Control-Request-Headers")
+                .unwrap_or_default()
+                .to_string()
+        } else {
+            self.headers.join(", ")
+        };
+
+        let headers = response.headers_mut();
+        headers.insert("Access-Control-Allow-Methods", methods);
+        if !allowed_headers.is_empty() {
+            headers.insert("Access-Control-Allow-Headers", allowed_headers);
+        }
+        if let Some(max_age) = self.max_age {
+            headers.insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        headers.append(
+            "Vary",
+            "Access-Control-Request-Method, Access-Control-Request-Headers",
+        );
+        response
+    }
+}
+
+impl Default for Cors {
+    fn default() -> Cors {
+        Cors::new()
+    }
+}
+
+impl Middleware for Cors {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let origin = match request.header("Origin") {
+            Some(origin) if self.allows(origin) => origin.to_string(),
+            _ => {
+                let mut response = next.handle(request);
+                if self.varies_by_origin() {
+                    response.headers_mut().append("Vary", "Origin");
+                }
+                return response;
+            }
+        };
+
+        if request.method() == Method::Options
+            && request.headers().contains("Access-Control-Request-Method")
+        {
+            return self.preflight(&request, &origin);
+        }
+
+        let mut response = next.handle(request);
+        self.decorate(&mut response, &origin);
+        if !self.expose.is_empty() {
+            response
+                .headers_mut()
+                .insert("Access-Control-Expose-Headers", self.expose.join(", "));
+        }
+        response
+    }
+}
+
+type Verify = dyn Fn(&str, &str) -> bool + Send + Sync;
+
+/// HTTP Basic 认证；没有凭据或凭据不对时以 401 回应，不调用内层。
+pub struct BasicAuth {
+    realm: String,
+    users: Vec<(String, String)>,
+    verify: Option<Box<Verify>>,
+}
+
+impl BasicAuth {
+    /// 创建一个还没有任何用户、因此拒绝一切请求的认证层。
+    pub fn new(realm: impl Into<String>) -> BasicAuth {
+        BasicAuth {
+            realm: realm.into(),
+            users: Vec::new(),
+            verify: None,
+        }
+    }
+
+    /// 允许一对用户名与密码；可多次调用。
+    pub fn user(mut self, name: impl Into<String>, password: impl Into<String>) -> BasicAuth {
+        self.users.push((name.into(), password.into()));
+        self
+    }
+
+    /// 用 `verify(用户名, 密码)` 检查 [`user`](BasicAuth::user) 以外的凭据。
+    pub fn verify<F>(mut self, verify: F) -> BasicAuth
+    where
+        F: Fn(&str, &str) -> bool + Send + Sync + 'static,
+    {
+        self.verify = Some(Box::new(verify));
+        self
+    }
+
+    /// 从请求的 `Authorization` 头部中取出 Basic 认证的用户名与密码。
+    pub fn credentials(request: &Request) -> Option<(String, String)> {
+        let value = request.header("Authorization")?.trim();
+        let (scheme, encoded) = value.split_once(' ')?;
+        if !scheme.eq_ignore_ascii_case("Basic") {
+            return None;
+        }
+
+        let decoded = String::from_utf8(base64::decode(encoded.trim())?).ok()?;
+        let (name, password) = decoded.split_once(':')?;
+        Some((name.to_string(), password.to_string()))
+    }
+
+    fn accepts(&self, name: &str, password: &str) -> bool {
+        // 逐个比较全部用户，不因为提前匹配而缩短耗时。
+        let listed = self.users.iter().fold(false, |found, (n, p)| {
+            found | (constant_time_eq(n, name) & constant_time_eq(p, password))
+        });
+        listed
+            || self
+                .verify
+                .as_ref()
+                .is_some_and(|verify| verify(name, password))
+    }
+
+    fn challenge(&self) -> Response {
+        let realm = self.realm.replace('\\', "\\\\").replace('"', "\\\"");
+        Response::plain(StatusCode::Unauthorized).with_header(
+            "WWW-Authenticate",
+            format!("Basic realm=\"{realm}\", charset=\"UTF-8\""),
+        )
+    }
+}
+
+impl Middleware for BasicAuth {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        match BasicAuth::credentials(&request) {
+            Some((name, password)) if self.accepts(&name, &password) => next.handle(request),
+            _ => self.challenge(),
+        }
+    }
+}
+
+/// 比较两个字符串，耗时只取决于长度而不取决于第一个不同的字节在哪里。
+fn constant_time_eq(a: &str, b: &str) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.bytes()
+        .zip(b.bytes())
+        .fold(0, |diff, (x, y)| diff | (x ^ y))
+        == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo(request: Request) -> Response {
+        let id = request.header("X-Request-Id").unwrap_or("-").to_string();
+        Response::text(StatusCode::Ok, format!("{} {id}", request.path()))
+    }
+
+    fn get(stack: &Stack, target: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::new(Method::Get, target).unwrap();
+        for (name, value) in headers {
+            request.headers_mut().insert(*name, *value);
+        }
+        stack.handle(request)
+    }
+
+    fn body(response: &Response) -> &str {
+        std::str::from_utf8(response.body().as_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn layers_run_outermost_first_and_can_short_circuit() {
+        let tag = |name: &'static str| {
+            move |request: Request, next: &dyn Handler| {
+                let mut response = next.handle(request);
+                response.headers_mut().append("X-Layer", name);
+                response
+            }
+        };
+        let rewrite = |mut request: Request, next: &dyn Handler| {
+            request.headers_mut().insert("X-Request-Id", "rewritten");
+            next.handle(request)
+        };
+        let block = |request: Request, next: &dyn Handler| {
+            if request.path() == "/blocked" {
+                Response::plain(StatusCode::Forbidden)
+            } else {
+                next.handle(request)
+            }
+        };
+
+        let stack = Stack::new(echo)
+            .wrap(tag("inner"))
+            .wrap(rewrite)
+            .wrap(block)
+            .wrap(tag("outer"));
+
+        let response = get(&stack, "/a", &[]);
+        assert_eq!(body(&response), "/a rewritten");
+        let layers: Vec<_> = response.headers().get_all("X-Layer").collect();
+        assert_eq!(layers, ["inner", "outer"]);
+
+        let response = get(&stack, "/blocked", &[]);
+        assert_eq!(response.status(), 403);
+        let layers: Vec<_> = response.headers().get_all("X-Layer").collect();
+        assert_eq!(layers, ["outer"]);
+    }
+
+    #[test]
+    fn request_id_is_propagated_or_generated() {
+        let stack = Stack::new(echo).wrap(RequestId::new());
+
+        let first = get(&stack, "/", &[]);
+        let second = get(&stack, "/", &[]);
+        let id = first.headers().get("X-Request-Id").unwrap();
+        assert_eq!(body(&first), format!("/ {id}"));
+        assert_ne!(Some(id), second.headers().get("X-Request-Id"));
+
+        let kept = get(&stack, "/", &[("X-Request-Id", "abc-123")]);
+        assert_eq!(kept.headers().get("X-Request-Id"), Some("abc-123"));
+
+        let replaced = get(&stack, "/", &[("X-Request-Id", "<script>")]);
+        assert_ne!(replaced.headers().get("X-Request-Id"), Some("<script>"));
+    }
+
+    #[test]
+    fn timing_adds_server_timing() {
+        let stack = Stack::new(echo).wrap(Timing::new());
+        let response = get(&stack, "/", &[]);
+        let timing = response.headers().get("Server-Timing").unwrap();
+        assert!(timing.starts_with("app;dur="), "{timing}");
+    }
+
+    #[test]
+    fn cors_answers_preflight_and_decorates_responses() {
+        let stack = Stack::new(echo).wrap(
+            Cors::new()
+                .allow_origin("https://app.example")
+                .allow_methods([Method::Get, Method::Put])
+                .allow_credentials(true)
+                .max_age(Duration::from_secs(600)),
+        );
+
+        let mut preflight = Request::new(Method::Options, "/items").unwrap();
+        let headers = preflight.headers_mut();
+        headers.insert("Origin", "https://app.example");
+        headers.insert("Access-Control-Request-Method", "PUT");
+        headers.insert("Access-Control-Request-Headers", "Content-Type");
+        let response = stack.handle(preflight);
+        let headers = response.headers();
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.example")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Methods"),
+            Some("GET, PUT")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Allow-Headers"),
+            Some("Content-Type")
+        );
+        assert_eq!(headers.get("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(
+            headers.get("Access-Control-Allow-Credentials"),
+            Some("true")
+        );
+
+        let response = get(&stack, "/items", &[("Origin", "https://app.example")]);
+        assert_eq!(body(&response), "/items -");
+        assert!(response.headers().has_token("Vary", "Origin"));
+
+        // 不被允许的来源与没有来源的请求也要带上 Vary，以免被缓存后交给别的来源。
+        for headers in [&[("Origin", "https://evil.example")][..], &[]] {
+            let response = get(&stack, "/items", headers);
+            assert!(!response.headers().contains("Access-Control-Allow-Origin"));
+            assert!(response.headers().has_token("Vary", "Origin"), "{headers:?}");
+        }
+
+        let any = Stack::new(echo).wrap(Cors::new());
+        let response = get(&any, "/", &[("Origin", "https://x.example")]);
+        assert_eq!(
+            response.headers().get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert!(!response.headers().contains("Vary"));
+        assert!(!get(&any, "/", &[])
+            .headers()
+            .contains("Access-Control-Allow-Origin"));
+    }
+
+    #[test]
+    fn basic_auth_challenges_until_credentials_match() {
+        let stack = Stack::new(echo).wrap(
+            BasicAuth::new("admin \"area\"")
+                .user("alice", "secret")
+                .verify(|name, password| name == "bob" && password == "hunter2"),
+        );
+
+        let response = get(&stack, "/", &[]);
+        assert_eq!(response.status(), 401);
+        assert_eq!(
+            response.headers().get("WWW-Authenticate"),
+            Some(r#"Basic realm="admin \"area\"", charset="UTF-8""#)
+        );
+
+        let basic = |credentials: &str| format!("Basic {}", base64::encode(credentials.as_bytes()));
+        for (credentials, status) in [
+            ("alice:secret", 200),
+            ("bob:hunter2", 200),
+            ("alice:wrong", 401),
+            ("alice", 401),
+            ("mallory:secret", 401),
+        ] {
+            let response = get(&stack, "/", &[("Authorization", &basic(credentials))]);
+            assert_eq!(response.status(), status, "{credentials}");
+        }
+
+        let response = get(&stack, "/", &[("Authorization", "Bearer abc")]);
+        assert_eq!(response.status(), 401);
+    }
+}
@@ -0,0 +1,1016 @@
+use std::{
+    any::Any,
+    error::Error,
+    fmt, io,
+    panic::{self, AssertUnwindSafe},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex, MutexGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    job::{self, JobHandle},
+    log::{Level, Logger, WriterLogger},
+    metrics::{Histogram, Recorder},
+    queue::{JobQueue, Local, Pop, Queued, Wait},
+};
+
+pub struct ThreadPool {
+    shared: Arc<Shared>,
+}
+
+pub(crate) type Job = Box<dyn FnOnce() + Send + 'static>;
+
+type PanicHandler = Arc<dyn Fn(&JobPanic<'_>) + Send + Sync>;
+
+/// 线程池与其 worker 共享的状态。
+struct Shared {
+    queue: JobQueue,
+    /// worker 死亡后，替代它的新 worker 会接管同一个位置。增减 worker 都持有这把锁。
+    workers: Mutex<Vec<Worker>>,
+    /// 当前的 worker 数，只在持有 `workers` 锁时修改。
+    threads: AtomicUsize,
+    next_id: AtomicUsize,
+    min_threads: usize,
+    max_threads: usize,
+    keep_alive: Duration,
+    busy_workers: AtomicUsize,
+    completed_jobs: AtomicUsize,
+    panicked_jobs: AtomicUsize,
+    respawned_workers: AtomicUsize,
+    wait_time: Recorder,
+    run_time: Recorder,
+    panic_handler: Option<PanicHandler>,
+    logger: Arc<dyn Logger>,
+}
+
+impl Shared {
+    fn workers(&self) -> MutexGuard<'_, Vec<Worker>> {
+        self.workers.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn stats(&self) -> PoolStats {
+        let threads = self.threads.load(Ordering::SeqCst);
+        // 两个计数不是同时读取的，忙碌数可能短暂地超过线程数。
+        let busy_workers = self.busy_workers.load(Ordering::SeqCst).min(threads);
+
+        PoolStats {
+            threads,
+            busy_workers,
+            idle_workers: threads - busy_workers,
+            queued_jobs: self.queue.len(),
+            completed_jobs: self.completed_jobs.load(Ordering::Relaxed),
+            panicked_jobs: self.panicked_jobs.load(Ordering::Relaxed),
+            respawned_workers: self.respawned_workers.load(Ordering::Relaxed),
+            wait_time: self.wait_time.snapshot(),
+            run_time: self.run_time.snapshot(),
+        }
+    }
+
+    /// 在 worker 线程上执行一个作业并记录统计，返回作业 panic 时的载荷。
+    fn run(&self, queued: Queued) -> thread::Result<()> {
+        self.wait_time.record(queued.since.elapsed());
+        self.busy_workers.fetch_add(1, Ordering::SeqCst);
+
+        let start = Instant::now();
+        let result = panic::catch_unwind(AssertUnwindSafe(queued.job));
+
+        self.run_time.record(start.elapsed());
+        self.busy_workers.fetch_sub(1, Ordering::SeqCst);
+        self.completed_jobs.fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    /// 创建一个带有新本地队列的 worker，并把它加入 `workers`。
+    fn add_worker(self: &Arc<Self>, workers: &mut Vec<Worker>) -> Result<(), PoolCreationError> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let local = self.queue.add_local();
+
+        match Worker::spawn(id, Arc::clone(&local), Arc::clone(self)) {
+            Ok(worker) => {
+                workers.push(worker);
+                self.threads.fetch_add(1, Ordering::SeqCst);
+                Ok(())
+            }
+            Err(source) => {
+                self.queue.remove_local(&local);
+                Err(PoolCreationError::Spawn { id, source })
+            }
+        }
+    }
+
+    /// 排队的作业多于空闲的 worker 时，在 `max_threads` 以内增加一个 worker。
+    fn grow(self: &Arc<Self>) {
+        let busy = |shared: &Shared| {
+            shared.queue.len() > shared.queue.sleepers()
+                && shared.threads.load(Ordering::SeqCst) < shared.max_threads
+        };
+        if !busy(self) {
+            return;
+        }
+
+        let mut workers = self.workers();
+        if self.queue.is_closed() || !busy(self) {
+            return;
+        }
+        match self.add_worker(&mut workers) {
+            Ok(()) => self.logger.debug(
+                "pool",
+                "扩容",
+                &[("threads", &self.threads.load(Ordering::SeqCst))],
+            ),
+            Err(e) => self.logger.error("pool", "扩容失败", &[("error", &e)]),
+        }
+    }
+
+    /// 空闲过久的 worker 在线程数多于 `min_threads` 时退出；返回 `true` 表示可以退出。
+    fn retire(&self, id: usize, local: &Arc<Local>) -> bool {
+        let mut workers = self.workers();
+        if self.queue.is_closed() || self.threads.load(Ordering::SeqCst) <= self.min_threads {
+            return false;
+        }
+        if !self.queue.remove_local(local) {
+            return false;
+        }
+
+        self.threads.fetch_sub(1, Ordering::SeqCst);
+        // 丢弃自己的 JoinHandle，相当于分离这个即将结束的线程。
+        workers.retain(|w| w.id != id);
+        true
+    }
+}
+
+/// 传给 panic 处理器的、某个作业 panic 时的信息。
+pub struct JobPanic<'a> {
+    /// 执行该作业的 worker 编号。
+    pub worker: usize,
+    /// `panic!` 的载荷。
+    pub payload: &'a (dyn Any + Send),
+}
+
+impl JobPanic<'_> {
+    /// 以 `&str` 或 `String` 为载荷的 panic 返回其消息。
+    pub fn message(&self) -> Option<&str> {
+        self.payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| self.payload.downcast_ref::<String>().map(String::as_str))
+    }
+}
+
+impl ThreadPool {
+    /// 创建一个新的 ThreadPool。
+    ///
+    /// 其中 size 为线程池中线程的数量。作业队列不限容量；需要限制时请使用
+    /// [`ThreadPool::builder`]。
+    ///
+    /// # Panics
+    ///
+    /// `new` 函数将在 size 为零，或无法创建线程时终止运行。需要处理这些错误时请使用
+    /// [`ThreadPool::build`]。
+    pub fn new(size: usize) -> ThreadPool {
+        ThreadPool::build(size).unwrap_or_else(|e| panic!("{e}"))
+    }
+
+    /// 创建一个新的 ThreadPool，失败时返回 [`PoolCreationError`] 而不是终止运行。
+    pub fn build(size: usize) -> Result<ThreadPool, PoolCreationError> {
+        ThreadPool::builder(size).build()
+    }
+
+    /// 返回一个用于配置其他选项的 [`ThreadPoolBuilder`]，`size` 为初始的线程数量。
+    ///
+    /// 默认情况下线程数量固定为 `size`；用 [`ThreadPoolBuilder::min_threads`] 与
+    /// [`ThreadPoolBuilder::max_threads`] 允许线程池随负载伸缩。
+    pub fn builder(size: usize) -> ThreadPoolBuilder {
+        ThreadPoolBuilder {
+            size,
+            min_threads: None,
+            max_threads: None,
+            keep_alive: Duration::from_secs(60),
+            queue_capacity: None,
+            panic_handler: None,
+            logger: None,
+        }
+    }
+
+    /// 提交一个作业。队列已满时阻塞，直到有空位。
+    ///
+    /// 线程池已关闭时以 [`ExecuteError::Closed`] 交还该作业。
+    pub fn execute<F>(&self, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(f, Wait::Forever, |f| Box::new(f))
+    }
+
+    /// 尝试提交一个作业，队列已满时立即以 [`ExecuteError::Full`] 交还该作业。
+    pub fn try_execute<F>(&self, f: F) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(f, Wait::Never, |f| Box::new(f))
+    }
+
+    /// 提交一个作业，队列已满时最多等待 `timeout`，超时后以
+    /// [`ExecuteError::Timeout`] 交还该作业。
+    pub fn execute_timeout<F>(&self, f: F, timeout: Duration) -> Result<(), ExecuteError<F>>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.submit(f, Wait::Timeout(timeout), |f| Box::new(f))
+    }
+
+    /// 提交一个有返回值的作业，返回可以 `join` 取回返回值或者取消作业的 [`JobHandle`]。
+    ///
+    /// 队列已满时阻塞，直到有空位。作业 panic 时，载荷经由 [`JobHandle::join`] 返回，
+    /// 同样会计入 [`ThreadPool::panicked_jobs`]，但不会交给 panic 处理器。
+    pub fn spawn<F, T>(&self, f: F) -> Result<JobHandle<T>, ExecuteError<F>>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let mut handle = None;
+        let shared = Arc::clone(&self.shared);
+
+        self.submit(f, Wait::Forever, |f| {
+            let (job, h) = job::wrap(f, move || {
+                shared.panicked_jobs.fetch_add(1, Ordering::Relaxed);
+            });
+            handle = Some(h);
+            job
+        })?;
+
+        Ok(handle.expect("作业入队时一定创建了句柄"))
+    }
+
+    fn submit<T>(
+        &self,
+        item: T,
+        wait: Wait,
+        into_job: impl FnOnce(T) -> Job,
+    ) -> Result<(), ExecuteError<T>> {
+        self.shared.queue.push_with(item, wait, into_job)?;
+        self.shared.grow();
+        Ok(())
+    }
+
+    /// 已提交但尚未被 worker 取走的作业数。
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.queue.len()
+    }
+
+    /// 当前的 worker 线程数。
+    pub fn threads(&self) -> usize {
+        self.shared.threads.load(Ordering::SeqCst)
+    }
+
+    /// 自创建以来 panic 了的作业数。panic 的作业不会杀死 worker。
+    pub fn panicked_jobs(&self) -> usize {
+        self.shared.panicked_jobs.load(Ordering::Relaxed)
+    }
+
+    /// 因 worker 线程意外退出而重新创建的 worker 数。
+    pub fn respawned_workers(&self) -> usize {
+        self.shared.respawned_workers.load(Ordering::Relaxed)
+    }
+
+    /// 返回线程池当前状态的快照。
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+
+    /// 返回一个可以在其他线程中读取统计的 [`PoolMonitor`]，比如在线程池被交给
+    /// [`crate::Server`] 之后。
+    pub fn monitor(&self) -> PoolMonitor {
+        PoolMonitor {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// 停止接收新作业，等待已排队与正在执行的作业完成，最多等待 `timeout`。
+    ///
+    /// 在期限内结束的 worker 会被 join；仍在运行的 worker 线程被分离，其编号记录在
+    /// 返回的 [`ShutdownReport`] 中。
+    pub fn shutdown(mut self, timeout: Duration) -> ShutdownReport {
+        self.close(Some(Instant::now() + timeout))
+    }
+
+    fn close(&mut self, deadline: Option<Instant>) -> ShutdownReport {
+        let start = Instant::now();
+        self.shared.queue.close();
+
+        if let Some(deadline) = deadline {
+            while Instant::now() < deadline
+                && self.shared.workers().iter().any(|w| !w.thread.is_finished())
+            {
+                thread::sleep(Duration::from_millis(10));
+            }
+        }
+
+        let mut report = ShutdownReport {
+            joined: Vec::new(),
+            unfinished: Vec::new(),
+            failed: Vec::new(),
+            elapsed: Duration::ZERO,
+        };
+
+        // 队列已关闭，此后不会再有 worker 被替换，可以放心取走全部 worker。
+        let workers = std::mem::take(&mut *self.shared.workers());
+
+        for worker in workers {
+            if deadline.is_some() && !worker.thread.is_finished() {
+                report.unfinished.push(worker.id);
+                continue;
+            }
+
+            self.shared.logger.debug("pool", "关闭 worker", &[("worker", &worker.id)]);
+
+            // 线程已经因 panic 而结束时 join 返回错误；这里只做记录，不能再次 panic。
+            match worker.thread.join() {
+                Ok(()) => report.joined.push(worker.id),
+                Err(_) => report.failed.push(worker.id),
+            }
+        }
+
+        report.elapsed = start.elapsed();
+        report
+    }
+}
+
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.close(None);
+    }
+}
+
+/// 读取线程池统计的句柄，由 [`ThreadPool::monitor`] 创建。
+#[derive(Clone)]
+pub struct PoolMonitor {
+    shared: Arc<Shared>,
+}
+
+impl PoolMonitor {
+    /// 与 [`ThreadPool::stats`] 相同。线程池关闭之后仍可调用，此时返回最后的统计。
+    pub fn stats(&self) -> PoolStats {
+        self.shared.stats()
+    }
+
+    /// 与 [`ThreadPool::queued_jobs`] 相同。
+    pub fn queued_jobs(&self) -> usize {
+        self.shared.queue.len()
+    }
+}
+
+impl fmt::Debug for PoolMonitor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PoolMonitor").finish_non_exhaustive()
+    }
+}
+
+/// [`ThreadPool::stats`] 返回的快照。各项分别读取，彼此之间不保证完全一致。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PoolStats {
+    /// 当前的 worker 线程数。
+    pub threads: usize,
+    /// 正在执行作业的 worker 数。
+    pub busy_workers: usize,
+    /// 正在等待作业的 worker 数。
+    pub idle_workers: usize,
+    /// 已提交但尚未被 worker 取走的作业数。
+    pub queued_jobs: usize,
+    /// 执行完毕的作业数，包括 panic 了的。
+    pub completed_jobs: usize,
+    /// panic 了的作业数。
+    pub panicked_jobs: usize,
+    /// 因线程意外退出而重新创建的 worker 数。
+    pub respawned_workers: usize,
+    /// 作业从提交到开始执行所等待的时间。
+    pub wait_time: Histogram,
+    /// 作业执行所用的时间。
+    pub run_time: Histogram,
+}
+
+/// [`ThreadPool::shutdown`] 的结果。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// 在期限内完成全部作业并被 join 的 worker 编号。
+    pub joined: Vec<usize>,
+    /// 期限已到仍在执行作业、因而被分离的 worker 编号。
+    pub unfinished: Vec<usize>,
+    /// 线程以 panic 结束的 worker 编号。
+    pub failed: Vec<usize>,
+    /// 关闭所花费的时间。
+    pub elapsed: Duration,
+}
+
+impl ShutdownReport {
+    /// 全部 worker 都在期限内结束时返回 `true`。
+    pub fn is_clean(&self) -> bool {
+        self.unfinished.is_empty()
+    }
+}
+
+/// 配置并创建 [`ThreadPool`]。
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    size: usize,
+    min_threads: Option<usize>,
+    max_threads: Option<usize>,
+    keep_alive: Duration,
+    queue_capacity: Option<usize>,
+    panic_handler: Option<PanicHandler>,
+    logger: Option<Arc<dyn Logger>>,
+}
+
+impl fmt::Debug for ThreadPoolBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ThreadPoolBuilder")
+            .field("size", &self.size)
+            .field("min_threads", &self.min_threads)
+            .field("max_threads", &self.max_threads)
+            .field("keep_alive", &self.keep_alive)
+            .field("queue_capacity", &self.queue_capacity)
+            .field("panic_handler", &self.panic_handler.is_some())
+            .field("logger", &self.logger.is_some())
+            .finish()
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// 设置空闲的 worker 可以退出到的最少线程数，默认与初始数量相同。
+    pub fn min_threads(mut self, min: usize) -> ThreadPoolBuilder {
+        self.min_threads = Some(min);
+        self
+    }
+
+    /// 设置排队的作业多于空闲的 worker 时可以增加到的最多线程数，默认与初始数量相同。
+    pub fn max_threads(mut self, max: usize) -> ThreadPoolBuilder {
+        self.max_threads = Some(max);
+        self
+    }
+
+    /// 设置线程数多于 `min_threads` 时，worker 空闲多久后退出，默认 60 秒。
+    pub fn keep_alive(mut self, keep_alive: Duration) -> ThreadPoolBuilder {
+        self.keep_alive = keep_alive;
+        self
+    }
+
+    /// 限制等待执行的作业数。队列满时 [`ThreadPool::execute`] 会阻塞，
+    /// [`ThreadPool::try_execute`] 与 [`ThreadPool::execute_timeout`] 则交还作业。
+    pub fn queue_capacity(mut self, capacity: usize) -> ThreadPoolBuilder {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// 设置作业 panic 时调用的处理器，它在执行该作业的 worker 线程上运行。
+    ///
+    /// 处理器本身 panic 时，该 worker 线程会退出，并由一个新的 worker 取代。
+    pub fn panic_handler<H>(mut self, handler: H) -> ThreadPoolBuilder
+    where
+        H: Fn(&JobPanic<'_>) + Send + Sync + 'static,
+    {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// 设置线程池的日志后端，默认把 `Info` 及以上的记录写到标准错误。
+    pub fn logger(mut self, logger: Arc<dyn Logger>) -> ThreadPoolBuilder {
+        self.logger = Some(logger);
+        self
+    }
+
+    pub fn build(self) -> Result<ThreadPool, PoolCreationError> {
+        if self.size == 0 {
+            return Err(PoolCreationError::ZeroSize);
+        }
+        let min = self.min_threads.unwrap_or(self.size);
+        let max = self.max_threads.unwrap_or(self.size);
+        if min == 0 || min > self.size || self.size > max {
+            return Err(PoolCreationError::InvalidThreadRange {
+                min,
+                size: self.size,
+                max,
+            });
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(PoolCreationError::ZeroQueueCapacity);
+        }
+
+        let pool = ThreadPool {
+            shared: Arc::new(Shared {
+                queue: JobQueue::new(self.queue_capacity),
+                workers: Mutex::new(Vec::with_capacity(max)),
+                threads: AtomicUsize::new(0),
+                next_id: AtomicUsize::new(0),
+                min_threads: min,
+                max_threads: max,
+                keep_alive: self.keep_alive,
+                busy_workers: AtomicUsize::new(0),
+                completed_jobs: AtomicUsize::new(0),
+                panicked_jobs: AtomicUsize::new(0),
+                respawned_workers: AtomicUsize::new(0),
+                wait_time: Recorder::new(),
+                run_time: Recorder::new(),
+                panic_handler: self.panic_handler,
+                logger: self
+                    .logger
+                    .unwrap_or_else(|| Arc::new(WriterLogger::stderr(Level::Info))),
+            }),
+        };
+
+        for _ in 0..self.size {
+            // 出错时返回前丢弃 pool，已经创建的 worker 会随之被关闭并 join。
+            pool.shared.add_worker(&mut pool.shared.workers())?;
+        }
+
+        Ok(pool)
+    }
+}
+
+/// 无法创建 [`ThreadPool`] 的原因。
+#[derive(Debug)]
+pub enum PoolCreationError {
+    /// 线程数量为零。
+    ZeroSize,
+    /// 不满足 `0 < min <= size <= max`。
+    InvalidThreadRange { min: usize, size: usize, max: usize },
+    /// 队列容量为零，任何作业都无法提交。
+    ZeroQueueCapacity,
+    /// 操作系统无法创建第 `id` 个 worker 线程。
+    Spawn { id: usize, source: io::Error },
+}
+
+impl fmt::Display for PoolCreationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PoolCreationError::ZeroSize => write!(f, "线程池的线程数量必须大于零"),
+            PoolCreationError::InvalidThreadRange { min, size, max } => write!(
+                f,
+                "线程数量范围无效：最少 {min}、初始 {size}、最多 {max}，应满足 0 < 最少 <= 初始 <= 最多"
+            ),
+            PoolCreationError::ZeroQueueCapacity => write!(f, "作业队列的容量必须大于零"),
+            PoolCreationError::Spawn { id, source } => {
+                write!(f, "无法创建 worker {id} 的线程：{source}")
+            }
+        }
+    }
+}
+
+impl Error for PoolCreationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PoolCreationError::Spawn { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// 作业未能提交的原因；每种情况都带着被拒绝的作业，调用方可以另做处理。
+pub enum ExecuteError<F> {
+    /// 队列已满（[`ThreadPool::try_execute`]）。
+    Full(F),
+    /// 等待队列空位超时（[`ThreadPool::execute_timeout`]）。
+    Timeout(F),
+    /// 线程池正在关闭，不再接受作业。
+    Closed(F),
+}
+
+impl<F> ExecuteError<F> {
+    /// 取回被拒绝的作业。
+    pub fn into_job(self) -> F {
+        match self {
+            ExecuteError::Full(f) | ExecuteError::Timeout(f) | ExecuteError::Closed(f) => f,
+        }
+    }
+}
+
+impl<F> fmt::Debug for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full(_) => f.write_str("Full(..)"),
+            ExecuteError::Timeout(_) => f.write_str("Timeout(..)"),
+            ExecuteError::Closed(_) => f.write_str("Closed(..)"),
+        }
+    }
+}
+
+impl<F> fmt::Display for ExecuteError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecuteError::Full(_) => write!(f, "作业队列已满"),
+            ExecuteError::Timeout(_) => write!(f, "等待作业队列空位超时"),
+            ExecuteError::Closed(_) => write!(f, "线程池已关闭"),
+        }
+    }
+}
+
+impl<F> Error for ExecuteError<F> {}
+
+struct Worker {
+    id: usize,
+    thread: thread::JoinHandle<()>,
+}
+
+impl Worker {
+    fn spawn(id: usize, local: Arc<Local>, shared: Arc<Shared>) -> io::Result<Worker> {
+        let builder = thread::Builder::new().name(format!("worker-{id}"));
+
+        let thread = builder.spawn(move || {
+            let sentinel = Sentinel { id, local, shared };
+            let (local, shared) = (&sentinel.local, &sentinel.shared);
+
+            // 线程数量固定时没有必要计时等待。
+            let keep_alive = (shared.min_threads < shared.max_threads).then_some(shared.keep_alive);
+
+            loop {
+                let queued = match shared.queue.pop(local, keep_alive) {
+                    Pop::Job(queued) => queued,
+                    Pop::Idle if shared.retire(id, local) => {
+                        shared.logger.debug("pool", "worker 空闲过久，退出", &[("worker", &id)]);
+                        return;
+                    }
+                    Pop::Idle => continue,
+                    Pop::Closed => break,
+                };
+
+                if let Err(payload) = shared.run(queued) {
+                    shared.panicked_jobs.fetch_add(1, Ordering::Relaxed);
+
+                    let panic = JobPanic {
+                        worker: id,
+                        payload: &*payload,
+                    };
+                    shared.logger.warn(
+                        "pool",
+                        "作业 panic 了，worker 继续运行",
+                        &[("worker", &id), ("panic", &panic.message().unwrap_or("?"))],
+                    );
+
+                    if let Some(handler) = &shared.panic_handler {
+                        handler(&panic);
+                    }
+                }
+            }
+
+            shared.logger.debug("pool", "队列已关闭，worker 退出", &[("worker", &id)]);
+        })?;
+
+        Ok(Worker { id, thread })
+    }
+}
+
+/// 随 worker 线程一同销毁；线程因 panic 退出时，创建一个新的 worker 取代它。
+struct Sentinel {
+    id: usize,
+    /// 替代的 worker 接管同一个本地队列，其中的作业不会丢失。
+    local: Arc<Local>,
+    shared: Arc<Shared>,
+}
+
+impl Drop for Sentinel {
+    fn drop(&mut self) {
+        if !thread::panicking() {
+            return;
+        }
+
+        // 持锁检查队列是否已关闭，这样关闭线程池时取走的 worker 列表不会再被修改。
+        let mut workers = self.shared.workers();
+        if self.shared.queue.is_closed() {
+            return;
+        }
+
+        // 先计数：新的 worker 可能在 `spawn` 返回之前就已经开始执行作业。
+        self.shared.respawned_workers.fetch_add(1, Ordering::Relaxed);
+
+        match Worker::spawn(self.id, Arc::clone(&self.local), Arc::clone(&self.shared)) {
+            Ok(replacement) => {
+                self.shared.logger.warn("pool", "worker 意外退出，已重新创建", &[("worker", &self.id)]);
+
+                match workers.iter_mut().find(|w| w.id == self.id) {
+                    // 旧的 JoinHandle 被丢弃，相当于分离这个即将结束的线程。
+                    Some(slot) => *slot = replacement,
+                    None => workers.push(replacement),
+                }
+            }
+            Err(e) => {
+                self.shared.respawned_workers.fetch_sub(1, Ordering::Relaxed);
+                self.shared.logger.error(
+                    "pool",
+                    "无法重新创建 worker",
+                    &[("worker", &self.id), ("error", &e)],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+
+    use crate::JoinError;
+
+    /// 创建一个只有一个 worker 的线程池，并让该 worker 阻塞，直到返回的发送端被丢弃。
+    fn blocked_pool(capacity: usize) -> (ThreadPool, mpsc::Sender<()>) {
+        let pool = ThreadPool::builder(1).queue_capacity(capacity).build().unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        started.recv().unwrap();
+
+        (pool, release)
+    }
+
+    #[test]
+    fn try_execute_returns_job_when_full() {
+        let (pool, release) = blocked_pool(1);
+        let (tx, rx) = mpsc::channel();
+
+        let tx1 = tx.clone();
+        pool.try_execute(move || tx1.send(1).unwrap()).unwrap();
+        assert_eq!(pool.queued_jobs(), 1);
+
+        let rejected = pool.try_execute(move || tx.send(2).unwrap());
+        let Err(ExecuteError::Full(job)) = rejected else {
+            panic!("队列已满时应交还作业");
+        };
+
+        drop(release);
+        assert_eq!(rx.recv().unwrap(), 1);
+
+        // 交还的作业仍然可以再次提交。
+        pool.execute(job).unwrap();
+        assert_eq!(rx.recv().unwrap(), 2);
+    }
+
+    #[test]
+    fn build_reports_invalid_configuration() {
+        assert!(matches!(ThreadPool::build(0), Err(PoolCreationError::ZeroSize)));
+        assert!(matches!(
+            ThreadPool::builder(2).queue_capacity(0).build(),
+            Err(PoolCreationError::ZeroQueueCapacity)
+        ));
+        assert!(matches!(
+            ThreadPool::builder(2).min_threads(3).build(),
+            Err(PoolCreationError::InvalidThreadRange { min: 3, size: 2, max: 2 })
+        ));
+        assert!(matches!(
+            ThreadPool::builder(4).max_threads(2).build(),
+            Err(PoolCreationError::InvalidThreadRange { .. })
+        ));
+        assert!(ThreadPool::build(2).is_ok());
+    }
+
+    #[test]
+    fn panicking_jobs_do_not_kill_workers() {
+        let (messages_tx, messages) = mpsc::channel();
+        let messages_tx = Mutex::new(messages_tx);
+
+        let pool = ThreadPool::builder(1)
+            .panic_handler(move |p| {
+                let message = p.message().unwrap_or_default().to_string();
+                messages_tx.lock().unwrap().send(message).unwrap();
+            })
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("作业出错")).unwrap();
+        assert_eq!(messages.recv().unwrap(), "作业出错");
+
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(42).unwrap()).unwrap();
+        assert_eq!(rx.recv().unwrap(), 42);
+
+        assert_eq!(pool.panicked_jobs(), 1);
+        assert_eq!(pool.respawned_workers(), 0);
+    }
+
+    #[test]
+    fn dead_workers_are_replaced() {
+        let pool = ThreadPool::builder(1)
+            .panic_handler(|_| panic!("处理器本身也出错"))
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("作业出错")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || {
+            tx.send(thread::current().name().map(String::from)).unwrap();
+        })
+        .unwrap();
+        assert_eq!(rx.recv().unwrap().as_deref(), Some("worker-0"));
+        assert_eq!(pool.respawned_workers(), 1);
+
+        let report = pool.shutdown(Duration::from_secs(5));
+        assert_eq!(report.joined, vec![0]);
+        assert!(report.failed.is_empty());
+    }
+
+    #[test]
+    fn spawn_returns_values_and_panics() {
+        let pool = ThreadPool::new(2);
+
+        let sum = pool.spawn(|| (1..=100).sum::<u32>()).unwrap();
+        let failed = pool.spawn(|| -> u32 { panic!("算不出来") }).unwrap();
+
+        assert_eq!(sum.join().unwrap(), 5050);
+        match failed.join() {
+            Err(JoinError::Panicked(payload)) => {
+                assert_eq!(payload.downcast_ref::<&str>(), Some(&"算不出来"));
+            }
+            other => panic!("应当返回 panic 载荷，实际为 {other:?}"),
+        }
+        assert_eq!(pool.panicked_jobs(), 1);
+    }
+
+    #[test]
+    fn pending_jobs_can_be_cancelled() {
+        let (pool, release) = blocked_pool(4);
+        let (tx, rx) = mpsc::channel();
+
+        let cancelled = pool.spawn(move || tx.send("不该运行").unwrap()).unwrap();
+        let kept = pool.spawn(|| "运行了").unwrap();
+
+        assert!(cancelled.cancel());
+        assert!(cancelled.is_finished());
+        drop(release);
+
+        assert_eq!(kept.join().unwrap(), "运行了");
+        assert!(matches!(cancelled.join(), Err(JoinError::Cancelled)));
+        assert!(rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn running_jobs_cannot_be_cancelled() {
+        let pool = ThreadPool::new(1);
+        let (started_tx, started) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+
+        let handle = pool
+            .spawn(move || {
+                started_tx.send(()).unwrap();
+                let _ = blocked.recv();
+                7
+            })
+            .unwrap();
+
+        started.recv().unwrap();
+        assert!(!handle.cancel());
+        drop(release);
+        assert_eq!(handle.join().unwrap(), 7);
+    }
+
+    #[test]
+    fn execute_timeout_waits_for_room() {
+        let (pool, release) = blocked_pool(1);
+        pool.execute(|| {}).unwrap();
+
+        let start = Instant::now();
+        let rejected = pool.execute_timeout(|| {}, Duration::from_millis(50));
+        assert!(matches!(rejected, Err(ExecuteError::Timeout(_))));
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        let releaser = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            drop(release);
+        });
+        assert!(pool.execute_timeout(|| {}, Duration::from_secs(5)).is_ok());
+        releaser.join().unwrap();
+    }
+
+    #[test]
+    fn idle_workers_steal_from_busy_ones() {
+        let (release, blocked) = mpsc::channel::<()>();
+        let (started_tx, started) = mpsc::channel();
+        let pool = ThreadPool::new(2);
+
+        pool.execute(move || {
+            started_tx.send(()).unwrap();
+            let _ = blocked.recv();
+        })
+        .unwrap();
+        started.recv().unwrap();
+
+        // 作业轮流放入两个本地队列，一半落在被阻塞的 worker 那里，只能被另一个 worker 窃取。
+        let (tx, rx) = mpsc::channel();
+        for i in 0..10 {
+            let tx = tx.clone();
+            pool.execute(move || tx.send(i).unwrap()).unwrap();
+        }
+
+        let mut done: Vec<i32> = (0..10)
+            .map(|_| rx.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        done.sort();
+        assert_eq!(done, (0..10).collect::<Vec<_>>());
+        drop(release);
+    }
+
+    #[test]
+    fn grows_under_load_and_shrinks_when_idle() {
+        let pool = ThreadPool::builder(1)
+            .max_threads(4)
+            .keep_alive(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        let (started_tx, started) = mpsc::channel();
+
+        for _ in 0..4 {
+            let blocked = Arc::clone(&blocked);
+            let started_tx = started_tx.clone();
+            pool.execute(move || {
+                started_tx.send(()).unwrap();
+                let _ = blocked.lock().unwrap().recv();
+            })
+            .unwrap();
+        }
+
+        // 四个作业同时在运行，说明线程池扩容到了上限。
+        for _ in 0..4 {
+            started.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(pool.threads(), 4);
+
+        drop(release);
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while pool.threads() > 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert_eq!(pool.threads(), 1);
+
+        // 缩容之后仍然可以执行作业。
+        let (tx, rx) = mpsc::channel();
+        pool.execute(move || tx.send(()).unwrap()).unwrap();
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn stats_track_workers_jobs_and_latency() {
+        let (pool, release) = blocked_pool(4);
+        pool.execute(|| thread::sleep(Duration::from_millis(20))).unwrap();
+
+        let stats = pool.stats();
+        assert_eq!(stats.threads, 1);
+        assert_eq!(stats.busy_workers, 1);
+        assert_eq!(stats.idle_workers, 0);
+        assert_eq!(stats.queued_jobs, 1);
+        assert_eq!(stats.completed_jobs, 0);
+
+        // 线程池被丢弃后，监视器仍能读到最后的统计。
+        let monitor = pool.monitor();
+        drop(release);
+        drop(pool);
+
+        let stats = monitor.stats();
+        assert_eq!(stats.busy_workers, 0);
+        assert_eq!(stats.queued_jobs, 0);
+        assert_eq!(stats.completed_jobs, 2);
+        assert_eq!(stats.wait_time.count, 2);
+        assert_eq!(stats.run_time.count, 2);
+        assert!(stats.run_time.sum >= Duration::from_millis(20));
+    }
+
+    #[test]
+    fn panics_are_reported_to_the_logger() {
+        #[derive(Default)]
+        struct Capture(Mutex<Vec<String>>);
+
+        impl Logger for Capture {
+            fn log(&self, record: &crate::log::Record<'_>) {
+                let fields: Vec<String> = record
+                    .fields
+                    .iter()
+                    .map(|(name, value)| format!("{name}={value}"))
+                    .collect();
+                let line = format!("{} {} {}", record.level, record.message, fields.join(" "));
+                self.0.lock().unwrap().push(line);
+            }
+        }
+
+        let capture = Arc::new(Capture::default());
+        let pool = ThreadPool::builder(1)
+            .logger(Arc::clone(&capture) as Arc<dyn Logger>)
+            .build()
+            .unwrap();
+
+        pool.execute(|| panic!("坏了")).unwrap();
+        pool.shutdown(Duration::from_secs(5));
+
+        let lines = capture.0.lock().unwrap();
+        assert!(lines.contains(&"WARN 作业 panic 了，worker 继续运行 worker=0 panic=坏了".to_string()));
+        assert!(lines.iter().any(|l| l.starts_with("DEBUG 关闭 worker")));
+    }
+}
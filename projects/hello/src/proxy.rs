@@ -0,0 +1,804 @@
+use std::{
+    fmt,
+    io::{self, Read},
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::{
+    client::{BodyReader, Client, ClientError, ClientResponse},
+    connection::error_response,
+    headers::Headers,
+    log::{Logger, NullLogger},
+    middleware::Middleware,
+    request::{Method, ParseError, Request},
+    response::Response,
+    router::Handler,
+    status::StatusCode,
+};
+
+/// 逐跳的头部，只对一个连接有意义，不转发给对端。
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "Proxy-Connection",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// 把路径前缀下的请求转发给上游的 HTTP 服务器的反向代理。
+///
+/// 路径等于 `prefix` 或者以 `prefix/` 开头的请求依次轮流发给各个上游，其余的请求
+/// 交给内层。转发时去掉逐跳的头部，把 `Host` 换成上游的地址（见
+/// [`preserve_host`](Proxy::preserve_host)），并加上 `X-Forwarded-For`、
+/// `X-Forwarded-Proto` 与 `X-Forwarded-Host`。
+///
+/// 请求体与上游的响应体都边读边发，不会整体缓冲：代理为属于它的请求返回
+/// [`streams_body`](Middleware::streams_body)，服务器只读完头部就交给它。请求体按
+/// `Content-Length` 原样转发，分块编码的请求体仍以分块编码转发；它们照样受
+/// [`Limits::max_body_size`](crate::Limits::max_body_size) 限制，但不占用内存，可以
+/// 放宽。读取请求体出错（比如客户端超时）时按 [`error_response`] 回应，不算上游的
+/// 失败。这样的请求之后，客户端的连接关闭。连接升级（比如 WebSocket）不会被转发。
+///
+/// 上游的状态码原样转发，包括 [`StatusCode`] 中没有列出的（见 [`StatusCode::Other`]）。
+///
+/// 被动健康检查：连接失败、读写出错或者超时，以及上游以 502、503、504 回应，都算
+/// 一次失败；其它状态码都不算。连续失败 [`max_failures`](Proxy::max_failures) 次的上游暂停使用
+/// [`eject_for`](Proxy::eject_for) 那么久，期满后再试；一次成功就清零。连接失败时
+/// 接着试下一个上游，因为请求还没有发出去。
+///
+/// ```
+/// use hello::{Proxy, Response, Stack, StatusCode};
+///
+/// let upstreams = ["127.0.0.1:9001".parse().unwrap(), "127.0.0.1:9002".parse().unwrap()];
+/// let app = Stack::new(|_| Response::text(StatusCode::Ok, "local"))
+///     .wrap(Proxy::new("/api", upstreams).strip_prefix(true));
+/// ```
+pub struct Proxy {
+    /// 不含末尾的 `/`；代理全部路径时为空字符串。
+    prefix: String,
+    upstreams: Vec<Arc<Upstream>>,
+    next: AtomicUsize,
+    strip_prefix: bool,
+    preserve_host: bool,
+    timeout: Option<Duration>,
+    health: HealthCheck,
+}
+
+/// 被动健康检查的设置与日志。转发出去的响应体读取出错时也要记失败，所以每个响应体
+/// 都持有一份。
+#[derive(Clone)]
+struct HealthCheck {
+    max_failures: u32,
+    eject_for: Duration,
+    logger: Arc<dyn Logger>,
+}
+
+struct Upstream {
+    addr: SocketAddr,
+    health: Mutex<Health>,
+}
+
+#[derive(Default)]
+struct Health {
+    /// 连续失败的次数。
+    failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+impl Proxy {
+    /// 把 `prefix` 下的请求转发给 `upstreams`。
+    ///
+    /// # Panics
+    ///
+    /// `prefix` 不以 `/` 开头或者 `upstreams` 为空时 panic。
+    pub fn new(prefix: &str, upstreams: impl IntoIterator<Item = SocketAddr>) -> Proxy {
+        assert!(prefix.starts_with('/'), "路径前缀 {prefix:?} 应以 / 开头");
+        let upstreams: Vec<_> = upstreams
+            .into_iter()
+            .map(|addr| {
+                Arc::new(Upstream {
+                    addr,
+                    health: Mutex::new(Health::default()),
+                })
+            })
+            .collect();
+        assert!(!upstreams.is_empty(), "路径前缀 {prefix} 没有上游");
+
+        Proxy {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            upstreams,
+            next: AtomicUsize::new(0),
+            strip_prefix: false,
+            preserve_host: false,
+            timeout: Some(Duration::from_secs(30)),
+            health: HealthCheck {
+                max_failures: 3,
+                eject_for: Duration::from_secs(10),
+                logger: Arc::new(NullLogger),
+            },
+        }
+    }
+
+    /// 转发时是否去掉路径前缀：`/api/users` 发给上游的是 `/users`。默认不去掉。
+    pub fn strip_prefix(mut self, strip: bool) -> Proxy {
+        self.strip_prefix = strip;
+        self
+    }
+
+    /// 是否把客户端的 `Host` 原样发给上游。默认换成上游的地址，原来的值放在
+    /// `X-Forwarded-Host` 中。
+    pub fn preserve_host(mut self, preserve: bool) -> Proxy {
+        self.preserve_host = preserve;
+        self
+    }
+
+    /// 连接上游、单次读取与单次写入的超时，默认 30 秒；`None` 表示一直等待。
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// 连续失败多少次后暂停使用一个上游，默认 3 次。
+    pub fn max_failures(mut self, failures: u32) -> Proxy {
+        self.health.max_failures = failures.max(1);
+        self
+    }
+
+    /// 暂停使用失败的上游多久，默认 10 秒。
+    pub fn eject_for(mut self, duration: Duration) -> Proxy {
+        self.health.eject_for = duration;
+        self
+    }
+
+    pub fn logger(mut self, logger: Arc<dyn Logger>) -> Proxy {
+        self.health.logger = logger;
+        self
+    }
+
+    /// 请求属于这个代理时返回发给上游的请求目标。
+    fn upstream_target(&self, target: &str) -> Option<String> {
+        // 绝对形式（`http://host/path`）只取其路径部分。
+        let origin = match target.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("/", |i| &rest[i..]),
+            None => target,
+        };
+        let origin = origin.split('#').next().unwrap_or_default();
+        let (path, query) = origin.split_at(origin.find('?').unwrap_or(origin.len()));
+
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        let path = match rest {
+            "" if self.strip_prefix => "/",
+            rest if self.strip_prefix => rest,
+            _ => path,
+        };
+        Some(format!("{path}{query}"))
+    }
+
+    /// 发给上游的请求：去掉逐跳的头部，加上 `X-Forwarded-*`。
+    fn upstream_request(&self, mut request: Request, target: &str) -> Option<Request> {
+        let mut upstream = Request::new(request.method(), target).ok()?;
+        let headers = upstream.headers_mut();
+        for (name, value) in end_to_end(request.headers()) {
+            let host = name.eq_ignore_ascii_case("Host");
+            if !name.eq_ignore_ascii_case("Expect") && (!host || self.preserve_host) {
+                headers.append(name, value);
+            }
+        }
+
+        if let Some(peer) = request.peer_addr() {
+            let ip = peer.ip().to_string();
+            let mut chain: Vec<_> = request.headers().get_all("X-Forwarded-For").collect();
+            chain.push(&ip);
+            headers.insert("X-Forwarded-For", chain.join(", "));
+        }
+        let scheme = if request.is_secure() { "https" } else { "http" };
+        headers.insert("X-Forwarded-Proto", scheme);
+        if let Some(host) = request.header("Host") {
+            headers.insert("X-Forwarded-Host", host);
+        }
+        headers.insert("Connection", "close");
+
+        if let Some(body) = request.take_body_stream() {
+            upstream.set_body_stream(body);
+        }
+        upstream.set_body(request.into_body());
+        Some(upstream)
+    }
+
+    /// 轮流选出可用的上游发送请求；连接失败时换下一个。
+    fn forward(&self, request: &Request) -> Response {
+        let now = Instant::now();
+        let len = self.upstreams.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed) % len;
+        let available = (0..len)
+            .map(|i| &self.upstreams[(start + i) % len])
+            .filter(|upstream| upstream.is_available(now));
+
+        // 全部上游都在暂停期内时不再尝试。
+        let mut status = StatusCode::ServiceUnavailable;
+        for upstream in available {
+            let client = Client::new(upstream.addr).timeout(self.timeout);
+            let connection = match client.connect() {
+                Ok(connection) => connection,
+                Err(e) => {
+                    let e = ClientError::Io(e);
+                    status = error_status(&e);
+                    self.health.failed(upstream, &e);
+                    continue;
+                }
+            };
+
+            return match connection.send_streaming(request) {
+                // 连接升级不会被转发，上游不该以 1xx 作为最终的响应。
+                Ok((response, _)) if response.status().is_informational() => {
+                    self.invalid_response(upstream, &format!("上游以 {} 回应", response.status()))
+                }
+                Ok((response, body)) => {
+                    let status = response.status();
+                    if matches!(
+                        status,
+                        StatusCode::BadGateway
+                            | StatusCode::ServiceUnavailable
+                            | StatusCode::GatewayTimeout
+                    ) {
+                        self.health
+                            .failed(upstream, &format!("上游以 {status} 回应"));
+                    } else {
+                        upstream.succeeded();
+                    }
+                    let body = UpstreamBody {
+                        body,
+                        upstream: Arc::clone(upstream),
+                        health: self.health.clone(),
+                    };
+                    respond(request.method(), response, body)
+                }
+                // 客户端的请求体有问题，与上游无关。
+                Err(ClientError::Body(e)) => {
+                    let e = e.downcast::<ParseError>().unwrap_or_else(ParseError::from);
+                    error_response(&e).with_header("Connection", "close")
+                }
+                // 只有连接出错才说明上游不健康，响应不合规范只影响这一个请求。
+                Err(e @ ClientError::Io(_)) => {
+                    self.health.failed(upstream, &e);
+                    Response::plain(error_status(&e))
+                }
+                Err(e) => self.invalid_response(upstream, &e),
+            };
+        }
+        Response::plain(status)
+    }
+
+    /// 上游的响应不合规范时以 502 回应。
+    fn invalid_response(&self, upstream: &Upstream, error: &dyn fmt::Display) -> Response {
+        self.health.logger.warn(
+            "proxy",
+            "上游的响应无效",
+            &[("upstream", &upstream.addr), ("error", error)],
+        );
+        Response::plain(StatusCode::BadGateway)
+    }
+}
+
+impl HealthCheck {
+    /// 记一次失败，连续失败太多次时暂停使用这个上游。
+    fn failed(&self, upstream: &Upstream, error: &dyn fmt::Display) {
+        let mut health = upstream.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.failures += 1;
+        self.logger.warn(
+            "proxy",
+            "转发请求失败",
+            &[
+                ("upstream", &upstream.addr),
+                ("failures", &health.failures),
+                ("error", error),
+            ],
+        );
+
+        // 暂停期满后失败次数不清零，再失败一次就再次暂停。
+        if health.failures >= self.max_failures {
+            health.ejected_until = Some(Instant::now() + self.eject_for);
+            self.logger.warn(
+                "proxy",
+                "暂停使用上游",
+                &[
+                    ("upstream", &upstream.addr),
+                    ("for", &format!("{:?}", self.eject_for)),
+                ],
+            );
+        }
+    }
+}
+
+impl Upstream {
+    fn is_available(&self, now: Instant) -> bool {
+        let health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        health.ejected_until.is_none_or(|until| until <= now)
+    }
+
+    fn succeeded(&self) {
+        *self.health.lock().unwrap_or_else(|e| e.into_inner()) = Health::default();
+    }
+}
+
+impl Middleware for Proxy {
+    fn handle(&self, request: Request, next: &dyn Handler) -> Response {
+        let Some(target) = self.upstream_target(request.target()) else {
+            return next.handle(request);
+        };
+        match self.upstream_request(request, &target) {
+            Some(upstream) => self.forward(&upstream),
+            None => Response::plain(StatusCode::BadRequest),
+        }
+    }
+
+    fn streams_body(&self, request: &Request, next: &dyn Handler) -> bool {
+        self.upstream_target(request.target()).is_some() || next.streams_body(request)
+    }
+}
+
+impl fmt::Debug for Proxy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Proxy")
+            .field("prefix", &self.prefix)
+            .field(
+                "upstreams",
+                &self.upstreams.iter().map(|u| u.addr).collect::<Vec<_>>(),
+            )
+            .finish_non_exhaustive()
+    }
+}
+
+/// 上游的响应体；读取出错时记这个上游一次失败，比如上游在发送响应体的中途断开。
+struct UpstreamBody {
+    body: BodyReader,
+    upstream: Arc<Upstream>,
+    health: HealthCheck,
+}
+
+impl Read for UpstreamBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body.read(buf).inspect_err(|e| {
+            if e.kind() != io::ErrorKind::Interrupted {
+                self.health.failed(&self.upstream, e);
+            }
+        })
+    }
+}
+
+/// 把上游的响应转成发给客户端的响应，响应体边读边发。
+fn respond(method: Method, upstream: ClientResponse, body: UpstreamBody) -> Response {
+    let mut response = Response::new(upstream.status());
+    for (name, value) in end_to_end(upstream.headers()) {
+        if !name.eq_ignore_ascii_case("Content-Length") {
+            response.headers_mut().append(name, value);
+        }
+    }
+
+    // HEAD 的响应没有响应体，但要保留上游给出的长度。
+    let length = match method {
+        Method::Head => upstream
+            .header("Content-Length")
+            .and_then(|len| len.parse().ok()),
+        _ => body.body.remaining(),
+    };
+    match (method, length) {
+        (Method::Head, Some(len)) => response.with_reader(io::empty(), len),
+        (Method::Head, None) => response.with_stream(io::empty()),
+        (_, Some(len)) => response.with_reader(body, len),
+        (_, None) => response.with_stream(body),
+    }
+}
+
+/// 去掉逐跳头部以及 `Connection` 中列出的头部之后剩下的头部。
+fn end_to_end(headers: &Headers) -> impl Iterator<Item = (&str, &str)> {
+    let listed: Vec<&str> = headers
+        .get_all("Connection")
+        .flat_map(|v| v.split(','))
+        .map(str::trim)
+        .collect();
+    headers.iter().filter(move |(name, _)| {
+        !HOP_BY_HOP
+            .iter()
+            .chain(&listed)
+            .any(|hop| hop.eq_ignore_ascii_case(name))
+    })
+}
+
+fn error_status(error: &ClientError) -> StatusCode {
+    match error {
+        ClientError::Io(e)
+            if matches!(
+                e.kind(),
+                io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+            ) =>
+        {
+            StatusCode::GatewayTimeout
+        }
+        _ => StatusCode::BadGateway,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        io::{BufReader, Read, Write},
+        net::{Shutdown, TcpListener, TcpStream},
+        sync::mpsc,
+        thread,
+    };
+
+    use crate::{
+        connection::{serve_connection, ConnectionOptions},
+        middleware::Stack,
+        request::Limits,
+        response::Body,
+    };
+
+    /// 在随机端口上用 `handler` 处理每个连接的服务器，用作上游，也用作代理本身。
+    fn upstream(handler: impl Handler + 'static) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let handler: Arc<dyn Handler> = Arc::new(handler);
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let handler = Arc::clone(&handler);
+                thread::spawn(move || {
+                    let _ = serve_connection(stream, &*handler, &ConnectionOptions::default());
+                });
+            }
+        });
+        addr
+    }
+
+    /// 回显请求行与头部的上游。
+    fn echo(name: &'static str) -> SocketAddr {
+        upstream(move |request: Request| {
+            let mut text = format!("{name} {} {}\n", request.method(), request.target());
+            for (header, value) in request.headers().iter() {
+                text.push_str(&format!("{header}: {value}\n"));
+            }
+            text.push_str(&String::from_utf8_lossy(request.body()));
+            Response::text(StatusCode::Ok, text).with_header("Keep-Alive", "timeout=5")
+        })
+    }
+
+    /// 对每个连接读一次请求、写出 `reply` 后就关闭连接的上游。
+    fn raw(reply: &'static [u8]) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for mut stream in listener.incoming().flatten() {
+                let _ = stream.read(&mut [0; 1024]);
+                let _ = stream.write_all(reply);
+            }
+        });
+        addr
+    }
+
+    /// 一个没有人监听的端口。
+    fn dead() -> SocketAddr {
+        TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+    }
+
+    fn stack(proxy: Proxy) -> Stack {
+        Stack::new(|_| Response::text(StatusCode::Ok, "local")).wrap(proxy)
+    }
+
+    fn send(stack: &Stack, method: Method, target: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::new(method, target).unwrap();
+        request.set_connection(Some("10.0.0.2:5000".parse().unwrap()), false);
+        for (name, value) in headers {
+            request.headers_mut().append(*name, *value);
+        }
+        stack.handle(request)
+    }
+
+    fn read_bytes(mut response: Response) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match std::mem::replace(response.body_mut(), Body::Bytes(Vec::new())) {
+            Body::Bytes(body) => bytes = body,
+            Body::Reader { mut reader, .. } | Body::Stream(mut reader) => {
+                reader.read_to_end(&mut bytes).unwrap();
+            }
+        }
+        bytes
+    }
+
+    fn read_body(response: Response) -> String {
+        String::from_utf8(read_bytes(response)).unwrap()
+    }
+
+    fn get(stack: &Stack, target: &str) -> String {
+        read_body(send(stack, Method::Get, target, &[]))
+    }
+
+    #[test]
+    fn rewrites_headers_and_forwards_the_body() {
+        let addr = echo("a");
+        let stack = stack(Proxy::new("/api", [addr]));
+
+        let mut request = Request::new(Method::Post, "/api/items?x=1").unwrap();
+        request.set_connection(Some("10.0.0.2:5000".parse().unwrap()), true);
+        for (name, value) in [
+            ("Host", "example.com"),
+            ("X-Forwarded-For", "192.0.2.1"),
+            ("Connection", "keep-alive, X-Secret"),
+            ("X-Secret", "1"),
+            ("Keep-Alive", "timeout=5"),
+            ("TE", "trailers"),
+            ("Expect", "100-continue"),
+            ("Content-Length", "4"),
+        ] {
+            request.headers_mut().append(name, value);
+        }
+        request.set_body(b"ping".to_vec());
+
+        let response = stack.handle(request);
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert!(!response.headers().contains("Keep-Alive"));
+        let text = read_body(response);
+
+        assert!(text.starts_with("a POST /api/items?x=1\n"), "{text}");
+        for line in [
+            format!("Host: {addr}"),
+            "X-Forwarded-For: 192.0.2.1, 10.0.0.2".to_string(),
+            "X-Forwarded-Proto: https".to_string(),
+            "X-Forwarded-Host: example.com".to_string(),
+            "Connection: close".to_string(),
+        ] {
+            assert!(text.contains(&format!("{line}\n")), "{line}: {text}");
+        }
+        for header in ["X-Secret", "Keep-Alive", "TE", "Expect"] {
+            assert!(!text.contains(&format!("\n{header}:")), "{header}: {text}");
+        }
+        assert!(text.ends_with("\nping"), "{text}");
+
+        let stack = self::stack(Proxy::new("/api", [addr]).preserve_host(true));
+        let response = send(&stack, Method::Get, "/api", &[("Host", "example.com")]);
+        assert!(read_body(response).contains("\nHost: example.com\n"));
+    }
+
+    /// 经由服务器发送 `raw`，读完响应。
+    fn exchange(front: SocketAddr, raw: &[u8]) -> String {
+        let mut stream = TcpStream::connect(front).unwrap();
+        stream.write_all(raw).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn streams_request_bodies() {
+        // 上游读到请求体的前一半就报告，读完之后回显请求体。
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (reads, read) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let head = Request::parse_head(&mut reader, &Limits::default()).unwrap();
+            assert_eq!(head.header("Content-Length"), Some("10"));
+            let mut body = [0; 10];
+            reader.read_exact(&mut body[..5]).unwrap();
+            reads.send(body[..5].to_vec()).unwrap();
+            reader.read_exact(&mut body[5..]).unwrap();
+            let mut stream = reader.into_inner();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\n")
+                .unwrap();
+            stream.write_all(&body).unwrap();
+        });
+        let front = upstream(stack(Proxy::new("/", [addr])));
+
+        // 客户端还没有发完请求体，上游就已经收到了前一半。
+        let mut stream = TcpStream::connect(front).unwrap();
+        stream
+            .write_all(b"POST /upload HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        assert_eq!(read.recv_timeout(Duration::from_secs(5)).unwrap(), b"hello");
+        stream.write_all(b"world").unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+        assert!(out.contains("Connection: close\r\n"));
+        assert!(out.ends_with("\r\n\r\nhelloworld"));
+
+        // 分块编码的请求体以分块编码转发。
+        let front = upstream(stack(Proxy::new("/", [echo("a")]).max_failures(1)));
+        let out = exchange(
+            front,
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n\
+              5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n",
+        );
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+        assert!(out.ends_with("\nhelloworld"), "{out}");
+
+        // 客户端没有发完请求体就关闭了连接：回应 400，不算上游的失败。
+        let mut stream = TcpStream::connect(front).unwrap();
+        stream
+            .write_all(b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nhello")
+            .unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{out}");
+        let out = exchange(front, b"GET / HTTP/1.1\r\nConnection: close\r\n\r\n");
+        assert!(out.starts_with("HTTP/1.1 200 OK\r\n"), "{out}");
+    }
+
+    #[test]
+    fn matches_the_prefix_and_can_strip_it() {
+        let addr = echo("a");
+        let stack = stack(Proxy::new("/api/", [addr]).strip_prefix(true));
+
+        assert!(get(&stack, "/api/users?page=2").starts_with("a GET /users?page=2\n"));
+        assert!(get(&stack, "/api").starts_with("a GET /\n"));
+        assert!(get(&stack, "http://example.com/api/x#top").starts_with("a GET /x\n"));
+        for target in ["/", "/apis", "/v1/api"] {
+            assert_eq!(get(&stack, target), "local", "{target}");
+        }
+
+        let stack = self::stack(Proxy::new("/", [addr]));
+        assert!(get(&stack, "/anything").starts_with("a GET /anything\n"));
+    }
+
+    #[test]
+    fn streams_response_bodies() {
+        let data: Vec<u8> = (0..200_000u32).map(|i| (i % 251) as u8).collect();
+        let expected = data.clone();
+        let addr = upstream(move |request: Request| {
+            let chunks: Vec<_> = data.chunks(10_000).map(<[u8]>::to_vec).collect();
+            match request.path() {
+                "/chunked" => Response::new(StatusCode::Ok).with_chunks(chunks),
+                _ => Response::new(StatusCode::Ok).with_body(data.clone()),
+            }
+        });
+        let stack = stack(Proxy::new("/", [addr]));
+
+        // 上游的分块编码由发给客户端时的 Response::send 重新编码。
+        let response = send(&stack, Method::Get, "/chunked", &[]);
+        assert!(matches!(response.body(), Body::Stream(_)));
+        assert!(!response.headers().contains("Transfer-Encoding"));
+        assert_eq!(read_bytes(response), expected);
+
+        let response = send(&stack, Method::Get, "/fixed", &[]);
+        assert!(matches!(response.body(), Body::Reader { .. }));
+        assert_eq!(
+            response.body().content_length(),
+            Some(expected.len() as u64)
+        );
+        assert_eq!(read_bytes(response), expected);
+
+        let response = send(&stack, Method::Head, "/fixed", &[]);
+        assert_eq!(
+            response.body().content_length(),
+            Some(expected.len() as u64)
+        );
+    }
+
+    #[test]
+    fn balances_across_upstreams_and_skips_dead_ones() {
+        let stack = stack(Proxy::new("/", [echo("a"), dead(), echo("b")]).max_failures(100));
+
+        let mut seen: Vec<_> = (0..6).map(|_| get(&stack, "/")[..1].to_string()).collect();
+        assert!(
+            seen.iter().all(|name| name == "a" || name == "b"),
+            "{seen:?}"
+        );
+        seen.sort();
+        seen.dedup();
+        assert_eq!(seen, ["a", "b"]);
+    }
+
+    #[test]
+    fn relays_any_status_code_without_ejecting() {
+        let teapot = upstream(|request: Request| {
+            let code = request.path()[1..].parse().unwrap();
+            Response::text(StatusCode::from_code(code).unwrap(), "teapot")
+        });
+        let stack = stack(Proxy::new("/", [teapot]).max_failures(1));
+
+        for code in [418, 451, 299, 599] {
+            let response = send(&stack, Method::Get, &format!("/{code}"), &[]);
+            assert_eq!(response.status().as_u16(), code);
+            assert_eq!(read_body(response), "teapot");
+        }
+        // 500 也是上游自己的回应，不算失败。
+        assert_eq!(send(&stack, Method::Get, "/500", &[]).status(), 500);
+        assert_eq!(send(&stack, Method::Get, "/418", &[]).status(), 418);
+    }
+
+    #[test]
+    fn ejects_failing_upstreams_for_a_while() {
+        let failing = upstream(|_| Response::plain(StatusCode::ServiceUnavailable));
+        let stack = stack(
+            Proxy::new("/", [failing, echo("b")])
+                .max_failures(2)
+                .eject_for(Duration::from_millis(300)),
+        );
+
+        // 轮流时 failing 两次失败之后被暂停，之后的请求都交给 b。
+        let statuses: Vec<_> = (0..4)
+            .map(|_| send(&stack, Method::Get, "/", &[]).status())
+            .collect();
+        assert_eq!(statuses.iter().filter(|s| **s != StatusCode::Ok).count(), 2);
+        for _ in 0..4 {
+            assert!(get(&stack, "/").starts_with("b "));
+        }
+
+        // 暂停期满后再试一次，仍然失败就立即再次暂停。
+        thread::sleep(Duration::from_millis(400));
+        let statuses: Vec<_> = (0..4)
+            .map(|_| send(&stack, Method::Get, "/", &[]).status())
+            .collect();
+        assert_eq!(statuses.iter().filter(|s| **s != StatusCode::Ok).count(), 1);
+    }
+
+    #[test]
+    fn reports_upstream_errors() {
+        let stack = stack(Proxy::new("/", [dead(), dead()]).max_failures(2));
+        let status = |stack: &Stack| send(stack, Method::Get, "/", &[]).status();
+
+        assert_eq!(status(&stack), StatusCode::BadGateway);
+        // 每个上游都失败了两次，全部暂停。
+        assert_eq!(status(&stack), StatusCode::BadGateway);
+        assert_eq!(status(&stack), StatusCode::ServiceUnavailable);
+
+        let slow = upstream(|_| {
+            thread::sleep(Duration::from_millis(500));
+            Response::plain(StatusCode::Ok)
+        });
+        let stack = self::stack(Proxy::new("/", [slow]).timeout(Some(Duration::from_millis(100))));
+        assert_eq!(status(&stack), StatusCode::GatewayTimeout);
+
+        // 上游在发送响应头部之前关闭连接。
+        let stack = self::stack(Proxy::new("/", [raw(b"garbage\r\n\r\n")]));
+        assert_eq!(status(&stack), StatusCode::BadGateway);
+
+        // 1xx 不能作为最终的响应，升级也不会被转发。
+        let switching = raw(b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\r\n");
+        let stack = self::stack(Proxy::new("/", [switching]).max_failures(1));
+        assert_eq!(status(&stack), StatusCode::BadGateway);
+        assert_eq!(status(&stack), StatusCode::BadGateway);
+    }
+
+    #[test]
+    fn ejects_upstreams_that_fail_mid_body() {
+        let truncated = raw(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\npartial");
+        let stack = stack(Proxy::new("/", [truncated]).max_failures(1));
+
+        let mut response = send(&stack, Method::Get, "/", &[]);
+        assert_eq!(response.status(), StatusCode::Ok);
+        let Body::Reader { mut reader, .. } =
+            std::mem::replace(response.body_mut(), Body::Bytes(Vec::new()))
+        else {
+            panic!("响应体应该边读边发");
+        };
+        let error = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+
+        // 读取响应体时的失败同样会让上游被暂停。
+        assert_eq!(
+            send(&stack, Method::Get, "/", &[]).status(),
+            StatusCode::ServiceUnavailable
+        );
+    }
+}
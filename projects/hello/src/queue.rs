@@ -0,0 +1,341 @@
+use std::{
+    collections::VecDeque,
+    ptr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Condvar, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::pool::{ExecuteError, Job};
+
+/// 找不到作业时，worker 在睡眠之前让出 CPU 的次数。
+const SPINS_BEFORE_SLEEP: u32 = 16;
+
+/// 提交作业时，队列已满该如何处理。
+pub(crate) enum Wait {
+    /// 一直等到有空位。
+    Forever,
+    /// 立即返回 [`ExecuteError::Full`]。
+    Never,
+    /// 最多等待这么久，之后返回 [`ExecuteError::Timeout`]。
+    Timeout(Duration),
+}
+
+/// 作业被拒绝的原因；拿到被拒绝的作业后再转换为 [`ExecuteError`]。
+enum Reject {
+    Full,
+    Timeout,
+    Closed,
+}
+
+impl Reject {
+    fn with<T>(self, item: T) -> ExecuteError<T> {
+        match self {
+            Reject::Full => ExecuteError::Full(item),
+            Reject::Timeout => ExecuteError::Timeout(item),
+            Reject::Closed => ExecuteError::Closed(item),
+        }
+    }
+}
+
+/// 队列中的作业及其入队时间。
+pub(crate) struct Queued {
+    pub(crate) job: Job,
+    pub(crate) since: Instant,
+}
+
+/// [`JobQueue::pop`] 的结果。
+pub(crate) enum Pop {
+    Job(Queued),
+    /// 在 keep-alive 期限内没有等到作业。
+    Idle,
+    /// 队列已关闭，并且所有作业都已取走。
+    Closed,
+}
+
+/// 某个 worker 自己的作业队列。所有者从队首取作业，其他 worker 从队尾窃取。
+pub(crate) struct Local {
+    jobs: Mutex<VecDeque<Queued>>,
+}
+
+impl Local {
+    fn lock(&self) -> MutexGuard<'_, VecDeque<Queued>> {
+        // 作业在锁外执行，所以即便有线程在持锁时 panic，队列本身也是完好的。
+        self.jobs.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+/// 线程池的作业队列：每个 worker 一个本地队列，提交的作业轮流放入各个本地队列，
+/// 自己的队列为空的 worker 从其他队列窃取作业。
+///
+/// 容量限制的是所有本地队列中的作业总数。
+pub(crate) struct JobQueue {
+    locals: RwLock<Vec<Arc<Local>>>,
+    /// 下一个作业放入的本地队列。
+    next: AtomicUsize,
+    /// 已占位、尚未被取走的作业数。提交者先占位再放入作业，因此它可能暂时多于
+    /// 各个本地队列中作业的总数。
+    len: AtomicUsize,
+    capacity: Option<usize>,
+    closed: AtomicBool,
+    /// 正在等待作业的 worker 数。
+    sleepers: AtomicUsize,
+    sleep: Mutex<()>,
+    not_empty: Condvar,
+    room: Mutex<()>,
+    not_full: Condvar,
+}
+
+fn relock<T>(guard: Result<T, std::sync::PoisonError<T>>) -> T {
+    guard.unwrap_or_else(|e| e.into_inner())
+}
+
+impl JobQueue {
+    pub(crate) fn new(capacity: Option<usize>) -> JobQueue {
+        JobQueue {
+            locals: RwLock::new(Vec::new()),
+            next: AtomicUsize::new(0),
+            len: AtomicUsize::new(0),
+            capacity,
+            closed: AtomicBool::new(false),
+            sleepers: AtomicUsize::new(0),
+            sleep: Mutex::new(()),
+            not_empty: Condvar::new(),
+            room: Mutex::new(()),
+            not_full: Condvar::new(),
+        }
+    }
+
+    fn locals(&self) -> RwLockReadGuard<'_, Vec<Arc<Local>>> {
+        relock(self.locals.read())
+    }
+
+    fn locals_mut(&self) -> RwLockWriteGuard<'_, Vec<Arc<Local>>> {
+        relock(self.locals.write())
+    }
+
+    /// 为一个新的 worker 创建本地队列。
+    pub(crate) fn add_local(&self) -> Arc<Local> {
+        let local = Arc::new(Local {
+            jobs: Mutex::new(VecDeque::new()),
+        });
+        self.locals_mut().push(Arc::clone(&local));
+        local
+    }
+
+    /// 移除某个 worker 的本地队列，其中剩下的作业转到其他队列。
+    ///
+    /// 至少保留一个本地队列；`local` 是最后一个时返回 `false`。
+    pub(crate) fn remove_local(&self, local: &Arc<Local>) -> bool {
+        let mut locals = self.locals_mut();
+        if locals.len() <= 1 {
+            return false;
+        }
+        let Some(i) = locals.iter().position(|l| Arc::ptr_eq(l, local)) else {
+            return false;
+        };
+        locals.remove(i);
+
+        // 持有写锁时没有提交者能再往这个队列里放作业。
+        let orphans: Vec<Queued> = local.lock().drain(..).collect();
+        if !orphans.is_empty() {
+            locals[0].lock().extend(orphans);
+            drop(locals);
+            self.wake_one();
+        }
+        true
+    }
+
+    /// 把 `item` 放入队列，被拒绝时原样交还。
+    ///
+    /// 只在确定入队后才用 `into_job` 把 `item` 包装成作业，因此被拒绝时交还的是未经包装的
+    /// `item`。
+    pub(crate) fn push_with<T>(
+        &self,
+        item: T,
+        wait: Wait,
+        into_job: impl FnOnce(T) -> Job,
+    ) -> Result<(), ExecuteError<T>> {
+        if let Err(reject) = self.reserve(wait) {
+            return Err(reject.with(item));
+        }
+
+        let job = Queued {
+            job: into_job(item),
+            since: Instant::now(),
+        };
+        {
+            let locals = self.locals();
+            let i = self.next.fetch_add(1, Ordering::Relaxed) % locals.len();
+            locals[i].lock().push_back(job);
+        }
+
+        self.wake_one();
+        Ok(())
+    }
+
+    /// 为一个作业占位，必要时按 `wait` 等待空位。
+    fn reserve(&self, wait: Wait) -> Result<(), Reject> {
+        let deadline = match wait {
+            Wait::Timeout(timeout) => Some(Instant::now() + timeout),
+            _ => None,
+        };
+
+        loop {
+            if self.is_closed() {
+                return Err(Reject::Closed);
+            }
+
+            let len = self.len.load(Ordering::SeqCst);
+            let Some(capacity) = self.capacity.filter(|&cap| len >= cap) else {
+                if self
+                    .len
+                    .compare_exchange_weak(len, len + 1, Ordering::SeqCst, Ordering::Relaxed)
+                    .is_err()
+                {
+                    continue;
+                }
+                // 占位之后再检查一次，以免在关闭之后放入作业。
+                if self.is_closed() {
+                    self.release();
+                    return Err(Reject::Closed);
+                }
+                return Ok(());
+            };
+
+            // 在锁内重新检查，`release` 与 `close` 在通知之前都会获取这把锁。
+            let room = relock(self.room.lock());
+            if self.len.load(Ordering::SeqCst) < capacity || self.is_closed() {
+                continue;
+            }
+
+            match wait {
+                Wait::Never => return Err(Reject::Full),
+                Wait::Forever => drop(relock(self.not_full.wait(room))),
+                Wait::Timeout(_) => {
+                    let now = Instant::now();
+                    let deadline = deadline.unwrap_or(now);
+                    if now >= deadline {
+                        return Err(Reject::Timeout);
+                    }
+                    drop(relock(self.not_full.wait_timeout(room, deadline - now)));
+                }
+            }
+        }
+    }
+
+    /// 归还一个位置。
+    fn release(&self) {
+        self.len.fetch_sub(1, Ordering::SeqCst);
+        if self.capacity.is_some() {
+            drop(relock(self.room.lock()));
+            self.not_full.notify_one();
+        }
+    }
+
+    /// 有 worker 在等待时唤醒其中一个。
+    fn wake_one(&self) {
+        if self.sleepers.load(Ordering::SeqCst) > 0 {
+            drop(relock(self.sleep.lock()));
+            self.not_empty.notify_one();
+        }
+    }
+
+    /// 先从 `local` 的队首取作业，没有时再从其他队列的队尾窃取。
+    fn take(&self, local: &Local) -> Option<Queued> {
+        if let Some(job) = local.lock().pop_front() {
+            return Some(job);
+        }
+
+        let locals = self.locals();
+        let start = locals
+            .iter()
+            .position(|l| ptr::eq(&**l, local))
+            .map_or(0, |i| i + 1);
+
+        locals
+            .iter()
+            .cycle()
+            .skip(start)
+            .take(locals.len())
+            .find_map(|other| other.lock().pop_back())
+    }
+
+    /// 为持有 `local` 的 worker 取出下一个作业。
+    ///
+    /// 没有作业时等待；给出 `keep_alive` 时，等待这么久仍没有作业则返回 [`Pop::Idle`]。
+    pub(crate) fn pop(&self, local: &Local, keep_alive: Option<Duration>) -> Pop {
+        let mut spins = 0;
+
+        loop {
+            if let Some(job) = self.take(local) {
+                self.release();
+                return Pop::Job(job);
+            }
+
+            if self.len.load(Ordering::SeqCst) > 0 {
+                // 作业已经占位但还没有放进队列，或者正从被移除的队列中转移出来。
+                thread::yield_now();
+                continue;
+            }
+            if self.is_closed() {
+                return Pop::Closed;
+            }
+            // 作业往往接连到来；先让出几次 CPU，比立刻睡眠再被唤醒便宜得多。
+            if spins < SPINS_BEFORE_SLEEP {
+                spins += 1;
+                thread::yield_now();
+                continue;
+            }
+
+            let sleep = relock(self.sleep.lock());
+            // 先登记再检查：提交者要么看到这里的登记并唤醒我们，要么它的作业已经被这里看到。
+            self.sleepers.fetch_add(1, Ordering::SeqCst);
+            if self.len.load(Ordering::SeqCst) > 0 || self.is_closed() {
+                self.sleepers.fetch_sub(1, Ordering::SeqCst);
+                continue;
+            }
+
+            let timed_out = match keep_alive {
+                Some(timeout) => relock(self.not_empty.wait_timeout(sleep, timeout))
+                    .1
+                    .timed_out(),
+                None => {
+                    drop(relock(self.not_empty.wait(sleep)));
+                    false
+                }
+            };
+            self.sleepers.fetch_sub(1, Ordering::SeqCst);
+
+            if timed_out && self.len.load(Ordering::SeqCst) == 0 {
+                return Pop::Idle;
+            }
+        }
+    }
+
+    /// 不再接受新作业。已在队列中的作业仍会被取出执行。
+    pub(crate) fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+
+        drop(relock(self.sleep.lock()));
+        self.not_empty.notify_all();
+        drop(relock(self.room.lock()));
+        self.not_full.notify_all();
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len.load(Ordering::SeqCst)
+    }
+
+    /// 正在等待作业的 worker 数。
+    pub(crate) fn sleepers(&self) -> usize {
+        self.sleepers.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,936 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, BufRead, Read, Take},
+    net::SocketAddr,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    chunked::{ChunkedReader, TrailerTooLarge},
+    headers::Headers,
+};
+
+/// HTTP 请求方法。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// 方法名区分大小写（RFC 9110 第 9.1 节）。
+    fn from_str(s: &str) -> Result<Method, ParseError> {
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "CONNECT" => Ok(Method::Connect),
+            "OPTIONS" => Ok(Method::Options),
+            "TRACE" => Ok(Method::Trace),
+            "PATCH" => Ok(Method::Patch),
+            other => Err(ParseError::UnsupportedMethod(other.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// HTTP 协议版本。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl Version {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Version::Http10 => "HTTP/1.0",
+            Version::Http11 => "HTTP/1.1",
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// 解析请求时可能出现的错误。
+#[derive(Debug)]
+pub enum ParseError {
+    /// 读取底层流时出错。
+    Io(io::Error),
+    /// 对端在发送任何字节之前就关闭了连接。
+    ConnectionClosed,
+    /// 请求尚未完整，流就结束了。
+    UnexpectedEof,
+    /// 请求行不是 `方法 目标 版本` 的形式。
+    MalformedRequestLine(String),
+    UnsupportedMethod(String),
+    UnsupportedVersion(String),
+    /// 请求目标既不是路径，也不是绝对 URI。
+    InvalidTarget(String),
+    MalformedHeader(String),
+    InvalidContentLength(String),
+    UnsupportedTransferEncoding(String),
+    /// 分块编码的请求体格式错误。
+    MalformedChunk(String),
+    /// 请求行与头部合计超过了 [`Limits::max_header_size`]。
+    HeaderTooLarge { limit: usize },
+    /// 请求体超过了 [`Limits::max_body_size`]。
+    BodyTooLarge { limit: u64 },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Io(e) => write!(f, "读取请求失败：{e}"),
+            ParseError::ConnectionClosed => write!(f, "连接已关闭"),
+            ParseError::UnexpectedEof => write!(f, "请求不完整"),
+            ParseError::MalformedRequestLine(line) => write!(f, "请求行格式错误：{line:?}"),
+            ParseError::UnsupportedMethod(m) => write!(f, "不支持的方法：{m:?}"),
+            ParseError::UnsupportedVersion(v) => write!(f, "不支持的 HTTP 版本：{v:?}"),
+            ParseError::InvalidTarget(t) => write!(f, "请求目标无效：{t:?}"),
+            ParseError::MalformedHeader(h) => write!(f, "头部格式错误：{h:?}"),
+            ParseError::InvalidContentLength(v) => write!(f, "Content-Length 无效：{v:?}"),
+            ParseError::UnsupportedTransferEncoding(v) => {
+                write!(f, "不支持的 Transfer-Encoding：{v:?}")
+            }
+            ParseError::MalformedChunk(msg) => write!(f, "分块编码错误：{msg}"),
+            ParseError::HeaderTooLarge { limit } => {
+                write!(f, "请求行与头部超过 {limit} 字节的上限")
+            }
+            ParseError::BodyTooLarge { limit } => write!(f, "请求体超过 {limit} 字节的上限"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        match e.kind() {
+            io::ErrorKind::UnexpectedEof => ParseError::UnexpectedEof,
+            _ => ParseError::Io(e),
+        }
+    }
+}
+
+/// 解析请求时施加的限制。默认不做限制。
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// 请求行与全部头部（含换行符）合计的最大字节数。
+    pub max_header_size: Option<usize>,
+    /// 请求体的最大字节数；分块编码的请求体按解码后的长度计算。
+    pub max_body_size: Option<u64>,
+}
+
+/// 一个已解析的 HTTP/1.x 请求。
+#[derive(Clone, Debug)]
+pub struct Request {
+    method: Method,
+    target: String,
+    path: String,
+    query: Vec<(String, String)>,
+    version: Version,
+    headers: Headers,
+    body: Vec<u8>,
+    body_stream: Option<RequestBody>,
+    params: Vec<(String, String)>,
+    peer_addr: Option<SocketAddr>,
+    secure: bool,
+}
+
+impl Request {
+    /// 构造一个没有头部和请求体的请求，`target` 为路径加可选的查询字符串。
+    pub fn new(method: Method, target: &str) -> Result<Request, ParseError> {
+        let (path, query) = parse_target(target)?;
+
+        Ok(Request {
+            method,
+            target: target.to_string(),
+            path,
+            query,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            body_stream: None,
+            params: Vec::new(),
+            peer_addr: None,
+            secure: false,
+        })
+    }
+
+    /// 从 `reader` 中读取并解析一个请求。
+    ///
+    /// 请求体的长度由 `Content-Length` 决定，或者按 `Transfer-Encoding: chunked`
+    /// 解码；两者都没有时请求体为空。分块编码的请求体解码后，`Transfer-Encoding`
+    /// 会被替换为相应的 `Content-Length`。
+    pub fn parse<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Request::parse_with_limits(reader, &Limits::default())
+    }
+
+    /// 与 [`Request::parse`] 相同，但请求超出 `limits` 时返回错误。
+    ///
+    /// `Content-Length` 超限的请求在读取请求体之前就被拒绝。
+    pub fn parse_with_limits<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut request = Request::parse_head(reader, limits)?;
+        request.read_body(reader, limits)?;
+        Ok(request)
+    }
+
+    /// 只读取请求行与头部，请求体留在 `reader` 中，由 [`Request::read_body`] 读取。
+    ///
+    /// 连接分别为两个阶段设置超时，因此把它们分开。
+    pub(crate) fn parse_head<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut head = HeadReader {
+            limit: limits.max_header_size,
+            used: 0,
+        };
+
+        // RFC 9112 第 2.2 节：应忽略请求行之前的空行。
+        let request_line = loop {
+            match head.read_line(reader)? {
+                None => return Err(ParseError::ConnectionClosed),
+                Some(line) if line.is_empty() => continue,
+                Some(line) => break line,
+            }
+        };
+
+        let (method, target, version) = parse_request_line(&request_line)?;
+
+        let mut request = Request::new(method, target)?;
+        request.version = version;
+        request.headers = parse_headers(reader, &mut head)?;
+        Ok(request)
+    }
+
+    /// 请求是否带有请求体：有 `Transfer-Encoding`，或者 `Content-Length` 不为 0。
+    pub(crate) fn has_body(&self) -> bool {
+        self.headers.contains("Transfer-Encoding")
+            || !matches!(content_length(&self.headers), Ok(None | Some(0)))
+    }
+
+    /// 不读取请求体，而是把 `reader` 中的请求体交给处理器边读边取，见
+    /// [`Request::take_body_stream`]。
+    ///
+    /// `Content-Length` 超限或者传输编码不受支持的请求在这里就被拒绝。
+    pub(crate) fn stream_body(
+        &mut self,
+        reader: impl BufRead + Send + 'static,
+        limits: &Limits,
+    ) -> Result<(), ParseError> {
+        self.body_stream = Some(RequestBody::new(reader, &self.headers, limits)?);
+        Ok(())
+    }
+
+    /// 按头部给出的长度读取请求体。
+    pub(crate) fn read_body<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<(), ParseError> {
+        if self.headers.contains("Transfer-Encoding") {
+            self.body = read_chunked_body(reader, &self.headers, limits)?;
+            self.headers.remove("Transfer-Encoding");
+            self.headers.insert("Content-Length", self.body.len().to_string());
+        } else if let Some(length) = content_length(&self.headers)? {
+            if let Some(limit) = limits.max_body_size.filter(|&limit| length > limit) {
+                return Err(ParseError::BodyTooLarge { limit });
+            }
+
+            let mut body = Vec::new();
+            reader.take(length).read_to_end(&mut body)?;
+
+            if (body.len() as u64) < length {
+                return Err(ParseError::UnexpectedEof);
+            }
+            self.body = body;
+        }
+
+        Ok(())
+    }
+
+    pub fn method(&self) -> Method {
+        self.method
+    }
+
+    /// 请求行中原样的请求目标，比如 `/search?q=rust%20lang`。
+    pub fn target(&self) -> &str {
+        &self.target
+    }
+
+    /// 已做百分号解码的路径部分，不含查询字符串。
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// 返回名为 `name` 的第一个查询参数。
+    pub fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    /// 按出现顺序返回全部查询参数。
+    pub fn query_pairs(&self) -> &[(String, String)] {
+        &self.query
+    }
+
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// 已经读进内存的请求体；请求体交给处理器边读边取时为空。
+    pub fn body(&self) -> &[u8] {
+        &self.body
+    }
+
+    /// 取走还留在连接上的请求体。
+    ///
+    /// 只有处理器的 [`streams_body`](crate::Handler::streams_body) 为这个请求返回真、
+    /// 而且请求带有请求体时才有，否则返回 `None`，请求体在 [`Request::body`] 中。
+    pub fn take_body_stream(&mut self) -> Option<RequestBody> {
+        self.body_stream.take()
+    }
+
+    pub(crate) fn body_stream(&self) -> Option<&RequestBody> {
+        self.body_stream.as_ref()
+    }
+
+    pub(crate) fn set_body_stream(&mut self, body: RequestBody) {
+        self.body_stream = Some(body);
+    }
+
+    pub fn set_body(&mut self, body: Vec<u8>) {
+        self.body = body;
+    }
+
+    /// 取走请求体，免得转发时再复制一份。
+    pub fn into_body(self) -> Vec<u8> {
+        self.body
+    }
+
+    /// 返回路由匹配得到的路径参数，比如模式 `/users/:id` 中的 `id`。
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.as_str())
+    }
+
+    pub fn params(&self) -> &[(String, String)] {
+        &self.params
+    }
+
+    pub(crate) fn set_params(&mut self, params: Vec<(String, String)>) {
+        self.params = params;
+    }
+
+    /// 发来这个请求的连接的对端地址；请求不是从连接上读到的时候为 `None`。
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// 请求是否经由 TLS 连接到达。
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+
+    /// 记下请求来自哪个连接，由服务器在读到请求后设置。
+    pub(crate) fn set_connection(&mut self, peer_addr: Option<SocketAddr>, secure: bool) {
+        self.peer_addr = peer_addr;
+        self.secure = secure;
+    }
+}
+
+/// 逐行读取请求行与头部，并累计已读的字节数。
+struct HeadReader {
+    limit: Option<usize>,
+    used: usize,
+}
+
+impl HeadReader {
+    /// 读取一行，去掉行尾的 `\r\n` 或 `\n`。流在行首就结束时返回 `None`。
+    ///
+    /// 最多只读到上限多一个字节，超长的一行不会被整个读进内存。
+    fn read_line<R: BufRead>(&mut self, reader: &mut R) -> Result<Option<String>, ParseError> {
+        let remaining = self.limit.map_or(u64::MAX, |limit| (limit - self.used) as u64 + 1);
+        let mut buf = Vec::new();
+        let n = (&mut *reader).take(remaining).read_until(b'\n', &mut buf)?;
+
+        self.used += n;
+        if let Some(limit) = self.limit.filter(|&limit| self.used > limit) {
+            return Err(ParseError::HeaderTooLarge { limit });
+        }
+        if n == 0 {
+            return Ok(None);
+        }
+        if buf.last() != Some(&b'\n') {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        buf.pop();
+        if buf.last() == Some(&b'\r') {
+            buf.pop();
+        }
+
+        String::from_utf8(buf).map(Some).map_err(|e| {
+            ParseError::MalformedHeader(String::from_utf8_lossy(e.as_bytes()).into_owned())
+        })
+    }
+}
+
+fn parse_request_line(line: &str) -> Result<(Method, &str, Version), ParseError> {
+    let malformed = || ParseError::MalformedRequestLine(line.to_string());
+
+    let mut parts = line.split(' ');
+    let (Some(method), Some(target), Some(version), None) =
+        (parts.next(), parts.next(), parts.next(), parts.next())
+    else {
+        return Err(malformed());
+    };
+
+    if method.is_empty() || target.is_empty() {
+        return Err(malformed());
+    }
+
+    let method = method.parse()?;
+    let version = match version {
+        "HTTP/1.1" => Version::Http11,
+        "HTTP/1.0" => Version::Http10,
+        v if v.starts_with("HTTP/") => {
+            return Err(ParseError::UnsupportedVersion(v.to_string()));
+        }
+        _ => return Err(malformed()),
+    };
+
+    Ok((method, target, version))
+}
+
+fn parse_headers<R: BufRead>(reader: &mut R, head: &mut HeadReader) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+
+    loop {
+        let line = head.read_line(reader)?.ok_or(ParseError::UnexpectedEof)?;
+
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(ParseError::MalformedHeader(line));
+        };
+
+        // 头部名与冒号之间不允许有空白，也不接受已废弃的折行写法。
+        if name.is_empty() || !name.bytes().all(is_token_byte) {
+            return Err(ParseError::MalformedHeader(line));
+        }
+
+        headers.append(name, value.trim_matches(|c| c == ' ' || c == '\t'));
+    }
+}
+
+fn content_length(headers: &Headers) -> Result<Option<u64>, ParseError> {
+    let mut length = None;
+
+    for value in headers.get_all("Content-Length") {
+        for v in value.split(',') {
+            let v = v.trim();
+            let parsed = v
+                .parse::<u64>()
+                .ok()
+                .filter(|_| v.bytes().all(|b| b.is_ascii_digit()))
+                .ok_or_else(|| ParseError::InvalidContentLength(value.to_string()))?;
+
+            match length {
+                Some(prev) if prev != parsed => {
+                    return Err(ParseError::InvalidContentLength(value.to_string()));
+                }
+                _ => length = Some(parsed),
+            }
+        }
+    }
+
+    Ok(length)
+}
+
+/// 还留在连接上、由处理器边读边取的请求体，见 [`Request::take_body_stream`]。
+///
+/// 读到请求体的末尾时返回 EOF。读取失败时，错误中携带相应的 [`ParseError`]：连接在
+/// 请求体结束之前关闭时是 [`ParseError::UnexpectedEof`]，分块编码的请求体超过
+/// [`Limits::max_body_size`] 时是 [`ParseError::BodyTooLarge`]，可以交给
+/// [`error_response`](crate::connection::error_response) 选择响应。读取超时时返回
+/// `TimedOut` 或 `WouldBlock` 错误。
+///
+/// 请求体读完或者放下之后，连接不再用于下一个请求。克隆的请求共用同一个请求体。
+#[derive(Clone)]
+pub struct RequestBody(Arc<Mutex<BodyState>>);
+
+struct BodyState {
+    framing: BodyFraming,
+    /// 已经读到的请求体字节数。
+    read: u64,
+    limit: Option<u64>,
+}
+
+enum BodyFraming {
+    Length(Take<Box<dyn BufRead + Send>>),
+    Chunked(ChunkedReader<Box<dyn BufRead + Send>>),
+}
+
+impl RequestBody {
+    fn new(
+        reader: impl BufRead + Send + 'static,
+        headers: &Headers,
+        limits: &Limits,
+    ) -> Result<RequestBody, ParseError> {
+        let reader: Box<dyn BufRead + Send> = Box::new(reader);
+        let framing = if headers.contains("Transfer-Encoding") {
+            check_chunked(headers)?;
+            BodyFraming::Chunked(
+                ChunkedReader::new(reader).max_trailer_size(limits.max_header_size),
+            )
+        } else {
+            let length = content_length(headers)?.unwrap_or(0);
+            if let Some(limit) = limits.max_body_size.filter(|&limit| length > limit) {
+                return Err(ParseError::BodyTooLarge { limit });
+            }
+            BodyFraming::Length(reader.take(length))
+        };
+
+        Ok(RequestBody(Arc::new(Mutex::new(BodyState {
+            framing,
+            read: 0,
+            limit: limits.max_body_size,
+        }))))
+    }
+}
+
+impl BodyState {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, ParseError> {
+        let n = match &mut self.framing {
+            BodyFraming::Length(reader) => match reader.read(buf)? {
+                0 if reader.limit() > 0 && !buf.is_empty() => {
+                    return Err(ParseError::UnexpectedEof);
+                }
+                n => n,
+            },
+            BodyFraming::Chunked(reader) => reader.read(buf).map_err(chunk_error)?,
+        };
+
+        self.read += n as u64;
+        match self.limit {
+            Some(limit) if self.read > limit => Err(ParseError::BodyTooLarge { limit }),
+            _ => Ok(n),
+        }
+    }
+}
+
+impl Read for RequestBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut state = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        state.read(buf).map_err(|e| match e {
+            ParseError::Io(e) => e,
+            ParseError::UnexpectedEof => io::Error::new(io::ErrorKind::UnexpectedEof, e),
+            e => io::Error::new(io::ErrorKind::InvalidData, e),
+        })
+    }
+}
+
+impl fmt::Debug for RequestBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("RequestBody")
+    }
+}
+
+/// 检查分块编码的请求体的头部：只支持 `chunked` 一种传输编码，也不能同时带有
+/// `Content-Length`。
+fn check_chunked(headers: &Headers) -> Result<(), ParseError> {
+    let codings: Vec<String> = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|v| v.split(','))
+        .map(|c| c.trim().to_ascii_lowercase())
+        .filter(|c| !c.is_empty())
+        .collect();
+
+    if codings != ["chunked"] {
+        return Err(ParseError::UnsupportedTransferEncoding(codings.join(", ")));
+    }
+
+    // 同时带有两种长度信息的请求可能被用来走私请求（RFC 9112 第 6.3 节），直接拒绝。
+    if headers.contains("Content-Length") {
+        return Err(ParseError::MalformedHeader(String::from(
+            "Transfer-Encoding 与 Content-Length 不能同时出现",
+        )));
+    }
+    Ok(())
+}
+
+/// 把解码分块时的错误转换为相应的 [`ParseError`]。
+fn chunk_error(e: io::Error) -> ParseError {
+    let trailer = e
+        .get_ref()
+        .and_then(|e| e.downcast_ref::<TrailerTooLarge>());
+    match (trailer, e.kind()) {
+        (Some(&TrailerTooLarge { limit }), _) => ParseError::HeaderTooLarge { limit },
+        (None, io::ErrorKind::InvalidData) => ParseError::MalformedChunk(e.to_string()),
+        (None, _) => ParseError::from(e),
+    }
+}
+
+fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    headers: &Headers,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    check_chunked(headers)?;
+
+    // 多读一个字节，才能区分恰好达到上限与超出上限。尾部字段与头部共用一个上限。
+    let limit = limits.max_body_size;
+    let mut body = Vec::new();
+    ChunkedReader::new(reader)
+        .max_trailer_size(limits.max_header_size)
+        .take(limit.map_or(u64::MAX, |limit| limit.saturating_add(1)))
+        .read_to_end(&mut body)
+        .map_err(chunk_error)?;
+
+    match limit {
+        Some(limit) if body.len() as u64 > limit => Err(ParseError::BodyTooLarge { limit }),
+        _ => Ok(body),
+    }
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+/// 把请求目标拆分为解码后的路径和查询参数。
+fn parse_target(target: &str) -> Result<(String, Vec<(String, String)>), ParseError> {
+    let invalid = || ParseError::InvalidTarget(target.to_string());
+
+    if target == "*" {
+        return Ok((target.to_string(), Vec::new()));
+    }
+
+    // 绝对形式（`http://host/path` 或 `https://host/path`）只取其路径部分。
+    let origin = match target.split_once("://") {
+        Some((scheme, rest))
+            if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") =>
+        {
+            rest.find('/').map_or("/", |i| &rest[i..])
+        }
+        Some(_) => return Err(invalid()),
+        None => target,
+    };
+
+    if !origin.starts_with('/') {
+        return Err(invalid());
+    }
+
+    let origin = origin.split('#').next().unwrap_or_default();
+    let (raw_path, raw_query) = match origin.split_once('?') {
+        Some((p, q)) => (p, Some(q)),
+        None => (origin, None),
+    };
+
+    let path = percent_decode(raw_path, false).ok_or_else(invalid)?;
+
+    let mut query = Vec::new();
+    for pair in raw_query.unwrap_or_default().split('&') {
+        if pair.is_empty() {
+            continue;
+        }
+        let (k, v) = pair.split_once('=').unwrap_or((pair, ""));
+        query.push((
+            percent_decode(k, true).ok_or_else(invalid)?,
+            percent_decode(v, true).ok_or_else(invalid)?,
+        ));
+    }
+
+    Ok((path, query))
+}
+
+/// 百分号解码。`plus_as_space` 为真时把 `+` 解码为空格（用于查询字符串）。
+fn percent_decode(s: &str, plus_as_space: bool) -> Option<String> {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                // `from_str_radix` 接受前导的 `+`，`%+1` 不能当作合法的转义。
+                let hex = bytes.get(i + 1..i + 3)?;
+                if !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let hex = std::str::from_utf8(hex).ok()?;
+                out.push(u8::from_str_radix(hex, 16).ok()?);
+                i += 3;
+            }
+            b'+' if plus_as_space => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8(out).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn parses_request_line_query_and_headers() {
+        let request = parse(
+            "GET /search%20page?q=rust+lang&page=2&flag HTTP/1.1\r\n\
+             Host: localhost:7878\r\n\
+             Accept: text/html\r\n\
+             accept: text/plain\r\n\
+             \r\n",
+        )
+        .unwrap();
+
+        assert_eq!(request.method(), Method::Get);
+        assert_eq!(request.path(), "/search page");
+        assert_eq!(request.query("q"), Some("rust lang"));
+        assert_eq!(request.query("page"), Some("2"));
+        assert_eq!(request.query("flag"), Some(""));
+        assert_eq!(request.version(), Version::Http11);
+        assert_eq!(request.header("host"), Some("localhost:7878"));
+        assert_eq!(
+            request.headers().get_all("ACCEPT").collect::<Vec<_>>(),
+            vec!["text/html", "text/plain"]
+        );
+        assert!(request.body().is_empty());
+    }
+
+    #[test]
+    fn takes_the_path_of_absolute_form_targets() {
+        for target in ["http://example.com/a%20b?x=1", "HTTPS://example.com:8443/a%20b?x=1"] {
+            let request = parse(&format!("GET {target} HTTP/1.1\r\n\r\n")).unwrap();
+            assert_eq!(request.path(), "/a b");
+            assert_eq!(request.query("x"), Some("1"));
+        }
+
+        let request = parse("GET http://example.com HTTP/1.1\r\n\r\n").unwrap();
+        assert_eq!(request.path(), "/");
+        assert!(matches!(
+            parse("GET ftp://example.com/ HTTP/1.1\r\n\r\n"),
+            Err(ParseError::InvalidTarget(_))
+        ));
+    }
+
+    #[test]
+    fn reads_body_by_content_length() {
+        let mut raw = "POST /echo HTTP/1.0\r\nContent-Length: 5\r\n\r\nhelloGET".as_bytes();
+        let request = Request::parse(&mut raw).unwrap();
+
+        assert_eq!(request.body(), b"hello");
+        assert_eq!(request.version(), Version::Http10);
+        assert_eq!(raw, b"GET");
+    }
+
+    #[test]
+    fn decodes_chunked_body() {
+        let mut raw = "POST /upload HTTP/1.1\r\n\
+                       Transfer-Encoding: chunked\r\n\
+                       \r\n\
+                       5\r\nhello\r\n7\r\n, world\r\n0\r\n\r\n\
+                       GET"
+            .as_bytes();
+        let request = Request::parse(&mut raw).unwrap();
+
+        assert_eq!(request.body(), b"hello, world");
+        assert_eq!(request.header("Content-Length"), Some("12"));
+        assert_eq!(request.header("Transfer-Encoding"), None);
+        assert_eq!(raw, b"GET");
+
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Err(ParseError::UnsupportedTransferEncoding(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n"),
+            Err(ParseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\nxyz\r\n"),
+            Err(ParseError::MalformedChunk(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert!(matches!(parse(""), Err(ParseError::ConnectionClosed)));
+        assert!(matches!(
+            parse("GET /\r\n\r\n"),
+            Err(ParseError::MalformedRequestLine(_))
+        ));
+        assert!(matches!(
+            parse("BREW / HTTP/1.1\r\n\r\n"),
+            Err(ParseError::UnsupportedMethod(_))
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/2.0\r\n\r\n"),
+            Err(ParseError::UnsupportedVersion(_))
+        ));
+        assert!(matches!(
+            parse("GET index.html HTTP/1.1\r\n\r\n"),
+            Err(ParseError::InvalidTarget(_))
+        ));
+        for target in ["/%+1", "/%-1", "/%2", "/%zz", "/?q=%+f"] {
+            assert!(matches!(
+                parse(&format!("GET {target} HTTP/1.1\r\n\r\n")),
+                Err(ParseError::InvalidTarget(_))
+            ));
+        }
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost : x\r\n\r\n"),
+            Err(ParseError::MalformedHeader(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n"),
+            Err(ParseError::InvalidContentLength(_))
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\nshort"),
+            Err(ParseError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: x\r\n"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn enforces_header_size_limit() {
+        let limits = Limits {
+            max_header_size: Some(34),
+            ..Limits::default()
+        };
+        let parse = |raw: &str| Request::parse_with_limits(&mut raw.as_bytes(), &limits);
+
+        // 恰好 34 字节。
+        assert!(parse("GET / HTTP/1.1\r\nHost: abcdefgh\r\n\r\n").is_ok());
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: abcdefghi\r\n\r\n"),
+            Err(ParseError::HeaderTooLarge { limit: 34 })
+        ));
+        // 没有换行的超长请求行在读到上限后就被拒绝。
+        let long = format!("GET /{} HTTP/1.1", "a".repeat(1 << 20));
+        assert!(matches!(
+            parse(&long),
+            Err(ParseError::HeaderTooLarge { limit: 34 })
+        ));
+
+        // 分块请求体的尾部字段也受同一个上限约束。
+        let limits = Limits {
+            max_header_size: Some(64),
+            ..Limits::default()
+        };
+        let chunked = |trailer: &str| {
+            let raw = format!(
+                "POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n{trailer}\r\n"
+            );
+            Request::parse_with_limits(&mut raw.as_bytes(), &limits)
+        };
+        assert!(chunked("X: 1\r\n").is_ok());
+        assert!(matches!(
+            chunked(&format!("X: {}\r\n", "a".repeat(64))),
+            Err(ParseError::HeaderTooLarge { limit: 64 })
+        ));
+    }
+
+    #[test]
+    fn enforces_body_size_limit() {
+        let limits = Limits {
+            max_body_size: Some(5),
+            ..Limits::default()
+        };
+        let parse = |raw: &str| Request::parse_with_limits(&mut raw.as_bytes(), &limits);
+
+        assert!(parse("POST / HTTP/1.1\r\nContent-Length: 5\r\n\r\nhello").is_ok());
+        // 超限的 Content-Length 不必等请求体到达就能拒绝。
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nContent-Length: 6\r\n\r\n"),
+            Err(ParseError::BodyTooLarge { limit: 5 })
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n3\r\ndef\r\n0\r\n\r\n"),
+            Err(ParseError::BodyTooLarge { limit: 5 })
+        ));
+    }
+}
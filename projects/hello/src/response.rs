@@ -0,0 +1,425 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+};
+
+use crate::{
+    chunked::ChunkedWriter,
+    date::DateTime,
+    headers::Headers,
+    request::Version,
+    status::StatusCode,
+    upgrade::{OnUpgrade, Upgraded},
+};
+
+/// 响应体。
+pub enum Body {
+    /// 已在内存中的字节。
+    Bytes(Vec<u8>),
+    /// 长度已知、写出时才从 `reader` 中读取的字节，比如一个打开的文件。
+    Reader {
+        reader: Box<dyn Read + Send>,
+        len: u64,
+    },
+    /// 长度未知、边生成边发送的字节，以分块编码写出，读到 EOF 为止。
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    /// 响应体的长度；流式响应体的长度事先未知，返回 `None`。
+    pub fn content_length(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::Reader { len, .. } => Some(*len),
+            Body::Stream(_) => None,
+        }
+    }
+
+    /// 内存中的响应体返回其字节，流式响应体返回 `None`。
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            Body::Reader { .. } | Body::Stream(_) => None,
+        }
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::Reader { len, .. } => f.debug_struct("Reader").field("len", len).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+/// 响应中 `Server` 头部的默认值。
+pub const SERVER: &str = concat!("hello/", env!("CARGO_PKG_VERSION"));
+
+/// 一个待发送的 HTTP 响应。
+#[derive(Debug)]
+pub struct Response {
+    status: StatusCode,
+    headers: Headers,
+    body: Body,
+    upgrade: Option<OnUpgrade>,
+}
+
+impl Response {
+    /// 以状态码创建一个空响应，比如 `Response::new(StatusCode::NoContent)`。
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::Bytes(Vec::new()),
+            upgrade: None,
+        }
+    }
+
+    /// 以 `404 Not Found` 这样的状态行文本作为响应体的纯文本响应。
+    pub fn plain(status: StatusCode) -> Response {
+        Response::text(status, status.to_string())
+    }
+
+    /// `text/plain` 响应。
+    pub fn text(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// `text/html` 响应。
+    pub fn html(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body.into())
+    }
+
+    /// `application/json` 响应；`body` 须是已序列化好的 JSON 文本。
+    pub fn json(status: StatusCode, body: impl Into<String>) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "application/json")
+            .with_body(body.into())
+    }
+
+    /// 以 3xx 状态码把客户端重定向到 `location`。
+    ///
+    /// # Panics
+    ///
+    /// `status` 不是 3xx 时 panic。
+    pub fn redirect(status: StatusCode, location: impl Into<String>) -> Response {
+        assert!(status.is_redirection(), "{status} 不是重定向状态码");
+        Response::new(status).with_header("Location", location)
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Response {
+        self.status = status;
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Response {
+        self.body = Body::Bytes(body.into());
+        self
+    }
+
+    /// 以一个长度为 `len` 的读取器作为响应体，写出时再从中读取字节。
+    pub fn with_reader(mut self, reader: impl Read + Send + 'static, len: u64) -> Response {
+        self.body = Body::Reader {
+            reader: Box::new(reader),
+            len,
+        };
+        self
+    }
+
+    /// 以一个长度未知的读取器作为响应体，写出时边读边发，不会整体缓冲。
+    ///
+    /// 对 HTTP/1.1 客户端使用分块编码；对 HTTP/1.0 客户端直接写出并在结束后关闭连接。
+    pub fn with_stream(mut self, reader: impl Read + Send + 'static) -> Response {
+        self.body = Body::Stream(Box::new(reader));
+        self
+    }
+
+    /// 以一个逐块产生数据的迭代器作为流式响应体。
+    pub fn with_chunks<I>(self, chunks: I) -> Response
+    where
+        I: IntoIterator<Item = Vec<u8>>,
+        I::IntoIter: Send + 'static,
+    {
+        self.with_stream(ChunkIter {
+            chunks: chunks.into_iter(),
+            current: io::Cursor::new(Vec::new()),
+        })
+    }
+
+    /// 发送这个 `101 Switching Protocols` 响应之后，把连接交给 `f`，由它按新的协议
+    /// 读写，比如 [`crate::websocket::upgrade`]。
+    ///
+    /// `f` 在处理器所在的线程上运行，返回或者放下连接时连接关闭。响应的状态码不是
+    /// 101 时（比如被中间件换掉了），响应照常发送，`f` 不会被调用。
+    pub fn with_upgrade(mut self, f: impl FnOnce(Upgraded) + Send + 'static) -> Response {
+        self.upgrade = Some(OnUpgrade::new(f));
+        self
+    }
+
+    pub fn status(&self) -> StatusCode {
+        self.status
+    }
+
+    pub fn set_status(&mut self, status: StatusCode) {
+        self.status = status;
+    }
+
+    pub fn reason(&self) -> &'static str {
+        self.status.reason()
+    }
+
+    pub fn headers(&self) -> &Headers {
+        &self.headers
+    }
+
+    pub fn headers_mut(&mut self) -> &mut Headers {
+        &mut self.headers
+    }
+
+    pub fn body(&self) -> &Body {
+        &self.body
+    }
+
+    pub fn body_mut(&mut self) -> &mut Body {
+        &mut self.body
+    }
+
+    /// 发送之后是否要把连接交给升级回调。
+    pub(crate) fn is_upgrade(&self) -> bool {
+        self.status == StatusCode::SwitchingProtocols && self.upgrade.is_some()
+    }
+
+    /// 取出升级回调；只有 101 响应才会升级。
+    pub(crate) fn take_upgrade(&mut self) -> Option<OnUpgrade> {
+        if self.is_upgrade() {
+            self.upgrade.take()
+        } else {
+            None
+        }
+    }
+
+    /// 补上处理器没有设置的 `Date` 与 `Server` 头部。
+    pub(crate) fn stamp(&mut self) {
+        if !self.headers.contains("Date") {
+            self.headers
+                .insert("Date", DateTime::now().http().to_string());
+        }
+        if !self.headers.contains("Server") {
+            self.headers.insert("Server", SERVER);
+        }
+    }
+
+    /// 把状态行、头部与响应体以 HTTP/1.1 格式写入 `w`。
+    pub fn write_to<W: Write>(self, w: &mut W) -> io::Result<()> {
+        self.send(w, Version::Http11, false).map(drop)
+    }
+
+    /// 按客户端的协议版本写出响应；`head_only` 为真时省略响应体（用于 `HEAD`）。
+    ///
+    /// 长度已知的响应体使用 `Content-Length`；流式响应体对 HTTP/1.1 使用分块编码，
+    /// 对 HTTP/1.0 则不带长度信息，由调用方在写完后关闭连接。1xx、204 与 304 响应
+    /// 按规定既不带响应体也不带长度信息。
+    ///
+    /// 返回写出的响应体字节数，不含头部与分块编码的开销。
+    pub fn send<W: Write>(self, w: &mut W, version: Version, head_only: bool) -> io::Result<u64> {
+        let no_body = self.status.forbids_body();
+        let length = self.body.content_length();
+        let chunked = length.is_none() && version == Version::Http11 && !no_body;
+
+        let mut head = format!("HTTP/1.1 {}\r\n", self.status);
+
+        for (name, value) in self.headers.iter() {
+            if !name.eq_ignore_ascii_case("Content-Length")
+                && !name.eq_ignore_ascii_case("Transfer-Encoding")
+            {
+                head.push_str(&format!("{name}: {value}\r\n"));
+            }
+        }
+        match length {
+            _ if no_body => {}
+            Some(len) => head.push_str(&format!("Content-Length: {len}\r\n")),
+            None if chunked => head.push_str("Transfer-Encoding: chunked\r\n"),
+            None => {}
+        }
+        head.push_str("\r\n");
+
+        w.write_all(head.as_bytes())?;
+
+        if head_only || no_body {
+            w.flush()?;
+            return Ok(0);
+        }
+
+        let sent = match self.body {
+            Body::Bytes(bytes) => {
+                w.write_all(&bytes)?;
+                bytes.len() as u64
+            }
+            Body::Reader { reader, len } => {
+                let copied = io::copy(&mut reader.take(len), w)?;
+
+                if copied < len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        format!("响应体只有 {copied} 字节，应为 {len} 字节"),
+                    ));
+                }
+                copied
+            }
+            Body::Stream(mut reader) if chunked => {
+                let mut writer = ChunkedWriter::new(&mut *w);
+                let copied = io::copy(&mut reader, &mut writer)?;
+                writer.finish()?;
+                copied
+            }
+            Body::Stream(mut reader) => io::copy(&mut reader, w)?,
+        };
+
+        w.flush()?;
+        Ok(sent)
+    }
+}
+
+/// 把一串 `Vec<u8>` 适配为 [`Read`]。
+struct ChunkIter<I> {
+    chunks: I,
+    current: io::Cursor<Vec<u8>>,
+}
+
+impl<I: Iterator<Item = Vec<u8>>> Read for ChunkIter<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.current.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                return Ok(n);
+            }
+
+            match self.chunks.next() {
+                Some(chunk) => self.current = io::Cursor::new(chunk),
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn serialize(response: Response, version: Version, head_only: bool) -> String {
+        let mut out = Vec::new();
+        response.send(&mut out, version, head_only).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn serializes_status_line_headers_and_body() {
+        let response = Response::text(StatusCode::NotFound, "missing")
+            .with_header("Content-Length", "999")
+            .with_header("X-Trace", "1");
+
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 404 Not Found\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             X-Trace: 1\r\n\
+             Content-Length: 7\r\n\
+             \r\n\
+             missing"
+        );
+
+        let response = Response::plain(StatusCode::ServiceUnavailable);
+        assert_eq!(
+            serialize(response, Version::Http11, true),
+            "HTTP/1.1 503 Service Unavailable\r\n\
+             Content-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: 23\r\n\
+             \r\n"
+        );
+    }
+
+    #[test]
+    fn frames_streaming_bodies_by_version() {
+        let chunks = || vec![b"ab".to_vec(), b"cde".to_vec()];
+
+        let response = Response::new(StatusCode::Ok).with_chunks(chunks());
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n"
+        );
+
+        let response = Response::new(StatusCode::Ok).with_chunks(chunks());
+        assert_eq!(
+            serialize(response, Version::Http10, false),
+            "HTTP/1.1 200 OK\r\n\r\nabcde"
+        );
+
+        let response = Response::new(StatusCode::Ok).with_reader(&b"abcdef"[..], 4);
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 200 OK\r\nContent-Length: 4\r\n\r\nabcd"
+        );
+
+        let mut out = Vec::new();
+        let short = Response::new(StatusCode::Ok).with_reader(&b"ab"[..], 4);
+        let err = short.send(&mut out, Version::Http11, false).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn omits_bodies_the_status_forbids() {
+        let response = Response::new(StatusCode::NotModified)
+            .with_header("ETag", "\"v1\"")
+            .with_body("ignored");
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"v1\"\r\n\r\n"
+        );
+
+        let response = Response::new(StatusCode::NoContent).with_chunks(vec![b"x".to_vec()]);
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 204 No Content\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn helpers_set_content_type_and_location() {
+        let response = Response::json(StatusCode::Created, r#"{"id":1}"#);
+        assert_eq!(response.status(), 201);
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("application/json")
+        );
+        assert_eq!(response.body().as_bytes(), Some(&br#"{"id":1}"#[..]));
+
+        let response = Response::html(StatusCode::Ok, "<p>hi</p>");
+        assert_eq!(
+            response.headers().get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+
+        let response = Response::redirect(StatusCode::SeeOther, "/login");
+        assert_eq!(
+            serialize(response, Version::Http11, false),
+            "HTTP/1.1 303 See Other\r\nLocation: /login\r\nContent-Length: 0\r\n\r\n"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "不是重定向状态码")]
+    fn redirect_requires_a_3xx_status() {
+        Response::redirect(StatusCode::Ok, "/");
+    }
+}
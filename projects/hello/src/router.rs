@@ -0,0 +1,364 @@
+use crate::{
+    request::{Method, Request},
+    response::Response,
+    status::StatusCode,
+};
+
+/// 把请求转换为响应的处理器。
+///
+/// 任何 `Fn(Request) -> Response` 闭包都自动实现了该特征。
+pub trait Handler: Send + Sync {
+    fn handle(&self, request: Request) -> Response;
+
+    /// 是否由处理器自己边读边取这个请求的请求体，`request` 中只有请求行与头部。
+    ///
+    /// 默认返回假，服务器先读完整个请求体再调用处理器。返回真时，服务器读完头部就
+    /// 调用处理器，请求体由 [`Request::take_body_stream`] 取得；处理之后连接关闭。
+    fn streams_body(&self, request: &Request) -> bool {
+        let _ = request;
+        false
+    }
+}
+
+impl<F> Handler for F
+where
+    F: Fn(Request) -> Response + Send + Sync,
+{
+    fn handle(&self, request: Request) -> Response {
+        self(request)
+    }
+}
+
+/// 路径模式中的一段。
+#[derive(Debug, PartialEq)]
+enum Segment {
+    /// 必须逐字相等的一段，比如 `users`。
+    Literal(String),
+    /// `:name`，匹配任意非空的一段。
+    Param(String),
+    /// `*name` 或 `*`，只能位于末尾，匹配剩余的全部路径（可为空）。
+    Wildcard(String),
+}
+
+#[derive(Debug)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    /// # Panics
+    ///
+    /// 模式不以 `/` 开头，或通配符不在末尾时终止运行。
+    fn parse(pattern: &str) -> Pattern {
+        assert! (pattern.starts_with('/'), "路由模式必须以 '/' 开头：{pattern:?}");
+
+        let parts: Vec<&str> = split_path(pattern).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert! (i == parts.len() - 1, "通配符只能位于模式末尾：{pattern:?}");
+                Segment::Wildcard(if name.is_empty() { "*" } else { name }.to_string())
+            } else {
+                Segment::Literal(part.to_string())
+            };
+            segments.push(segment);
+        }
+
+        Pattern { segments }
+    }
+
+    /// 匹配成功时返回提取出的路径参数。
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let parts: Vec<&str> = split_path(path).collect();
+        let mut params = Vec::new();
+
+        for (i, segment) in self.segments.iter().enumerate() {
+            match segment {
+                Segment::Literal(lit) => {
+                    if parts.get(i) != Some(&lit.as_str()) {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.push((name.clone(), parts.get(i)?.to_string()));
+                }
+                Segment::Wildcard(name) => {
+                    params.push((name.clone(), parts.get(i..).unwrap_or_default().join("/")));
+                    return Some(params);
+                }
+            }
+        }
+
+        (parts.len() == self.segments.len()).then_some(params)
+    }
+}
+
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|s| !s.is_empty())
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+/// 按方法与路径模式分发请求的路由器。
+///
+/// 路由按注册顺序匹配，第一个方法与路径都匹配的路由胜出；没有 HEAD 路由时，HEAD
+/// 请求由 GET 路由处理。路径匹配但方法不匹配时返回 405，并附上 `Allow` 头部；
+/// 没有 OPTIONS 路由时，OPTIONS 请求得到带 `Allow` 的 204。没有任何路径匹配时交给
+/// 404 处理器。
+pub struct Router {
+    routes: Vec<Route>,
+    not_found: Box<dyn Handler>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router {
+            routes: Vec::new(),
+            not_found: Box::new(|_| Response::plain(StatusCode::NotFound)),
+        }
+    }
+
+    /// 为 `method` 与 `pattern` 注册一个处理器。
+    ///
+    /// 模式由 `/` 分隔的段组成：字面量段、`:name` 参数段，以及末尾可选的
+    /// `*name` 通配段。参数值可通过 [`Request::param`] 取得。
+    pub fn route(mut self, method: Method, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete(self, pattern: &str, handler: impl Handler + 'static) -> Router {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    /// 替换没有路由匹配时使用的处理器。
+    pub fn not_found(mut self, handler: impl Handler + 'static) -> Router {
+        self.not_found = Box::new(handler);
+        self
+    }
+}
+
+impl Default for Router {
+    fn default() -> Router {
+        Router::new()
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, mut request: Request) -> Response {
+        let method = request.method();
+
+        // `OPTIONS *` 询问的是整个服务器，回答所有路由用到的方法。
+        if method == Method::Options && request.target() == "*" {
+            let allowed: Vec<Method> = self.routes.iter().map(|route| route.method).collect();
+            return Response::new(StatusCode::NoContent).with_header("Allow", allow(&allowed));
+        }
+
+        let mut allowed: Vec<Method> = Vec::new();
+        let mut get_route = None;
+
+        for route in &self.routes {
+            let Some(params) = route.pattern.matches(request.path()) else {
+                continue;
+            };
+
+            if route.method == method {
+                request.set_params(params);
+                return route.handler.handle(request);
+            }
+            // 没有专门的 HEAD 路由时，HEAD 请求交给同一路径的 GET 路由，响应体在
+            // 发送时去掉。
+            if method == Method::Head && route.method == Method::Get && get_route.is_none() {
+                get_route = Some((route, params));
+            }
+            allowed.push(route.method);
+        }
+
+        if let Some((route, params)) = get_route {
+            request.set_params(params);
+            return route.handler.handle(request);
+        }
+
+        if allowed.is_empty() {
+            return self.not_found.handle(request);
+        }
+
+        let allow = allow(&allowed);
+        if method == Method::Options {
+            return Response::new(StatusCode::NoContent).with_header("Allow", allow);
+        }
+        Response::plain(StatusCode::MethodNotAllowed).with_header("Allow", allow)
+    }
+
+    /// 交给会处理这个请求的路由决定；HEAD 与 OPTIONS 回退时没有请求体，不必考虑。
+    fn streams_body(&self, request: &Request) -> bool {
+        let mut matched = false;
+        for route in &self.routes {
+            if route.pattern.matches(request.path()).is_none() {
+                continue;
+            }
+            if route.method == request.method() {
+                return route.handler.streams_body(request);
+            }
+            matched = true;
+        }
+        !matched && self.not_found.streams_body(request)
+    }
+}
+
+/// `Allow` 头部的值：去重后的 `methods`，GET 之后补上 HEAD，末尾补上 OPTIONS。
+fn allow(methods: &[Method]) -> String {
+    let mut allowed: Vec<Method> = Vec::new();
+    let mut push = |method| {
+        if !allowed.contains(&method) {
+            allowed.push(method);
+        }
+    };
+
+    for &method in methods {
+        push(method);
+        if method == Method::Get {
+            push(Method::Head);
+        }
+    }
+    push(Method::Options);
+
+    allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn echo_params(request: Request) -> Response {
+        let body = request
+            .params()
+            .iter()
+            .map(|(k, v)| format!("{k}={v}"))
+            .collect::<Vec<_>>()
+            .join(";");
+        Response::new(StatusCode::Ok).with_body(body)
+    }
+
+    fn dispatch(router: &Router, method: Method, target: &str) -> Response {
+        router.handle(Request::new(method, target).unwrap())
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        response.body().as_bytes().unwrap().to_vec()
+    }
+
+    #[test]
+    fn dispatches_with_path_params() {
+        let router = Router::new()
+            .get("/", |_| Response::new(StatusCode::Ok).with_body("root"))
+            .get("/users/:id", echo_params)
+            .get("/users/:id/posts/:post", echo_params)
+            .get("/static/*path", echo_params);
+
+        assert_eq!(body(dispatch(&router, Method::Get, "/")), b"root");
+        assert_eq!(body(dispatch(&router, Method::Get, "/users/42")), b"id=42");
+        assert_eq!(
+            body(dispatch(&router, Method::Get, "/users/7/posts/hello%20world")),
+            b"id=7;post=hello world"
+        );
+        assert_eq!(
+            body(dispatch(&router, Method::Get, "/static/css/site.css")),
+            b"path=css/site.css"
+        );
+        assert_eq!(body(dispatch(&router, Method::Get, "/static")), b"path=");
+        assert_eq!(dispatch(&router, Method::Get, "/users").status(), 404);
+        assert_eq!(dispatch(&router, Method::Get, "/users/1/extra").status(), 404);
+    }
+
+    #[test]
+    fn wrong_method_is_405_with_allow() {
+        let router = Router::new()
+            .get("/items/:id", echo_params)
+            .put("/items/:id", echo_params);
+
+        let response = dispatch(&router, Method::Delete, "/items/1");
+
+        assert_eq!(response.status(), 405);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("GET, HEAD, PUT, OPTIONS")
+        );
+        assert_eq!(dispatch(&router, Method::Delete, "/nothing").status(), 404);
+    }
+
+    #[test]
+    fn head_falls_back_to_get_routes() {
+        let router = Router::new()
+            .get("/items/:id", echo_params)
+            .route(Method::Head, "/only-head", |_| {
+                Response::new(StatusCode::Ok).with_body("head")
+            })
+            .get("/only-head", |_| Response::new(StatusCode::Ok).with_body("get"));
+
+        let response = dispatch(&router, Method::Head, "/items/1");
+        assert_eq!(response.status(), 200);
+        assert_eq!(body(response), b"id=1");
+        assert_eq!(body(dispatch(&router, Method::Head, "/only-head")), b"head");
+        assert_eq!(dispatch(&router, Method::Head, "/nothing").status(), 404);
+    }
+
+    #[test]
+    fn options_lists_the_allowed_methods() {
+        let router = Router::new()
+            .get("/items/:id", echo_params)
+            .delete("/items/:id", echo_params)
+            .post("/upload", echo_params);
+
+        let response = dispatch(&router, Method::Options, "/items/1");
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("GET, HEAD, DELETE, OPTIONS")
+        );
+
+        let response = dispatch(&router, Method::Options, "*");
+        assert_eq!(response.status(), 204);
+        assert_eq!(
+            response.headers().get("Allow"),
+            Some("GET, HEAD, DELETE, POST, OPTIONS")
+        );
+        assert_eq!(dispatch(&router, Method::Options, "/nothing").status(), 404);
+    }
+
+    #[test]
+    fn custom_not_found_handler() {
+        let router = Router::new().not_found(|r: Request| {
+            Response::new(StatusCode::NotFound).with_body(format!("no {}", r.path()))
+        });
+
+        assert_eq!(body(dispatch(&router, Method::Get, "/x")), b"no /x");
+    }
+}
@@ -0,0 +1,472 @@
+use std::{
+    io::{self, Read},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use crate::{
+    access_log::AccessLog,
+    async_server::{self, Context},
+    connection::{serve_pooled, ConnectionOptions},
+    log::{Level, Logger, WriterLogger},
+    response::Response,
+    router::Handler,
+    status::StatusCode,
+    ShutdownReport, ThreadPool,
+};
+
+#[cfg(feature = "tls")]
+use crate::tls::Tls;
+
+/// 接受循环在没有新连接时休眠的最长时间；休眠从 1 毫秒开始，每次空转后加倍。
+///
+/// 监听套接字是非阻塞的，接受循环才能注意到关闭信号；刚接受过连接时休眠很短，
+/// 新连接不必等满一个间隔。
+const MAX_ACCEPT_BACKOFF: Duration = Duration::from_millis(10);
+
+/// 接受连接出错之后，重试之前等待的时间。
+const ACCEPT_ERROR_DELAY: Duration = Duration::from_millis(50);
+
+/// 用于从其他线程（比如信号处理器）请求关闭服务器的句柄。
+#[derive(Clone, Debug, Default)]
+pub struct ShutdownHandle {
+    requested: Arc<AtomicBool>,
+}
+
+impl ShutdownHandle {
+    pub fn new() -> ShutdownHandle {
+        ShutdownHandle::default()
+    }
+
+    /// 请求关闭。可以多次调用，也可以在任何线程中调用。
+    pub fn shutdown(&self) {
+        self.requested.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_shutdown(&self) -> bool {
+        self.requested.load(Ordering::SeqCst)
+    }
+}
+
+/// 服务器接受连接的一个监听套接字。
+///
+/// 启用 `tls` 特性后，用 [`Listener::tls`] 创建的监听套接字接受 HTTPS 连接。
+pub struct Listener {
+    pub(crate) socket: TcpListener,
+    pub(crate) security: Security,
+}
+
+/// 接受的连接是否要先进行 TLS 握手。
+#[derive(Clone)]
+pub(crate) enum Security {
+    Plain,
+    #[cfg(feature = "tls")]
+    Tls(Tls),
+}
+
+impl Listener {
+    /// 接受明文的 HTTP 连接。
+    pub fn new(socket: TcpListener) -> Listener {
+        Listener {
+            socket,
+            security: Security::Plain,
+        }
+    }
+
+    /// 接受 HTTPS 连接，握手使用 `tls` 中的证书。
+    #[cfg(feature = "tls")]
+    pub fn tls(socket: TcpListener, tls: Tls) -> Listener {
+        Listener {
+            socket,
+            security: Security::Tls(tls),
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// 这个监听套接字上的连接是否使用 TLS。
+    pub fn is_tls(&self) -> bool {
+        !matches!(self.security, Security::Plain)
+    }
+}
+
+impl From<TcpListener> for Listener {
+    fn from(socket: TcpListener) -> Listener {
+        Listener::new(socket)
+    }
+}
+
+/// 在线程池上为每个连接运行 [`serve_connection`](crate::serve_connection) 的服务器。
+///
+/// 也可以用 [`Server::run_async`] 以异步方式运行：每个连接是一个任务，线程池只用来
+/// 执行处理器。两种方式使用同样的设置、处理器与请求/响应类型。
+pub struct Server {
+    listeners: Vec<Listener>,
+    pool: ThreadPool,
+    handler: Arc<dyn Handler>,
+    options: ConnectionOptions,
+    drain_timeout: Duration,
+    queue_timeout: Duration,
+    logger: Arc<dyn Logger>,
+}
+
+impl Server {
+    pub fn new(
+        listener: impl Into<Listener>,
+        pool: ThreadPool,
+        handler: impl Handler + 'static,
+    ) -> Server {
+        Server {
+            listeners: vec![listener.into()],
+            pool,
+            handler: Arc::new(handler),
+            options: ConnectionOptions::default(),
+            drain_timeout: Duration::from_secs(30),
+            queue_timeout: Duration::from_millis(500),
+            logger: Arc::new(WriterLogger::stderr(Level::Info)),
+        }
+    }
+
+    /// 再接受另一个监听套接字上的连接，比如同时监听 IPv4 与 IPv6 地址，或者在 HTTP
+    /// 之外再接受 HTTPS。
+    pub fn listener(mut self, listener: impl Into<Listener>) -> Server {
+        self.listeners.push(listener.into());
+        self
+    }
+
+    /// 设置每个连接的选项。选项中的关闭句柄会被服务器自己的句柄取代。
+    pub fn options(mut self, options: ConnectionOptions) -> Server {
+        let shutdown = self.options.shutdown.clone();
+        self.options = ConnectionOptions { shutdown, ..options };
+        self
+    }
+
+    /// 为每个请求写一行访问日志；等同于设置 [`ConnectionOptions::access_log`]。
+    pub fn access_log(mut self, log: AccessLog) -> Server {
+        self.options.access_log = Some(Arc::new(log));
+        self
+    }
+
+    /// 设置服务器的日志后端，默认把 `Info` 及以上的记录写到标准错误。
+    pub fn logger(mut self, logger: Arc<dyn Logger>) -> Server {
+        self.logger = logger;
+        self
+    }
+
+    /// 设置关闭时等待进行中的连接结束的最长时间，默认 30 秒。
+    pub fn drain_timeout(mut self, timeout: Duration) -> Server {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// 设置线程池队列已满时，新连接最多等待多久；超时后以 503 拒绝该连接。默认 500 毫秒。
+    ///
+    /// 只有通过 [`crate::ThreadPoolBuilder::queue_capacity`] 限制了队列容量时才会生效。
+    pub fn queue_timeout(mut self, timeout: Duration) -> Server {
+        self.queue_timeout = timeout;
+        self
+    }
+
+    /// 第一个监听套接字的地址。
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listeners[0].local_addr()
+    }
+
+    /// 全部监听套接字的地址，顺序与添加的顺序相同。
+    pub fn local_addrs(&self) -> io::Result<Vec<SocketAddr>> {
+        self.listeners.iter().map(Listener::local_addr).collect()
+    }
+
+    /// 返回一个可以让 [`Server::run`] 返回的句柄。
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.options.shutdown.clone()
+    }
+
+    /// 接受并处理连接，直到通过 [`ShutdownHandle`] 请求关闭。
+    ///
+    /// 每个连接在处理期间占用一个 worker，空闲的持久连接也不例外，因此并发的持久连接
+    /// 数受线程池大小限制。线程池中有连接排队时，空闲超过 100 毫秒的持久连接会被关闭，
+    /// 把 worker 让给排队的连接；客户端之后需要重新建立连接。
+    ///
+    /// 请求关闭后不再接受新连接；保持中的连接在当前请求完成后关闭，随后在
+    /// `drain_timeout` 期限内等待线程池中的作业结束。
+    pub fn run(self) -> io::Result<ShutdownReport> {
+        let shutdown = self.options.shutdown.clone();
+        for listener in &self.listeners {
+            listener.socket.set_nonblocking(true)?;
+        }
+
+        // 轮流询问每个监听套接字；一轮下来都没有新连接时才休眠。
+        let mut next = 0;
+        let mut idle = 0;
+        let mut backoff = Duration::from_millis(1);
+
+        while !shutdown.is_shutdown() {
+            if idle == self.listeners.len() {
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(MAX_ACCEPT_BACKOFF);
+                idle = 0;
+            }
+            let listener = &self.listeners[next];
+            next = (next + 1) % self.listeners.len();
+
+            let stream = match listener.socket.accept() {
+                Ok((stream, _)) => {
+                    idle = 0;
+                    backoff = Duration::from_millis(1);
+                    stream
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    idle += 1;
+                    continue;
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    // 比如文件描述符耗尽；稍后重试，而不是让整个服务器退出。
+                    self.logger.error("server", "接受连接失败", &[("error", &e)]);
+                    thread::sleep(ACCEPT_ERROR_DELAY);
+                    continue;
+                }
+            };
+
+            // 某些平台上，接受的连接会继承监听套接字的非阻塞模式。设置失败时只放弃这一个
+            // 连接。
+            if let Err(e) = stream.set_nonblocking(false) {
+                self.logger.warn("server", "无法设置连接为阻塞模式", &[("error", &e)]);
+                continue;
+            }
+
+            let handler = Arc::clone(&self.handler);
+            let options = self.options.clone();
+            let pool = self.pool.monitor();
+            let logger = Arc::clone(&self.logger);
+            let security = listener.security.clone();
+            // TLS 连接还没有握手，无法写出 503，只能直接关闭。
+            let overflow = stream.try_clone().ok().filter(|_| !listener.is_tls());
+
+            let job = move || {
+                let peer = stream.peer_addr();
+                let others_waiting = || pool.queued_jobs() > 0;
+                let result = match security {
+                    Security::Plain => serve_pooled(stream, &*handler, &options, &others_waiting),
+                    #[cfg(feature = "tls")]
+                    Security::Tls(tls) => tls.accept(stream).and_then(|stream| {
+                        serve_pooled(stream, &*handler, &options, &others_waiting)
+                    }),
+                };
+                if let Err(e) = result {
+                    let peer = peer.map_or_else(|_| "-".to_string(), |p| p.to_string());
+                    logger.warn("server", "连接出错", &[("peer", &peer), ("error", &e)]);
+                }
+            };
+
+            if let Err(e) = self.pool.execute_timeout(job, self.queue_timeout) {
+                self.logger.warn("server", "线程池繁忙，以 503 拒绝连接", &[]);
+                // 丢弃被交还的作业会关闭它持有的连接副本，503 通过另一个副本写出。
+                drop(e.into_job());
+                if let Some(stream) = overflow {
+                    reject(stream);
+                }
+            }
+        }
+
+        drop(self.listeners);
+        Ok(self.pool.shutdown(self.drain_timeout))
+    }
+
+    /// 与 [`Server::run`] 相同，但在 trpl 的异步运行时上为每个连接运行一个任务。
+    ///
+    /// 读取请求与写出响应都在任务中进行，只有处理器被提交给线程池，因此空闲的持久
+    /// 连接与发送缓慢的客户端不会占住 worker。线程池队列已满时，请求最多等待
+    /// `queue_timeout`，之后以 503 回应。
+    pub fn run_async(self) -> io::Result<ShutdownReport> {
+        let context = Context {
+            pool: self.pool,
+            handler: self.handler,
+            options: self.options,
+            queue_timeout: self.queue_timeout,
+            logger: self.logger,
+        };
+        async_server::run(self.listeners, context, self.drain_timeout)
+    }
+}
+
+/// 在接受线程上直接以 503 回应一个无法排队的连接。
+fn reject(mut stream: TcpStream) {
+    let mut response = Response::plain(StatusCode::ServiceUnavailable)
+        .with_header("Retry-After", "1")
+        .with_header("Connection", "close");
+    response.stamp();
+
+    if response.write_to(&mut stream).is_err() {
+        return;
+    }
+    let _ = stream.shutdown(Shutdown::Write);
+
+    // 关闭时若还有未读的请求数据，内核会发送 RST，客户端可能因此丢掉 503。
+    // 这里只读掉已经到达的数据，不等待更多数据，以免阻塞接受循环。
+    if stream.set_nonblocking(true).is_ok() {
+        let mut buf = [0; 4096];
+        while matches!(stream.read(&mut buf), Ok(n) if n > 0) {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{io::Write, time::Instant};
+
+    use crate::{Request, Response};
+
+    fn start(
+        handler: impl Handler + 'static,
+        drain: Duration,
+    ) -> (SocketAddr, ShutdownHandle, thread::JoinHandle<ShutdownReport>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(2), handler).drain_timeout(drain);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+
+        (addr, handle, thread::spawn(move || server.run().unwrap()))
+    }
+
+    fn get(addr: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\n\r\n").unwrap();
+        stream
+    }
+
+    fn slow(request: Request) -> Response {
+        let ms = request.query("ms").unwrap_or("0").parse().unwrap();
+        thread::sleep(Duration::from_millis(ms));
+        Response::new(StatusCode::Ok).with_body("done")
+    }
+
+    #[test]
+    fn drains_in_flight_requests_before_returning() {
+        let (addr, handle, server) = start(slow, Duration::from_secs(5));
+
+        let mut stream = get(addr, "/?ms=300");
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 200 OK"));
+        assert!(out.contains("Connection: close"));
+        assert!(out.ends_with("done"));
+
+        let report = server.join().unwrap();
+        assert!(report.is_clean());
+        assert_eq!(report.joined.len(), 2);
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn idle_keep_alive_connections_do_not_block_shutdown() {
+        let (addr, handle, server) = start(slow, Duration::from_secs(5));
+
+        let mut stream = get(addr, "/");
+        let mut buf = [0; 64];
+        let _ = stream.read(&mut buf).unwrap();
+
+        let start = Instant::now();
+        handle.shutdown();
+        let report = server.join().unwrap();
+
+        assert!(report.is_clean());
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn idle_keep_alive_connections_yield_to_queued_ones() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(listener, ThreadPool::new(1), slow);
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run().unwrap());
+
+        // 第一个连接得到响应后保持空闲，占住唯一的 worker。
+        let mut idle = get(addr, "/");
+        let mut buf = [0; 256];
+        assert!(idle.read(&mut buf).unwrap() > 0);
+
+        let start = Instant::now();
+        let mut queued = get(addr, "/");
+        assert!(queued.read(&mut buf).unwrap() > 0);
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(idle.read(&mut buf).unwrap(), 0);
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn answers_503_when_the_queue_stays_full() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let pool = ThreadPool::builder(1).queue_capacity(1).build().unwrap();
+        let server = Server::new(listener, pool, slow).queue_timeout(Duration::from_millis(50));
+        let addr = server.local_addr().unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run().unwrap());
+
+        // 第一个连接占住唯一的 worker，第二个占住唯一的队列位置。
+        let _busy = get(addr, "/?ms=500");
+        thread::sleep(Duration::from_millis(100));
+        let _queued = get(addr, "/?ms=0");
+        thread::sleep(Duration::from_millis(100));
+
+        let mut rejected = get(addr, "/");
+        let mut out = String::new();
+        rejected.read_to_string(&mut out).unwrap();
+        assert!(out.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(out.contains("Retry-After: 1"));
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn reports_jobs_still_running_at_the_deadline() {
+        let (addr, handle, server) = start(slow, Duration::from_millis(100));
+
+        let _stream = get(addr, "/?ms=2000");
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        let report = server.join().unwrap();
+        assert_eq!(report.unfinished.len(), 1);
+        assert_eq!(report.joined.len(), 1);
+        assert!(!report.is_clean());
+    }
+
+    #[test]
+    fn accepts_on_every_listener() {
+        let first = TcpListener::bind("127.0.0.1:0").unwrap();
+        let second = TcpListener::bind("127.0.0.1:0").unwrap();
+        let server = Server::new(first, ThreadPool::new(2), slow).listener(second);
+        let addrs = server.local_addrs().unwrap();
+        let handle = server.shutdown_handle();
+        let server = thread::spawn(move || server.run().unwrap());
+
+        assert_eq!(addrs.len(), 2);
+        for addr in addrs {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            write!(stream, "GET / HTTP/1.1\r\nConnection: close\r\n\r\n").unwrap();
+            let mut out = String::new();
+            stream.read_to_string(&mut out).unwrap();
+            assert!(out.ends_with("done"));
+        }
+
+        handle.shutdown();
+        server.join().unwrap();
+    }
+}
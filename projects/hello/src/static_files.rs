@@ -0,0 +1,522 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    date::DateTime,
+    file_cache::{CachedFile, FileCache},
+    log::{Level, Logger, WriterLogger},
+    mime,
+    request::{Method, Request},
+    response::Response,
+    router::Handler,
+    status::StatusCode,
+};
+
+/// 默认的文件缓存容量。
+const DEFAULT_CACHE_SIZE: u64 = 32 * 1024 * 1024;
+
+/// 从某个文档根目录提供静态文件的处理器。
+///
+/// 挂载到带通配符的路由（比如 `/static/*path`）时，通配符参数 `path` 被当作
+/// 相对于根目录的路径；否则使用整个请求路径。
+///
+/// 响应带 `ETag` 与 `Last-Modified`，并据此回应条件请求（304、412）与单个字节范围的
+/// `Range` 请求（206、416）。文件内容经过一个按修改时间失效的 [`FileCache`]。
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    not_found_page: Option<String>,
+    cache: FileCache,
+    logger: Arc<dyn Logger>,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: String::from("index.html"),
+            not_found_page: None,
+            cache: FileCache::new(DEFAULT_CACHE_SIZE),
+            logger: Arc::new(WriterLogger::stderr(Level::Info)),
+        }
+    }
+
+    /// 设置请求目录时提供的文件名，默认为 `index.html`。
+    pub fn index(mut self, name: impl Into<String>) -> StaticFiles {
+        self.index = name.into();
+        self
+    }
+
+    /// 设置文件不存在时作为 404 响应体的页面，路径相对于根目录。
+    pub fn not_found_page(mut self, path: impl Into<String>) -> StaticFiles {
+        self.not_found_page = Some(path.into());
+        self
+    }
+
+    /// 替换文件缓存，默认缓存 32 MiB；`FileCache::new(0)` 表示不缓存。
+    pub fn cache(mut self, cache: FileCache) -> StaticFiles {
+        self.cache = cache;
+        self
+    }
+
+    /// 设置记录读取错误的日志后端，默认写到标准错误。
+    pub fn logger(mut self, logger: Arc<dyn Logger>) -> StaticFiles {
+        self.logger = logger;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// 以 200 提供 `path` 处的文件；出错时返回对应的 403、404 或 500 响应。
+    pub fn serve(&self, path: &str) -> Response {
+        self.serve_file(path, None)
+    }
+
+    /// 按 `request` 的条件头部与 `Range` 提供 `path` 处的文件。
+    pub fn serve_request(&self, request: &Request, path: &str) -> Response {
+        self.serve_file(path, Some(request))
+    }
+
+    fn serve_file(&self, path: &str, request: Option<&Request>) -> Response {
+        let (file, content_type) = match self.open(path) {
+            Ok(opened) => opened,
+            Err(e) => return self.error_response(&e),
+        };
+
+        let etag = etag(&file);
+        let last_modified = DateTime::from(file.modified).http().to_string();
+        let validators = |response: Response| {
+            response
+                .with_header("ETag", etag.as_str())
+                .with_header("Last-Modified", last_modified.as_str())
+        };
+
+        let range = match request {
+            Some(request) => {
+                if let Some(status) = check_preconditions(request, &etag, file.modified) {
+                    return match status {
+                        StatusCode::NotModified => validators(Response::new(status)),
+                        _ => Response::plain(status),
+                    };
+                }
+                requested_range(request, &etag, file.modified, file.len)
+            }
+            None => RangeRequest::Full,
+        };
+
+        let len = file.len;
+        let (status, start, count) = match range {
+            RangeRequest::Full => (StatusCode::Ok, 0, len),
+            RangeRequest::Partial { start, end } => (StatusCode::PartialContent, start, end - start),
+            RangeRequest::Unsatisfiable => {
+                return Response::plain(StatusCode::RangeNotSatisfiable)
+                    .with_header("Content-Range", format!("bytes */{len}"));
+            }
+        };
+
+        let reader = match file.reader(start, count) {
+            Ok(reader) => reader,
+            Err(e) => return self.error_response(&e),
+        };
+        let mut response = validators(Response::new(status))
+            .with_header("Content-Type", content_type)
+            .with_header("Accept-Ranges", "bytes")
+            .with_reader(reader, count);
+        if status == StatusCode::PartialContent {
+            let end = start + count - 1;
+            response = response.with_header("Content-Range", format!("bytes {start}-{end}/{len}"));
+        }
+        response
+    }
+
+    /// 把请求路径映射为根目录下的文件，拒绝任何逃出根目录的路径。
+    pub fn resolve(&self, path: &str) -> io::Result<PathBuf> {
+        let mut resolved = self.root.clone();
+
+        for segment in path.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return Err(forbidden(path)),
+                s if s.contains(['\\', '\0']) => return Err(forbidden(path)),
+                s => resolved.push(s),
+            }
+        }
+
+        // 符号链接可能指向根目录之外，因此还要比较规范化后的路径。
+        let canonical = resolved.canonicalize()?;
+        if !canonical.starts_with(self.root.canonicalize()?) {
+            return Err(forbidden(path));
+        }
+
+        if canonical.is_dir() {
+            Ok(canonical.join(&self.index))
+        } else {
+            Ok(canonical)
+        }
+    }
+
+    fn open(&self, path: &str) -> io::Result<(CachedFile, &'static str)> {
+        let path = self.resolve(path)?;
+        let file = self.cache.open(&path)?;
+        Ok((file, mime::from_path(&path)))
+    }
+
+    fn error_response(&self, e: &io::Error) -> Response {
+        match e.kind() {
+            io::ErrorKind::NotFound | io::ErrorKind::NotADirectory => {
+                let page = self
+                    .not_found_page
+                    .as_ref()
+                    .and_then(|p| fs::read(self.root.join(p)).ok());
+
+                match page {
+                    Some(page) => Response::new(StatusCode::NotFound)
+                        .with_header("Content-Type", "text/html; charset=utf-8")
+                        .with_body(page),
+                    None => Response::plain(StatusCode::NotFound),
+                }
+            }
+            io::ErrorKind::PermissionDenied => Response::plain(StatusCode::Forbidden),
+            _ => {
+                self.logger.error("static", "读取静态文件失败", &[("error", &e)]);
+                Response::plain(StatusCode::InternalServerError)
+            }
+        }
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: Request) -> Response {
+        if !matches!(request.method(), Method::Get | Method::Head) {
+            return Response::plain(StatusCode::MethodNotAllowed).with_header("Allow", "GET, HEAD");
+        }
+
+        self.serve_request(&request, request.param("path").unwrap_or(request.path()))
+    }
+}
+
+/// 由长度与修改时间生成的强校验器，和 nginx 的做法一样，不必读取文件内容。
+fn etag(file: &CachedFile) -> String {
+    let modified = file
+        .modified
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    format!(
+        "\"{:x}.{:x}-{:x}\"",
+        modified.as_secs(),
+        modified.subsec_nanos(),
+        file.len
+    )
+}
+
+/// 把修改时间截断到秒，与 HTTP 日期的精度一致。
+fn unix_secs(time: SystemTime) -> i64 {
+    DateTime::from(time).unix()
+}
+
+/// 去掉弱校验器的 `W/` 前缀。
+fn opaque(tag: &str) -> &str {
+    tag.strip_prefix("W/").unwrap_or(tag)
+}
+
+/// 按 RFC 9110 第 13.2.2 节的顺序检查条件头部，返回应当代替正常响应的状态码。
+fn check_preconditions(request: &Request, etag: &str, modified: SystemTime) -> Option<StatusCode> {
+    let headers = request.headers();
+    let modified = unix_secs(modified);
+    let since = |name| headers.get(name).and_then(DateTime::parse_http).map(|t| t.unix());
+
+    // If-Match 使用强比较，我们的校验器都是强的。
+    if let Some(tags) = headers.get("If-Match") {
+        let matched = tags.trim() == "*" || tags.split(',').any(|tag| tag.trim() == etag);
+        if !matched {
+            return Some(StatusCode::PreconditionFailed);
+        }
+    } else if since("If-Unmodified-Since").is_some_and(|date| modified > date) {
+        return Some(StatusCode::PreconditionFailed);
+    }
+
+    if !matches!(request.method(), Method::Get | Method::Head) {
+        return None;
+    }
+
+    // If-None-Match 使用弱比较：压缩后的响应带的是弱校验器。
+    if let Some(tags) = headers.get("If-None-Match") {
+        let matched = tags.trim() == "*"
+            || tags
+                .split(',')
+                .any(|tag| opaque(tag.trim()) == opaque(etag));
+        return matched.then_some(StatusCode::NotModified);
+    }
+    if since("If-Modified-Since").is_some_and(|date| modified <= date) {
+        return Some(StatusCode::NotModified);
+    }
+
+    None
+}
+
+#[derive(Debug, PartialEq)]
+enum RangeRequest {
+    Full,
+    /// `start..end`，不含 `end`。
+    Partial { start: u64, end: u64 },
+    Unsatisfiable,
+}
+
+/// 决定回应整个文件还是其中一段。
+///
+/// 只支持单个字节范围；多个范围、无法解析的 `Range` 以及与 `If-Range` 不符的请求都
+/// 按规定忽略 `Range`，回应整个文件。
+fn requested_range(request: &Request, etag: &str, modified: SystemTime, len: u64) -> RangeRequest {
+    let Some(range) = request.header("Range") else {
+        return RangeRequest::Full;
+    };
+    if request.method() != Method::Get {
+        return RangeRequest::Full;
+    }
+
+    if let Some(condition) = request.header("If-Range") {
+        let condition = condition.trim();
+        let current = if condition.starts_with('"') || condition.starts_with("W/") {
+            condition == etag
+        } else {
+            DateTime::parse_http(condition).is_some_and(|t| t.unix() == unix_secs(modified))
+        };
+        if !current {
+            return RangeRequest::Full;
+        }
+    }
+
+    parse_range(range, len)
+}
+
+fn parse_range(range: &str, len: u64) -> RangeRequest {
+    let Some(spec) = range.trim().strip_prefix("bytes=") else {
+        return RangeRequest::Full;
+    };
+    if spec.contains(',') {
+        return RangeRequest::Full;
+    }
+    let Some((first, last)) = spec.trim().split_once('-') else {
+        return RangeRequest::Full;
+    };
+
+    let (start, end) = match (first.trim(), last.trim()) {
+        // bytes=-500：最后 500 个字节。
+        ("", suffix) => match suffix.parse::<u64>() {
+            Ok(0) => return RangeRequest::Unsatisfiable,
+            Ok(suffix) => (len.saturating_sub(suffix), len),
+            Err(_) => return RangeRequest::Full,
+        },
+        // bytes=500-：从第 500 个字节到结尾。
+        (first, "") => match first.parse::<u64>() {
+            Ok(first) => (first, len),
+            Err(_) => return RangeRequest::Full,
+        },
+        (first, last) => match (first.parse::<u64>(), last.parse::<u64>()) {
+            (Ok(first), Ok(last)) if first <= last => (first, last.saturating_add(1).min(len)),
+            _ => return RangeRequest::Full,
+        },
+    };
+
+    if start >= len {
+        RangeRequest::Unsatisfiable
+    } else {
+        RangeRequest::Partial { start, end }
+    }
+}
+
+fn forbidden(path: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::PermissionDenied, format!("路径逃出了文档根目录：{path:?}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env, process,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// 测试用的文档根目录，离开作用域时删除。
+    struct TempRoot(PathBuf);
+
+    impl TempRoot {
+        fn path(&self) -> &Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempRoot {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn temp_root() -> TempRoot {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let dir = env::temp_dir().join(format!(
+            "hello-static-{}-{}",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::SeqCst)
+        ));
+        fs::create_dir_all(dir.join("docs")).unwrap();
+        fs::write(dir.join("index.html"), "<h1>home</h1>").unwrap();
+        fs::write(dir.join("docs/index.html"), "<h1>docs</h1>").unwrap();
+        fs::write(dir.join("logo.png"), [0x89, b'P', b'N', b'G', 0, 0xff]).unwrap();
+        fs::write(dir.join("404.html"), "<h1>missing</h1>").unwrap();
+        TempRoot(dir)
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        let mut raw = Vec::new();
+        response.write_to(&mut raw).unwrap();
+
+        let start = raw.windows(4).position(|w| w == b"\r\n\r\n").unwrap() + 4;
+        raw.split_off(start)
+    }
+
+    #[test]
+    fn serves_bytes_with_content_type() {
+        let root = temp_root();
+        let files = StaticFiles::new(root.path());
+        let response = files.serve("/logo.png");
+
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("Content-Type"), Some("image/png"));
+        assert_eq!(body(response), [0x89, b'P', b'N', b'G', 0, 0xff]);
+    }
+
+    #[test]
+    fn directories_serve_index() {
+        let root = temp_root();
+        let files = StaticFiles::new(root.path());
+
+        assert_eq!(body(files.serve("/")), b"<h1>home</h1>");
+        assert_eq!(body(files.serve("/docs/")), b"<h1>docs</h1>");
+        assert_eq!(body(files.serve("/docs")), b"<h1>docs</h1>");
+    }
+
+    #[test]
+    fn rejects_traversal_and_maps_errors() {
+        let root = temp_root();
+        let files = StaticFiles::new(root.path().join("docs"));
+
+        assert_eq!(files.serve("/../index.html").status(), 403);
+        assert_eq!(files.serve("/a/../../index.html").status(), 403);
+        assert_eq!(files.serve("/missing.txt").status(), 404);
+        assert_eq!(files.serve("/index.html/child").status(), 404);
+
+        let files = StaticFiles::new(root.path()).not_found_page("404.html");
+        let response = files.serve("/nope");
+        assert_eq!(response.status(), 404);
+        assert_eq!(body(response), b"<h1>missing</h1>");
+    }
+
+    fn get(files: &StaticFiles, path: &str, headers: &[(&str, &str)]) -> Response {
+        let mut request = Request::new(Method::Get, path).unwrap();
+        for (name, value) in headers {
+            request.headers_mut().insert(*name, *value);
+        }
+        files.handle(request)
+    }
+
+    #[test]
+    fn answers_conditional_requests() {
+        let root = temp_root();
+        let files = StaticFiles::new(root.path());
+
+        let response = get(&files, "/index.html", &[]);
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers().get("Accept-Ranges"), Some("bytes"));
+        let etag = response.headers().get("ETag").unwrap().to_string();
+        let last_modified = response.headers().get("Last-Modified").unwrap().to_string();
+
+        for (name, value, status) in [
+            ("If-None-Match", etag.clone(), 304),
+            ("If-None-Match", format!("\"other\", W/{etag}"), 304),
+            ("If-None-Match", String::from("\"other\""), 200),
+            ("If-Modified-Since", last_modified.clone(), 304),
+            ("If-Modified-Since", String::from("Thu, 01 Jan 1970 00:00:00 GMT"), 200),
+            ("If-Match", String::from("\"other\""), 412),
+            ("If-Match", etag.clone(), 200),
+            ("If-Unmodified-Since", String::from("Thu, 01 Jan 1970 00:00:00 GMT"), 412),
+            // 无效的日期被忽略，年份过大也不会在换算时溢出。
+            (
+                "If-Modified-Since",
+                String::from("Sunday, 06-Nov-9223372036854775807 08:49:37 GMT"),
+                200,
+            ),
+            ("If-Unmodified-Since", String::from("Sun Nov  6 08:49:37 99999999999999999"), 200),
+        ] {
+            let response = get(&files, "/index.html", &[(name, &value)]);
+            assert_eq!(response.status(), status, "{name}: {value}");
+        }
+
+        let response = get(&files, "/index.html", &[("If-None-Match", &etag)]);
+        assert_eq!(response.headers().get("ETag"), Some(etag.as_str()));
+        assert_eq!(body(response), b"");
+
+        // 文件改变后校验器随之改变。
+        fs::write(root.path().join("index.html"), "<h1>changed</h1>").unwrap();
+        let response = get(&files, "/index.html", &[("If-None-Match", &etag)]);
+        assert_eq!(response.status(), 200);
+        assert_ne!(response.headers().get("ETag"), Some(etag.as_str()));
+        assert_eq!(body(response), b"<h1>changed</h1>");
+    }
+
+    #[test]
+    fn serves_byte_ranges() {
+        let root = temp_root();
+        let files = StaticFiles::new(root.path());
+        let etag = get(&files, "/index.html", &[])
+            .headers()
+            .get("ETag")
+            .unwrap()
+            .to_string();
+
+        // 文件内容为 `<h1>home</h1>`，共 13 字节。
+        for (range, content_range, expected) in [
+            ("bytes=1-2", "bytes 1-2/13", &b"h1"[..]),
+            ("bytes=4-", "bytes 4-12/13", b"home</h1>"),
+            ("bytes=-5", "bytes 8-12/13", b"</h1>"),
+            ("bytes=9-100", "bytes 9-12/13", b"/h1>"),
+        ] {
+            let response = get(&files, "/index.html", &[("Range", range)]);
+            assert_eq!(response.status(), 206, "{range}");
+            assert_eq!(response.headers().get("Content-Range"), Some(content_range));
+            assert_eq!(body(response), expected, "{range}");
+        }
+
+        let response = get(&files, "/index.html", &[("Range", "bytes=13-")]);
+        assert_eq!(response.status(), 416);
+        assert_eq!(response.headers().get("Content-Range"), Some("bytes */13"));
+
+        for ignored in ["bytes=0-1,4-5", "bytes=5-1", "items=0-1"] {
+            let response = get(&files, "/index.html", &[("Range", ignored)]);
+            assert_eq!(response.status(), 200, "{ignored}");
+        }
+
+        let stale = [("Range", "bytes=0-3"), ("If-Range", "\"stale\"")];
+        assert_eq!(get(&files, "/index.html", &stale).status(), 200);
+        let current = [("Range", "bytes=0-3"), ("If-Range", etag.as_str())];
+        assert_eq!(get(&files, "/index.html", &current).status(), 206);
+    }
+
+    #[test]
+    fn handler_uses_wildcard_param() {
+        let root = temp_root();
+        let router =
+            crate::router::Router::new().get("/assets/*path", StaticFiles::new(root.path()));
+
+        let response = router.handle(Request::new(Method::Get, "/assets/docs/").unwrap());
+        assert_eq!(body(response), b"<h1>docs</h1>");
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+macro_rules! status_codes {
+    ($($(#[$doc:meta])* $name:ident = $code:literal, $reason:literal;)+) => {
+        /// HTTP 状态码，原因短语取自 RFC 9110。
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+        #[non_exhaustive]
+        pub enum StatusCode {
+            $($(#[$doc])* $name,)+
+            /// 上面没有列出的状态码，比如代理转发的 418 或者自定义的状态码，原因短语为空。
+            ///
+            /// 只能通过 [`StatusCode::from_code`] 得到，数字由 [`StatusCode::as_u16`] 取出。
+            Other(OtherCode),
+        }
+
+        impl StatusCode {
+            /// 由数字状态码得到对应的枚举值，不认识的状态码返回 `None`。
+            pub fn from_u16(code: u16) -> Option<StatusCode> {
+                match code {
+                    $($code => Some(StatusCode::$name),)+
+                    _ => None,
+                }
+            }
+
+            pub fn as_u16(self) -> u16 {
+                match self {
+                    $(StatusCode::$name => $code,)+
+                    StatusCode::Other(OtherCode(code)) => code,
+                }
+            }
+
+            /// 标准的原因短语，比如 404 对应 `Not Found`；[`StatusCode::Other`] 为空。
+            pub fn reason(self) -> &'static str {
+                match self {
+                    $(StatusCode::$name => $reason,)+
+                    StatusCode::Other(_) => "",
+                }
+            }
+        }
+    };
+}
+
+status_codes! {
+    Continue = 100, "Continue";
+    SwitchingProtocols = 101, "Switching Protocols";
+
+    Ok = 200, "OK";
+    Created = 201, "Created";
+    Accepted = 202, "Accepted";
+    NoContent = 204, "No Content";
+    PartialContent = 206, "Partial Content";
+
+    MovedPermanently = 301, "Moved Permanently";
+    Found = 302, "Found";
+    SeeOther = 303, "See Other";
+    NotModified = 304, "Not Modified";
+    TemporaryRedirect = 307, "Temporary Redirect";
+    PermanentRedirect = 308, "Permanent Redirect";
+
+    BadRequest = 400, "Bad Request";
+    Unauthorized = 401, "Unauthorized";
+    Forbidden = 403, "Forbidden";
+    NotFound = 404, "Not Found";
+    MethodNotAllowed = 405, "Method Not Allowed";
+    NotAcceptable = 406, "Not Acceptable";
+    RequestTimeout = 408, "Request Timeout";
+    Conflict = 409, "Conflict";
+    Gone = 410, "Gone";
+    LengthRequired = 411, "Length Required";
+    PreconditionFailed = 412, "Precondition Failed";
+    ContentTooLarge = 413, "Content Too Large";
+    UriTooLong = 414, "URI Too Long";
+    UnsupportedMediaType = 415, "Unsupported Media Type";
+    RangeNotSatisfiable = 416, "Range Not Satisfiable";
+    ExpectationFailed = 417, "Expectation Failed";
+    UnprocessableContent = 422, "Unprocessable Content";
+    UpgradeRequired = 426, "Upgrade Required";
+    TooManyRequests = 429, "Too Many Requests";
+    RequestHeaderFieldsTooLarge = 431, "Request Header Fields Too Large";
+
+    InternalServerError = 500, "Internal Server Error";
+    NotImplemented = 501, "Not Implemented";
+    BadGateway = 502, "Bad Gateway";
+    ServiceUnavailable = 503, "Service Unavailable";
+    GatewayTimeout = 504, "Gateway Timeout";
+    HttpVersionNotSupported = 505, "HTTP Version Not Supported";
+}
+
+/// [`StatusCode::Other`] 中没有列出的三位数状态码。
+///
+/// 字段是私有的：它只能由 [`StatusCode::from_code`] 构造，因此总在 100 到 999 之间，
+/// 而且不会是已经列出的状态码（否则 `Other(404)` 与 `NotFound` 并不相等）。
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OtherCode(u16);
+
+impl StatusCode {
+    /// 任意三位数的状态码：认识的得到对应的枚举值，其余的得到 [`StatusCode::Other`]。
+    pub fn from_code(code: u16) -> Option<StatusCode> {
+        match code {
+            100..=999 => StatusCode::from_u16(code).or(Some(StatusCode::Other(OtherCode(code)))),
+            _ => None,
+        }
+    }
+
+    /// 1xx。
+    pub fn is_informational(self) -> bool {
+        (100..200).contains(&self.as_u16())
+    }
+
+    /// 2xx。
+    pub fn is_success(self) -> bool {
+        (200..300).contains(&self.as_u16())
+    }
+
+    /// 3xx。
+    pub fn is_redirection(self) -> bool {
+        (300..400).contains(&self.as_u16())
+    }
+
+    /// 4xx。
+    pub fn is_client_error(self) -> bool {
+        (400..500).contains(&self.as_u16())
+    }
+
+    /// 5xx。
+    pub fn is_server_error(self) -> bool {
+        (500..600).contains(&self.as_u16())
+    }
+
+    /// 这个状态码的响应是否不能带响应体（1xx、204 与 304）。
+    pub fn forbids_body(self) -> bool {
+        self.is_informational() || matches!(self, StatusCode::NoContent | StatusCode::NotModified)
+    }
+}
+
+impl From<StatusCode> for u16 {
+    fn from(status: StatusCode) -> u16 {
+        status.as_u16()
+    }
+}
+
+impl PartialEq<u16> for StatusCode {
+    fn eq(&self, other: &u16) -> bool {
+        self.as_u16() == *other
+    }
+}
+
+impl fmt::Display for StatusCode {
+    /// 输出状态行中的形式，比如 `404 Not Found`。
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.as_u16(), self.reason())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_codes_to_reasons() {
+        assert_eq!(StatusCode::from_u16(404), Some(StatusCode::NotFound));
+        assert_eq!(StatusCode::from_u16(299), None);
+        assert_eq!(StatusCode::ContentTooLarge.reason(), "Content Too Large");
+        assert_eq!(StatusCode::Ok.to_string(), "200 OK");
+        assert_eq!(StatusCode::ServiceUnavailable, 503);
+
+        for code in 100..600 {
+            if let Some(status) = StatusCode::from_u16(code) {
+                assert_eq!(status.as_u16(), code);
+            }
+        }
+
+        assert_eq!(StatusCode::from_code(404), Some(StatusCode::NotFound));
+        let teapot = StatusCode::from_code(418).unwrap();
+        assert!(matches!(teapot, StatusCode::Other(_)));
+        assert_ne!(teapot, StatusCode::from_code(419).unwrap());
+        assert_eq!(teapot.as_u16(), 418);
+        assert_eq!(teapot.to_string(), "418 ");
+        assert!(teapot.is_client_error());
+        assert_eq!(StatusCode::from_code(99), None);
+        assert_eq!(StatusCode::from_code(1000), None);
+
+        assert!(StatusCode::SeeOther.is_redirection());
+        assert!(StatusCode::NotModified.forbids_body());
+        assert!(!StatusCode::Ok.forbids_body());
+    }
+}
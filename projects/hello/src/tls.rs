@@ -0,0 +1,315 @@
+use std::{
+    cell::RefCell,
+    error::Error,
+    fmt,
+    io::{self, Read, Write},
+    net::{Shutdown, TcpStream},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use rustls::{
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+    ServerConfig, ServerConnection,
+};
+
+use crate::connection::Transport;
+
+/// HTTPS 监听套接字使用的证书与 TLS 设置，见 [`crate::Listener::tls`]。
+///
+/// 只提供 HTTP/1.1，ALPN 协商时也只通告 `http/1.1`。
+#[derive(Clone)]
+pub struct Tls {
+    config: Arc<ServerConfig>,
+}
+
+/// 加载证书或私钥时的错误。
+#[derive(Debug)]
+pub enum TlsError {
+    /// 无法读取 PEM 文件，或者文件中没有需要的内容。
+    Pem {
+        path: PathBuf,
+        error: rustls::pki_types::pem::Error,
+    },
+    /// 证书与私钥不匹配，或者私钥的算法不受支持。
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Pem { path, error } => {
+                write!(f, "无法从 {} 读取 PEM：{error}", path.display())
+            }
+            TlsError::Rustls(error) => write!(f, "证书或私钥无效：{error}"),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TlsError::Pem { error, .. } => Some(error),
+            TlsError::Rustls(error) => Some(error),
+        }
+    }
+}
+
+impl Tls {
+    /// 使用自己构造的 rustls 设置，比如需要验证客户端证书时。
+    pub fn new(config: Arc<ServerConfig>) -> Tls {
+        Tls { config }
+    }
+
+    /// 从 PEM 文件加载证书链与私钥。
+    ///
+    /// `cert` 中的证书按顺序组成证书链，第一个是服务器自己的证书；`key` 中取第一个
+    /// PKCS#8、PKCS#1 或 SEC1 格式的私钥。
+    pub fn from_pem_files(cert: impl AsRef<Path>, key: impl AsRef<Path>) -> Result<Tls, TlsError> {
+        let pem_error = |path: &Path| {
+            let path = path.to_path_buf();
+            move |error| TlsError::Pem { path, error }
+        };
+        let (cert, key) = (cert.as_ref(), key.as_ref());
+
+        let chain = CertificateDer::pem_file_iter(cert)
+            .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+            .map_err(pem_error(cert))?;
+        if chain.is_empty() {
+            return Err(pem_error(cert)(rustls::pki_types::pem::Error::NoItemsFound));
+        }
+        let key = PrivateKeyDer::from_pem_file(key).map_err(pem_error(key))?;
+
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ServerConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .and_then(|builder| builder.with_no_client_auth().with_single_cert(chain, key))
+            .map_err(TlsError::Rustls)?;
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Ok(Tls::new(Arc::new(config)))
+    }
+
+    pub fn config(&self) -> &Arc<ServerConfig> {
+        &self.config
+    }
+
+    /// 开始一个服务器端的 TLS 连接；握手在第一次读写时进行。
+    pub(crate) fn accept(&self, socket: TcpStream) -> io::Result<TlsStream> {
+        let connection =
+            ServerConnection::new(Arc::clone(&self.config)).map_err(io::Error::other)?;
+        Ok(TlsStream {
+            socket,
+            connection: RefCell::new(connection),
+        })
+    }
+
+    /// 异步服务器使用的握手器。
+    pub(crate) fn acceptor(&self) -> tokio_rustls::TlsAcceptor {
+        tokio_rustls::TlsAcceptor::from(Arc::clone(&self.config))
+    }
+}
+
+/// 一个 TLS 连接，读写都经过加密。
+///
+/// 一个连接只在一个线程上读写，所以用 `RefCell` 让读端与写端共享 TLS 状态。
+pub struct TlsStream {
+    socket: TcpStream,
+    connection: RefCell<ServerConnection>,
+}
+
+impl TlsStream {
+    fn with_stream<T>(
+        &self,
+        f: impl FnOnce(&mut rustls::Stream<'_, ServerConnection, &TcpStream>) -> T,
+    ) -> T {
+        let mut connection = self.connection.borrow_mut();
+        let mut socket = &self.socket;
+        f(&mut rustls::Stream::new(&mut *connection, &mut socket))
+    }
+}
+
+impl Transport for TlsStream {
+    fn socket(&self) -> &TcpStream {
+        &self.socket
+    }
+
+    fn read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self.with_stream(|stream| stream.read(buf)) {
+            // 客户端常常不发送 close_notify 就关闭连接，HTTP 自己标明了消息的边界，
+            // 当作正常关闭即可。
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+            result => result,
+        }
+    }
+
+    fn write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.with_stream(|stream| stream.write(buf))
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        self.with_stream(|stream| stream.flush())
+    }
+
+    /// 先发送 close_notify，对端才能区分正常关闭与被截断的连接。
+    fn shutdown_write(&self) -> io::Result<()> {
+        self.connection.borrow_mut().send_close_notify();
+        self.flush()?;
+        self.socket.shutdown(Shutdown::Write)
+    }
+
+    fn is_tls(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{env, fs, net::TcpListener, process, thread};
+
+    use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+    use crate::{Listener, NullLogger, Request, Response, Server, StatusCode, ThreadPool};
+
+    /// 临时目录中的证书与私钥文件；离开作用域时删除整个目录。
+    struct CertFiles {
+        cert: PathBuf,
+        key: PathBuf,
+    }
+
+    impl Drop for CertFiles {
+        fn drop(&mut self) {
+            if let Some(dir) = self.cert.parent() {
+                let _ = fs::remove_dir_all(dir);
+            }
+        }
+    }
+
+    /// 在临时目录中写出一张 localhost 的自签名证书，返回证书与私钥的文件。
+    fn self_signed(name: &str) -> (CertFiles, CertificateDer<'static>) {
+        let rcgen::CertifiedKey { cert, key_pair } =
+            rcgen::generate_simple_self_signed(vec![String::from("localhost")]).unwrap();
+
+        let dir = env::temp_dir().join(format!("hello-tls-{}-{name}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let files = CertFiles {
+            cert: dir.join("cert.pem"),
+            key: dir.join("key.pem"),
+        };
+        fs::write(&files.cert, cert.pem()).unwrap();
+        fs::write(&files.key, key_pair.serialize_pem()).unwrap();
+
+        (files, cert.der().clone())
+    }
+
+    fn client(root: CertificateDer<'static>) -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        roots.add(root).unwrap();
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let mut config = ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = vec![b"http/1.1".to_vec()];
+        Arc::new(config)
+    }
+
+    fn read_all(stream: &mut impl Read) -> String {
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn serves_https_alongside_http() {
+        let (files, root) = self_signed("serve");
+        let tls = Tls::from_pem_files(&files.cert, &files.key).unwrap();
+        let client = client(root);
+
+        for asynchronous in [false, true] {
+            let plain = TcpListener::bind("127.0.0.1:0").unwrap();
+            let secure = Listener::tls(TcpListener::bind("127.0.0.1:0").unwrap(), tls.clone());
+            assert!(secure.is_tls());
+
+            let handler = |request: Request| {
+                let scheme = if request.is_secure() { "https" } else { "http" };
+                assert!(request.peer_addr().unwrap().ip().is_loopback());
+                Response::new(StatusCode::Ok)
+                    .with_body(format!("hello {} over {scheme}", request.path()))
+            };
+            // 明文请求发到 HTTPS 端口会记录一条警告，这里不需要看到它。
+            let server = Server::new(plain, ThreadPool::new(2), handler)
+                .listener(secure)
+                .logger(Arc::new(NullLogger));
+            let addrs = server.local_addrs().unwrap();
+            let handle = server.shutdown_handle();
+            let server = thread::spawn(move || {
+                if asynchronous {
+                    server.run_async().unwrap()
+                } else {
+                    server.run().unwrap()
+                }
+            });
+
+            let mut stream = TcpStream::connect(addrs[0]).unwrap();
+            stream
+                .write_all(b"GET /plain HTTP/1.1\r\nConnection: close\r\n\r\n")
+                .unwrap();
+            let out = read_all(&mut stream);
+            assert!(out.ends_with("hello /plain over http"), "{out}");
+
+            // 同一个 TLS 连接上的两个请求，第二个要求关闭连接。
+            let connection =
+                ClientConnection::new(Arc::clone(&client), "localhost".try_into().unwrap())
+                    .unwrap();
+            let mut stream = StreamOwned::new(connection, TcpStream::connect(addrs[1]).unwrap());
+            stream
+                .write_all(
+                    b"GET /one HTTP/1.1\r\n\r\nGET /two HTTP/1.1\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+            let out = read_all(&mut stream);
+            assert_eq!(out.matches("HTTP/1.1 200 OK").count(), 2, "{out}");
+            assert!(out.contains("hello /one over https"));
+            assert!(out.ends_with("hello /two over https"));
+            assert_eq!(stream.conn.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+            // 明文请求发到 HTTPS 端口得不到 HTTP 响应。
+            let mut stream = TcpStream::connect(addrs[1]).unwrap();
+            stream.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+            let mut out = Vec::new();
+            let _ = stream.read_to_end(&mut out);
+            assert!(!out.starts_with(b"HTTP/1.1"));
+
+            handle.shutdown();
+            server.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn reports_unusable_certificates() {
+        let (files, _) = self_signed("bad");
+        let (other, _) = self_signed("other");
+        let (cert, key, other_key) = (&files.cert, &files.key, &other.key);
+        let missing = cert.with_file_name("missing.pem");
+
+        match Tls::from_pem_files(&missing, key) {
+            Err(TlsError::Pem { path, .. }) => assert_eq!(path, missing),
+            other => panic!("{:?}", other.err()),
+        }
+        // 把私钥当作证书：文件里没有证书。
+        match Tls::from_pem_files(key, key) {
+            Err(TlsError::Pem { path, .. }) => assert_eq!(&path, key),
+            other => panic!("{:?}", other.err()),
+        }
+        match Tls::from_pem_files(cert, cert) {
+            Err(TlsError::Pem { path, .. }) => assert_eq!(&path, cert),
+            other => panic!("{:?}", other.err()),
+        }
+        let error = Tls::from_pem_files(cert, other_key).err().unwrap();
+        assert!(matches!(error, TlsError::Rustls(_)), "{error}");
+    }
+}
@@ -0,0 +1,144 @@
+use std::{
+    fmt,
+    io::{self, Read, Write},
+    sync::mpsc::{Receiver, RecvTimeoutError},
+    time::Duration,
+};
+
+use crate::connection::Transport;
+
+/// 协议升级之后的连接，见 [`crate::Response::with_upgrade`]。
+///
+/// 读取时先返回升级之前已经读进缓冲区的数据。读写都会阻塞，可以用
+/// [`set_read_timeout`](Upgraded::set_read_timeout) 限制单次读取等待的时间。
+/// 放下它就关闭连接。
+pub struct Upgraded {
+    buffered: io::Cursor<Vec<u8>>,
+    io: Io,
+}
+
+enum Io {
+    /// 阻塞版本的服务器直接交出连接。
+    Stream(Box<dyn Transport + Send>),
+    /// 异步版本的服务器中，连接任务经由通道转发两个方向的数据。
+    Channel {
+        incoming: Receiver<Vec<u8>>,
+        outgoing: Box<dyn Write + Send>,
+        read_timeout: Option<Duration>,
+    },
+}
+
+impl Upgraded {
+    pub(crate) fn stream(buffered: Vec<u8>, stream: Box<dyn Transport + Send>) -> Upgraded {
+        Upgraded {
+            buffered: io::Cursor::new(buffered),
+            io: Io::Stream(stream),
+        }
+    }
+
+    /// `incoming` 的发送端被丢弃时表示对端关闭了连接。
+    pub(crate) fn channel(
+        incoming: Receiver<Vec<u8>>,
+        outgoing: impl Write + Send + 'static,
+    ) -> Upgraded {
+        Upgraded {
+            buffered: io::Cursor::new(Vec::new()),
+            io: Io::Channel {
+                incoming,
+                outgoing: Box::new(outgoing),
+                read_timeout: None,
+            },
+        }
+    }
+
+    /// 设置单次读取最长等待多久，`None` 表示一直等待。超时的读取返回 `WouldBlock` 或
+    /// `TimedOut` 错误，之后仍然可以继续读取。
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) -> io::Result<()> {
+        match &mut self.io {
+            Io::Stream(stream) => stream.socket().set_read_timeout(timeout),
+            Io::Channel { read_timeout, .. } => {
+                *read_timeout = timeout;
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.buffered.position() < self.buffered.get_ref().len() as u64 {
+            return self.buffered.read(buf);
+        }
+
+        match &mut self.io {
+            Io::Stream(stream) => stream.read(buf),
+            Io::Channel {
+                incoming,
+                read_timeout,
+                ..
+            } => {
+                let received = match read_timeout {
+                    Some(timeout) => incoming.recv_timeout(*timeout),
+                    None => incoming.recv().map_err(RecvTimeoutError::from),
+                };
+                match received {
+                    Ok(bytes) => {
+                        self.buffered = io::Cursor::new(bytes);
+                        self.buffered.read(buf)
+                    }
+                    Err(RecvTimeoutError::Timeout) => Err(io::ErrorKind::TimedOut.into()),
+                    Err(RecvTimeoutError::Disconnected) => Ok(0),
+                }
+            }
+        }
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.io {
+            Io::Stream(stream) => stream.write(buf),
+            Io::Channel { outgoing, .. } => outgoing.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.io {
+            Io::Stream(stream) => stream.flush(),
+            Io::Channel { outgoing, .. } => outgoing.flush(),
+        }
+    }
+}
+
+impl Drop for Upgraded {
+    fn drop(&mut self) {
+        if let Io::Stream(stream) = &self.io {
+            let _ = stream.shutdown_write();
+        }
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgraded")
+    }
+}
+
+/// 升级之后接管连接的回调。
+pub(crate) struct OnUpgrade(Box<dyn FnOnce(Upgraded) + Send>);
+
+impl OnUpgrade {
+    pub(crate) fn new(f: impl FnOnce(Upgraded) + Send + 'static) -> OnUpgrade {
+        OnUpgrade(Box::new(f))
+    }
+
+    pub(crate) fn run(self, upgraded: Upgraded) {
+        (self.0)(upgraded)
+    }
+}
+
+impl fmt::Debug for OnUpgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("OnUpgrade")
+    }
+}
@@ -0,0 +1,202 @@
+use std::fmt;
+
+use crate::{request::Request, response::Response, router::Handler};
+
+/// 按 `Host` 头部把请求分给各个站点的处理器。
+///
+/// 主机名不区分大小写，忽略端口与末尾的点；`*.example.com` 匹配 `example.com` 的任意
+/// 子域名，但不匹配 `example.com` 本身。精确的名字优先于通配符，较长的通配符优先于
+/// 较短的。请求目标是绝对形式（`http://host/path`）时以其中的主机为准。没有匹配的
+/// 站点或者请求没有给出主机时交给默认处理器。
+///
+/// ```
+/// use hello::{Response, Router, StatusCode, VirtualHosts};
+///
+/// let blog = Router::new().get("/", |_| Response::text(StatusCode::Ok, "blog"));
+/// let hosts = VirtualHosts::new(|_| Response::plain(StatusCode::NotFound))
+///     .host("blog.example.com", blog)
+///     .host("*.example.com", |_| Response::text(StatusCode::Ok, "other"));
+/// ```
+pub struct VirtualHosts {
+    hosts: Vec<(Pattern, Box<dyn Handler>)>,
+    default: Box<dyn Handler>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Pattern {
+    Exact(String),
+    /// `*.example.com` 中 `*` 之后的部分，即 `.example.com`。
+    Suffix(String),
+}
+
+impl VirtualHosts {
+    /// 以 `default` 处理不属于任何站点的请求。
+    pub fn new(default: impl Handler + 'static) -> VirtualHosts {
+        VirtualHosts {
+            hosts: Vec::new(),
+            default: Box::new(default),
+        }
+    }
+
+    /// 以 `handler` 处理主机名为 `name` 的请求，`name` 可以是 `*.` 开头的通配符。
+    ///
+    /// # Panics
+    ///
+    /// 同一个名字添加两次时 panic。
+    pub fn host(mut self, name: &str, handler: impl Handler + 'static) -> VirtualHosts {
+        let name = normalize(name);
+        let pattern = match name.strip_prefix('*') {
+            Some(suffix) if suffix.starts_with('.') => Pattern::Suffix(suffix.to_string()),
+            _ => Pattern::Exact(name),
+        };
+        assert!(
+            !self.hosts.iter().any(|(p, _)| *p == pattern),
+            "主机 {pattern} 重复"
+        );
+
+        self.hosts.push((pattern, Box::new(handler)));
+        self
+    }
+
+    /// 处理 `request` 的站点。
+    fn site(&self, request: &Request) -> &dyn Handler {
+        match host(request) {
+            Some(host) => self.find(&host),
+            None => self.default.as_ref(),
+        }
+    }
+
+    fn find(&self, host: &str) -> &dyn Handler {
+        let exact = self.hosts.iter().find(|(pattern, _)| match pattern {
+            Pattern::Exact(name) => name == host,
+            Pattern::Suffix(_) => false,
+        });
+        let wildcard = || {
+            self.hosts
+                .iter()
+                .filter_map(|(pattern, handler)| match pattern {
+                    Pattern::Suffix(suffix) if host.ends_with(suffix.as_str()) => {
+                        Some((suffix.len(), handler))
+                    }
+                    _ => None,
+                })
+                .max_by_key(|(len, _)| *len)
+                .map(|(_, handler)| handler)
+        };
+
+        match exact.map(|(_, handler)| handler).or_else(wildcard) {
+            Some(handler) => handler.as_ref(),
+            None => self.default.as_ref(),
+        }
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: Request) -> Response {
+        self.site(&request).handle(request)
+    }
+
+    fn streams_body(&self, request: &Request) -> bool {
+        self.site(request).streams_body(request)
+    }
+}
+
+impl fmt::Debug for VirtualHosts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list()
+            .entries(self.hosts.iter().map(|(pattern, _)| pattern))
+            .finish()
+    }
+}
+
+impl fmt::Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pattern::Exact(name) => f.write_str(name),
+            Pattern::Suffix(suffix) => write!(f, "*{suffix}"),
+        }
+    }
+}
+
+/// 请求的目标主机，已经去掉端口并转为小写。
+fn host(request: &Request) -> Option<String> {
+    let authority = match request.target().split_once("://") {
+        Some((_, rest)) => rest.split(['/', '?', '#']).next(),
+        None => request.header("Host"),
+    }?;
+    // 去掉可能的用户信息。
+    let authority = authority.rsplit('@').next().unwrap_or_default();
+
+    let name = match authority.strip_prefix('[') {
+        // IPv6 字面量：[::1]:8080
+        Some(rest) => &authority[..rest.find(']')? + 2],
+        None => authority.split(':').next().unwrap_or_default(),
+    };
+    Some(normalize(name)).filter(|name| !name.is_empty())
+}
+
+fn normalize(name: &str) -> String {
+    name.trim().trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{request::Method, status::StatusCode};
+
+    fn site(name: &'static str) -> impl Handler {
+        move |_| Response::text(StatusCode::Ok, name)
+    }
+
+    fn body(hosts: &VirtualHosts, target: &str, host: Option<&str>) -> String {
+        let mut request = Request::new(Method::Get, target).unwrap();
+        if let Some(host) = host {
+            request.headers_mut().insert("Host", host);
+        }
+        let response = hosts.handle(request);
+        String::from_utf8(response.body().as_bytes().unwrap().to_vec()).unwrap()
+    }
+
+    #[test]
+    fn picks_the_most_specific_host() {
+        let hosts = VirtualHosts::new(site("default"))
+            .host("Example.com", site("example"))
+            .host("*.example.com", site("any"))
+            .host("*.blog.example.com", site("blogs"))
+            .host("api.example.com", site("api"))
+            .host("[::1]", site("ipv6"));
+
+        let cases = [
+            (Some("example.com"), "example"),
+            (Some("EXAMPLE.COM:8080"), "example"),
+            (Some("example.com."), "example"),
+            (Some("api.example.com"), "api"),
+            (Some("www.example.com"), "any"),
+            (Some("a.b.example.com"), "any"),
+            (Some("me.blog.example.com"), "blogs"),
+            (Some("badexample.com"), "default"),
+            (Some("[::1]:7878"), "ipv6"),
+            (Some("127.0.0.1:7878"), "default"),
+            (Some(""), "default"),
+            (None, "default"),
+        ];
+        for (host, expected) in cases {
+            assert_eq!(body(&hosts, "/", host), expected, "{host:?}");
+        }
+
+        // 绝对形式的请求目标优先于 Host 头部。
+        assert_eq!(
+            body(&hosts, "http://api.example.com:80/x", Some("example.com")),
+            "api"
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "主机 *.example.com 重复")]
+    fn rejects_duplicate_hosts() {
+        let _ = VirtualHosts::new(site("default"))
+            .host("*.example.com", site("a"))
+            .host("*.EXAMPLE.com", site("b"));
+    }
+}
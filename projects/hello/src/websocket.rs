@@ -0,0 +1,972 @@
+//! RFC 6455 WebSocket：握手、帧的编解码，以及收发消息的 [`WebSocket`]。
+//!
+//! 服务器端在处理器中调用 [`upgrade`]，握手成功后回调得到一个 [`WebSocket`]：
+//!
+//! ```no_run
+//! use hello::{websocket::{self, Message}, Router};
+//!
+//! let router = Router::new().get("/ws", |request| {
+//!     websocket::upgrade(&request, |mut socket| {
+//!         while let Ok(message) = socket.recv() {
+//!             if let Message::Text(_) | Message::Binary(_) = message {
+//!                 let _ = socket.send(message);
+//!             }
+//!         }
+//!     })
+//! });
+//! ```
+
+use std::{
+    collections::hash_map::RandomState,
+    error::Error,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    io::{self, Read, Write},
+    net::{TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    base64,
+    request::{Method, Request, Version},
+    response::Response,
+    sha1,
+    status::StatusCode,
+    upgrade::Upgraded,
+};
+
+/// 计算 `Sec-WebSocket-Accept` 时附加在客户端密钥后面的固定字符串。
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// 默认的最大消息长度，见 [`WebSocket::max_message_size`]。
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// 控制帧的负载最多 125 字节。
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// 客户端读取握手响应头部的上限。
+const MAX_HANDSHAKE_SIZE: usize = 16 * 1024;
+
+const READ_CHUNK: usize = 8 * 1024;
+
+/// 握手响应中对应客户端密钥 `key` 的 `Sec-WebSocket-Accept` 值。
+pub fn accept_key(key: &str) -> String {
+    base64::encode(&sha1::digest(format!("{key}{GUID}").as_bytes()))
+}
+
+/// 把请求升级为 WebSocket 连接，握手完成后在处理器的线程上以 `f` 接管连接。
+///
+/// 不是有效的握手请求时以 400 回应；版本不是 13 时以 426 回应，并在
+/// `Sec-WebSocket-Version` 中告诉客户端支持的版本。需要选择子协议时，在返回的响应上
+/// 加上 `Sec-WebSocket-Protocol` 头部即可。
+///
+/// 连接打开期间一直占用一个 worker，线程池的大小限制了同时打开的连接数。
+pub fn upgrade(request: &Request, f: impl FnOnce(WebSocket) + Send + 'static) -> Response {
+    let headers = request.headers();
+    if request.method() != Method::Get
+        || request.version() != Version::Http11
+        || !headers.has_token("Upgrade", "websocket")
+        || !headers.has_token("Connection", "upgrade")
+    {
+        return Response::plain(StatusCode::BadRequest);
+    }
+    if headers.get("Sec-WebSocket-Version") != Some("13") {
+        return Response::plain(StatusCode::UpgradeRequired)
+            .with_header("Sec-WebSocket-Version", "13");
+    }
+    let key = match headers.get("Sec-WebSocket-Key") {
+        Some(key) if base64::decode(key).is_some_and(|nonce| nonce.len() == 16) => key,
+        _ => return Response::plain(StatusCode::BadRequest),
+    };
+
+    Response::new(StatusCode::SwitchingProtocols)
+        .with_header("Upgrade", "websocket")
+        .with_header("Connection", "Upgrade")
+        .with_header("Sec-WebSocket-Accept", accept_key(key))
+        .with_upgrade(|upgraded| f(WebSocket::server(upgraded)))
+}
+
+/// 帧的类型。
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    Continuation = 0x0,
+    Text = 0x1,
+    Binary = 0x2,
+    Close = 0x8,
+    Ping = 0x9,
+    Pong = 0xa,
+}
+
+impl Opcode {
+    fn from_u8(bits: u8) -> Option<Opcode> {
+        match bits {
+            0x0 => Some(Opcode::Continuation),
+            0x1 => Some(Opcode::Text),
+            0x2 => Some(Opcode::Binary),
+            0x8 => Some(Opcode::Close),
+            0x9 => Some(Opcode::Ping),
+            0xa => Some(Opcode::Pong),
+            _ => None,
+        }
+    }
+
+    /// 关闭、ping、pong 是控制帧，可以插在分片消息的帧之间。
+    pub fn is_control(self) -> bool {
+        self as u8 & 0x8 != 0
+    }
+}
+
+/// 一个 WebSocket 帧。
+///
+/// `payload` 总是未加掩码的内容；`mask` 为 `Some` 时编码时以它加掩码，解码得到的帧
+/// 保留对端使用的掩码。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: Opcode,
+    pub mask: Option<[u8; 4]>,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    /// 一个不加掩码、`fin` 置位的帧。
+    pub fn new(opcode: Opcode, payload: impl Into<Vec<u8>>) -> Frame {
+        Frame {
+            fin: true,
+            opcode,
+            mask: None,
+            payload: payload.into(),
+        }
+    }
+
+    /// 把帧编码后追加到 `out`。
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.push(u8::from(self.fin) << 7 | self.opcode as u8);
+
+        let mask_bit = if self.mask.is_some() { 0x80 } else { 0 };
+        let len = self.payload.len();
+        if len < 126 {
+            out.push(mask_bit | len as u8);
+        } else if let Ok(len) = u16::try_from(len) {
+            out.push(mask_bit | 126);
+            out.extend_from_slice(&len.to_be_bytes());
+        } else {
+            out.push(mask_bit | 127);
+            out.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+
+        match self.mask {
+            Some(mask) => {
+                out.extend_from_slice(&mask);
+                let start = out.len();
+                out.extend_from_slice(&self.payload);
+                apply_mask(&mut out[start..], mask);
+            }
+            None => out.extend_from_slice(&self.payload),
+        }
+    }
+
+    /// 从 `buf` 开头解码一个帧，返回帧与它占用的字节数；数据还不完整时返回 `None`。
+    ///
+    /// 负载超过 `max_payload` 字节时不等数据到齐就返回 [`WebSocketError::TooLarge`]。
+    pub fn decode(
+        buf: &[u8],
+        max_payload: usize,
+    ) -> Result<Option<(Frame, usize)>, WebSocketError> {
+        let [first, second, ..] = *buf else {
+            return Ok(None);
+        };
+
+        if first & 0x70 != 0 {
+            return Err(WebSocketError::Protocol("未协商扩展却设置了 RSV 位"));
+        }
+        let fin = first & 0x80 != 0;
+        let opcode =
+            Opcode::from_u8(first & 0x0f).ok_or(WebSocketError::Protocol("未知的操作码"))?;
+
+        let (len, mut pos) = match second & 0x7f {
+            126 => match buf.get(2..4) {
+                Some(bytes) => (u64::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4),
+                None => return Ok(None),
+            },
+            127 => match buf.get(2..10) {
+                Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+                None => return Ok(None),
+            },
+            len => (u64::from(len), 2),
+        };
+
+        if opcode.is_control() && (!fin || len > MAX_CONTROL_PAYLOAD as u64) {
+            return Err(WebSocketError::Protocol("控制帧被分片或者负载过长"));
+        }
+        if len > max_payload as u64 {
+            return Err(WebSocketError::TooLarge);
+        }
+        let len = usize::try_from(len).map_err(|_| WebSocketError::TooLarge)?;
+
+        let mask = if second & 0x80 != 0 {
+            let Some(bytes) = buf.get(pos..pos + 4) else {
+                return Ok(None);
+            };
+            pos += 4;
+            Some(<[u8; 4]>::try_from(bytes).unwrap())
+        } else {
+            None
+        };
+
+        // max_payload 可以设为 usize::MAX，64 位的长度加上头部的长度可能溢出。
+        let end = pos
+            .checked_add(len)
+            .ok_or(WebSocketError::Protocol("负载长度超出范围"))?;
+        let Some(payload) = buf.get(pos..end) else {
+            return Ok(None);
+        };
+        let mut payload = payload.to_vec();
+        if let Some(mask) = mask {
+            apply_mask(&mut payload, mask);
+        }
+
+        let frame = Frame {
+            fin,
+            opcode,
+            mask,
+            payload,
+        };
+        Ok(Some((frame, pos + len)))
+    }
+}
+
+fn apply_mask(bytes: &mut [u8], mask: [u8; 4]) {
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b ^= mask[i % 4];
+    }
+}
+
+/// 一条完整的消息或者一个控制帧。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// 关闭帧；对端没有给出状态码时为 `None`。
+    Close(Option<CloseFrame>),
+}
+
+/// 关闭帧中的状态码与原因。
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+impl CloseFrame {
+    /// 正常关闭。
+    pub const NORMAL: u16 = 1000;
+    /// 对端违反了协议。
+    pub const PROTOCOL_ERROR: u16 = 1002;
+    /// 文本消息不是有效的 UTF-8。
+    pub const INVALID_DATA: u16 = 1007;
+    /// 消息超过了 [`WebSocket::max_message_size`]。
+    pub const TOO_LARGE: u16 = 1009;
+
+    fn parse(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+        let (code, reason) = match payload {
+            [] => return Ok(None),
+            [_] => return Err(WebSocketError::Protocol("关闭帧的负载只有一个字节")),
+            [a, b, reason @ ..] => (u16::from_be_bytes([*a, *b]), reason),
+        };
+        // 1004–1006、1015 保留给本地使用，不能出现在帧中。
+        if !matches!(code, 1000..=1003 | 1007..=1014 | 3000..=4999) {
+            return Err(WebSocketError::Protocol("关闭帧的状态码无效"));
+        }
+        let reason = String::from_utf8(reason.to_vec()).map_err(|_| WebSocketError::InvalidUtf8)?;
+        Ok(Some(CloseFrame { code, reason }))
+    }
+
+    fn payload(&self) -> Vec<u8> {
+        let mut payload = self.code.to_be_bytes().to_vec();
+        payload.extend_from_slice(self.reason.as_bytes());
+        payload
+    }
+}
+
+impl Message {
+    fn into_frame(self) -> Frame {
+        match self {
+            Message::Text(text) => Frame::new(Opcode::Text, text),
+            Message::Binary(bytes) => Frame::new(Opcode::Binary, bytes),
+            Message::Ping(bytes) => Frame::new(Opcode::Ping, bytes),
+            Message::Pong(bytes) => Frame::new(Opcode::Pong, bytes),
+            Message::Close(close) => Frame::new(
+                Opcode::Close,
+                close.map(|close| close.payload()).unwrap_or_default(),
+            ),
+        }
+    }
+}
+
+/// 收发消息时的错误。
+#[derive(Debug)]
+pub enum WebSocketError {
+    Io(io::Error),
+    /// 对端违反了协议；已经以 1002 关闭连接。
+    Protocol(&'static str),
+    /// 文本消息不是有效的 UTF-8；已经以 1007 关闭连接。
+    InvalidUtf8,
+    /// 消息超过了 [`WebSocket::max_message_size`]；已经以 1009 关闭连接。
+    TooLarge,
+    /// 连接已经关闭，不能再收发消息。
+    Closed,
+    /// 客户端握手时服务器没有同意升级。
+    Handshake(String),
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Io(e) => write!(f, "读写连接时出错：{e}"),
+            WebSocketError::Protocol(reason) => write!(f, "违反 WebSocket 协议：{reason}"),
+            WebSocketError::InvalidUtf8 => f.write_str("文本消息不是有效的 UTF-8"),
+            WebSocketError::TooLarge => f.write_str("消息过长"),
+            WebSocketError::Closed => f.write_str("连接已经关闭"),
+            WebSocketError::Handshake(reason) => write!(f, "握手失败：{reason}"),
+        }
+    }
+}
+
+impl Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebSocketError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(e: io::Error) -> WebSocketError {
+        WebSocketError::Io(e)
+    }
+}
+
+impl WebSocketError {
+    /// 因这个错误关闭连接时使用的状态码。
+    fn close_code(&self) -> Option<u16> {
+        match self {
+            WebSocketError::Protocol(_) => Some(CloseFrame::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(CloseFrame::INVALID_DATA),
+            WebSocketError::TooLarge => Some(CloseFrame::TOO_LARGE),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Role {
+    Server,
+    Client,
+}
+
+/// 一个 WebSocket 连接。
+///
+/// [`recv`](WebSocket::recv) 自动回应 ping、重组分片的消息，收到关闭帧时回应一个关闭帧。
+/// 读取超时（见 [`Upgraded::set_read_timeout`]）不会丢失已经读到的数据，可以再次调用。
+pub struct WebSocket<S = Upgraded> {
+    stream: S,
+    role: Role,
+    /// 已经读到、还没有解码的字节。
+    buf: Vec<u8>,
+    /// 分片消息的类型与已经收到的内容。
+    fragments: Option<(Opcode, Vec<u8>)>,
+    max_message_size: usize,
+    sent_close: bool,
+    received_close: bool,
+}
+
+impl<S: Read + Write> WebSocket<S> {
+    /// 服务器端的连接：要求客户端的帧都加掩码，自己发送的帧不加掩码。
+    pub fn server(stream: S) -> WebSocket<S> {
+        WebSocket::new(stream, Role::Server, Vec::new())
+    }
+
+    /// 客户端的连接，`stream` 必须已经完成握手；见 [`WebSocket::handshake`]。
+    pub fn client(stream: S) -> WebSocket<S> {
+        WebSocket::new(stream, Role::Client, Vec::new())
+    }
+
+    fn new(stream: S, role: Role, buf: Vec<u8>) -> WebSocket<S> {
+        WebSocket {
+            stream,
+            role,
+            buf,
+            fragments: None,
+            max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+            sent_close: false,
+            received_close: false,
+        }
+    }
+
+    /// 在 `stream` 上以客户端身份发起握手，请求 `host` 上的 `path`。
+    pub fn handshake(
+        mut stream: S,
+        host: &str,
+        path: &str,
+    ) -> Result<WebSocket<S>, WebSocketError> {
+        let nonce = [random(), random()].concat();
+        let key = base64::encode(&nonce);
+        write!(
+            stream,
+            "GET {path} HTTP/1.1\r\nHost: {host}\r\nUpgrade: websocket\r\n\
+             Connection: Upgrade\r\nSec-WebSocket-Key: {key}\r\nSec-WebSocket-Version: 13\r\n\r\n"
+        )?;
+        stream.flush()?;
+
+        // 逐块读到头部结束，多读到的字节属于第一个帧。
+        let mut buf = Vec::new();
+        let end = loop {
+            if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                break i + 4;
+            }
+            if buf.len() > MAX_HANDSHAKE_SIZE {
+                return Err(WebSocketError::Handshake("响应头部过长".to_string()));
+            }
+            if read_into(&mut stream, &mut buf)? == 0 {
+                return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+            }
+        };
+        let head = String::from_utf8_lossy(&buf[..end]).into_owned();
+        let rest = buf.split_off(end);
+
+        let mut lines = head.split("\r\n");
+        let status = lines.next().unwrap_or_default();
+        if status.split(' ').nth(1) != Some("101") {
+            return Err(WebSocketError::Handshake(format!("服务器回应 {status}")));
+        }
+        let accept = lines
+            .filter_map(|line| line.split_once(':'))
+            .find(|(name, _)| name.trim().eq_ignore_ascii_case("Sec-WebSocket-Accept"))
+            .map(|(_, value)| value.trim());
+        if accept != Some(accept_key(&key).as_str()) {
+            return Err(WebSocketError::Handshake(
+                "Sec-WebSocket-Accept 与密钥不符".to_string(),
+            ));
+        }
+
+        Ok(WebSocket::new(stream, Role::Client, rest))
+    }
+
+    /// 设置最大消息长度，分片消息按重组后的总长度计算。默认为
+    /// [`DEFAULT_MAX_MESSAGE_SIZE`]。
+    pub fn max_message_size(mut self, bytes: usize) -> WebSocket<S> {
+        self.max_message_size = bytes;
+        self
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    pub fn into_inner(self) -> S {
+        self.stream
+    }
+
+    /// 接收下一条消息或者控制帧。
+    ///
+    /// 对端违反协议时先以相应的状态码关闭连接再返回错误。收到关闭帧之后再调用返回
+    /// [`WebSocketError::Closed`]。
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.received_close {
+            return Err(WebSocketError::Closed);
+        }
+        match self.next_message() {
+            Err(e) => {
+                if let Some(code) = e.close_code() {
+                    // 尽力通知对端，连接已经出错，发送失败也不必理会。
+                    let _ = self.close_without_waiting(code, "");
+                    self.received_close = true;
+                }
+                Err(e)
+            }
+            result => result,
+        }
+    }
+
+    fn next_message(&mut self) -> Result<Message, WebSocketError> {
+        loop {
+            let frame = match Frame::decode(&self.buf, self.max_message_size)? {
+                Some((frame, used)) => {
+                    self.buf.drain(..used);
+                    frame
+                }
+                None => {
+                    if read_into(&mut self.stream, &mut self.buf)? == 0 {
+                        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+                    }
+                    continue;
+                }
+            };
+
+            // 客户端的帧必须加掩码，服务器的帧不能加。
+            if frame.mask.is_some() != (self.role == Role::Server) {
+                return Err(WebSocketError::Protocol("帧的掩码不符合规定"));
+            }
+
+            match frame.opcode {
+                Opcode::Ping => {
+                    if !self.sent_close {
+                        self.send_frame(Frame::new(Opcode::Pong, frame.payload.clone()))?;
+                    }
+                    return Ok(Message::Ping(frame.payload));
+                }
+                Opcode::Pong => return Ok(Message::Pong(frame.payload)),
+                Opcode::Close => {
+                    let close = CloseFrame::parse(&frame.payload)?;
+                    self.received_close = true;
+                    if !self.sent_close {
+                        let code = close
+                            .as_ref()
+                            .map_or(CloseFrame::NORMAL, |close| close.code);
+                        self.close_without_waiting(code, "")?;
+                    }
+                    return Ok(Message::Close(close));
+                }
+                Opcode::Text | Opcode::Binary if self.fragments.is_some() => {
+                    return Err(WebSocketError::Protocol("上一条分片消息还没有结束"));
+                }
+                Opcode::Text | Opcode::Binary => {
+                    self.fragments = Some((frame.opcode, Vec::new()));
+                }
+                Opcode::Continuation if self.fragments.is_none() => {
+                    return Err(WebSocketError::Protocol("没有需要继续的分片消息"));
+                }
+                Opcode::Continuation => {}
+            }
+
+            let (_, data) = self.fragments.as_mut().unwrap();
+            if data.len() + frame.payload.len() > self.max_message_size {
+                return Err(WebSocketError::TooLarge);
+            }
+            data.extend_from_slice(&frame.payload);
+
+            if frame.fin {
+                let (opcode, data) = self.fragments.take().unwrap();
+                return match opcode {
+                    Opcode::Text => String::from_utf8(data)
+                        .map(Message::Text)
+                        .map_err(|_| WebSocketError::InvalidUtf8),
+                    _ => Ok(Message::Binary(data)),
+                };
+            }
+        }
+    }
+
+    /// 发送一条消息，整条消息作为一个帧。发送关闭帧之后不能再发送。
+    pub fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        self.send_frame(message.into_frame())
+    }
+
+    pub fn send_text(&mut self, text: impl Into<String>) -> Result<(), WebSocketError> {
+        self.send(Message::Text(text.into()))
+    }
+
+    pub fn send_binary(&mut self, bytes: impl Into<Vec<u8>>) -> Result<(), WebSocketError> {
+        self.send(Message::Binary(bytes.into()))
+    }
+
+    /// 发送一个帧，比如分片发送一条长消息。客户端发送的帧总是加上随机的掩码，服务器
+    /// 发送的帧总是不加掩码。
+    pub fn send_frame(&mut self, mut frame: Frame) -> Result<(), WebSocketError> {
+        if self.sent_close {
+            return Err(WebSocketError::Closed);
+        }
+        if frame.opcode == Opcode::Close {
+            self.sent_close = true;
+        }
+        frame.mask = match self.role {
+            Role::Client => Some(random()[..4].try_into().unwrap()),
+            Role::Server => None,
+        };
+
+        let mut out = Vec::with_capacity(frame.payload.len() + 14);
+        frame.encode(&mut out);
+        self.stream.write_all(&out)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// 以状态码 `code` 关闭连接，并等待对端回应的关闭帧；等待期间收到的消息被丢弃。
+    pub fn close(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        self.close_without_waiting(code, reason)?;
+        while !self.received_close {
+            match self.next_message() {
+                Ok(Message::Close(_)) => break,
+                Ok(_) => {}
+                // 对端直接断开也算关闭完成。
+                Err(WebSocketError::Io(e)) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(e),
+            }
+        }
+        self.received_close = true;
+        Ok(())
+    }
+
+    fn close_without_waiting(&mut self, code: u16, reason: &str) -> Result<(), WebSocketError> {
+        let close = CloseFrame {
+            code,
+            reason: reason.to_string(),
+        };
+        self.send(Message::Close(Some(close)))
+    }
+}
+
+impl WebSocket<TcpStream> {
+    /// 连接到 `addr` 并以客户端身份请求 `path`。
+    pub fn connect(
+        addr: impl ToSocketAddrs,
+        path: &str,
+    ) -> Result<WebSocket<TcpStream>, WebSocketError> {
+        let stream = TcpStream::connect(addr)?;
+        let host = stream.peer_addr()?.to_string();
+        WebSocket::handshake(stream, &host, path)
+    }
+}
+
+impl<S> fmt::Debug for WebSocket<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebSocket")
+            .field("role", &self.role)
+            .field("sent_close", &self.sent_close)
+            .field("received_close", &self.received_close)
+            .finish()
+    }
+}
+
+fn read_into(stream: &mut impl Read, buf: &mut Vec<u8>) -> io::Result<usize> {
+    let mut chunk = [0; READ_CHUNK];
+    let n = stream.read(&mut chunk)?;
+    buf.extend_from_slice(&chunk[..n]);
+    Ok(n)
+}
+
+/// 8 个随机字节，用作掩码和握手密钥；与请求 ID 一样取自 `RandomState` 的随机种子。
+fn random() -> [u8; 8] {
+    RandomState::new().build_hasher().finish().to_be_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        net::{SocketAddr, TcpListener},
+        sync::Arc,
+        thread,
+    };
+
+    use crate::{NullLogger, Router, Server, ThreadPool};
+
+    /// 内存中的连接：从 `input` 读取，写到 `output`。
+    struct Duplex {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Duplex {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Duplex {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// 以客户端的帧作为输入的服务器端连接。
+    fn server_reading(frames: &[Frame]) -> WebSocket<Duplex> {
+        let mut input = Vec::new();
+        for frame in frames {
+            let mut frame = frame.clone();
+            frame.mask.get_or_insert([1, 2, 3, 4]);
+            frame.encode(&mut input);
+        }
+        WebSocket::server(Duplex {
+            input: io::Cursor::new(input),
+            output: Vec::new(),
+        })
+    }
+
+    fn decode_all(mut bytes: &[u8]) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        while let Some((frame, used)) = Frame::decode(bytes, usize::MAX).unwrap() {
+            frames.push(frame);
+            bytes = &bytes[used..];
+        }
+        assert!(bytes.is_empty());
+        frames
+    }
+
+    fn close_code(frame: &Frame) -> u16 {
+        assert_eq!(frame.opcode, Opcode::Close);
+        u16::from_be_bytes([frame.payload[0], frame.payload[1]])
+    }
+
+    #[test]
+    fn computes_the_rfc_6455_accept_key() {
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[test]
+    fn encodes_and_decodes_frames() {
+        // RFC 6455 5.7 中的例子。
+        let mut out = Vec::new();
+        Frame::new(Opcode::Text, "Hello").encode(&mut out);
+        assert_eq!(out, b"\x81\x05Hello");
+
+        let masked = b"\x81\x85\x37\xfa\x21\x3d\x7f\x9f\x4d\x51\x58";
+        let (frame, used) = Frame::decode(masked, 125).unwrap().unwrap();
+        assert_eq!(used, masked.len());
+        assert_eq!(frame.payload, b"Hello");
+        assert_eq!(frame.mask, Some([0x37, 0xfa, 0x21, 0x3d]));
+        let mut out = Vec::new();
+        frame.encode(&mut out);
+        assert_eq!(out, masked);
+
+        for len in [0, 125, 126, 65535, 65536] {
+            for mask in [None, Some([9, 8, 7, 6])] {
+                let frame = Frame {
+                    fin: len % 2 == 0,
+                    opcode: Opcode::Binary,
+                    mask,
+                    payload: (0..len).map(|i| i as u8).collect(),
+                };
+                let mut out = Vec::new();
+                frame.encode(&mut out);
+
+                // 数据不完整时等待更多数据。
+                assert_eq!(
+                    Frame::decode(&out[..out.len() - 1], usize::MAX).unwrap(),
+                    None
+                );
+                assert_eq!(
+                    Frame::decode(&out, usize::MAX).unwrap(),
+                    Some((frame, out.len()))
+                );
+            }
+        }
+
+        assert!(matches!(
+            Frame::decode(b"\x82\x7e\x01\x00", 255),
+            Err(WebSocketError::TooLarge)
+        ));
+        // 不限制负载长度时，64 位的长度加上头部的长度也不能溢出。
+        assert!(matches!(
+            Frame::decode(
+                b"\x82\xff\xff\xff\xff\xff\xff\xff\xff\xff\0\0\0\0",
+                usize::MAX
+            ),
+            Err(WebSocketError::Protocol(_))
+        ));
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let bad: [&[u8]; 4] = [
+            b"\xc1\x00",         // RSV1
+            b"\x83\x00",         // 保留的操作码
+            b"\x09\x00",         // 分片的 ping
+            b"\x89\x7e\x00\x7e", // 126 字节的 ping
+        ];
+        for bytes in bad {
+            assert!(
+                matches!(
+                    Frame::decode(bytes, usize::MAX),
+                    Err(WebSocketError::Protocol(_))
+                ),
+                "{bytes:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn reassembles_fragments_around_control_frames() {
+        let mut socket = server_reading(&[
+            Frame {
+                fin: false,
+                ..Frame::new(Opcode::Text, "héllo ")
+            },
+            Frame::new(Opcode::Ping, "are you there"),
+            Frame {
+                fin: false,
+                ..Frame::new(Opcode::Continuation, "wor")
+            },
+            Frame::new(Opcode::Continuation, "ld"),
+            Frame::new(Opcode::Binary, vec![0, 1, 2]),
+            Frame::new(Opcode::Close, b"\x03\xe8bye".to_vec()),
+        ]);
+
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Ping(b"are you there".to_vec())
+        );
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Text("héllo world".to_string())
+        );
+        assert_eq!(socket.recv().unwrap(), Message::Binary(vec![0, 1, 2]));
+        assert_eq!(
+            socket.recv().unwrap(),
+            Message::Close(Some(CloseFrame {
+                code: 1000,
+                reason: "bye".to_string()
+            }))
+        );
+        assert!(matches!(socket.recv(), Err(WebSocketError::Closed)));
+        assert!(matches!(
+            socket.send_text("late"),
+            Err(WebSocketError::Closed)
+        ));
+
+        // 回应了 pong 与关闭帧，服务器的帧不加掩码。
+        let sent = decode_all(&socket.get_ref().output);
+        assert_eq!(sent.len(), 2);
+        assert_eq!(sent[0], Frame::new(Opcode::Pong, "are you there"));
+        assert_eq!(close_code(&sent[1]), 1000);
+        assert_eq!(sent[1].mask, None);
+    }
+
+    #[test]
+    fn closes_with_an_error_code_on_bad_input() {
+        let unmasked = {
+            let mut input = Vec::new();
+            Frame::new(Opcode::Text, "hi").encode(&mut input);
+            WebSocket::server(Duplex {
+                input: io::Cursor::new(input),
+                output: Vec::new(),
+            })
+        };
+        let cases = [
+            (unmasked, 1002),
+            (
+                server_reading(&[Frame::new(Opcode::Text, vec![0xff, 0xfe])]),
+                1007,
+            ),
+            (
+                server_reading(&[Frame::new(Opcode::Continuation, "x")]),
+                1002,
+            ),
+            (server_reading(&[Frame::new(Opcode::Close, vec![3])]), 1002),
+            (
+                server_reading(&[
+                    Frame {
+                        fin: false,
+                        ..Frame::new(Opcode::Binary, vec![0; 6])
+                    },
+                    Frame::new(Opcode::Continuation, vec![0; 6]),
+                ])
+                .max_message_size(10),
+                1009,
+            ),
+        ];
+
+        for (mut socket, code) in cases {
+            let error = socket.recv().unwrap_err();
+            let sent = decode_all(&socket.get_ref().output);
+            assert_eq!(sent.len(), 1, "{error}");
+            assert_eq!(close_code(&sent[0]), code, "{error}");
+            assert!(matches!(socket.recv(), Err(WebSocketError::Closed)));
+        }
+    }
+
+    fn echo(mut socket: WebSocket) {
+        while let Ok(message) = socket.recv() {
+            if let Message::Text(_) | Message::Binary(_) = message
+                && socket.send(message).is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    fn request(addr: SocketAddr, head: &str) -> String {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream.write_all(head.as_bytes()).unwrap();
+        let mut out = String::new();
+        stream.read_to_string(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn echoes_over_both_servers() {
+        for asynchronous in [false, true] {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            let addr = listener.local_addr().unwrap();
+            let router = Router::new().get("/ws", |request: Request| upgrade(&request, echo));
+            let server =
+                Server::new(listener, ThreadPool::new(2), router).logger(Arc::new(NullLogger));
+            let handle = server.shutdown_handle();
+            let server = thread::spawn(move || {
+                if asynchronous {
+                    server.run_async().unwrap()
+                } else {
+                    server.run().unwrap()
+                }
+            });
+
+            let mut socket = WebSocket::connect(addr, "/ws").unwrap();
+            socket.send_text("hello").unwrap();
+            assert_eq!(socket.recv().unwrap(), Message::Text("hello".to_string()));
+
+            // 比通道中的一段和读缓冲区都大的消息，分片发送。
+            let big: Vec<u8> = (0..200_000).map(|i| i as u8).collect();
+            for (i, part) in big.chunks(70_000).enumerate() {
+                let opcode = if i == 0 {
+                    Opcode::Binary
+                } else {
+                    Opcode::Continuation
+                };
+                let frame = Frame {
+                    fin: (i + 1) * 70_000 >= big.len(),
+                    ..Frame::new(opcode, part)
+                };
+                socket.send_frame(frame).unwrap();
+            }
+            assert_eq!(socket.recv().unwrap(), Message::Binary(big));
+
+            socket.send(Message::Ping(b"ping".to_vec())).unwrap();
+            assert_eq!(socket.recv().unwrap(), Message::Pong(b"ping".to_vec()));
+
+            socket.close(CloseFrame::NORMAL, "done").unwrap();
+            let mut rest = Vec::new();
+            assert_eq!(socket.get_mut().read_to_end(&mut rest).unwrap(), 0);
+
+            // 不是有效的握手请求。
+            let out = request(addr, "GET /ws HTTP/1.1\r\nConnection: close\r\n\r\n");
+            assert!(out.starts_with("HTTP/1.1 400 "), "{out}");
+            let out = request(
+                addr,
+                "GET /ws HTTP/1.1\r\nUpgrade: websocket\r\nConnection: Upgrade, close\r\n\
+                 Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 8\r\n\r\n",
+            );
+            assert!(out.starts_with("HTTP/1.1 426 "), "{out}");
+            assert!(out.contains("Sec-WebSocket-Version: 13\r\n"), "{out}");
+            let error = WebSocket::connect(addr, "/missing").unwrap_err();
+            assert!(matches!(error, WebSocketError::Handshake(_)), "{error}");
+
+            handle.shutdown();
+            server.join().unwrap();
+        }
+    }
+}
@@ -0,0 +1,392 @@
+//! 在随机端口上启动 hello 可执行文件，用 [`hello::Client`] 检查每个路由。
+
+use std::{
+    env, fs,
+    io::{BufRead, BufReader, Read},
+    net::{SocketAddr, TcpListener},
+    path::{Path, PathBuf},
+    process::{self, Child, Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use flate2::read::GzDecoder;
+use hello::{
+    serve_connection,
+    websocket::{CloseFrame, Message},
+    Client, ConnectionOptions, Method, Request, Response, StatusCode, WebSocket,
+};
+
+const MODES: [&str; 2] = ["threaded", "async"];
+
+/// 一个运行中的 hello 进程，放下时结束它。
+struct App {
+    child: Child,
+    /// 连接第一个监听地址的客户端。
+    client: Client,
+    addrs: Vec<SocketAddr>,
+}
+
+impl App {
+    fn start(mode: &str) -> App {
+        App::with_args(&["--listen", "127.0.0.1:0", "--mode", mode])
+    }
+
+    fn with_args(args: &[&str]) -> App {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_hello"))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            .args(["--threads", "4", "--access-log", "off"])
+            .args(args)
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        // 从日志中找到实际监听的地址，之后的日志继续读掉，免得管道写满。
+        let listeners = args.iter().filter(|&&arg| arg == "--listen").count();
+        let mut stderr = BufReader::new(child.stderr.take().unwrap());
+        let mut addrs = Vec::new();
+        let mut line = String::new();
+        while addrs.len() < listeners {
+            line.clear();
+            assert_ne!(
+                stderr.read_line(&mut line).unwrap(),
+                0,
+                "hello 没有开始监听"
+            );
+            if let Some(rest) = line.split("addr=").nth(1) {
+                addrs.push(rest.split_whitespace().next().unwrap().parse().unwrap());
+            }
+        }
+        thread::spawn(move || std::io::copy(&mut stderr, &mut std::io::sink()));
+
+        App {
+            child,
+            client: Client::new(addrs[0]).timeout(Some(Duration::from_secs(15))),
+            addrs,
+        }
+    }
+}
+
+impl Drop for App {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 测试用的临时目录，放下时连同内容一起删除。
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new(name: &str) -> TempDir {
+        let dir = env::temp_dir().join(format!("hello-routes-{name}-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        TempDir(dir)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+fn request(method: Method, target: &str, headers: &[(&str, &str)]) -> Request {
+    let mut request = Request::new(method, target).unwrap();
+    for (name, value) in headers {
+        request.headers_mut().insert(*name, *value);
+    }
+    request
+}
+
+#[test]
+fn serves_the_home_page() {
+    for mode in MODES {
+        let app = App::start(mode);
+        let response = app.client.get("/").unwrap();
+        assert_eq!(response.status(), StatusCode::Ok, "{mode}");
+        assert_eq!(
+            response.header("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+        assert!(response.text().contains("来自 Rust 的问好"));
+        assert!(response.header("Server").unwrap().starts_with("hello/"));
+        assert!(response.header("Date").unwrap().ends_with(" GMT"));
+        assert!(response.header("X-Request-Id").is_some());
+        assert!(response
+            .header("Server-Timing")
+            .unwrap()
+            .starts_with("app;dur="));
+
+        // 内容没有变化时以 304 回应。
+        let etag = response.header("ETag").unwrap();
+        let cached = app
+            .client
+            .send(request(Method::Get, "/", &[("If-None-Match", etag)]))
+            .unwrap();
+        assert_eq!(cached.status(), StatusCode::NotModified);
+        assert!(cached.body().is_empty());
+        assert!(cached.headers().has_token("Vary", "Accept-Encoding"));
+
+        // HEAD 由 GET 路由处理，只是没有响应体。
+        let head = app.client.send(request(Method::Head, "/", &[])).unwrap();
+        assert_eq!(head.status(), StatusCode::Ok);
+        assert_eq!(head.header("ETag"), Some(etag));
+        assert!(head.body().is_empty());
+        let head = app
+            .client
+            .send(request(Method::Head, "/metrics", &[]))
+            .unwrap();
+        assert_eq!(head.status(), StatusCode::Ok);
+
+        let response = app.client.send(request(Method::Post, "/", &[])).unwrap();
+        assert_eq!(response.status(), StatusCode::MethodNotAllowed);
+        assert_eq!(response.header("Allow"), Some("GET, HEAD, OPTIONS"));
+
+        let response = app
+            .client
+            .send(request(Method::Options, "*", &[]))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NoContent);
+        assert!(response.headers().has_token("Allow", "HEAD"));
+    }
+}
+
+#[test]
+fn serves_static_files_and_the_not_found_page() {
+    for mode in MODES {
+        let app = App::start(mode);
+        let home = app.client.get("/").unwrap().into_body();
+
+        // 一个持久连接上的几个请求。
+        let mut connection = app.client.connect().unwrap();
+        let response = connection
+            .send(&request(Method::Get, "/hello.html", &[]))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::Ok, "{mode}");
+        assert_eq!(response.body(), home);
+
+        let response = connection
+            .send(&request(
+                Method::Get,
+                "/hello.html",
+                &[("Range", "bytes=0-14")],
+            ))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::PartialContent);
+        assert_eq!(
+            response.header("Content-Range"),
+            Some(format!("bytes 0-14/{}", home.len()).as_str())
+        );
+        assert_eq!(response.body(), &home[..15]);
+
+        let response = connection
+            .send(&request(Method::Get, "/missing", &[]))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NotFound);
+        assert!(response.text().contains("哎呀"));
+        assert!(!connection.is_closed());
+
+        // 不能访问根目录以外的文件。
+        let response = app.client.get("/../Cargo.toml").unwrap();
+        assert_eq!(response.status(), StatusCode::Forbidden);
+    }
+}
+
+#[test]
+fn reports_metrics() {
+    for mode in MODES {
+        let app = App::start(mode);
+        let response = app.client.get("/metrics").unwrap();
+        assert_eq!(response.status(), StatusCode::Ok, "{mode}");
+        assert!(response
+            .text()
+            .contains("# TYPE hello_pool_threads gauge\n"));
+
+        let response = app
+            .client
+            .send(request(
+                Method::Get,
+                "/metrics",
+                &[("Accept-Encoding", "gzip")],
+            ))
+            .unwrap();
+        assert_eq!(response.header("Content-Encoding"), Some("gzip"));
+        let mut text = String::new();
+        GzDecoder::new(response.body())
+            .read_to_string(&mut text)
+            .unwrap();
+        assert!(text.contains("# TYPE hello_pool_threads gauge\n"));
+    }
+}
+
+#[test]
+fn echoes_over_websocket() {
+    for mode in MODES {
+        let app = App::start(mode);
+        let mut socket = WebSocket::connect(app.client.addr(), "/ws").unwrap();
+        socket.send_text("你好").unwrap();
+        assert_eq!(socket.recv().unwrap(), Message::Text("你好".to_string()));
+        socket.send_binary(vec![1, 2, 3]).unwrap();
+        assert_eq!(socket.recv().unwrap(), Message::Binary(vec![1, 2, 3]));
+        socket.close(CloseFrame::NORMAL, "").unwrap();
+
+        let response = app.client.get("/ws").unwrap();
+        assert_eq!(response.status(), StatusCode::BadRequest, "{mode}");
+    }
+}
+
+#[test]
+fn serves_virtual_hosts_on_every_listener() {
+    let temp = TempDir::new("hosts");
+    let dir = temp.path();
+    fs::create_dir_all(dir.join("blog")).unwrap();
+    fs::write(dir.join("blog/index.html"), "<h1>blog</h1>").unwrap();
+    fs::write(dir.join("blog/404.html"), "no such post").unwrap();
+    let config = dir.join("hello.toml");
+    fs::write(
+        &config,
+        "[host.blog]\nnames = [\"blog.localhost\", \"*.blog.localhost\"]\n\
+         root = \"blog\"\nnot_found_page = \"404.html\"\n",
+    )
+    .unwrap();
+
+    let app = App::with_args(&[
+        "--config",
+        config.to_str().unwrap(),
+        "--listen",
+        "127.0.0.1:0",
+        "--listen",
+        "[::1]:0",
+    ]);
+    assert!(app.addrs[0].is_ipv4() && app.addrs[1].is_ipv6());
+
+    for addr in &app.addrs {
+        let client = Client::new(*addr);
+        let get = |target: &str, host: &str| {
+            client
+                .send(request(Method::Get, target, &[("Host", host)]))
+                .unwrap()
+        };
+
+        let response = get("/", "Blog.Localhost:7878");
+        assert_eq!(response.status(), StatusCode::Ok, "{addr}");
+        assert_eq!(response.text(), "<h1>blog</h1>");
+        assert_eq!(get("/", "me.blog.localhost").text(), "<h1>blog</h1>");
+
+        // 站点各有自己的路由：默认站点的 /metrics 不属于 blog。
+        let response = get("/metrics", "blog.localhost");
+        assert_eq!(response.status(), StatusCode::NotFound);
+        assert_eq!(response.text(), "no such post");
+
+        // 其它主机名交给默认站点。
+        for host in ["localhost", "other.localhost"] {
+            let response = get("/", host);
+            assert!(response.text().contains("来自 Rust 的问好"), "{host}");
+        }
+        assert_eq!(get("/metrics", &addr.to_string()).status(), StatusCode::Ok);
+    }
+}
+
+#[test]
+fn forwards_proxied_prefixes_upstream() {
+    // 回显请求目标与转发头部的上游。
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let upstream = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let echo = |request: Request| {
+                let header = |name| request.header(name).unwrap_or("-").to_string();
+                let text = format!(
+                    "{} for={} host={}",
+                    request.target(),
+                    header("X-Forwarded-For"),
+                    header("X-Forwarded-Host")
+                );
+                Response::text(StatusCode::Ok, text)
+            };
+            let _ = serve_connection(stream, &echo, &ConnectionOptions::default());
+        }
+    });
+
+    let temp = TempDir::new("proxy");
+    let config = temp.path().join("hello.toml");
+    fs::write(
+        &config,
+        format!(
+            "[server]\nroot = {:?}\n\n[proxy.api]\nprefix = \"/api\"\n\
+             upstreams = [\"{upstream}\"]\nstrip_prefix = true\n",
+            env::current_dir().unwrap().join("public")
+        ),
+    )
+    .unwrap();
+
+    for mode in MODES {
+        let app = App::with_args(&[
+            "--config",
+            config.to_str().unwrap(),
+            "--listen",
+            "127.0.0.1:0",
+            "--mode",
+            mode,
+        ]);
+        let response = app
+            .client
+            .send(request(
+                Method::Get,
+                "/api/users?page=2",
+                &[("Host", "example.com")],
+            ))
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::Ok, "{mode}");
+        assert_eq!(
+            response.text(),
+            "/users?page=2 for=127.0.0.1 host=example.com"
+        );
+        assert!(response.header("X-Request-Id").is_some());
+
+        // 前缀以外的请求照常由本地处理。
+        let response = app.client.get("/").unwrap();
+        assert!(response.text().contains("来自 Rust 的问好"), "{mode}");
+    }
+}
+
+/// 同时发出几个 /sleep，它们应当并行执行，期间其它请求也不受影响。
+fn sleeps_concurrently(mode: &str) {
+    let app = App::start(mode);
+    let started = Instant::now();
+    let sleepers: Vec<_> = (0..3)
+        .map(|_| {
+            let client = app.client.clone();
+            thread::spawn(move || client.get("/sleep").unwrap())
+        })
+        .collect();
+
+    thread::sleep(Duration::from_millis(200));
+    let response = app.client.get("/").unwrap();
+    assert_eq!(response.status(), StatusCode::Ok);
+    assert!(started.elapsed() < Duration::from_secs(2), "{mode}");
+
+    for sleeper in sleepers {
+        let response = sleeper.join().unwrap();
+        assert_eq!(response.status(), StatusCode::Ok);
+        assert!(response.text().contains("来自 Rust 的问好"));
+    }
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_secs(5), "{mode}: {elapsed:?}");
+    assert!(elapsed < Duration::from_secs(9), "{mode}: {elapsed:?}");
+}
+
+#[test]
+fn sleeps_concurrently_threaded() {
+    sleeps_concurrently("threaded");
+}
+
+#[test]
+fn sleeps_concurrently_async() {
+    sleeps_concurrently("async");
+}